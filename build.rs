@@ -143,6 +143,7 @@ fn main() {
         FilterType::Paeth,
         FilterType::Sub,
         FilterType::Up,
+        FilterType::Adaptive,
     ];
     write_enum_variants_to_file(&mut file, "FILTER_TYPES", libpng_filter_types);
 
@@ -0,0 +1,25 @@
+#!/bin/bash
+# -*- coding: utf-8 -*-
+
+# builds the seed corpus of the fuzz targets (in `corpus/<target>`) from
+# the test assets, the PNG files seed the decodings and the blending (as
+# raw bytes) and the composition documents seed the document parser
+
+set -e
+
+FUZZ_DIR=$(cd "$(dirname "$0")" && pwd)
+ASSETS_DIR="$FUZZ_DIR/../assets/test"
+
+mkdir -p "$FUZZ_DIR/corpus/decode_png" "$FUZZ_DIR/corpus/decode_image" "$FUZZ_DIR/corpus/blend_images" "$FUZZ_DIR/corpus/composition"
+
+for file in "$ASSETS_DIR"/*.png; do
+    name=$(basename "$file")
+    case "$name" in
+        result_*|*texture*) continue ;;
+    esac
+    cp "$file" "$FUZZ_DIR/corpus/decode_png/$name"
+    cp "$file" "$FUZZ_DIR/corpus/decode_image/$name"
+    head -c 4096 "$file" > "$FUZZ_DIR/corpus/blend_images/$name"
+done
+
+cp "$FUZZ_DIR"/seeds/composition/*.json "$FUZZ_DIR/corpus/composition/"
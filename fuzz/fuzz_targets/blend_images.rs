@@ -0,0 +1,91 @@
+#![no_main]
+
+use image::{ImageBuffer, Rgba};
+use libfuzzer_sys::arbitrary::{Result, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use pconvert_rust::blending::dimensions::{DimensionPolicy, Gravity};
+use pconvert_rust::blending::params::{BlendAlgorithmParams, Value};
+use pconvert_rust::blending::{
+    blend_images, blend_multiple_images, demultiply_image, get_blending_algorithm,
+    is_algorithm_multiplied, multiply_image, BlendAlgorithm,
+};
+use pconvert_rust::constants;
+use std::str::FromStr;
+
+/// Largest side of the fuzzed layers, small layers are enough to cover
+/// every branch of the algorithms and keep the iterations fast.
+const MAX_SIDE: u32 = 16;
+
+const GRAVITIES: [Gravity; 3] = [Gravity::TopLeft, Gravity::Center, Gravity::BottomRight];
+
+fn layer(input: &mut Unstructured) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    let width = input.int_in_range(0..=MAX_SIDE)?;
+    let height = input.int_in_range(0..=MAX_SIDE)?;
+    let mut layer = ImageBuffer::new(width, height);
+    for pixel in layer.pixels_mut() {
+        *pixel = Rgba(input.arbitrary()?);
+    }
+    Ok(layer)
+}
+
+fn params(input: &mut Unstructured) -> Result<Option<BlendAlgorithmParams>> {
+    if !input.arbitrary()? {
+        return Ok(None);
+    }
+    let mut params = BlendAlgorithmParams::new();
+    for _ in 0..input.int_in_range(0..=3)? {
+        let name = match input.arbitrary()? {
+            true => "factor".to_string(),
+            false => input.arbitrary()?,
+        };
+        let value = match input.int_in_range(0..=4)? {
+            0 => Value::Bool(input.arbitrary()?),
+            1 => Value::Long(input.arbitrary()?),
+            2 => Value::Float(input.arbitrary()?),
+            3 => Value::UInt(input.arbitrary()?),
+            _ => Value::Str(input.arbitrary()?),
+        };
+        params.insert(name, value);
+    }
+    Ok(Some(params))
+}
+
+fn policy(input: &mut Unstructured) -> Result<DimensionPolicy> {
+    Ok(match input.int_in_range(0..=3)? {
+        0 => DimensionPolicy::Error,
+        1 => DimensionPolicy::Anchor(*input.choose(&GRAVITIES)?),
+        2 => DimensionPolicy::Stretch,
+        _ => DimensionPolicy::Fit(*input.choose(&GRAVITIES)?),
+    })
+}
+
+fn fuzz(data: &[u8]) -> Result<()> {
+    let mut input = Unstructured::new(data);
+    let algorithm = BlendAlgorithm::from_str(input.choose(&constants::ALGORITHMS)?).unwrap();
+    let params = params(&mut input)?;
+    let mut bot = layer(&mut input)?;
+    let top = layer(&mut input)?;
+
+    // blends the layers as they are (even with different dimensions,
+    // only the overlapping pixels are blended) in the two alpha modes
+    let algorithm_fn = get_blending_algorithm(&algorithm);
+    let mut result = bot.clone();
+    blend_images(&mut result, &top, &algorithm_fn, &params);
+    if is_algorithm_multiplied(&algorithm) {
+        demultiply_image(&mut bot);
+        blend_images(&mut bot, &top, &algorithm_fn, &params);
+        multiply_image(&mut bot);
+    }
+
+    // and as a stack of layers, conformed by the dimension policy
+    let layers = vec![result, top, layer(&mut input)?];
+    let algorithms = vec![(algorithm.clone(), params.clone()), (algorithm, params)];
+    if let Ok(composition) = blend_multiple_images(layers, &algorithms, &policy(&mut input)?) {
+        assert!(composition.width() <= MAX_SIDE && composition.height() <= MAX_SIDE);
+    }
+    Ok(())
+}
+
+fuzz_target!(|data: &[u8]| {
+    let _ = fuzz(data);
+});
@@ -0,0 +1,60 @@
+#![no_main]
+
+use image::{ImageBuffer, Rgba};
+use libfuzzer_sys::fuzz_target;
+use pconvert_rust::batch::BatchItem;
+use pconvert_rust::blending::blend_multiple_images;
+use pconvert_rust::limits::Limits;
+use std::str::{self, FromStr};
+
+/// Largest number of layers of a document that are blended, further
+/// layers only add iterations of the same code paths.
+const MAX_LAYERS: usize = 4;
+
+/// Largest number of pixels of the resized composition, larger
+/// sizes are valid documents but slow down the fuzzing.
+const MAX_PIXELS: u64 = 256 * 256;
+
+fuzz_target!(|data: &[u8]| {
+    let document = match str::from_utf8(data) {
+        Ok(document) => document,
+        Err(_) => return,
+    };
+    let item = match BatchItem::from_str(document) {
+        Ok(item) => item,
+        Err(_) => return,
+    };
+
+    // the options (transforms and effects) of the layers are applied to
+    // small layers of different dimensions, which are then blended and
+    // resized, as in a composition of the document
+    let layers = item
+        .layers
+        .iter()
+        .take(MAX_LAYERS)
+        .enumerate()
+        .map(|(index, _)| {
+            let side = 4 + index as u32 * 3;
+            let layer = ImageBuffer::from_fn(side, side + 1, |x, y| {
+                Rgba([x * 40, y * 30, index as u32 * 60, x * y * 17].map(|value| value as u8))
+            });
+            item.apply_layer_options(index, layer, &Limits::default())
+        })
+        .collect::<Result<Vec<_>, _>>();
+    let layers = match layers {
+        Ok(layers) => layers,
+        Err(_) => return,
+    };
+    let algorithms = &item.algorithms[..item.algorithms.len().min(layers.len().saturating_sub(1))];
+    let composition = match blend_multiple_images(layers, algorithms, &item.dimension_policy) {
+        Ok(composition) => composition,
+        Err(_) => return,
+    };
+    if let Some(resize) = &item.resize {
+        if let Ok((width, height)) = resize.size.dimensions(composition.dimensions()) {
+            if width as u64 * height as u64 <= MAX_PIXELS {
+                let _ = resize.apply(&composition);
+            }
+        }
+    }
+});
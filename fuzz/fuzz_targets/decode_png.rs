@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pconvert_rust::utils::decode_png;
+
+fuzz_target!(|data: &[u8]| {
+    // any input must either be decoded into a consistent image
+    // buffer or be rejected with an error, never panic
+    let demultiply = data.len() % 2 == 0;
+    if let Ok(image) = decode_png(data, demultiply) {
+        let (width, height) = image.dimensions();
+        assert_eq!(image.as_raw().len(), width as usize * height as usize * 4);
+    }
+});
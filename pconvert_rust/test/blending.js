@@ -1,4 +1,5 @@
 const fs = require("fs");
+const os = require("os");
 const path = require("path");
 const assert = require("assert");
 
@@ -59,6 +60,163 @@ describe("NodeJS WASM", async function() {
             pconvert.blendMultipleFs(paths, out, algorithm, algorithms, true);
             assert(fs.existsSync(out));
         });
+
+        it("should throw an error with the offending layer for files with different dimensions", () => {
+            const paths = [
+                path.resolve(`${TEST_ASSETS}/sole.png`),
+                path.resolve(`${TEST_ASSETS}/tux.png`)
+            ];
+            const out = path.resolve(`${TEST_ASSETS}/result_alpha_alpha_Fast_NoFilter.png`);
+            assert.throws(
+                () => pconvert.blendMultipleFs(paths, out, "alpha", null, true),
+                error =>
+                    error instanceof Error && error.code === "DIMENSION_MISMATCH" && error.layer === 1
+            );
+        });
+
+        it("should conform layers with different dimensions using the dimension policy", () => {
+            const paths = [
+                path.resolve(`${TEST_ASSETS}/sole.png`),
+                path.resolve(`${TEST_ASSETS}/tux.png`)
+            ];
+            const out = path.join(os.tmpdir(), "pconvert_dimension_policy.png");
+            const options = { dimension_policy: "fit", gravity: "bottom" };
+            pconvert.blendMultipleFs(paths, out, "alpha", null, true, options);
+            assert(fs.existsSync(out));
+        });
+
+        it("should resize the composition when a scale is given", () => {
+            const paths = [
+                path.resolve(`${TEST_ASSETS}/sole.png`),
+                path.resolve(`${TEST_ASSETS}/back.png`)
+            ];
+            const out = path.join(os.tmpdir(), "pconvert_resize.png");
+            pconvert.blendMultipleFs(paths, out, "source_over", null, true, { scale: 0.5 });
+            assert.strictEqual(fs.readFileSync(out).readUInt32BE(16), 375);
+        });
+
+        it("should apply the transforms of layer objects before blending", () => {
+            const paths = [{ path: path.resolve(`${TEST_ASSETS}/tux.png`), transforms: ["rotate:90"] }];
+            const out = path.join(os.tmpdir(), "pconvert_transform.png");
+            pconvert.blendMultipleFs(paths, out, null, null, true);
+            const data = fs.readFileSync(out);
+            assert.strictEqual(data.readUInt32BE(16), 314);
+            assert.strictEqual(data.readUInt32BE(20), 265);
+        });
+
+        it("should throw an error for an invalid layer effect", () => {
+            const paths = [{ path: path.resolve(`${TEST_ASSETS}/tux.png`), effects: ["brightness:2"] }];
+            const out = path.join(os.tmpdir(), "pconvert_effects.png");
+            assert.throws(
+                () => pconvert.blendMultipleFs(paths, out, null, null, true),
+                error => error.code === "INVALID_PARAMETER"
+            );
+        });
+
+        it("should throw a limit error for a composition beyond the limits", () => {
+            const paths = [
+                path.resolve(`${TEST_ASSETS}/sole.png`),
+                path.resolve(`${TEST_ASSETS}/back.png`)
+            ];
+            const out = path.join(os.tmpdir(), "pconvert_limits.png");
+            assert.throws(
+                () => pconvert.blendMultipleFs(paths, out, null, null, true, { max_layers: 1 }),
+                error => error.code === "LIMIT_EXCEEDED" && error.limit === "max_layers"
+            );
+            assert.throws(
+                () => pconvert.blendMultipleFs(paths, out, null, null, true, { max_width: 749 }),
+                error => error.code === "LIMIT_EXCEEDED" && error.limit === "max_width"
+            );
+        });
+
+        it("should blend multiple files into the format given or inferred", () => {
+            const paths = [
+                path.resolve(`${TEST_ASSETS}/sole.png`),
+                path.resolve(`${TEST_ASSETS}/back.png`)
+            ];
+            const out = path.join(os.tmpdir(), "pconvert_format.qoi");
+            pconvert.blendMultipleFs(paths, out, "source_over", null, true);
+            assert.strictEqual(fs.readFileSync(out).subarray(0, 4).toString(), "qoif");
+            pconvert.blendMultipleFs(paths, out, "source_over", null, true, { format: "jpeg", quality: 80 });
+            assert.deepStrictEqual([...fs.readFileSync(out).subarray(0, 3)], [0xff, 0xd8, 0xff]);
+            assert.throws(
+                () => pconvert.blendMultipleFs(paths, out, null, null, true, { format: "png", quality: 80 }),
+                error => error.code === "INVALID_PARAMETER"
+            );
+        });
+
+        it("should blend multiple files into an indexed PNG", () => {
+            const paths = [
+                path.resolve(`${TEST_ASSETS}/sole.png`),
+                path.resolve(`${TEST_ASSETS}/back.png`)
+            ];
+            const out = path.join(os.tmpdir(), "pconvert_png8.png");
+            pconvert.blendMultipleFs(paths, out, "source_over", null, true, { colors: 64, dither: true });
+            assert.strictEqual(fs.readFileSync(out)[25], 3);
+            assert.throws(
+                () => pconvert.blendMultipleFs(paths, out, null, null, true, { dither: true }),
+                error => error.code === "INVALID_PARAMETER"
+            );
+        });
+
+        it("should blend multiple files into an optimized PNG", () => {
+            const paths = [
+                path.resolve(`${TEST_ASSETS}/sole.png`),
+                path.resolve(`${TEST_ASSETS}/back.png`)
+            ];
+            const out = path.join(os.tmpdir(), "pconvert_plain.png");
+            const optimized = path.join(os.tmpdir(), "pconvert_optimized.png");
+            pconvert.blendMultipleFs(paths, out, "source_over", null, true);
+            pconvert.blendMultipleFs(paths, optimized, "source_over", null, true, { optimize: true });
+            assert(fs.statSync(optimized).size < fs.statSync(out).size);
+        });
+
+        it("should blend multiple files with a numeric compression level", () => {
+            const paths = [
+                path.resolve(`${TEST_ASSETS}/sole.png`),
+                path.resolve(`${TEST_ASSETS}/back.png`)
+            ];
+            const out = path.join(os.tmpdir(), "pconvert_plain.png");
+            const stored = path.join(os.tmpdir(), "pconvert_stored.png");
+            pconvert.blendMultipleFs(paths, out, "source_over", null, true);
+            pconvert.blendMultipleFs(paths, stored, "source_over", null, true, { compression: 0 });
+            assert(fs.statSync(stored).size > fs.statSync(out).size);
+            pconvert.blendMultipleFs(paths, stored, "source_over", null, true, {
+                compression: "3",
+                strategy: "rle",
+                filter: "adaptive"
+            });
+            assert.throws(
+                () => pconvert.blendMultipleFs(paths, out, null, null, true, { compression: "medium" }),
+                error => error.code === "INVALID_PARAMETER"
+            );
+            assert.throws(
+                () => pconvert.blendMultipleFs(paths, out, null, null, true, { filter: "median" }),
+                error => error.code === "INVALID_PARAMETER"
+            );
+        });
+
+        it("should blend layers in any of the input formats", () => {
+            const qoi = path.join(os.tmpdir(), "pconvert_layer.qoi");
+            pconvert.blendMultipleFs([path.resolve(`${TEST_ASSETS}/back.png`)], qoi, null, null, true);
+            const paths = [path.resolve(`${TEST_ASSETS}/sole.png`), qoi];
+            const out = path.join(os.tmpdir(), "pconvert_layer.png");
+            pconvert.blendMultipleFs(paths, out, "source_over", null, true);
+            assert(fs.existsSync(out), `Expected final composition to be at ${out}`);
+        });
+
+        it("should throw a decode error for a corrupt file", () => {
+            const data = fs.readFileSync(path.resolve(`${TEST_ASSETS}/tux.png`));
+            const corrupt = path.join(os.tmpdir(), "pconvert_corrupt.png");
+            fs.writeFileSync(corrupt, data.subarray(0, Math.floor(data.length / 2)));
+
+            const paths = [path.resolve(`${TEST_ASSETS}/tux.png`), corrupt];
+            const out = path.resolve(`${TEST_ASSETS}/result_alpha_alpha_Fast_NoFilter.png`);
+            assert.throws(
+                () => pconvert.blendMultipleFs(paths, out, "alpha", null, true),
+                error => error.code === "DECODE_ERROR" && error.path === corrupt && error.layer === 1
+            );
+        });
     });
 
     describe("#blendMultipleFsAsync", function() {
@@ -2,6 +2,7 @@
 # -*- coding: utf-8 -*-
 
 import os
+import tempfile
 import unittest
 import pconvert_rust
 
@@ -149,3 +150,441 @@ class BlendingTest(unittest.TestCase):
             algorithms=["alpha", "multiplicative", "destination_over", "source_over"],
             options={"num_threads": 5},
         )
+
+    def test_blend_multiple_cache(self):
+        pconvert_rust.clear_cache()
+        before = pconvert_rust.get_cache_stats()
+
+        for _ in range(2):
+            pconvert_rust.blend_multiple(
+                (
+                    os.path.abspath(f"{TEST_ASSETS}sole.png"),
+                    os.path.abspath(f"{TEST_ASSETS}back.png"),
+                    os.path.abspath(f"{TEST_ASSETS}front.png"),
+                ),
+                os.path.abspath(f"{TEST_ASSETS}result_alpha_alpha_Fast_NoFilter.png"),
+                options={"cache": True},
+            )
+
+        stats = pconvert_rust.get_cache_stats()
+        self.assertEqual(stats["misses"] - before["misses"], 3)
+        self.assertEqual(stats["hits"] - before["hits"], 3)
+        self.assertEqual(stats["entries"], 3)
+
+        pconvert_rust.clear_cache()
+        stats = pconvert_rust.get_cache_stats()
+        self.assertEqual(stats["entries"], 0)
+        self.assertEqual(stats["size"], 0)
+
+    def test_errors(self):
+        with self.assertRaises(pconvert_rust.UnknownAlgorithmError) as context:
+            pconvert_rust.blend_images(
+                os.path.abspath(f"{TEST_ASSETS}sole.png"),
+                os.path.abspath(f"{TEST_ASSETS}back.png"),
+                os.path.abspath(f"{TEST_ASSETS}result_alpha_alpha_Fast_NoFilter.png"),
+                algorithm="unknown",
+            )
+        self.assertEqual(context.exception.algorithm, "unknown")
+
+        with self.assertRaises(pconvert_rust.DimensionMismatchError) as context:
+            pconvert_rust.blend_multiple(
+                (
+                    os.path.abspath(f"{TEST_ASSETS}sole.png"),
+                    os.path.abspath(f"{TEST_ASSETS}back.png"),
+                    os.path.abspath(f"{TEST_ASSETS}tux.png"),
+                ),
+                os.path.abspath(f"{TEST_ASSETS}result_alpha_alpha_Fast_NoFilter.png"),
+            )
+        self.assertEqual(context.exception.layer, 2)
+        self.assertEqual(context.exception.expected, (750, 750))
+        self.assertEqual(context.exception.found, (265, 314))
+
+        # every specific error is a subclass of the base pconvert error
+        self.assertTrue(
+            issubclass(pconvert_rust.DecodeError, pconvert_rust.PConvertError)
+        )
+        self.assertTrue(issubclass(pconvert_rust.PConvertError, Exception))
+
+    def test_dimension_policy(self):
+        out_path = os.path.join(tempfile.gettempdir(), "pconvert_dimension_policy.png")
+        for options in (
+            dict(dimension_policy="anchor", gravity="top_left"),
+            dict(dimension_policy="stretch"),
+            dict(dimension_policy="fit", num_threads=2),
+        ):
+            pconvert_rust.blend_multiple(
+                (
+                    os.path.abspath(f"{TEST_ASSETS}sole.png"),
+                    os.path.abspath(f"{TEST_ASSETS}tux.png"),
+                ),
+                out_path,
+                algorithm="alpha",
+                options=options,
+            )
+            self.assertTrue(os.path.exists(out_path))
+
+        with self.assertRaises(pconvert_rust.InvalidParameterError) as context:
+            pconvert_rust.blend_images(
+                os.path.abspath(f"{TEST_ASSETS}sole.png"),
+                os.path.abspath(f"{TEST_ASSETS}tux.png"),
+                out_path,
+                options=dict(dimension_policy="unknown"),
+            )
+        self.assertEqual(context.exception.name, "dimension_policy")
+
+    def test_resize(self):
+        out_path = os.path.join(tempfile.gettempdir(), "pconvert_resize.png")
+        for options in (
+            dict(scale=0.5),
+            dict(size="200x", resize_filter="bicubic", num_threads=2),
+        ):
+            pconvert_rust.blend_images(
+                os.path.abspath(f"{TEST_ASSETS}sole.png"),
+                os.path.abspath(f"{TEST_ASSETS}back.png"),
+                out_path,
+                algorithm="source_over",
+                options=options,
+            )
+            with open(out_path, "rb") as file:
+                width = int.from_bytes(file.read(24)[16:20], "big")
+            self.assertEqual(width, 375 if "scale" in options else 200)
+
+        with self.assertRaises(pconvert_rust.InvalidParameterError):
+            pconvert_rust.blend_images(
+                os.path.abspath(f"{TEST_ASSETS}sole.png"),
+                os.path.abspath(f"{TEST_ASSETS}back.png"),
+                out_path,
+                options=dict(size="200x100", scale=2.0),
+            )
+
+    def test_transforms(self):
+        out_path = os.path.join(tempfile.gettempdir(), "pconvert_transforms.png")
+        for options in (dict(), dict(num_threads=2)):
+            pconvert_rust.blend_multiple(
+                (
+                    dict(
+                        path=os.path.abspath(f"{TEST_ASSETS}tux.png"),
+                        transforms=["rotate:90", "flip_h"],
+                    ),
+                    dict(
+                        path=os.path.abspath(f"{TEST_ASSETS}tux.png"),
+                        transforms=["crop:0,0,265,265", "affine:0.5,0,10,0,0.5,10"],
+                    ),
+                ),
+                out_path,
+                algorithm="alpha",
+                options=dict(dimension_policy="anchor", **options),
+            )
+            with open(out_path, "rb") as file:
+                header = file.read(24)
+            self.assertEqual(int.from_bytes(header[16:20], "big"), 314)
+            self.assertEqual(int.from_bytes(header[20:24], "big"), 265)
+
+        with self.assertRaises(pconvert_rust.InvalidParameterError):
+            pconvert_rust.blend_multiple(
+                (dict(path=os.path.abspath(f"{TEST_ASSETS}tux.png"), transforms=["rotate:45"]),),
+                out_path,
+            )
+
+    def test_effects(self):
+        tux_path = os.path.abspath(f"{TEST_ASSETS}tux.png")
+        out_path = os.path.join(tempfile.gettempdir(), "pconvert_effects.png")
+
+        def blend(effects, options):
+            pconvert_rust.blend_multiple(
+                (dict(path=tux_path, effects=effects),), out_path, options=options
+            )
+            with open(out_path, "rb") as file:
+                return file.read()
+
+        # inverting twice keeps the layer as it is, while any other
+        # effect (e.g. grayscale) changes the resulting image
+        for options in (dict(), dict(num_threads=2)):
+            original = blend([], options)
+            self.assertEqual(blend(["invert", "invert"], options), original)
+            self.assertNotEqual(blend(["grayscale", "brightness:0.1"], options), original)
+            self.assertNotEqual(blend(["drop_shadow:10,10,12", "sharpen:0.5"], options), original)
+
+        with self.assertRaises(pconvert_rust.InvalidParameterError):
+            blend(["brightness:2"], dict())
+
+    def test_compare(self):
+        tux = os.path.abspath(f"{TEST_ASSETS}tux.png")
+        comparison = pconvert_rust.compare(tux, tux)
+        self.assertEqual(comparison["max_difference"], 0)
+        self.assertEqual(comparison["psnr"], float("inf"))
+        self.assertAlmostEqual(comparison["ssim"], 1.0)
+        self.assertEqual(comparison["matches"], True)
+
+        # the heatmap of the differences has the dimensions of the images
+        # and the tolerance is taken into account in the matches entry
+        heatmap = os.path.join(tempfile.gettempdir(), "pconvert_heatmap.png")
+        first = os.path.abspath(f"{TEST_ASSETS}result_alpha_blue_Fast_NoFilter.png")
+        second = os.path.abspath(f"{TEST_ASSETS}result_destination_over_blue_Fast_NoFilter.png")
+        comparison = pconvert_rust.compare(first, second, heatmap_path=heatmap)
+        self.assertGreater(comparison["max_difference"], 0)
+        self.assertLess(comparison["ssim"], 1.0)
+        self.assertEqual(comparison["matches"], False)
+        self.assertEqual(pconvert_rust.compare(first, second, tolerance=255)["matches"], True)
+        self.assertEqual(pconvert_rust.compare(first, heatmap)["max_difference"] > 0, True)
+
+        with self.assertRaises(pconvert_rust.DimensionMismatchError):
+            pconvert_rust.compare(tux, first)
+
+    def test_corrupt(self):
+        with open(f"{TEST_ASSETS}tux.png", "rb") as file:
+            data = file.read()
+
+        path = os.path.join(tempfile.gettempdir(), "pconvert_corrupt.png")
+        with open(path, "wb") as file:
+            file.write(data[: len(data) // 2])
+
+        # corrupt layers raise an error (with the offending layer) in both
+        # the single and multiple threaded versions, without crashing
+        for options in ({}, {"num_threads": 5}):
+            with self.assertRaises(pconvert_rust.DecodeError) as context:
+                pconvert_rust.blend_multiple(
+                    (os.path.abspath(f"{TEST_ASSETS}tux.png"), path),
+                    os.path.abspath(f"{TEST_ASSETS}result_alpha_alpha_Fast_NoFilter.png"),
+                    options=options,
+                )
+            self.assertEqual(context.exception.path, path)
+            self.assertEqual(context.exception.layer, 1)
+
+    def test_limits(self):
+        out_path = os.path.join(tempfile.gettempdir(), "pconvert_limits.png")
+        layers = (
+            os.path.abspath(f"{TEST_ASSETS}sole.png"),
+            os.path.abspath(f"{TEST_ASSETS}back.png"),
+        )
+
+        # every limit is checked before decoding (or resizing) the
+        # images, in both the single and multiple threaded versions
+        for options in ({}, {"num_threads": 5}):
+            for limit, value in (
+                ("max_width", 749),
+                ("max_pixels", 750 * 750 - 1),
+                ("max_layers", 1),
+            ):
+                with self.assertRaises(pconvert_rust.LimitExceededError) as context:
+                    pconvert_rust.blend_multiple(
+                        layers, out_path, options=dict(options, **{limit: value})
+                    )
+                self.assertEqual(context.exception.limit, limit)
+
+            with self.assertRaises(pconvert_rust.LimitExceededError):
+                pconvert_rust.blend_multiple(
+                    layers, out_path, options=dict(options, scale=2.0, max_width=1000)
+                )
+
+        pconvert_rust.blend_multiple(layers, out_path, options=dict(max_width=750))
+
+        with self.assertRaises(pconvert_rust.InvalidParameterError):
+            pconvert_rust.blend_multiple(layers, out_path, options=dict(max_width=-1))
+
+    def test_output_format(self):
+        bot_path = os.path.abspath(f"{TEST_ASSETS}sole.png")
+        top_path = os.path.abspath(f"{TEST_ASSETS}back.png")
+        qoi_path = os.path.join(tempfile.gettempdir(), "pconvert_format.qoi")
+        jpeg_path = os.path.join(tempfile.gettempdir(), "pconvert_format.out")
+
+        # the format is inferred from the extension unless given
+        pconvert_rust.blend_images(bot_path, top_path, qoi_path, "source_over")
+        with open(qoi_path, "rb") as file:
+            self.assertEqual(file.read(4), b"qoif")
+        pconvert_rust.blend_multiple(
+            (bot_path, top_path),
+            jpeg_path,
+            algorithm="source_over",
+            options=dict(format="jpeg", quality=80, background="#000000"),
+        )
+        with open(jpeg_path, "rb") as file:
+            self.assertEqual(file.read(3), b"\xff\xd8\xff")
+
+        # layers in the other formats are sniffed and decoded as well
+        out_path = os.path.join(tempfile.gettempdir(), "pconvert_format.png")
+        pconvert_rust.blend_images(jpeg_path, qoi_path, out_path, "source_over")
+        comparison = pconvert_rust.compare(jpeg_path, out_path)
+        self.assertEqual(comparison["max_difference"], 0)
+
+        with self.assertRaises(pconvert_rust.InvalidParameterError):
+            pconvert_rust.blend_images(
+                bot_path, top_path, qoi_path, options=dict(quality=80)
+            )
+        with self.assertRaises(pconvert_rust.InvalidParameterError):
+            pconvert_rust.blend_images(
+                bot_path, top_path, qoi_path, options=dict(format="gif")
+            )
+
+    def test_palette(self):
+        bot_path = os.path.abspath(f"{TEST_ASSETS}sole.png")
+        top_path = os.path.abspath(f"{TEST_ASSETS}back.png")
+        rgba_path = os.path.join(tempfile.gettempdir(), "pconvert_rgba.png")
+        png8_path = os.path.join(tempfile.gettempdir(), "pconvert_png8.png")
+
+        # the quantised composition is an indexed PNG (colour type 3),
+        # smaller than the true colour one
+        pconvert_rust.blend_images(bot_path, top_path, rgba_path, "source_over")
+        pconvert_rust.blend_images(
+            bot_path,
+            top_path,
+            png8_path,
+            "source_over",
+            options=dict(colors=64, dither=True),
+        )
+        with open(png8_path, "rb") as file:
+            data = file.read()
+        self.assertEqual(data[25], 3)
+        self.assertIn(b"PLTE", data)
+        self.assertLess(len(data), os.path.getsize(rgba_path))
+
+        with self.assertRaises(pconvert_rust.InvalidParameterError):
+            pconvert_rust.blend_images(
+                bot_path, top_path, png8_path, options=dict(colors=1000)
+            )
+        with self.assertRaises(pconvert_rust.InvalidParameterError):
+            pconvert_rust.blend_images(
+                bot_path, top_path, png8_path, options=dict(dither=True)
+            )
+
+    def test_optimize(self):
+        bot_path = os.path.abspath(f"{TEST_ASSETS}sole.png")
+        top_path = os.path.abspath(f"{TEST_ASSETS}back.png")
+        out_path = os.path.join(tempfile.gettempdir(), "pconvert_optimize.png")
+        optimized_path = os.path.join(tempfile.gettempdir(), "pconvert_optimized.png")
+
+        # the optimized composition is smaller and has the same pixels
+        pconvert_rust.blend_images(bot_path, top_path, out_path, "source_over")
+        pconvert_rust.blend_images(
+            bot_path,
+            top_path,
+            optimized_path,
+            "source_over",
+            options=dict(optimize=True),
+        )
+        self.assertLess(os.path.getsize(optimized_path), os.path.getsize(out_path))
+        comparison = pconvert_rust.compare(out_path, optimized_path)
+        self.assertEqual(comparison["max_difference"], 0)
+
+        # files are optimized in place, reporting the saving, and are
+        # kept as they are when no smaller encoding is found
+        size = os.path.getsize(out_path)
+        report = pconvert_rust.optimize(out_path)
+        self.assertEqual(report["original_size"], size)
+        self.assertEqual(report["size"], os.path.getsize(out_path))
+        self.assertGreater(report["saving"], 0)
+        self.assertGreater(report["saving_percent"], 0.0)
+        self.assertIsNotNone(report["settings"])
+        report = pconvert_rust.optimize(out_path, optimized_path)
+        self.assertEqual(report["saving"], 0)
+        self.assertIsNone(report["settings"])
+
+        with self.assertRaises(pconvert_rust.InvalidParameterError):
+            pconvert_rust.blend_images(
+                bot_path,
+                top_path,
+                os.path.join(tempfile.gettempdir(), "pconvert_optimize.qoi"),
+                options=dict(optimize=True),
+            )
+
+    def test_compression(self):
+        bot_path = os.path.abspath(f"{TEST_ASSETS}sole.png")
+        top_path = os.path.abspath(f"{TEST_ASSETS}back.png")
+        out_path = os.path.join(tempfile.gettempdir(), "pconvert_compression.png")
+        stored_path = os.path.join(tempfile.gettempdir(), "pconvert_stored.png")
+        self.assertIn("Adaptive", pconvert_rust.FILTER_TYPES)
+
+        # numeric levels, strategies and the adaptive filter are honoured
+        # by both the serial and the multi-threaded writes
+        pconvert_rust.blend_images(bot_path, top_path, out_path, "source_over")
+        pconvert_rust.blend_images(
+            bot_path,
+            top_path,
+            stored_path,
+            "source_over",
+            options=dict(compression=0),
+        )
+        self.assertGreater(os.path.getsize(stored_path), os.path.getsize(out_path))
+        pconvert_rust.blend_images(
+            bot_path,
+            top_path,
+            stored_path,
+            "source_over",
+            is_inline=False,
+            options=dict(compression="3", strategy="rle", filter="adaptive", num_threads=2),
+        )
+        comparison = pconvert_rust.compare(out_path, stored_path)
+        self.assertEqual(comparison["max_difference"], 0)
+
+        # unknown values are errors instead of falling back into defaults
+        for options in (
+            dict(compression="medium"),
+            dict(compression=10),
+            dict(strategy="lz77"),
+            dict(filter="median"),
+        ):
+            with self.assertRaises(pconvert_rust.InvalidParameterError):
+                pconvert_rust.blend_images(
+                    bot_path, top_path, out_path, "source_over", options=options
+                )
+
+    def test_animation(self):
+        out_path = os.path.join(tempfile.gettempdir(), "pconvert_animation.png")
+        sole_path = os.path.abspath(f"{TEST_ASSETS}sole.png")
+        turntable_path = os.path.abspath(f"{TEST_ASSETS}turntable.png")
+
+        # a static layer composed over every frame of the animated one,
+        # results in an animation with the frames of the animated layer
+        pconvert_rust.blend_images(
+            sole_path, turntable_path, out_path, "destination_over"
+        )
+        with open(out_path, "rb") as file:
+            data = file.read()
+        self.assertIn(b"acTL", data)
+        self.assertEqual(data.count(b"fcTL"), 8)
+
+        pconvert_rust.blend_multiple(
+            (turntable_path, sole_path, turntable_path),
+            out_path,
+            algorithm="source_over",
+            options=dict(scale=0.5),
+        )
+        with open(out_path, "rb") as file:
+            self.assertEqual(file.read().count(b"fcTL"), 8)
+
+        with self.assertRaises(AttributeError):
+            pconvert_rust.blend_multiple(
+                (sole_path, turntable_path),
+                os.path.join(tempfile.gettempdir(), "pconvert_animation.qoi"),
+            )
+
+    def test_stream(self):
+        stream_path = os.path.join(tempfile.gettempdir(), "pconvert_stream.png")
+        out_path = os.path.join(tempfile.gettempdir(), "pconvert_no_stream.png")
+        layers = (
+            os.path.abspath(f"{TEST_ASSETS}sole.png"),
+            os.path.abspath(f"{TEST_ASSETS}back.png"),
+            os.path.abspath(f"{TEST_ASSETS}front.png"),
+        )
+
+        # the streamed composition (in bands of any height) matches
+        # the one blended with the whole layers in memory
+        pconvert_rust.blend_multiple(layers, out_path, algorithm="source_over")
+        for band_height in (1, 7, 64):
+            pconvert_rust.blend_multiple(
+                layers,
+                stream_path,
+                algorithm="source_over",
+                options=dict(stream=True, band_height=band_height),
+            )
+            comparison = pconvert_rust.compare(stream_path, out_path)
+            self.assertEqual(comparison["max_difference"], 0)
+
+        with self.assertRaises(AttributeError):
+            pconvert_rust.blend_multiple(
+                layers, stream_path, options=dict(stream=True, scale=2.0)
+            )
+        with self.assertRaises(pconvert_rust.InvalidParameterError):
+            pconvert_rust.blend_multiple(
+                layers, stream_path, options=dict(stream=True, band_height=0)
+            )
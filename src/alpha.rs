@@ -0,0 +1,113 @@
+//! Alpha channel utilities: bounding box of the visible pixels (and
+//! trimming to it), alpha thresholding, matte removal and flattening.
+
+use crate::blending::multiply_image;
+use image::imageops::crop_imm;
+use image::{ImageBuffer, Rgb, Rgba};
+
+/// Returns the bounding box, as `(x, y, width, height)`, of the pixels
+/// with an alpha above `threshold`, or `None` when there are none.
+///
+/// # Arguments
+///
+/// * `image` - The image buffer (with straight or premultiplied alpha).
+/// * `threshold` - The alpha value a pixel must exceed to be visible.
+pub fn bounding_box(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    threshold: u8,
+) -> Option<(u32, u32, u32, u32)> {
+    let (mut min_x, mut min_y) = (u32::MAX, u32::MAX);
+    let (mut max_x, mut max_y) = (0, 0);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel[3] > threshold {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    match min_x <= max_x {
+        true => Some((min_x, min_y, max_x - min_x + 1, max_y - min_y + 1)),
+        false => None,
+    }
+}
+
+/// Crops the image to the bounding box of the pixels with an alpha above
+/// `threshold`, an image without any such pixel is trimmed into a single
+/// transparent pixel (the smallest image that may be encoded).
+///
+/// # Arguments
+///
+/// * `image` - The image buffer to trim.
+/// * `threshold` - The alpha value a pixel must exceed to be kept.
+pub fn trim(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    threshold: u8,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    match bounding_box(image, threshold) {
+        Some((x, y, width, height)) => crop_imm(image, x, y, width, height).to_image(),
+        None => ImageBuffer::new(1, 1),
+    }
+}
+
+/// Makes the alpha channel binary, pixels with an alpha equal or
+/// above `threshold` become opaque and the remaining transparent.
+///
+/// # Arguments
+///
+/// * `image` - The image buffer (with straight alpha).
+/// * `threshold` - The minimum alpha value of the opaque pixels.
+pub fn threshold_alpha(image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, threshold: u8) {
+    for pixel in image.pixels_mut() {
+        pixel[3] = match pixel[3] >= threshold {
+            true => 255,
+            false => 0,
+        };
+    }
+}
+
+/// Removes the colour of the matte (e.g. white) the semi-transparent pixels
+/// were blended with, leaving halo free colours (colour decontamination).
+///
+/// Each colour channel is assumed to be `alpha * colour + (1 - alpha) * matte`,
+/// so the matte contribution is removed (leaving the premultiplied colour)
+/// and the result is then divided by the alpha.
+///
+/// # Arguments
+///
+/// * `image` - The image buffer (with straight alpha).
+/// * `matte` - The colour of the matte, as `[red, green, blue]`.
+pub fn remove_matte(image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, matte: [u8; 3]) {
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3] as f32;
+        let coverage = 1.0 - alpha / 255.0;
+        for (channel, matte) in pixel.0.iter_mut().take(3).zip(matte) {
+            let value = *channel as f32 - matte as f32 * coverage;
+            *channel = value.round().clamp(0.0, alpha) as u8;
+        }
+    }
+    multiply_image(image);
+}
+
+/// Flattens the image over an opaque background colour, dropping the alpha
+/// channel (e.g. for formats without one, such as JPEG).
+///
+/// # Arguments
+///
+/// * `image` - The image buffer (with straight alpha).
+/// * `background` - The colour of the background, as `[red, green, blue]`.
+pub fn flatten(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    background: [u8; 3],
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y);
+        let alpha = pixel[3] as u32;
+        let mut flattened = Rgb([0, 0, 0]);
+        for (index, background) in background.iter().enumerate() {
+            let value = pixel[index] as u32 * alpha + *background as u32 * (255 - alpha);
+            flattened[index] = ((value + 127) / 255) as u8;
+        }
+        flattened
+    })
+}
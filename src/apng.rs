@@ -0,0 +1,526 @@
+//! Animated PNGs (APNG), decoded into their fully rendered frames (applying
+//! the dispose and blend operations of each frame) and encoded from them, and
+//! the frame-wise composition of animated (and static) layers.
+
+use crate::blending::algorithms::blend_destination_over;
+use crate::blending::dimensions::DimensionPolicy;
+use crate::blending::params::BlendAlgorithmParams;
+use crate::blending::{blend_multiple_images, BlendAlgorithm};
+use crate::encoding::{Compression, Strategy};
+use crate::errors::PConvertError;
+use crate::limits::Limits;
+use crate::utils::{
+    check_png_header, expand_row_to_rgba8, png_compression_from, png_decode_error,
+    png_encode_error, png_filter_from, png_samples, read_image_from_file_with_limits,
+};
+use image::codecs::png::FilterType;
+use image::{ImageBuffer, Rgba};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::mem;
+
+/// Delay of a frame, as a fraction of a second (as in the `fcTL` chunk).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delay {
+    pub numerator: u16,
+    /// A denominator of zero stands for hundredths of a second.
+    pub denominator: u16,
+}
+
+impl Delay {
+    /// The delay of the given milliseconds, in the finest unit (from
+    /// milliseconds to seconds) that fits the numerator.
+    pub fn from_millis(millis: u64) -> Delay {
+        let (numerator, denominator) = [(1, 1000), (10, 100), (100, 10), (1000, 1)]
+            .iter()
+            .map(|&(divisor, denominator)| (millis / divisor, denominator))
+            .find(|&(numerator, _)| numerator <= u16::MAX as u64)
+            .unwrap_or((u16::MAX as u64, 1));
+        Delay {
+            numerator: numerator as u16,
+            denominator,
+        }
+    }
+
+    /// The delay in milliseconds.
+    pub fn as_millis(&self) -> u64 {
+        let denominator = match self.denominator {
+            0 => 100,
+            denominator => denominator as u64,
+        };
+        self.numerator as u64 * 1000 / denominator
+    }
+}
+
+/// Defaults to a tenth of a second.
+impl Default for Delay {
+    fn default() -> Delay {
+        Delay {
+            numerator: 1,
+            denominator: 10,
+        }
+    }
+}
+
+/// A fully rendered frame of an animation, shown for its delay.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    pub delay: Delay,
+}
+
+/// An animation, as a sequence of frames with the dimensions of the first.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    /// Number of times the animation is played, zero for infinite.
+    pub num_plays: u32,
+}
+
+impl Animation {
+    /// Builds a (still) animation with the image as its single frame.
+    pub fn from_image(image: ImageBuffer<Rgba<u8>, Vec<u8>>) -> Animation {
+        Animation {
+            frames: vec![Frame {
+                image,
+                delay: Delay::default(),
+            }],
+            num_plays: 0,
+        }
+    }
+
+    /// The dimensions of the animation (of its first frame).
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.frames
+            .first()
+            .map_or((0, 0), |frame| frame.image.dimensions())
+    }
+
+    /// Applies the (fallible) function to the image of every frame,
+    /// keeping the timing of the frames.
+    pub fn map_frames(
+        self,
+        mut function: impl FnMut(
+            ImageBuffer<Rgba<u8>, Vec<u8>>,
+        ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError>,
+    ) -> Result<Animation, PConvertError> {
+        let frames = self
+            .frames
+            .into_iter()
+            .map(|frame| {
+                Ok(Frame {
+                    image: function(frame.image)?,
+                    delay: frame.delay,
+                })
+            })
+            .collect::<Result<Vec<_>, PConvertError>>()?;
+        Ok(Animation {
+            frames,
+            num_plays: self.num_plays,
+        })
+    }
+}
+
+/// Whether the stream is an animated PNG (a PNG with an `acTL` chunk before
+/// its image data), any other stream (even an invalid one) is not.
+pub fn is_apng(readable_stream: impl Read) -> bool {
+    png::Decoder::new(readable_stream)
+        .read_info()
+        .is_ok_and(|reader| reader.info().animation_control.is_some())
+}
+
+/// Whether the file in the local file system is an animated PNG.
+pub fn is_apng_file(file_in: &str) -> bool {
+    File::open(file_in).is_ok_and(|file| is_apng(BufReader::new(file)))
+}
+
+/// Decodes an animated PNG into its fully rendered frames, within the
+/// given resource limits (where the bytes limit applies to the frames
+/// altogether), a PNG that is not animated is decoded as a single frame.
+///
+/// # Arguments
+///
+/// * `readable_stream` - Any structure that implements the `Read` trait.
+/// * `limits` - The resource limits the animation must be within.
+pub fn decode_apng_with_limits(
+    readable_stream: impl Read,
+    limits: &Limits,
+) -> Result<Animation, PConvertError> {
+    let readable_stream = check_png_header(readable_stream, |width, height| {
+        limits.check_dimensions(width, height)
+    })?;
+
+    let png_limits = png::Limits {
+        bytes: usize::try_from(limits.max_bytes).unwrap_or(usize::MAX),
+    };
+    let mut decoder = png::Decoder::new_with_limits(readable_stream, png_limits);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(png_decode_error)?;
+    let (width, height) = reader.info().size();
+    limits.check_dimensions(width, height)?;
+    // frames of any color type are expanded into RGBA8, as static PNGs are
+    let samples = png_samples(reader.output_color_type())?;
+
+    // the default image (IDAT) is not part of the animation when it
+    // has no frame control chunk, being skipped when decoding
+    let (num_frames, num_plays, skip_default) = match reader.info().animation_control {
+        Some(control) => (
+            control.num_frames,
+            control.num_plays,
+            reader.info().frame_control.is_none(),
+        ),
+        None => (1, 0, false),
+    };
+    if num_frames == 0 {
+        return Err(PConvertError::invalid_parameter(
+            "frames",
+            "the animation has no frames",
+        ));
+    }
+    // every frame costs its pixels and its own (buffer) structure, so that
+    // a tiny image declaring a huge number of frames is rejected as well
+    let frame_bytes = width as u64 * height as u64 * 4 + mem::size_of::<Frame>() as u64;
+    limits.check_bytes(num_frames as u64 * frame_bytes)?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    if skip_default {
+        reader.next_frame(&mut buffer).map_err(png_decode_error)?;
+    }
+
+    let mut canvas = ImageBuffer::new(width, height);
+    // the frames are pushed as they are decoded, as the number of frames
+    // (from the file) may not match the frames actually in it
+    let mut frames = Vec::new();
+    for _ in 0..num_frames {
+        reader.next_frame(&mut buffer).map_err(png_decode_error)?;
+        let control = reader.info().frame_control.unwrap_or(png::FrameControl {
+            width,
+            height,
+            ..Default::default()
+        });
+        // the decoder already checks the frame to be within the image
+        let row_size = control.width as usize * samples.0 * samples.1;
+        let pixels_size = control.width as usize * 4;
+        let mut region = vec![0; pixels_size * control.height as usize];
+        for y in 0..control.height as usize {
+            expand_row_to_rgba8(
+                &buffer[y * row_size..][..row_size],
+                samples,
+                &mut region[y * pixels_size..][..pixels_size],
+            );
+        }
+        let region = ImageBuffer::<Rgba<u8>, _>::from_raw(control.width, control.height, region)
+            .ok_or(PConvertError::UnsupportedImageTypeError)?;
+        let previous = match control.dispose_op {
+            png::DisposeOp::Previous => Some(canvas.clone()),
+            _ => None,
+        };
+
+        for (x, y, pixel) in region.enumerate_pixels() {
+            let canvas_pixel = canvas.get_pixel_mut(x + control.x_offset, y + control.y_offset);
+            match control.blend_op {
+                png::BlendOp::Source => *canvas_pixel = *pixel,
+                png::BlendOp::Over => blend_destination_over((canvas_pixel, pixel), &None),
+            }
+        }
+        frames.push(Frame {
+            image: canvas.clone(),
+            delay: Delay {
+                numerator: control.delay_num,
+                denominator: control.delay_den,
+            },
+        });
+
+        // the region of the frame is disposed of before the next frame, the
+        // canvas starts transparent, so restoring the canvas before the first
+        // frame is the same as clearing it (as the specification requires)
+        match (control.dispose_op, previous) {
+            (png::DisposeOp::Previous, Some(previous)) => canvas = previous,
+            (png::DisposeOp::Background, _) => {
+                for y in control.y_offset..control.y_offset + control.height {
+                    for x in control.x_offset..control.x_offset + control.width {
+                        canvas.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(Animation { frames, num_plays })
+}
+
+/// Reads any layer from the local file system as an animation, animated PNGs
+/// are decoded into their frames and static images (in any of the supported
+/// input formats) into a single frame.
+///
+/// # Arguments
+///
+/// * `file_in` - Local file system path to the image file.
+/// * `limits` - The resource limits the animation must be within.
+pub fn read_animation_from_file_with_limits(
+    file_in: String,
+    limits: &Limits,
+) -> Result<Animation, PConvertError> {
+    if !is_apng_file(&file_in) {
+        let image = read_image_from_file_with_limits(file_in, false, limits)?;
+        return Ok(Animation::from_image(image));
+    }
+    let file = BufReader::new(File::open(&file_in)?);
+    decode_apng_with_limits(file, limits).map_err(|err| err.with_path(&file_in))
+}
+
+/// Encodes an animation as an animated PNG, every frame (but the first) is
+/// encoded as the region that changed since the previous frame.
+///
+/// # Arguments
+///
+/// * `writable_buff` - Any buffer structure that implements the `Write` trait.
+/// * `animation` - The animation, whose frames must all have the same dimensions.
+/// * `compression` - Compression to use in the encoding, only the fast,
+///   default and best levels (with the default strategy) are supported.
+/// * `filter` - Filter type to use in the encoding.
+pub fn encode_apng(
+    writable_buff: impl Write,
+    animation: &Animation,
+    compression: Compression,
+    filter: FilterType,
+) -> Result<(), PConvertError> {
+    if animation.frames.is_empty() {
+        return Err(PConvertError::ArgumentError(
+            "'animation' must contain at least one frame".to_string(),
+        ));
+    }
+    if compression.strategy() != Strategy::Default {
+        return Err(PConvertError::invalid_parameter(
+            "strategy",
+            format!(
+                "the {} strategy is not supported in animated PNGs",
+                compression.strategy()
+            ),
+        ));
+    }
+    let level = png_compression_from(compression).ok_or_else(|| {
+        PConvertError::invalid_parameter(
+            "compression",
+            format!(
+                "level {} is not supported in animated PNGs (only fast, default and best are)",
+                compression.level()
+            ),
+        )
+    })?;
+    let (width, height) = animation.dimensions();
+    for (index, frame) in animation.frames.iter().enumerate() {
+        if frame.image.dimensions() != (width, height) {
+            return Err(PConvertError::invalid_parameter(
+                "frames",
+                format!(
+                    "frame {} is {}x{} instead of {}x{}",
+                    index,
+                    frame.image.width(),
+                    frame.image.height(),
+                    width,
+                    height
+                ),
+            ));
+        }
+    }
+
+    let mut encoder = png::Encoder::new(BufWriter::new(writable_buff), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(level);
+    let (filter, adaptive_filter) = png_filter_from(filter);
+    encoder.set_filter(filter);
+    encoder.set_adaptive_filter(adaptive_filter);
+    encoder
+        .set_animated(animation.frames.len() as u32, animation.num_plays)
+        .map_err(png_encode_error)?;
+    let mut writer = encoder.write_header().map_err(png_encode_error)?;
+
+    let mut previous: Option<&ImageBuffer<Rgba<u8>, Vec<u8>>> = None;
+    for frame in &animation.frames {
+        let (x, y, region_width, region_height) = match previous {
+            Some(previous) => changed_region(previous, &frame.image),
+            None => (0, 0, width, height),
+        };
+        let mut data = Vec::with_capacity(region_width as usize * region_height as usize * 4);
+        for row in y..y + region_height {
+            let start = (row as usize * width as usize + x as usize) * 4;
+            data.extend_from_slice(&frame.image.as_raw()[start..start + region_width as usize * 4]);
+        }
+
+        // each region replaces the pixels of the previous frame, which
+        // are kept (not disposed of) for the following frame
+        writer
+            .set_frame_delay(frame.delay.numerator, frame.delay.denominator)
+            .and_then(|_| writer.set_blend_op(png::BlendOp::Source))
+            .and_then(|_| writer.set_dispose_op(png::DisposeOp::None))
+            .and_then(|_| writer.reset_frame_position())
+            .and_then(|_| writer.set_frame_dimension(region_width, region_height))
+            .and_then(|_| writer.set_frame_position(x, y))
+            .and_then(|_| writer.write_image_data(&data))
+            .map_err(png_encode_error)?;
+        previous = Some(&frame.image);
+    }
+
+    writer.finish().map_err(png_encode_error)
+}
+
+/// Writes an animation to the local file system as an animated PNG.
+///
+/// # Arguments
+///
+/// * `file_out` - Local file system path where to write the APNG file.
+/// * `animation` - The animation, whose frames must all have the same dimensions.
+/// * `compression` - Compression to use in the encoding.
+/// * `filter` - Filter type to use in the encoding.
+pub fn write_apng_to_file(
+    file_out: String,
+    animation: &Animation,
+    compression: Compression,
+    filter: FilterType,
+) -> Result<(), PConvertError> {
+    let file = File::create(&file_out)?;
+    encode_apng(file, animation, compression, filter).map_err(|err| err.with_path(&file_out))
+}
+
+/// Blends a stack of animations frame by frame, from the bottom-most layer
+/// (the first one) to the top-most one (the last one).
+///
+/// The timelines of the animated layers are merged by the cumulative delays
+/// of their frames, a frame of the composition starting at every frame
+/// boundary of any layer, so that every layer keeps its own timing. The
+/// composition lasts as long as the longest animated layer (the first of
+/// them when several), whose number of plays it takes, the shorter ones are
+/// looped and static layers are the same in every frame. Delays are merged
+/// in milliseconds, with frames of zero delay lasting a millisecond.
+///
+/// # Arguments
+///
+/// * `layers` - The animations to blend, bottom to top.
+/// * `algorithms` - The blending algorithm and optional parameters to use
+///   in each blending operation, one per layer except the first.
+/// * `dimension_policy` - The policy for frames whose dimensions differ
+///   from the ones of the bottom layer.
+/// * `limits` - The resource limits, where the bytes limit applies to the
+///   frames of the composition altogether.
+pub fn blend_animations(
+    layers: Vec<Animation>,
+    algorithms: &[(BlendAlgorithm, Option<BlendAlgorithmParams>)],
+    dimension_policy: &DimensionPolicy,
+    limits: &Limits,
+) -> Result<Animation, PConvertError> {
+    if layers.is_empty() {
+        return Err(PConvertError::ArgumentError(
+            "'layers' must contain at least one image".to_string(),
+        ));
+    }
+    if let Some(index) = layers.iter().position(|layer| layer.frames.is_empty()) {
+        return Err(PConvertError::ArgumentError(format!(
+            "layer {} has no frames",
+            index
+        )));
+    }
+
+    // the end of every frame of each layer, from the start of the layer
+    let ends: Vec<Vec<u64>> = layers
+        .iter()
+        .map(|layer| {
+            layer
+                .frames
+                .iter()
+                .scan(0, |end, frame| {
+                    *end += frame.delay.as_millis().max(1);
+                    Some(*end)
+                })
+                .collect()
+        })
+        .collect();
+    let duration = |index: usize| *ends[index].last().unwrap();
+    let animated: Vec<usize> = (0..layers.len())
+        .filter(|&index| layers[index].frames.len() > 1)
+        .collect();
+    let timing = animated
+        .iter()
+        .copied()
+        .fold(None, |timing: Option<usize>, index| match timing {
+            Some(timing) if duration(timing) >= duration(index) => Some(timing),
+            _ => Some(index),
+        })
+        .unwrap_or(0);
+    let total = duration(timing);
+
+    // the number of frames is bounded (before merging the timelines) by
+    // the frames of every loop of each animated layer, every frame costing
+    // its pixels and its own (buffer) structure
+    let max_frames = animated.iter().fold(1u64, |max_frames, &index| {
+        let loops = total.div_ceil(duration(index));
+        max_frames.saturating_add(loops.saturating_mul(ends[index].len() as u64))
+    });
+    let (width, height) = layers[0].dimensions();
+    let frame_bytes = width as u64 * height as u64 * 4 + mem::size_of::<Frame>() as u64;
+    limits.check_bytes(max_frames.saturating_mul(frame_bytes))?;
+
+    let mut boundaries = BTreeSet::new();
+    boundaries.insert(0);
+    boundaries.insert(total);
+    for &index in &animated {
+        for start in (0..total).step_by(duration(index) as usize) {
+            boundaries.extend(
+                ends[index]
+                    .iter()
+                    .map(|end| start + end)
+                    .filter(|&end| end < total),
+            );
+        }
+    }
+    let boundaries: Vec<u64> = boundaries.into_iter().collect();
+
+    let frames = boundaries
+        .windows(2)
+        .map(|window| {
+            let images = layers
+                .iter()
+                .zip(ends.iter())
+                .map(|(layer, ends)| {
+                    let position = window[0] % ends.last().unwrap();
+                    let index = ends.partition_point(|&end| end <= position);
+                    layer.frames[index].image.clone()
+                })
+                .collect();
+            Ok(Frame {
+                image: blend_multiple_images(images, algorithms, dimension_policy)?,
+                delay: Delay::from_millis(window[1] - window[0]),
+            })
+        })
+        .collect::<Result<Vec<_>, PConvertError>>()?;
+
+    Ok(Animation {
+        frames,
+        num_plays: layers[timing].num_plays,
+    })
+}
+
+/// Returns the bounding box (x, y, width and height) of the pixels that differ
+/// between the (same sized) images, a single pixel when none differs.
+fn changed_region(
+    previous: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> (u32, u32, u32, u32) {
+    let (mut x_min, mut y_min, mut x_max, mut y_max) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel != previous.get_pixel(x, y) {
+            x_min = x_min.min(x);
+            y_min = y_min.min(y);
+            x_max = x_max.max(x);
+            y_max = y_max.max(y);
+        }
+    }
+    match x_min {
+        u32::MAX => (0, 0, 1, 1),
+        _ => (x_min, y_min, x_max - x_min + 1, y_max - y_min + 1),
+    }
+}
@@ -0,0 +1,595 @@
+//! Batch processing of many compositions described in a manifest
+//! (JSON lines or CSV), results report and shared layers cache.
+
+use crate::blending::dimensions::DimensionPolicy;
+use crate::blending::params::{BlendAlgorithmParams, Value};
+use crate::blending::{blend_multiple_images, BlendAlgorithm};
+use crate::encoding::{parse_filter, Compression, CompressionLevel};
+use crate::errors::PConvertError;
+use crate::format::{write_image_to_file, OutputOptions, OutputValues};
+use crate::layer::LayerOptions;
+use crate::limits::Limits;
+use crate::parallelism::{ResultMessage, ThreadPool};
+use crate::resize::{resize_output, ResizeOptions};
+use crate::utils::read_image_from_file_with_limits;
+use image::codecs::png::FilterType;
+use image::{ImageBuffer, Rgba};
+use serde::Deserialize;
+use serde_json::{json, Value as JSONValue};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Supported formats for the batch manifest.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ManifestFormat {
+    /// One JSON object per line, e.g.
+    /// `{"out": "out.png", "layers": ["bot.png", "top.png"], "algorithm": "source_over"}`.
+    JsonLines,
+
+    /// One composition per line, as `<out>,<algorithm>,<layer>,<layer>,...`.
+    Csv,
+}
+
+impl ManifestFormat {
+    /// Infers the manifest format from the extension of the manifest path,
+    /// `.csv` files are read as CSV and everything else as JSON lines.
+    pub fn from_path(path: &str) -> ManifestFormat {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => ManifestFormat::Csv,
+            _ => ManifestFormat::JsonLines,
+        }
+    }
+}
+
+impl FromStr for ManifestFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" | "json" => Ok(ManifestFormat::JsonLines),
+            "csv" => Ok(ManifestFormat::Csv),
+            s => Err(s.to_string()),
+        }
+    }
+}
+
+/// A single composition of the batch: the layers to blend (bottom
+/// to top), the blending operations and the output definition.
+#[derive(Clone, Debug)]
+pub struct BatchItem {
+    pub layers: Vec<String>,
+    pub out: String,
+    pub algorithms: Vec<(BlendAlgorithm, Option<BlendAlgorithmParams>)>,
+    pub compression: Compression,
+    pub filter: FilterType,
+    pub dimension_policy: DimensionPolicy,
+    pub resize: Option<ResizeOptions>,
+    /// The options (transforms and effects) of each layer, by index,
+    /// layers without an entry (e.g. when empty) are used as they are.
+    pub layers_options: Vec<LayerOptions>,
+    /// The format of `out`, inferred from its extension unless given.
+    pub output: OutputOptions,
+}
+
+impl BatchItem {
+    /// Applies the options of the layer with the given index to it,
+    /// within the given limits.
+    pub fn apply_layer_options(
+        &self,
+        index: usize,
+        layer: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        limits: &Limits,
+    ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+        match self.layers_options.get(index) {
+            Some(options) => options.apply_with_limits(layer, limits),
+            None => Ok(layer),
+        }
+    }
+}
+
+impl FromStr for BatchItem {
+    type Err = PConvertError;
+
+    /// Parses a composition document, a JSON object with the fields of
+    /// a batch manifest entry (e.g. `out`, `layers` and `algorithm`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_json_entry(s)
+    }
+}
+
+/// Manifest entries as pairs of line number and parsed composition
+/// (or the error found while parsing it).
+pub type ManifestEntries = Vec<(usize, Result<BatchItem, PConvertError>)>;
+
+/// Outcome of a single manifest entry, `out` is only available
+/// when the entry could be parsed.
+#[derive(Debug)]
+pub struct BatchResult {
+    pub line: usize,
+    pub out: Option<String>,
+    pub result: Result<(), PConvertError>,
+}
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    layers: Vec<JSONValue>,
+    out: String,
+    algorithm: Option<String>,
+    algorithms: Option<Vec<JSONValue>>,
+    compression: Option<JSONValue>,
+    strategy: Option<String>,
+    filter: Option<String>,
+    dimension_policy: Option<String>,
+    gravity: Option<String>,
+    size: Option<String>,
+    scale: Option<f64>,
+    resize_filter: Option<String>,
+    format: Option<String>,
+    quality: Option<i64>,
+    background: Option<String>,
+    colors: Option<i64>,
+    dither: Option<bool>,
+    optimize: Option<bool>,
+}
+
+/// Reads a batch manifest, returning one entry per composition (blank
+/// lines and lines starting with `#` are ignored). Entries that fail
+/// to parse are kept as errors so that they can be reported without
+/// aborting the remaining batch.
+///
+/// # Arguments
+///
+/// * `path` - Local file system path to the manifest.
+/// * `format` - The format of the manifest.
+pub fn read_manifest(
+    path: &str,
+    format: &ManifestFormat,
+) -> Result<ManifestEntries, PConvertError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let item = match format {
+            ManifestFormat::JsonLines => parse_json_entry(line),
+            ManifestFormat::Csv => parse_csv_entry(line),
+        };
+        entries.push((index + 1, item));
+    }
+
+    Ok(entries)
+}
+
+/// Runs every composition of the batch in the given thread pool, returning
+/// one result per manifest entry, in the same order as the manifest.
+///
+/// Layers used by more than one composition are decoded only once and
+/// kept in memory until their last composition is blended.
+///
+/// # Arguments
+///
+/// * `entries` - The manifest entries, as returned by `read_manifest`.
+/// * `thread_pool` - The (started) thread pool where compositions run.
+/// * `limits` - The resource limits every composition must be within.
+pub fn run_batch(
+    entries: ManifestEntries,
+    thread_pool: &ThreadPool,
+    limits: &Limits,
+) -> Vec<BatchResult> {
+    let cache = Arc::new(SharedLayers::new(&entries, limits));
+
+    let channels = entries
+        .into_iter()
+        .map(|(line, item)| match item {
+            Ok(item) => {
+                let out = item.out.clone();
+                let cache = cache.clone();
+                let channel = thread_pool
+                    .execute(move || ResultMessage::CompositionResult(compose_item(item, &cache)));
+                (line, Some(out), Ok(channel))
+            }
+            Err(err) => (line, None, Err(err)),
+        })
+        .collect::<Vec<_>>();
+
+    channels
+        .into_iter()
+        .map(|(line, out, channel)| {
+            let result = channel.and_then(|channel| match channel.recv() {
+                Ok(ResultMessage::CompositionResult(result)) => result,
+                _ => Err(PConvertError::ThreadPoolError(
+                    "composition task did not complete".to_string(),
+                )),
+            });
+            BatchResult { line, out, result }
+        })
+        .collect()
+}
+
+/// Writes the batch results as a JSON lines report, one object per
+/// manifest entry with its status and error message (if any).
+pub fn write_report(
+    writable_buff: &mut impl Write,
+    results: &[BatchResult],
+) -> Result<(), PConvertError> {
+    for result in results {
+        let entry = match &result.result {
+            Ok(()) => json!({"line": result.line, "out": result.out, "status": "ok"}),
+            Err(err) => json!({
+                "line": result.line,
+                "out": result.out,
+                "status": "error",
+                "error": err.to_string()
+            }),
+        };
+        writeln!(writable_buff, "{}", entry)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn compose_item(item: BatchItem, cache: &SharedLayers) -> Result<(), PConvertError> {
+    // the layers never fetched (as when failing early) are released
+    // from the shared set once the pending layers are dropped
+    let mut pending = PendingLayers {
+        cache,
+        paths: &item.layers,
+    };
+    cache.limits.check_layers(item.layers.len())?;
+    let layers = pending
+        .by_ref()
+        .enumerate()
+        .map(|(index, layer)| {
+            let layer = layer.map_err(|err| err.with_layer(index))?;
+            item.apply_layer_options(index, layer, &cache.limits)
+        })
+        .collect::<Result<Vec<_>, PConvertError>>()?;
+    drop(pending);
+    let composition = blend_multiple_images(layers, &item.algorithms, &item.dimension_policy)?;
+    let composition = resize_output(composition, &item.resize, &cache.limits)?;
+    write_image_to_file(
+        item.out,
+        &composition,
+        item.compression,
+        item.filter,
+        &item.output,
+    )
+}
+
+pub(crate) fn parse_json_entry(line: &str) -> Result<BatchItem, PConvertError> {
+    let entry: ManifestEntry = serde_json::from_str(line)
+        .map_err(|err| PConvertError::ArgumentError(format!("invalid manifest entry ({})", err)))?;
+
+    let (layers, layers_options) = entry
+        .layers
+        .iter()
+        .map(parse_json_layer)
+        .collect::<Result<Vec<_>, PConvertError>>()?
+        .into_iter()
+        .unzip();
+    let algorithms = build_json_algorithms(entry.algorithm, entry.algorithms, entry.layers.len())?;
+    let dimension_policy =
+        DimensionPolicy::from_options(entry.dimension_policy.as_deref(), entry.gravity.as_deref())?;
+    let resize = ResizeOptions::from_options(
+        entry.size.as_deref(),
+        entry.scale,
+        entry.resize_filter.as_deref(),
+    )?;
+    let output = OutputOptions::from_options(OutputValues {
+        format: entry.format.as_deref(),
+        quality: entry.quality,
+        background: entry.background.as_deref(),
+        colors: entry.colors,
+        dither: entry.dither,
+        optimize: entry.optimize,
+        path: Some(&entry.out),
+    })?;
+
+    Ok(BatchItem {
+        layers,
+        out: entry.out,
+        algorithms,
+        compression: Compression::from_options(
+            entry
+                .compression
+                .as_ref()
+                .map(CompressionLevel::from_json)
+                .transpose()?,
+            entry.strategy.as_deref(),
+        )?,
+        filter: entry
+            .filter
+            .as_deref()
+            .map_or(Ok(FilterType::NoFilter), parse_filter)?,
+        dimension_policy,
+        resize,
+        layers_options,
+        output,
+    })
+}
+
+/// Parses a layer of a manifest entry, given either as a path or as an object
+/// with the `path` and the (optional) `transforms` and `effects` of the layer.
+fn parse_json_layer(layer: &JSONValue) -> Result<(String, LayerOptions), PConvertError> {
+    match layer {
+        JSONValue::String(path) => Ok((path.clone(), LayerOptions::default())),
+        JSONValue::Object(object) => match object.get("path") {
+            Some(JSONValue::String(path)) => Ok((path.clone(), LayerOptions::from_json(layer)?)),
+            _ => Err(PConvertError::ArgumentError(
+                "layers must have a 'path'".to_string(),
+            )),
+        },
+        layer => Err(PConvertError::ArgumentError(format!(
+            "invalid layer definition '{}'",
+            layer
+        ))),
+    }
+}
+
+/// Builds the blending operations for a stack of `num_layers` layers from
+/// either an explicit (JSON) list of algorithms or a single algorithm used
+/// in every operation, defaulting to `BlendAlgorithm::Multiplicative`.
+pub(crate) fn build_json_algorithms(
+    algorithm: Option<String>,
+    algorithms: Option<Vec<JSONValue>>,
+    num_layers: usize,
+) -> Result<Vec<(BlendAlgorithm, Option<BlendAlgorithmParams>)>, PConvertError> {
+    let num_blends = num_layers.saturating_sub(1);
+    match (algorithms, algorithm) {
+        (Some(algorithms), _) => algorithms
+            .into_iter()
+            .map(parse_json_algorithm)
+            .collect::<Result<Vec<_>, PConvertError>>(),
+        (None, Some(algorithm)) => Ok(vec![(parse_algorithm(&algorithm)?, None); num_blends]),
+        (None, None) => Ok(vec![(BlendAlgorithm::Multiplicative, None); num_blends]),
+    }
+}
+
+fn parse_json_algorithm(
+    value: JSONValue,
+) -> Result<(BlendAlgorithm, Option<BlendAlgorithmParams>), PConvertError> {
+    match value {
+        JSONValue::String(algorithm) => Ok((parse_algorithm(&algorithm)?, None)),
+        JSONValue::Object(mut object) => {
+            let algorithm = match object.remove("algorithm") {
+                Some(JSONValue::String(algorithm)) => parse_algorithm(&algorithm)?,
+                _ => {
+                    return Err(PConvertError::ArgumentError(
+                        "'algorithm' not specified".to_string(),
+                    ))
+                }
+            };
+            let mut params = BlendAlgorithmParams::new();
+            match object.remove("params") {
+                Some(JSONValue::Object(object)) => {
+                    for (name, value) in object {
+                        let value = json_value_to_value(&name, value)?;
+                        params.insert(name, value);
+                    }
+                }
+                Some(value) => {
+                    return Err(PConvertError::invalid_parameter(
+                        "params",
+                        format!("expected an object, got '{}'", value),
+                    ))
+                }
+                None => (),
+            }
+            Ok((algorithm, Some(params)))
+        }
+        value => Err(PConvertError::ArgumentError(format!(
+            "invalid algorithm definition '{}'",
+            value
+        ))),
+    }
+}
+
+fn json_value_to_value(name: &str, value: JSONValue) -> Result<Value, PConvertError> {
+    match value {
+        JSONValue::Bool(boolean) => Ok(Value::Bool(boolean)),
+        JSONValue::String(string) => Ok(Value::Str(string)),
+        JSONValue::Number(number) if number.is_f64() => Ok(Value::Float(number.as_f64().unwrap())),
+        JSONValue::Number(number) if number.is_i64() => Ok(Value::Long(number.as_i64().unwrap())),
+        value => Err(PConvertError::invalid_parameter(
+            name,
+            format!("unsupported value '{}'", value),
+        )),
+    }
+}
+
+fn parse_csv_entry(line: &str) -> Result<BatchItem, PConvertError> {
+    let mut fields = split_csv_fields(line)?.into_iter();
+    let out = fields.next().unwrap_or_default();
+    let algorithm = fields.next().unwrap_or_default();
+    let layers = fields.collect::<Vec<String>>();
+
+    if out.is_empty() {
+        return Err(PConvertError::ArgumentError(
+            "'out' not specified".to_string(),
+        ));
+    }
+
+    let algorithm = if algorithm.is_empty() {
+        BlendAlgorithm::Multiplicative
+    } else {
+        parse_algorithm(&algorithm)?
+    };
+
+    Ok(BatchItem {
+        algorithms: vec![(algorithm, None); layers.len().saturating_sub(1)],
+        layers,
+        output: OutputOptions::from_options(OutputValues {
+            path: Some(&out),
+            ..Default::default()
+        })?,
+        out,
+        compression: Compression::FAST,
+        filter: FilterType::NoFilter,
+        dimension_policy: DimensionPolicy::default(),
+        resize: None,
+        layers_options: Vec::new(),
+    })
+}
+
+/// Splits a CSV row into its (trimmed) fields, where fields quoted as in
+/// RFC 4180 may contain commas and (doubled) quotes, their content being
+/// kept as is. Quoted fields spanning several lines are not supported.
+fn split_csv_fields(line: &str) -> Result<Vec<String>, PConvertError> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|&char| char == ' ' || char == '\t').is_some() {}
+        let field = if chars.next_if_eq(&'"').is_some() {
+            let mut field = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                    Some('"') => break,
+                    Some(char) => field.push(char),
+                    None => {
+                        return Err(PConvertError::ArgumentError(
+                            "unterminated quoted field".to_string(),
+                        ))
+                    }
+                }
+            }
+            while chars.next_if(|&char| char == ' ' || char == '\t').is_some() {}
+            if !matches!(chars.peek(), None | Some(',')) {
+                return Err(PConvertError::ArgumentError(format!(
+                    "unexpected characters after the quoted field '{}'",
+                    field
+                )));
+            }
+            field
+        } else {
+            let mut field = String::new();
+            while let Some(char) = chars.next_if(|&char| char != ',') {
+                if char == '"' {
+                    return Err(PConvertError::ArgumentError(
+                        "quotes are only allowed around whole fields".to_string(),
+                    ));
+                }
+                field.push(char);
+            }
+            field.trim_end().to_string()
+        };
+        fields.push(field);
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
+
+pub(crate) fn parse_algorithm(algorithm: &str) -> Result<BlendAlgorithm, PConvertError> {
+    BlendAlgorithm::from_str(algorithm)
+}
+
+type SharedLayer = Arc<Mutex<Option<Arc<ImageBuffer<Rgba<u8>, Vec<u8>>>>>>;
+
+/// Decoded layers shared by more than one composition of the batch,
+/// each layer is dropped as soon as its last composition reads it.
+pub(crate) struct SharedLayers {
+    layers: Mutex<HashMap<String, (usize, SharedLayer)>>,
+    limits: Limits,
+}
+
+impl SharedLayers {
+    pub(crate) fn new(entries: &ManifestEntries, limits: &Limits) -> Self {
+        let mut counts = HashMap::new();
+        for item in entries.iter().filter_map(|(_, item)| item.as_ref().ok()) {
+            for path in &item.layers {
+                *counts.entry(path.clone()).or_insert(0) += 1;
+            }
+        }
+
+        let layers = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(path, count)| (path, (count, Arc::new(Mutex::new(None)))))
+            .collect();
+
+        SharedLayers {
+            layers: Mutex::new(layers),
+            limits: limits.clone(),
+        }
+    }
+
+    /// The number of layers still shared by pending compositions.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.layers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Decrements the number of pending uses of the layer, removing it from
+    /// the shared set once this is the last composition using it, returning
+    /// the shared layer (if the layer is shared at all).
+    fn release(&self, path: &str) -> Option<SharedLayer> {
+        let mut layers = self.layers.lock().unwrap_or_else(PoisonError::into_inner);
+        let (count, layer) = layers.get_mut(path)?;
+        *count -= 1;
+        let layer = layer.clone();
+        if *count == 0 {
+            layers.remove(path);
+        }
+        Some(layer)
+    }
+
+    fn get(&self, path: &str) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+        let layer = match self.release(path) {
+            Some(layer) => layer,
+            None => return read_image_from_file_with_limits(path.to_string(), false, &self.limits),
+        };
+
+        // locks the layer while decoding so that concurrent compositions
+        // wait for the first decode instead of decoding it again
+        let mut layer = layer.lock().unwrap_or_else(PoisonError::into_inner);
+        match &*layer {
+            Some(image) => Ok((**image).clone()),
+            None => {
+                let image = Arc::new(read_image_from_file_with_limits(
+                    path.to_string(),
+                    false,
+                    &self.limits,
+                )?);
+                *layer = Some(image.clone());
+                Ok((*image).clone())
+            }
+        }
+    }
+}
+
+/// The layers of a composition still to be fetched from the shared set,
+/// in order, the uses of the ones never fetched are released on drop.
+struct PendingLayers<'a> {
+    cache: &'a SharedLayers,
+    paths: &'a [String],
+}
+
+impl Iterator for PendingLayers<'_> {
+    type Item = Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, paths) = self.paths.split_first()?;
+        self.paths = paths;
+        Some(self.cache.get(path))
+    }
+}
+
+impl Drop for PendingLayers<'_> {
+    fn drop(&mut self) {
+        for path in self.paths {
+            self.cache.release(path);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Policies for layers whose dimensions differ from the dimensions of
+//! the composition they are blended into.
+
+use crate::errors::PConvertError;
+use crate::resize::{resize, ResizeFilter};
+use image::{ImageBuffer, Rgba};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::result;
+use std::str::FromStr;
+
+/// Position of a layer inside of the composition, used when the layer
+/// is not (or can not be) resized to the dimensions of the composition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gravity {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Gravity {
+    /// Returns the offset of a layer with the given dimensions inside of
+    /// a composition with the given dimensions (negative when the layer
+    /// is larger than the composition).
+    pub fn offset(&self, layer: (u32, u32), composition: (u32, u32)) -> (i64, i64) {
+        let (dx, dy) = (
+            composition.0 as i64 - layer.0 as i64,
+            composition.1 as i64 - layer.1 as i64,
+        );
+        let x = match self {
+            Gravity::TopLeft | Gravity::Left | Gravity::BottomLeft => 0,
+            Gravity::Top | Gravity::Center | Gravity::Bottom => dx / 2,
+            Gravity::TopRight | Gravity::Right | Gravity::BottomRight => dx,
+        };
+        let y = match self {
+            Gravity::TopLeft | Gravity::Top | Gravity::TopRight => 0,
+            Gravity::Left | Gravity::Center | Gravity::Right => dy / 2,
+            Gravity::BottomLeft | Gravity::Bottom | Gravity::BottomRight => dy,
+        };
+        (x, y)
+    }
+}
+
+impl FromStr for Gravity {
+    type Err = PConvertError;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace('-', "_").as_str() {
+            "top_left" => Ok(Gravity::TopLeft),
+            "top" => Ok(Gravity::Top),
+            "top_right" => Ok(Gravity::TopRight),
+            "left" => Ok(Gravity::Left),
+            "center" => Ok(Gravity::Center),
+            "right" => Ok(Gravity::Right),
+            "bottom_left" => Ok(Gravity::BottomLeft),
+            "bottom" => Ok(Gravity::Bottom),
+            "bottom_right" => Ok(Gravity::BottomRight),
+            _ => Err(PConvertError::invalid_parameter(
+                "gravity",
+                format!("unknown gravity '{}'", s),
+            )),
+        }
+    }
+}
+
+impl Display for Gravity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Gravity::TopLeft => write!(f, "top_left"),
+            Gravity::Top => write!(f, "top"),
+            Gravity::TopRight => write!(f, "top_right"),
+            Gravity::Left => write!(f, "left"),
+            Gravity::Center => write!(f, "center"),
+            Gravity::Right => write!(f, "right"),
+            Gravity::BottomLeft => write!(f, "bottom_left"),
+            Gravity::Bottom => write!(f, "bottom"),
+            Gravity::BottomRight => write!(f, "bottom_right"),
+        }
+    }
+}
+
+/// Enumeration of the supported policies for layers whose dimensions
+/// differ from the dimensions of the composition (the bottom layer).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DimensionPolicy {
+    /// Fails with a `DimensionMismatchError` naming the layer.
+    #[default]
+    Error,
+    /// Places the layer (as is) at the given gravity, cropping
+    /// whatever falls outside of the composition.
+    Anchor(Gravity),
+    /// Resizes the layer to the dimensions of the composition,
+    /// ignoring its aspect ratio.
+    Stretch,
+    /// Resizes the layer, keeping its aspect ratio, to the largest size
+    /// that fits the composition and places it at the given gravity.
+    Fit(Gravity),
+}
+
+impl DimensionPolicy {
+    /// Builds a policy from the (optional) names of the policy and of
+    /// the gravity, as given in the options of the external APIs.
+    /// Defaults to `DimensionPolicy::Error`.
+    pub fn from_options(
+        policy: Option<&str>,
+        gravity: Option<&str>,
+    ) -> Result<DimensionPolicy, PConvertError> {
+        let policy = match policy {
+            Some(policy) => DimensionPolicy::from_str(policy)?,
+            None => DimensionPolicy::default(),
+        };
+        match gravity {
+            Some(gravity) => Ok(policy.with_gravity(Gravity::from_str(gravity)?)),
+            None => Ok(policy),
+        }
+    }
+
+    /// Returns the same policy placing the layers at the given gravity,
+    /// policies that do not place layers are returned unchanged.
+    pub fn with_gravity(self, gravity: Gravity) -> DimensionPolicy {
+        match self {
+            DimensionPolicy::Anchor(_) => DimensionPolicy::Anchor(gravity),
+            DimensionPolicy::Fit(_) => DimensionPolicy::Fit(gravity),
+            policy => policy,
+        }
+    }
+}
+
+/// Parses the name of the policy, policies that place
+/// layers use the `Gravity::Center` gravity.
+impl FromStr for DimensionPolicy {
+    type Err = PConvertError;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "error" => Ok(DimensionPolicy::Error),
+            "anchor" => Ok(DimensionPolicy::Anchor(Gravity::Center)),
+            "stretch" => Ok(DimensionPolicy::Stretch),
+            "fit" => Ok(DimensionPolicy::Fit(Gravity::Center)),
+            _ => Err(PConvertError::invalid_parameter(
+                "dimension_policy",
+                format!("unknown policy '{}'", s),
+            )),
+        }
+    }
+}
+
+impl Display for DimensionPolicy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            DimensionPolicy::Error => write!(f, "error"),
+            DimensionPolicy::Anchor(_) => write!(f, "anchor"),
+            DimensionPolicy::Stretch => write!(f, "stretch"),
+            DimensionPolicy::Fit(_) => write!(f, "fit"),
+        }
+    }
+}
+
+/// Conforms a layer to the dimensions of the composition it is going
+/// to be blended into, according to the given policy.
+///
+/// Layers are expected with straight (not premultiplied) alpha, which
+/// is the case before they are demultiplied for blending.
+///
+/// # Arguments
+///
+/// * `layer` - The image buffer of the layer, returned as is when it
+///   already has the dimensions of the composition.
+/// * `dimensions` - The dimensions of the composition.
+/// * `index` - The index of the layer in the stack of layers, used as
+///   context of the `DimensionMismatchError`.
+/// * `policy` - The policy to apply on dimension mismatch.
+pub fn conform_layer(
+    layer: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    dimensions: (u32, u32),
+    index: usize,
+    policy: &DimensionPolicy,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    if layer.dimensions() == dimensions {
+        return Ok(layer);
+    }
+
+    match policy {
+        DimensionPolicy::Error => Err(PConvertError::DimensionMismatchError {
+            layer: index,
+            expected: dimensions,
+            found: layer.dimensions(),
+        }),
+        DimensionPolicy::Anchor(gravity) => Ok(anchor(&layer, dimensions, gravity)),
+        DimensionPolicy::Stretch => Ok(resize(
+            &layer,
+            dimensions.0,
+            dimensions.1,
+            ResizeFilter::Bilinear,
+        )),
+        DimensionPolicy::Fit(gravity) => {
+            let (width, height) = layer.dimensions();
+            if width == 0 || height == 0 {
+                return Ok(ImageBuffer::new(dimensions.0, dimensions.1));
+            }
+
+            let scale = f64::min(
+                dimensions.0 as f64 / width as f64,
+                dimensions.1 as f64 / height as f64,
+            );
+            let fitted = (
+                ((width as f64 * scale).round() as u32).clamp(1, dimensions.0.max(1)),
+                ((height as f64 * scale).round() as u32).clamp(1, dimensions.1.max(1)),
+            );
+            let layer = resize(&layer, fitted.0, fitted.1, ResizeFilter::Bilinear);
+            Ok(anchor(&layer, dimensions, gravity))
+        }
+    }
+}
+
+/// Copies the layer into a transparent image with the given dimensions,
+/// at the position defined by the gravity.
+fn anchor(
+    layer: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    dimensions: (u32, u32),
+    gravity: &Gravity,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (offset_x, offset_y) = gravity.offset(layer.dimensions(), dimensions);
+    let mut canvas = ImageBuffer::new(dimensions.0, dimensions.1);
+
+    // only the intersection of the (offset) layer with the canvas is copied
+    let x_start = offset_x.max(0);
+    let y_start = offset_y.max(0);
+    let x_end = (offset_x + layer.width() as i64).min(dimensions.0 as i64);
+    let y_end = (offset_y + layer.height() as i64).min(dimensions.1 as i64);
+    for y in y_start..y_end {
+        for x in x_start..x_end {
+            let pixel = layer.get_pixel((x - offset_x) as u32, (y - offset_y) as u32);
+            canvas.put_pixel(x as u32, y as u32, *pixel);
+        }
+    }
+
+    canvas
+}
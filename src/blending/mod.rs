@@ -1,13 +1,16 @@
 //! Blending algorithms and associated utility functions and enums.
 
 pub mod algorithms;
+pub mod dimensions;
 pub mod params;
 
+use crate::errors::PConvertError;
 use algorithms::{
     blend_alpha, blend_destination_over, blend_disjoint_debug, blend_disjoint_over,
     blend_disjoint_under, blend_first_bottom, blend_first_top, blend_mask_top,
     blend_multiplicative, blend_source_over,
 };
+use dimensions::{conform_layer, DimensionPolicy};
 use image::{ImageBuffer, Rgba};
 use params::BlendAlgorithmParams;
 use std::fmt;
@@ -31,7 +34,7 @@ pub enum BlendAlgorithm {
 }
 
 impl FromStr for BlendAlgorithm {
-    type Err = String;
+    type Err = PConvertError;
 
     fn from_str(s: &str) -> result::Result<Self, Self::Err> {
         match s {
@@ -45,7 +48,7 @@ impl FromStr for BlendAlgorithm {
             "disjoint_over" => Ok(BlendAlgorithm::DisjointOver),
             "disjoint_under" => Ok(BlendAlgorithm::DisjointUnder),
             "disjoint_debug" => Ok(BlendAlgorithm::DisjointDebug),
-            s => Err(s.to_string()),
+            s => Err(PConvertError::UnknownAlgorithmError(s.to_string())),
         }
     }
 }
@@ -73,13 +76,13 @@ impl Display for BlendAlgorithm {
 /// # Arguments
 ///
 /// * `bot` - An image buffer corresponding to the bottom layer, in typical
-/// composition language this should be considered the `source`.
+///   composition language this should be considered the `source`.
 /// * `top` - An image buffer corresponding to the top layer, in typical
-/// composition language this should be considered the `destination`.
+///   composition language this should be considered the `destination`.
 /// * `blending_algorithm` - A function that blends two pixels according
-/// to optional blending parameters.
+///   to optional blending parameters.
 /// * `algorithm_params` - A optional map of key-value pairs of blending
-/// properties and values.
+///   properties and values.
 ///
 /// # Examples
 ///
@@ -104,6 +107,84 @@ pub fn blend_images(
     }
 }
 
+/// Blends a stack of image buffers into a single composition, from the
+/// bottom-most layer (the first one) to the top-most one (the last one).
+///
+/// Layers are expected as decoded (not demultiplied), each one is
+/// demultiplied when required by the algorithm used to blend it.
+/// The first layer defines the dimensions of the composition.
+///
+/// # Arguments
+///
+/// * `layers` - The image buffers to blend, bottom to top.
+/// * `algorithms` - The blending algorithm and optional parameters to use
+///   in each blending operation, one per layer except the first.
+/// * `policy` - The policy for layers whose dimensions differ from the
+///   dimensions of the composition.
+///
+/// # Examples
+///
+/// ```no_run
+/// use pconvert_rust::blending::dimensions::{DimensionPolicy, Gravity};
+/// use pconvert_rust::blending::{blend_multiple_images, BlendAlgorithm};
+/// use pconvert_rust::utils::read_png_from_file;
+///
+/// let bot = read_png_from_file("bot.png".to_string(), false).unwrap();
+/// let top = read_png_from_file("top.png".to_string(), false).unwrap();
+/// let algorithms = vec![(BlendAlgorithm::SourceOver, None)];
+/// let policy = DimensionPolicy::Anchor(Gravity::Center);
+///
+/// let composition = blend_multiple_images(vec![bot, top], &algorithms, &policy).unwrap();
+/// ```
+pub fn blend_multiple_images(
+    layers: Vec<ImageBuffer<Rgba<u8>, Vec<u8>>>,
+    algorithms: &[(BlendAlgorithm, Option<BlendAlgorithmParams>)],
+    policy: &DimensionPolicy,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    let num_layers = layers.len();
+
+    if num_layers < 1 {
+        return Err(PConvertError::ArgumentError(
+            "'layers' must contain at least one image".to_string(),
+        ));
+    }
+
+    if algorithms.len() != num_layers - 1 {
+        return Err(PConvertError::ArgumentError(format!(
+            "'algorithms' must be of size {} (one per blending operation)",
+            num_layers - 1
+        )));
+    }
+
+    let mut layers_iter = layers.into_iter();
+    let mut composition = layers_iter.next().unwrap();
+    if !algorithms.is_empty() && is_algorithm_multiplied(&algorithms[0].0) {
+        demultiply_image(&mut composition);
+    }
+
+    // loops through the algorithms to apply and blends the
+    // current composition with the next layer
+    for (index, (current_layer, (algorithm, algorithm_params))) in
+        layers_iter.zip(algorithms.iter()).enumerate()
+    {
+        let mut current_layer =
+            conform_layer(current_layer, composition.dimensions(), index + 1, policy)?;
+        let algorithm_fn = get_blending_algorithm(algorithm);
+        if is_algorithm_multiplied(algorithm) {
+            demultiply_image(&mut current_layer);
+        }
+
+        blend_images(
+            &mut composition,
+            &current_layer,
+            &algorithm_fn,
+            algorithm_params,
+        );
+    }
+
+    Ok(composition)
+}
+
 /// Demultiplies an image buffer, by applying the demultiply operation over the
 /// complete set of pixels in the provided image buffer.
 ///
@@ -0,0 +1,241 @@
+//! Bounded in-memory cache of decoded layers, shared across compositions.
+
+use crate::errors::PConvertError;
+use crate::limits::Limits;
+use crate::utils::read_image_from_file_with_limits;
+use image::{ImageBuffer, Rgba};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// Default capacity (in bytes) of the layer cache, 256 MB.
+pub const DEFAULT_CACHE_CAPACITY: usize = 256 * 1024 * 1024;
+
+/// Decoded image buffer shared between the cache and its readers.
+pub type CachedImage = Arc<ImageBuffer<Rgba<u8>, Vec<u8>>>;
+
+/// Least recently used (LRU) cache of decoded layers, bounded by the
+/// total number of bytes of the decoded image buffers.
+///
+/// Entries are keyed by path and demultiply flag and are only considered
+/// valid while the modification time of the file remains the same.
+pub struct LayerCache {
+    state: Mutex<CacheState>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+struct CacheState {
+    entries: HashMap<(String, bool), CacheEntry>,
+    size: usize,
+    capacity: usize,
+    tick: u64,
+}
+
+struct CacheEntry {
+    image: CachedImage,
+    modified: Option<SystemTime>,
+    last_used: u64,
+}
+
+impl LayerCache {
+    /// Creates an empty cache that holds up to `capacity` bytes
+    /// of decoded image data.
+    pub fn new(capacity: usize) -> Self {
+        LayerCache {
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                size: 0,
+                capacity,
+                tick: 0,
+            }),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reads an image (in any of the supported input formats) from the
+    /// local file system, returning the cached decoded buffer when the file did
+    /// not change since it was cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_in` - Local file system path to the image file.
+    /// * `demultiply` - Whether or not to demultiply the image.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use pconvert_rust::cache::LayerCache;
+    ///
+    /// let cache = LayerCache::new(64 * 1024 * 1024);
+    /// let first = cache.read_image("sole.png", false).unwrap();
+    /// let second = cache.read_image("sole.png", false).unwrap();
+    /// assert_eq!(cache.get_stats().hits(), 1);
+    /// ```
+    pub fn read_image(
+        &self,
+        file_in: &str,
+        demultiply: bool,
+    ) -> Result<CachedImage, PConvertError> {
+        self.read_image_with_limits(file_in, demultiply, &Limits::default())
+    }
+
+    /// Reads an image from the local file system (as `read_image`), within the
+    /// given resource limits, which are also checked for cached images as
+    /// they may have been decoded with other limits.
+    pub fn read_image_with_limits(
+        &self,
+        file_in: &str,
+        demultiply: bool,
+        limits: &Limits,
+    ) -> Result<CachedImage, PConvertError> {
+        let modified = fs::metadata(file_in)?.modified().ok();
+        let key = (file_in.to_string(), demultiply);
+
+        if let Some(image) = self.lookup(&key, modified) {
+            limits.check_dimensions(image.width(), image.height())?;
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(image);
+        }
+
+        // decodes the layer outside of the lock so that other
+        // layers can be read concurrently from the cache
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let image = Arc::new(read_image_from_file_with_limits(
+            file_in.to_string(),
+            demultiply,
+            limits,
+        )?);
+        self.insert(key, image.clone(), modified);
+
+        Ok(image)
+    }
+
+    /// Changes the capacity (in bytes) of the cache, evicting the least
+    /// recently used entries if the current size exceeds it.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.capacity = capacity;
+        state.evict(0);
+    }
+
+    /// Removes every entry from the cache (statistics are kept).
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.entries.clear();
+        state.size = 0;
+    }
+
+    pub fn get_stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: state.entries.len(),
+            size: state.size,
+            capacity: state.capacity,
+        }
+    }
+
+    fn lookup(&self, key: &(String, bool), modified: Option<SystemTime>) -> Option<CachedImage> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.tick += 1;
+        let tick = state.tick;
+
+        match state.entries.get_mut(key) {
+            Some(entry) if modified.is_some() && entry.modified == modified => {
+                entry.last_used = tick;
+                Some(entry.image.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn insert(&self, key: (String, bool), image: CachedImage, modified: Option<SystemTime>) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(entry) = state.entries.remove(&key) {
+            state.size -= entry.image.as_raw().len();
+        }
+
+        // images larger than the complete cache are never cached
+        let image_size = image.as_raw().len();
+        if image_size > state.capacity {
+            return;
+        }
+
+        state.evict(image_size);
+        state.tick += 1;
+        let last_used = state.tick;
+        state.size += image_size;
+        state.entries.insert(
+            key,
+            CacheEntry {
+                image,
+                modified,
+                last_used,
+            },
+        );
+    }
+}
+
+impl CacheState {
+    /// Evicts the least recently used entries until there is room
+    /// for `required` more bytes.
+    fn evict(&mut self, required: usize) {
+        while self.size + required > self.capacity {
+            let key = match self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            {
+                Some(key) => key,
+                None => break,
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.size -= entry.image.as_raw().len();
+            }
+        }
+    }
+}
+
+impl Default for LayerCache {
+    fn default() -> Self {
+        LayerCache::new(DEFAULT_CACHE_CAPACITY)
+    }
+}
+
+/// Represents the status of the layer cache (e.g. hits, misses, size in bytes).
+#[derive(Clone, Debug)]
+pub struct CacheStats {
+    hits: usize,
+    misses: usize,
+    entries: usize,
+    size: usize,
+    capacity: usize,
+}
+
+impl CacheStats {
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
@@ -1,17 +1,43 @@
-use image::codecs::png::{CompressionType, FilterType};
-use image::ImageFormat;
+use image::codecs::png::FilterType;
+use image::{ImageBuffer, Rgba};
+use pconvert_rust::alpha::{remove_matte, threshold_alpha, trim};
+use pconvert_rust::apng::{
+    blend_animations, is_apng_file, read_animation_from_file_with_limits, write_apng_to_file,
+};
+use pconvert_rust::batch::{read_manifest, run_batch, write_report, BatchItem, ManifestFormat};
 use pconvert_rust::benchmark::Benchmark;
-use pconvert_rust::blending::BlendAlgorithm;
+use pconvert_rust::blending::dimensions::DimensionPolicy;
+use pconvert_rust::blending::params::{BlendAlgorithmParams, Value};
+use pconvert_rust::blending::{blend_multiple_images, BlendAlgorithm};
+use pconvert_rust::compare::{compare, diff_heatmap};
 use pconvert_rust::compose::{apply_blue_filter, compose, compose_parallel, Background};
 use pconvert_rust::constants;
+use pconvert_rust::effects::Effect;
+use pconvert_rust::encoding::{parse_filter, Compression, CompressionLevel};
 use pconvert_rust::errors::PConvertError;
-use pconvert_rust::utils::read_png_from_file;
+use pconvert_rust::format::{
+    write_image_parallel, write_image_to_file, OutputOptions, OutputValues,
+};
+use pconvert_rust::layer::LayerOptions;
+use pconvert_rust::limits::{Limits, LIMIT_NAMES};
+use pconvert_rust::optimize::optimize_file;
+use pconvert_rust::parallelism::{ResultMessage, ThreadPool};
+use pconvert_rust::resize::{resize_output, ResizeOptions};
+use pconvert_rust::server::{Server, ServerOptions};
+use pconvert_rust::stream::{blend_files_streaming, DEFAULT_BAND_HEIGHT};
+use pconvert_rust::transform::Transform;
+use pconvert_rust::utils::{parse_hex_color, read_image_from_file_with_limits, write_png_to_file};
+use pconvert_rust::watch::{read_scene, Watcher};
 use std::env;
+use std::fs::{self, File};
+use std::io;
 use std::str;
 use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 pub fn print_usage() {
-    println!("Usage: pconvert-rust <command> [args...]\nwhere command can be one of the following: compose, convert, benchmark, version");
+    println!("Usage: pconvert-rust <command> [args...]\nwhere command can be one of the following: compose, convert, resize, blend, batch, serve, watch, compare, optimize, benchmark, version");
 }
 
 pub fn pcompose(args: &mut env::Args) -> Result<(), PConvertError> {
@@ -25,7 +51,7 @@ pub fn pcompose(args: &mut env::Args) -> Result<(), PConvertError> {
         }
         None => {
             return Err(PConvertError::ArgumentError(
-                "ArgumentError: 'directory' not specified".to_string(),
+                "'directory' not specified".to_string(),
             ))
         }
     };
@@ -44,9 +70,9 @@ pub fn pcompose(args: &mut env::Args) -> Result<(), PConvertError> {
         for algorithm in constants::ALGORITHMS.iter() {
             compose(
                 &dir,
-                BlendAlgorithm::from_str(algorithm).unwrap(),
+                BlendAlgorithm::from_str(algorithm)?,
                 &background,
-                CompressionType::Fast,
+                Compression::FAST,
                 FilterType::NoFilter,
                 &mut benchmark,
             )?;
@@ -61,7 +87,7 @@ pub fn pconvert(args: &mut env::Args) -> Result<(), PConvertError> {
         Some(name) => name,
         None => {
             return Err(PConvertError::ArgumentError(
-                "ArgumentError: 'file_in' not specified".to_string(),
+                "'file_in' not specified".to_string(),
             ))
         }
     };
@@ -70,19 +96,568 @@ pub fn pconvert(args: &mut env::Args) -> Result<(), PConvertError> {
         Some(name) => name,
         None => {
             return Err(PConvertError::ArgumentError(
-                "ArgumentError: 'file_out' not specified".to_string(),
+                "'file_out' not specified".to_string(),
             ))
         }
     };
 
-    let mut img = read_png_from_file(file_in, false)?;
+    let mut limits = Limits::default();
+    let mut output_args = OutputArgs::default();
+    while let Some(arg) = args.next() {
+        if !output_args.parse(&arg, args)? && !parse_limit(&mut limits, &arg, args)? {
+            return Err(PConvertError::ArgumentError(format!(
+                "unknown argument '{}'",
+                arg
+            )));
+        }
+    }
+    let output = output_args.build(&file_out)?;
+
+    let mut img = read_image_from_file_with_limits(file_in, false, &limits)?;
 
     for pixel in img.pixels_mut() {
         apply_blue_filter(pixel);
     }
 
-    img.save_with_format(file_out, ImageFormat::Png)?;
+    write_image_to_file(
+        file_out,
+        &img,
+        Compression::FAST,
+        FilterType::NoFilter,
+        &output,
+    )
+}
+
+pub fn presize(args: &mut env::Args) -> Result<(), PConvertError> {
+    let file_in = match args.next() {
+        Some(name) => name,
+        None => {
+            return Err(PConvertError::ArgumentError(
+                "'file_in' not specified".to_string(),
+            ))
+        }
+    };
+
+    let file_out = match args.next() {
+        Some(name) => name,
+        None => {
+            return Err(PConvertError::ArgumentError(
+                "'file_out' not specified".to_string(),
+            ))
+        }
+    };
+
+    let mut size = None;
+    let mut scale = None;
+    let mut resize_filter = None;
+    let mut compression = None;
+    let mut strategy = None;
+    let mut filter = FilterType::NoFilter;
+    let mut alpha_args = AlphaArgs::default();
+    let mut output_args = OutputArgs::default();
+    let mut limits = Limits::default();
+
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--size" => size = Some(next_value(args, &arg)?),
+            "--scale" => scale = Some(parse_scale(&next_value(args, &arg)?)?),
+            "--resize-filter" => resize_filter = Some(next_value(args, &arg)?),
+            "--compression" => compression = Some(next_value(args, &arg)?),
+            "--strategy" => strategy = Some(next_value(args, &arg)?),
+            "--filter" => filter = parse_filter(&next_value(args, &arg)?)?,
+            flag if alpha_args.parse(flag, args)? => (),
+            flag if output_args.parse(flag, args)? => (),
+            flag if parse_limit(&mut limits, flag, args)? => (),
+            _ => {
+                return Err(PConvertError::ArgumentError(format!(
+                    "unknown argument '{}'",
+                    arg
+                )))
+            }
+        }
+    }
+    let output = output_args.build(&file_out)?;
+    let compression = Compression::from_options(
+        compression.as_deref().map(CompressionLevel::Name),
+        strategy.as_deref(),
+    )?;
+
+    let resize = ResizeOptions::from_options(size.as_deref(), scale, resize_filter.as_deref())?
+        .ok_or_else(|| {
+            PConvertError::ArgumentError(
+                "either '--size' or '--scale' must be specified".to_string(),
+            )
+        })?;
+
+    let img = read_image_from_file_with_limits(file_in, false, &limits)?;
+    let img = alpha_args.apply(img);
+    let img = resize_output(img, &Some(resize), &limits)?;
+    write_image_to_file(file_out, &img, compression, filter, &output)
+}
+
+pub fn pblend(args: &mut env::Args) -> Result<(), PConvertError> {
+    let file_out = match args.next() {
+        Some(name) => name,
+        None => {
+            return Err(PConvertError::ArgumentError(
+                "'file_out' not specified".to_string(),
+            ))
+        }
+    };
+
+    let mut alpha_args = AlphaArgs::default();
+    let mut output_args = OutputArgs::default();
+    let mut limits = Limits::default();
+    let mut stream = false;
+    let mut band_height = DEFAULT_BAND_HEIGHT;
+
+    let BlendArgs {
+        files_in,
+        algorithms,
+        num_threads,
+        compression,
+        filter,
+        dimension_policy,
+        resize,
+        layers_options,
+    } = parse_blend_args(args, |flag, args| match flag {
+        "--stream" => {
+            stream = true;
+            Ok(true)
+        }
+        "--band-height" => {
+            let value = next_value(args, flag)?;
+            band_height = value.parse::<u32>().map_err(|_| {
+                PConvertError::invalid_parameter(
+                    "band_height",
+                    format!("'{}' is not a number", value),
+                )
+            })?;
+            Ok(true)
+        }
+        _ => Ok(alpha_args.parse(flag, args)?
+            || output_args.parse(flag, args)?
+            || parse_limit(&mut limits, flag, args)?),
+    })?;
+    let num_layers = files_in.len();
+    limits.check_layers(num_layers)?;
+    let output = output_args.build(&file_out)?;
+
+    // the streaming composition never holds the whole layers (nor the
+    // whole composition) in memory, so the options that need them fail
+    if stream {
+        if dimension_policy != DimensionPolicy::Error
+            || resize.is_some()
+            || !alpha_args.is_empty()
+            || layers_options.iter().any(|options| !options.is_empty())
+        {
+            return Err(PConvertError::ArgumentError(
+                "'--stream' does not support transforms, effects, resizing, alpha \
+                options or dimension policies"
+                    .to_string(),
+            ));
+        }
+        if !output.is_plain_png() {
+            return Err(PConvertError::ArgumentError(
+                "'--stream' only supports the PNG format (without '--colors' or '--optimize')"
+                    .to_string(),
+            ));
+        }
+        if files_in.iter().any(|path| is_apng_file(path)) {
+            return Err(PConvertError::ArgumentError(
+                "'--stream' does not support animated layers".to_string(),
+            ));
+        }
+        return blend_files_streaming(
+            &files_in,
+            file_out,
+            &algorithms,
+            compression,
+            filter,
+            band_height,
+            &limits,
+        );
+    }
+
+    // compositions with any animated layer are blended frame by frame,
+    // into an animated PNG (static layers being the same in every frame)
+    if files_in.iter().any(|path| is_apng_file(path)) {
+        if !output.is_plain_png() {
+            return Err(PConvertError::ArgumentError(
+                "animated compositions only support the PNG format (without '--colors' \
+                or '--optimize')"
+                    .to_string(),
+            ));
+        }
+        if alpha_args.trim {
+            return Err(PConvertError::ArgumentError(
+                "'--trim' is not supported by animated compositions".to_string(),
+            ));
+        }
+        let layers = files_in
+            .into_iter()
+            .enumerate()
+            .zip(layers_options.iter())
+            .map(|((index, path), layer_options)| {
+                read_animation_from_file_with_limits(path, &limits)
+                    .and_then(|layer| {
+                        layer.map_frames(|frame| layer_options.apply_with_limits(frame, &limits))
+                    })
+                    .map_err(|err| err.with_layer(index))
+            })
+            .collect::<Result<Vec<_>, PConvertError>>()?;
+        let composition = blend_animations(layers, &algorithms, &dimension_policy, &limits)?;
+        let composition = composition
+            .map_frames(|frame| resize_output(alpha_args.apply(frame), &resize, &limits))?;
+        return write_apng_to_file(file_out, &composition, compression, filter);
+    }
+
+    if num_threads == 0 {
+        let layers = files_in
+            .into_iter()
+            .enumerate()
+            .zip(layers_options.iter())
+            .map(|((index, path), layer_options)| {
+                let layer = read_image_from_file_with_limits(path, false, &limits)
+                    .map_err(|err| err.with_layer(index))?;
+                layer_options.apply_with_limits(layer, &limits)
+            })
+            .collect::<Result<Vec<_>, PConvertError>>()?;
+        let composition = blend_multiple_images(layers, &algorithms, &dimension_policy)?;
+        let composition = alpha_args.apply(composition);
+        let composition = resize_output(composition, &resize, &limits)?;
+        write_image_to_file(file_out, &composition, compression, filter, &output)
+    } else {
+        let mut thread_pool = ThreadPool::new(num_threads)?;
+        thread_pool.start();
+
+        // sends the PNG reading tasks to the thread pool and then
+        // waits for each of the decoded layers, in order
+        let result_channels = files_in
+            .into_iter()
+            .zip(layers_options)
+            .map(|(path, layer_options)| {
+                let limits = limits.clone();
+                thread_pool.execute(move || {
+                    ResultMessage::ImageResult(
+                        read_image_from_file_with_limits(path, false, &limits)
+                            .and_then(|layer| layer_options.apply_with_limits(layer, &limits)),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut layers = Vec::with_capacity(num_layers);
+        for (index, result_channel) in result_channels.into_iter().enumerate() {
+            match result_channel.recv() {
+                Ok(ResultMessage::ImageResult(result)) => {
+                    layers.push(result.map_err(|err| err.with_layer(index))?)
+                }
+                _ => {
+                    return Err(PConvertError::ThreadPoolError(
+                        "failure reading layer in thread pool".to_string(),
+                    ))
+                }
+            }
+        }
+
+        let composition = blend_multiple_images(layers, &algorithms, &dimension_policy)?;
+        let composition = alpha_args.apply(composition);
+        let composition = resize_output(composition, &resize, &limits)?;
+        write_image_parallel(file_out, &composition, compression, filter, &output)
+    }
+}
+
+pub fn pbatch(args: &mut env::Args) -> Result<(), PConvertError> {
+    let manifest = match args.next() {
+        Some(name) => name,
+        None => {
+            return Err(PConvertError::ArgumentError(
+                "'manifest' not specified".to_string(),
+            ))
+        }
+    };
+
+    let mut format = ManifestFormat::from_path(&manifest);
+    let mut report = None;
+    let mut num_threads = constants::DEFAULT_THREAD_POOL_SIZE;
+    let mut limits = Limits::default();
+
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--format" => {
+                let value = next_value(args, &arg)?;
+                format = ManifestFormat::from_str(&value).map_err(|format| {
+                    PConvertError::ArgumentError(format!("invalid manifest format '{}'", format))
+                })?;
+            }
+            "--report" => report = Some(next_value(args, &arg)?),
+            "--threads" => num_threads = parse_num_threads(&next_value(args, &arg)?)?,
+            flag if parse_limit(&mut limits, flag, args)? => (),
+            _ => {
+                return Err(PConvertError::ArgumentError(format!(
+                    "unknown argument '{}'",
+                    arg
+                )))
+            }
+        }
+    }
+
+    let entries = read_manifest(&manifest, &format)?;
+
+    let mut thread_pool = ThreadPool::new(num_threads)?;
+    thread_pool.start();
+    let results = run_batch(entries, &thread_pool, &limits);
+
+    match report {
+        Some(path) => write_report(&mut File::create(path)?, &results)?,
+        None => write_report(&mut io::stdout(), &results)?,
+    }
+
+    let failed = results
+        .iter()
+        .filter(|result| result.result.is_err())
+        .count();
+    eprintln!(
+        "{} compositions, {} succeeded, {} failed",
+        results.len(),
+        results.len() - failed,
+        failed
+    );
+
+    // every composition is processed, but a run with failures exits with
+    // an error so that it can be told apart from a clean one
+    if failed > 0 {
+        return Err(PConvertError::ArgumentError(format!(
+            "{} of {} compositions failed",
+            failed,
+            results.len()
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn pserve(args: &mut env::Args) -> Result<(), PConvertError> {
+    let mut host = "127.0.0.1".to_string();
+    let mut port = "8080".to_string();
+    let mut num_threads = constants::DEFAULT_THREAD_POOL_SIZE;
+    let mut options = ServerOptions::default();
+
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--host" => host = next_value(args, &arg)?,
+            "--port" => port = next_value(args, &arg)?,
+            "--threads" => num_threads = parse_num_threads(&next_value(args, &arg)?)?,
+            "--allow-paths" => options.allow_paths = true,
+            flag if parse_limit(&mut options.limits, flag, args)? => (),
+            _ => {
+                return Err(PConvertError::ArgumentError(format!(
+                    "unknown argument '{}'",
+                    arg
+                )))
+            }
+        }
+    }
+
+    let server = Server::bind(&format!("{}:{}", host, port), num_threads, options)?;
+    println!("Listening on http://{}", server.local_addr()?);
+    server.run()
+}
+
+pub fn pwatch(args: &mut env::Args) -> Result<(), PConvertError> {
+    let first = match args.next() {
+        Some(name) => name,
+        None => {
+            return Err(PConvertError::ArgumentError(
+                "'file_out' or '--scene' not specified".to_string(),
+            ))
+        }
+    };
+
+    let mut interval = 250;
+    let mut limits = Limits::default();
+    let mut output_args = OutputArgs::default();
+    let mut parse_watch_args = |flag: &str, args: &mut env::Args| match flag {
+        "--interval" => {
+            let value = next_value(args, flag)?;
+            interval = value.parse::<u64>().map_err(|_| {
+                PConvertError::invalid_parameter("interval", format!("'{}' is not a number", value))
+            })?;
+            Ok(true)
+        }
+        _ => Ok(output_args.parse(flag, args)? || parse_limit(&mut limits, flag, args)?),
+    };
+
+    // the composition is either given by a (JSON) scene file, which is also
+    // watched for changes, or by the output and layers in the command line
+    let scene = if first == "--scene" {
+        let scene = next_value(args, &first)?;
+        while let Some(arg) = args.next() {
+            if !parse_watch_args(&arg, args)? {
+                return Err(PConvertError::ArgumentError(format!(
+                    "unknown argument '{}'",
+                    arg
+                )));
+            }
+        }
+        Some(scene)
+    } else {
+        None
+    };
+    let item = match &scene {
+        Some(scene) => read_scene(scene)?,
+        None => {
+            let BlendArgs {
+                files_in,
+                algorithms,
+                compression,
+                filter,
+                dimension_policy,
+                resize,
+                layers_options,
+                ..
+            } = parse_blend_args(args, parse_watch_args)?;
+            BatchItem {
+                layers: files_in,
+                algorithms,
+                compression,
+                filter,
+                dimension_policy,
+                resize,
+                layers_options,
+                output: output_args.build(&first)?,
+                out: first,
+            }
+        }
+    };
+
+    let mut scene_modified = scene_modified_time(&scene);
+    let mut watcher = Watcher::new(item, limits.clone())?;
+    println!("Watching for changes (press Ctrl+C to stop)");
+
+    loop {
+        let modified = scene_modified_time(&scene);
+        if modified != scene_modified {
+            scene_modified = modified;
+            match read_scene(scene.as_ref().unwrap())
+                .and_then(|item| Watcher::new(item, limits.clone()))
+            {
+                Ok(scene_watcher) => watcher = scene_watcher,
+                Err(err) => eprintln!("Failed reading scene: {}", err),
+            }
+        }
+
+        let start = Instant::now();
+        match watcher.update() {
+            Ok(Some(layer)) => println!(
+                "Rendered from layer {} in {:.2}ms",
+                layer + 1,
+                start.elapsed().as_micros() as f64 / 1000.0
+            ),
+            Ok(None) => {}
+            Err(err) => eprintln!("Failed rendering: {}", err),
+        }
+
+        thread::sleep(Duration::from_millis(interval));
+    }
+}
+
+pub fn pcompare(args: &mut env::Args) -> Result<(), PConvertError> {
+    let first = match args.next() {
+        Some(name) => name,
+        None => {
+            return Err(PConvertError::ArgumentError(
+                "'file_a' not specified".to_string(),
+            ))
+        }
+    };
+
+    let second = match args.next() {
+        Some(name) => name,
+        None => {
+            return Err(PConvertError::ArgumentError(
+                "'file_b' not specified".to_string(),
+            ))
+        }
+    };
+
+    let mut tolerance = 0;
+    let mut heatmap = None;
+    let mut limits = Limits::default();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--tolerance" => {
+                let value = next_value(args, &arg)?;
+                tolerance = value.parse::<u8>().map_err(|_| {
+                    PConvertError::invalid_parameter(
+                        "tolerance",
+                        format!("'{}' is not a number from 0 to 255", value),
+                    )
+                })?;
+            }
+            "--heatmap" => heatmap = Some(next_value(args, &arg)?),
+            flag if parse_limit(&mut limits, flag, args)? => (),
+            _ => {
+                return Err(PConvertError::ArgumentError(format!(
+                    "unknown argument '{}'",
+                    arg
+                )))
+            }
+        }
+    }
+
+    let first_image = read_image_from_file_with_limits(first, false, &limits)?;
+    let second_image = read_image_from_file_with_limits(second, false, &limits)?;
+    let comparison = compare(&first_image, &second_image)?;
+    println!("Max difference: {}", comparison.max_difference());
+    println!("Mean difference: {:.4}", comparison.mean_difference());
+    println!("PSNR: {:.2}dB", comparison.psnr());
+    println!("SSIM: {:.4}", comparison.ssim());
+
+    if let Some(heatmap) = heatmap {
+        let image = diff_heatmap(&first_image, &second_image)?;
+        write_png_to_file(heatmap, &image, Compression::FAST, FilterType::NoFilter)?;
+    }
+
+    // exits with a failure status when the images differ beyond the
+    // tolerance so that the command may be used in scripts (e.g. CI)
+    if !comparison.matches(tolerance) {
+        println!("Images differ (tolerance {})", tolerance);
+        std::process::exit(1);
+    }
+    println!("Images match (tolerance {})", tolerance);
+    Ok(())
+}
+
+pub fn poptimize(args: &mut env::Args) -> Result<(), PConvertError> {
+    let file_in = match args.next() {
+        Some(name) => name,
+        None => {
+            return Err(PConvertError::ArgumentError(
+                "'file_in' not specified".to_string(),
+            ))
+        }
+    };
+
+    // the image is optimized in place unless an output file is given
+    let mut file_out = None;
+    let mut limits = Limits::default();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            flag if parse_limit(&mut limits, flag, args)? => (),
+            flag if flag.starts_with("--") || file_out.is_some() => {
+                return Err(PConvertError::ArgumentError(format!(
+                    "unknown argument '{}'",
+                    arg
+                )))
+            }
+            _ => file_out = Some(arg),
+        }
+    }
 
+    let file_out = file_out.unwrap_or_else(|| file_in.clone());
+    let report = optimize_file(&file_in, &file_out, &limits)?;
+    println!("{}: {}", file_out, report);
     Ok(())
 }
 
@@ -97,7 +672,7 @@ pub fn pbenchmark(args: &mut env::Args) -> Result<(), PConvertError> {
         }
         None => {
             return Err(PConvertError::ArgumentError(
-                "ArgumentError: 'directory' not specified".to_string(),
+                "'directory' not specified".to_string(),
             ))
         }
     };
@@ -126,7 +701,7 @@ pub fn pbenchmark(args: &mut env::Args) -> Result<(), PConvertError> {
                 if run_parallel {
                     compose_parallel(
                         &dir,
-                        BlendAlgorithm::from_str(algorithm).unwrap(),
+                        BlendAlgorithm::from_str(algorithm)?,
                         &Background::Alpha,
                         *compression,
                         *filter,
@@ -135,7 +710,7 @@ pub fn pbenchmark(args: &mut env::Args) -> Result<(), PConvertError> {
                 } else {
                     compose(
                         &dir,
-                        BlendAlgorithm::from_str(algorithm).unwrap(),
+                        BlendAlgorithm::from_str(algorithm)?,
                         &Background::Alpha,
                         *compression,
                         *filter,
@@ -172,3 +747,365 @@ pub fn pversion() {
     );
     println!("Copyright (c) 2008-2022 Platforme International Limited. All rights reserved.");
 }
+
+fn scene_modified_time(scene: &Option<String>) -> Option<SystemTime> {
+    scene
+        .as_ref()
+        .and_then(|scene| fs::metadata(scene).ok())
+        .and_then(|metadata| metadata.modified().ok())
+}
+
+/// Layers and blending options shared by the commands that
+/// compose a stack of layers (e.g. `blend` and `watch`).
+struct BlendArgs {
+    files_in: Vec<String>,
+    algorithms: Vec<(BlendAlgorithm, Option<BlendAlgorithmParams>)>,
+    num_threads: usize,
+    compression: Compression,
+    filter: FilterType,
+    dimension_policy: DimensionPolicy,
+    resize: Option<ResizeOptions>,
+    layers_options: Vec<LayerOptions>,
+}
+
+/// Alpha channel options applied to the output image
+/// (e.g. `--trim`), before it is resized.
+#[derive(Default)]
+struct AlphaArgs {
+    trim: bool,
+    alpha_threshold: Option<u8>,
+    matte: Option<[u8; 3]>,
+}
+
+impl AlphaArgs {
+    /// Parses the flag (and its value) in case it is an alpha
+    /// channel option, returning whether it was consumed.
+    fn parse(&mut self, flag: &str, args: &mut env::Args) -> Result<bool, PConvertError> {
+        match flag {
+            "--trim" => self.trim = true,
+            "--alpha-threshold" => {
+                let value = next_value(args, flag)?;
+                self.alpha_threshold = Some(value.parse::<u8>().map_err(|_| {
+                    PConvertError::invalid_parameter(
+                        "alpha_threshold",
+                        format!("'{}' is not a number between 0 and 255", value),
+                    )
+                })?);
+            }
+            "--remove-matte" => {
+                let value = next_value(args, flag)?;
+                self.matte = Some(parse_hex_color(&value).ok_or_else(|| {
+                    PConvertError::invalid_parameter(
+                        "remove_matte",
+                        format!("'{}' is not a '#rrggbb' colour", value),
+                    )
+                })?);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Whether no alpha channel option was given.
+    fn is_empty(&self) -> bool {
+        !self.trim && self.alpha_threshold.is_none() && self.matte.is_none()
+    }
+
+    /// Removes the matte, thresholds the alpha channel and trims
+    /// the image (in this order), as requested.
+    fn apply(&self, mut image: ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        if let Some(matte) = self.matte {
+            remove_matte(&mut image, matte);
+        }
+        if let Some(threshold) = self.alpha_threshold {
+            threshold_alpha(&mut image, threshold);
+        }
+        match self.trim {
+            true => trim(&image, 0),
+            false => image,
+        }
+    }
+}
+
+/// Output format options (e.g. `--format`), the format is otherwise
+/// inferred from the extension of the output file.
+#[derive(Default)]
+struct OutputArgs {
+    format: Option<String>,
+    quality: Option<i64>,
+    background: Option<String>,
+    colors: Option<i64>,
+    dither: Option<bool>,
+    optimize: Option<bool>,
+}
+
+impl OutputArgs {
+    /// Parses the flag (and its value) in case it is an output
+    /// format option, returning whether it was consumed.
+    fn parse(&mut self, flag: &str, args: &mut env::Args) -> Result<bool, PConvertError> {
+        match flag {
+            "--format" => self.format = Some(next_value(args, flag)?),
+            "--quality" => {
+                let value = next_value(args, flag)?;
+                self.quality = Some(value.parse::<i64>().map_err(|_| {
+                    PConvertError::invalid_parameter(
+                        "quality",
+                        format!("'{}' is not a number", value),
+                    )
+                })?);
+            }
+            "--background" => self.background = Some(next_value(args, flag)?),
+            "--colors" => {
+                let value = next_value(args, flag)?;
+                self.colors = Some(value.parse::<i64>().map_err(|_| {
+                    PConvertError::invalid_parameter(
+                        "colors",
+                        format!("'{}' is not a number", value),
+                    )
+                })?);
+            }
+            "--dither" => self.dither = Some(true),
+            "--optimize" => self.optimize = Some(true),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Builds the output options of the given output file.
+    fn build(&self, file_out: &str) -> Result<OutputOptions, PConvertError> {
+        OutputOptions::from_options(OutputValues {
+            format: self.format.as_deref(),
+            quality: self.quality,
+            background: self.background.as_deref(),
+            colors: self.colors,
+            dither: self.dither,
+            optimize: self.optimize,
+            path: Some(file_out),
+        })
+    }
+}
+
+/// Parses the flag (and its value) in case it is a resource limit
+/// (e.g. `--max-pixels`), returning whether it was consumed.
+fn parse_limit(
+    limits: &mut Limits,
+    flag: &str,
+    args: &mut env::Args,
+) -> Result<bool, PConvertError> {
+    let name = match flag.strip_prefix("--") {
+        Some(name) => name.replace('-', "_"),
+        None => return Ok(false),
+    };
+    if !LIMIT_NAMES.contains(&&name[..]) {
+        return Ok(false);
+    }
+    let value = next_value(args, flag)?;
+    let value = value.parse::<u64>().map_err(|_| {
+        PConvertError::invalid_parameter(&name, format!("'{}' is not a number", value))
+    })?;
+    limits.set(&name, value)?;
+    Ok(true)
+}
+
+/// Parses the layers and blending options from the remaining command line
+/// arguments, any flag not known is first offered to the `extra` handler
+/// which returns whether it consumed the flag.
+fn parse_blend_args(
+    args: &mut env::Args,
+    mut extra: impl FnMut(&str, &mut env::Args) -> Result<bool, PConvertError>,
+) -> Result<BlendArgs, PConvertError> {
+    let mut files_in = Vec::new();
+    let mut algorithm = BlendAlgorithm::Multiplicative;
+    let mut layer_algorithms = Vec::new();
+    let mut params = Vec::new();
+    let mut num_threads = 0;
+    let mut compression = None;
+    let mut strategy = None;
+    let mut filter = FilterType::NoFilter;
+    let mut dimension_policy = None;
+    let mut gravity = None;
+    let mut size = None;
+    let mut scale = None;
+    let mut resize_filter = None;
+    let mut layer_transforms = Vec::new();
+    let mut layer_effects = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--algorithm" => algorithm = BlendAlgorithm::from_str(&next_value(args, &arg)?)?,
+            "--layer-algorithm" => {
+                let value = next_value(args, &arg)?;
+                let (layer, algorithm) = split_layer_value(&value, '=')?;
+                layer_algorithms.push((layer, BlendAlgorithm::from_str(algorithm)?));
+            }
+            "--transform" => {
+                let value = next_value(args, &arg)?;
+                let (layer, transform) = split_layer_value(&value, '=')?;
+                layer_transforms.push((layer, Transform::from_str(transform)?));
+            }
+            "--effect" => {
+                let value = next_value(args, &arg)?;
+                let (layer, effect) = split_layer_value(&value, '=')?;
+                layer_effects.push((layer, Effect::from_str(effect)?));
+            }
+            "--param" => {
+                let value = next_value(args, &arg)?;
+                let (layer, param) = match value.split_once(':') {
+                    Some(_) => {
+                        let (layer, param) = split_layer_value(&value, ':')?;
+                        (Some(layer), param)
+                    }
+                    None => (None, &value[..]),
+                };
+                let (key, value) = param.split_once('=').ok_or_else(|| {
+                    PConvertError::ArgumentError(format!(
+                        "invalid parameter '{}' (expected <key>=<value>)",
+                        param
+                    ))
+                })?;
+                params.push((layer, key.to_string(), parse_value(value)));
+            }
+            "--threads" => num_threads = parse_num_threads(&next_value(args, &arg)?)?,
+            "--compression" => compression = Some(next_value(args, &arg)?),
+            "--strategy" => strategy = Some(next_value(args, &arg)?),
+            "--filter" => filter = parse_filter(&next_value(args, &arg)?)?,
+            "--dimension-policy" => dimension_policy = Some(next_value(args, &arg)?),
+            "--gravity" => gravity = Some(next_value(args, &arg)?),
+            "--size" => size = Some(next_value(args, &arg)?),
+            "--scale" => scale = Some(parse_scale(&next_value(args, &arg)?)?),
+            "--resize-filter" => resize_filter = Some(next_value(args, &arg)?),
+            flag if flag.starts_with("--") => {
+                if !extra(flag, args)? {
+                    return Err(PConvertError::ArgumentError(format!(
+                        "unknown argument '{}'",
+                        flag
+                    )));
+                }
+            }
+            _ => files_in.push(arg),
+        }
+    }
+
+    let num_layers = files_in.len();
+    if num_layers < 1 {
+        return Err(PConvertError::ArgumentError(
+            "at least one 'file_in' must be specified".to_string(),
+        ));
+    }
+
+    // builds the algorithm (and parameters) used in each blending operation, the
+    // layer numbers are 1-based and the first layer (the base) is never blended
+    let mut algorithms: Vec<(BlendAlgorithm, Option<BlendAlgorithmParams>)> =
+        vec![(algorithm, None); num_layers - 1];
+    for (layer, algorithm) in layer_algorithms {
+        algorithms[blend_index(layer, num_layers)?].0 = algorithm;
+    }
+    let mut layers_options = vec![LayerOptions::default(); num_layers];
+    for (layer, transform) in layer_transforms {
+        layers_options[layer_index(layer, num_layers)?]
+            .transforms
+            .push(transform);
+    }
+    for (layer, effect) in layer_effects {
+        layers_options[layer_index(layer, num_layers)?]
+            .effects
+            .push(effect);
+    }
+    for (layer, key, value) in params {
+        let indexes = match layer {
+            Some(layer) => vec![blend_index(layer, num_layers)?],
+            None => (0..num_layers - 1).collect(),
+        };
+        for index in indexes {
+            algorithms[index]
+                .1
+                .get_or_insert_with(BlendAlgorithmParams::new)
+                .insert(key.clone(), value.clone());
+        }
+    }
+
+    Ok(BlendArgs {
+        files_in,
+        algorithms,
+        num_threads,
+        compression: Compression::from_options(
+            compression.as_deref().map(CompressionLevel::Name),
+            strategy.as_deref(),
+        )?,
+        filter,
+        dimension_policy: DimensionPolicy::from_options(
+            dimension_policy.as_deref(),
+            gravity.as_deref(),
+        )?,
+        resize: ResizeOptions::from_options(size.as_deref(), scale, resize_filter.as_deref())?,
+        layers_options,
+    })
+}
+
+fn next_value(args: &mut env::Args, flag: &str) -> Result<String, PConvertError> {
+    args.next()
+        .ok_or_else(|| PConvertError::ArgumentError(format!("'{}' requires a value", flag)))
+}
+
+fn parse_num_threads(value: &str) -> Result<usize, PConvertError> {
+    value.parse::<usize>().map_err(|_| {
+        PConvertError::invalid_parameter("threads", format!("'{}' is not a number", value))
+    })
+}
+
+fn parse_scale(value: &str) -> Result<f64, PConvertError> {
+    value.parse::<f64>().map_err(|_| {
+        PConvertError::invalid_parameter("scale", format!("'{}' is not a number", value))
+    })
+}
+
+/// Parses a command line parameter value, trying (in order) boolean,
+/// integer and floating point interpretations before falling back to a string.
+fn parse_value(value: &str) -> Value {
+    if let Ok(boolean) = value.parse::<bool>() {
+        Value::Bool(boolean)
+    } else if let Ok(long) = value.parse::<i64>() {
+        Value::Long(long)
+    } else if let Ok(float) = value.parse::<f64>() {
+        Value::Float(float)
+    } else {
+        Value::Str(value.to_string())
+    }
+}
+
+/// Splits a `<layer><separator><value>` argument into the layer number
+/// and the remaining value.
+fn split_layer_value(value: &str, separator: char) -> Result<(usize, &str), PConvertError> {
+    value
+        .split_once(separator)
+        .and_then(|(layer, value)| layer.parse::<usize>().ok().map(|layer| (layer, value)))
+        .ok_or_else(|| {
+            PConvertError::ArgumentError(format!(
+                "invalid layer value '{}' (expected <layer>{}<value>)",
+                value, separator
+            ))
+        })
+}
+
+/// Converts a 1-based layer number into the index of the layer.
+fn layer_index(layer: usize, num_layers: usize) -> Result<usize, PConvertError> {
+    if layer < 1 || layer > num_layers {
+        return Err(PConvertError::ArgumentError(format!(
+            "invalid layer {} (must be between 1 and {})",
+            layer, num_layers
+        )));
+    }
+    Ok(layer - 1)
+}
+
+/// Converts a 1-based layer number into the index of the blending
+/// operation that places that layer on top of the composition.
+fn blend_index(layer: usize, num_layers: usize) -> Result<usize, PConvertError> {
+    if layer < 2 || layer > num_layers {
+        return Err(PConvertError::ArgumentError(format!(
+            "invalid layer {} (must be between 2 and {})",
+            layer, num_layers
+        )));
+    }
+    Ok(layer - 2)
+}
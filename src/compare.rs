@@ -0,0 +1,223 @@
+//! Comparison of images (e.g. blended outputs and reference renders)
+//! with difference metrics (max/mean difference, PSNR and SSIM) and
+//! heatmaps of the differences.
+
+use crate::blending::demultiply_image;
+use crate::errors::PConvertError;
+use image::{ImageBuffer, Rgba};
+
+/// Side of the (square) windows used to compute the SSIM.
+const SSIM_WINDOW: u32 = 8;
+
+/// Distance between the consecutive SSIM windows.
+const SSIM_STRIDE: u32 = 4;
+
+/// Metrics of the difference between two images, computed over the
+/// premultiplied channels (so that the colour of fully transparent
+/// pixels, which is not visible, does not count as a difference).
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    max_difference: u8,
+    mean_difference: f64,
+    psnr: f64,
+    ssim: f64,
+}
+
+impl Comparison {
+    /// Largest absolute difference of any channel of any pixel.
+    pub fn max_difference(&self) -> u8 {
+        self.max_difference
+    }
+
+    /// Mean absolute difference of the channels of every pixel.
+    pub fn mean_difference(&self) -> f64 {
+        self.mean_difference
+    }
+
+    /// Peak signal-to-noise ratio in decibels (infinite
+    /// when the images are the same).
+    pub fn psnr(&self) -> f64 {
+        self.psnr
+    }
+
+    /// Mean structural similarity index (`1.0` when the images are the same).
+    pub fn ssim(&self) -> f64 {
+        self.ssim
+    }
+
+    /// Whether the images are the same, allowing for channels that
+    /// differ by up to `tolerance` (e.g. from floating point rounding).
+    pub fn matches(&self, tolerance: u8) -> bool {
+        self.max_difference <= tolerance
+    }
+}
+
+/// Compares two images (with straight alpha) of the same dimensions.
+///
+/// # Arguments
+///
+/// * `first` - The first image buffer (e.g. the reference).
+/// * `second` - The second image buffer, must have the dimensions of the first.
+///
+/// # Examples
+///
+/// ```no_run
+/// use pconvert_rust::compare::compare;
+/// use pconvert_rust::utils::read_png_from_file;
+///
+/// let reference = read_png_from_file("reference.png".to_string(), false).unwrap();
+/// let result = read_png_from_file("result.png".to_string(), false).unwrap();
+/// let comparison = compare(&reference, &result).unwrap();
+/// assert!(comparison.matches(1));
+/// ```
+pub fn compare(
+    first: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    second: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> Result<Comparison, PConvertError> {
+    check_dimensions(first, second)?;
+    let (first, second) = (premultiplied(first), premultiplied(second));
+
+    let mut max_difference = 0;
+    let mut sum = 0.0;
+    let mut squared_sum = 0.0;
+    for (first, second) in first.as_raw().iter().zip(second.as_raw()) {
+        let difference = first.abs_diff(*second);
+        max_difference = max_difference.max(difference);
+        sum += difference as f64;
+        squared_sum += (difference as f64).powi(2);
+    }
+
+    let count = first.as_raw().len().max(1) as f64;
+    let mse = squared_sum / count;
+    let psnr = match mse > 0.0 {
+        true => 10.0 * (255.0f64.powi(2) / mse).log10(),
+        false => f64::INFINITY,
+    };
+    Ok(Comparison {
+        max_difference,
+        mean_difference: sum / count,
+        psnr,
+        ssim: ssim(&first, &second),
+    })
+}
+
+/// Builds a heatmap of the differences between two images, the pixels
+/// that are the same show the first image (in dark gray) and the ones
+/// that differ go from red (smaller differences) to yellow and white
+/// (the largest difference between the images).
+///
+/// # Arguments
+///
+/// * `first` - The first image buffer (e.g. the reference).
+/// * `second` - The second image buffer, must have the dimensions of the first.
+pub fn diff_heatmap(
+    first: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    second: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    check_dimensions(first, second)?;
+    let (first, second) = (premultiplied(first), premultiplied(second));
+    let differences = first
+        .pixels()
+        .zip(second.pixels())
+        .map(|(first, second)| {
+            first
+                .0
+                .iter()
+                .zip(second.0)
+                .map(|(first, second)| first.abs_diff(second))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let max_difference = differences.iter().copied().max().unwrap_or(0).max(1) as f32;
+
+    let mut heatmap = ImageBuffer::new(first.width(), first.height());
+    for ((pixel, difference), first) in heatmap.pixels_mut().zip(differences).zip(first.pixels()) {
+        *pixel = match difference {
+            0 => {
+                let luma =
+                    0.299 * first[0] as f32 + 0.587 * first[1] as f32 + 0.114 * first[2] as f32;
+                let gray = (luma * 0.25).round() as u8;
+                Rgba([gray, gray, gray, 255])
+            }
+            _ => {
+                let level = 0.25 + 0.75 * difference as f32 / max_difference;
+                let channel = |offset: f32| ((level * 3.0 - offset).clamp(0.0, 1.0) * 255.0) as u8;
+                Rgba([channel(0.0), channel(1.0), channel(2.0), 255])
+            }
+        };
+    }
+    Ok(heatmap)
+}
+
+fn check_dimensions(
+    first: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    second: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> Result<(), PConvertError> {
+    match first.dimensions() == second.dimensions() {
+        true => Ok(()),
+        false => Err(PConvertError::DimensionMismatchError {
+            layer: 1,
+            expected: first.dimensions(),
+            found: second.dimensions(),
+        }),
+    }
+}
+
+fn premultiplied(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut image = image.clone();
+    demultiply_image(&mut image);
+    image
+}
+
+/// Mean structural similarity index of every channel over the (overlapping)
+/// windows of the images, the whole image is used as a single window when
+/// it is smaller than a window.
+fn ssim(first: &ImageBuffer<Rgba<u8>, Vec<u8>>, second: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (width, height) = first.dimensions();
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+    let (window_width, window_height) = (width.min(SSIM_WINDOW), height.min(SSIM_WINDOW));
+    let mut total = 0.0;
+    let mut count = 0;
+    for y in (0..=height - window_height).step_by(SSIM_STRIDE as usize) {
+        for x in (0..=width - window_width).step_by(SSIM_STRIDE as usize) {
+            // sums (and sums of the products) of the channels of the
+            // window, from which the means, variances and covariance follow
+            let mut sums = [[0.0f64; 5]; 4];
+            for window_y in y..y + window_height {
+                for window_x in x..x + window_width {
+                    let (first, second) = (
+                        first.get_pixel(window_x, window_y),
+                        second.get_pixel(window_x, window_y),
+                    );
+                    for (channel, sums) in sums.iter_mut().enumerate() {
+                        let (first, second) = (first[channel] as f64, second[channel] as f64);
+                        sums[0] += first;
+                        sums[1] += second;
+                        sums[2] += first * first;
+                        sums[3] += second * second;
+                        sums[4] += first * second;
+                    }
+                }
+            }
+            let size = (window_width * window_height) as f64;
+            for sums in sums {
+                let mean_first = sums[0] / size;
+                let mean_second = sums[1] / size;
+                let variance_first = sums[2] / size - mean_first * mean_first;
+                let variance_second = sums[3] / size - mean_second * mean_second;
+                let covariance = sums[4] / size - mean_first * mean_second;
+                total += ((2.0 * mean_first * mean_second + C1) * (2.0 * covariance + C2))
+                    / ((mean_first.powi(2) + mean_second.powi(2) + C1)
+                        * (variance_first + variance_second + C2));
+                count += 1;
+            }
+        }
+    }
+    total / count as f64
+}
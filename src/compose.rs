@@ -2,11 +2,12 @@ use crate::benchmark::Benchmark;
 use crate::blending::{
     blend_images, get_blending_algorithm, is_algorithm_multiplied, multiply_image, BlendAlgorithm,
 };
+use crate::encoding::Compression;
 use crate::errors::PConvertError;
 use crate::parallelism::{ResultMessage, ThreadPool};
 use crate::utils::{read_png_from_file, write_png_parallel, write_png_to_file};
 use image::codecs::png::{CompressionType, FilterType};
-use image::Rgba;
+use image::{ImageBuffer, Rgba};
 use std::fmt::{Display, Formatter};
 use std::{fmt, sync::mpsc::Receiver};
 
@@ -39,10 +40,11 @@ pub fn compose(
     dir: &str,
     algorithm: BlendAlgorithm,
     background: &Background,
-    compression: CompressionType,
+    compression: impl Into<Compression>,
     filter: FilterType,
     benchmark: &mut Benchmark,
 ) -> Result<String, PConvertError> {
+    let compression = compression.into();
     let demultiply = is_algorithm_multiplied(&algorithm);
 
     let algorithm_fn = get_blending_algorithm(&algorithm);
@@ -50,7 +52,7 @@ pub fn compose(
     // reads one PNG at the time and blends it with the current result
     // these values are hardcoded by the multiple layer files
     let background_file = format!("background_{}.png", background);
-    let png_file_names = vec![
+    let png_file_names = [
         "sole.png",
         "back.png",
         "front.png",
@@ -67,22 +69,19 @@ pub fn compose(
         read_png_from_file(format!("{}sole.png", dir), demultiply)
     })?;
 
-    let mut bot =
-        png_paths[..png_file_names.len() - 1]
-            .iter()
-            .fold(top, |mut composition, path| {
-                let layer = benchmark
-                    .execute(Benchmark::add_read_png_time, || {
-                        read_png_from_file(path.clone(), demultiply)
-                    })
-                    .unwrap();
-
-                benchmark.execute(Benchmark::add_blend_time, || {
-                    blend_images(&mut composition, &layer, &algorithm_fn, &None)
-                });
+    // as in the original P(NG)Convert, the sole starts the composition
+    // and is blended onto itself as the first of the layers (unlike in
+    // `compose_parallel`, which starts blending with the layer above it)
+    let mut bot = top;
+    for path in &png_paths[..png_file_names.len() - 1] {
+        let layer = benchmark.execute(Benchmark::add_read_png_time, || {
+            read_png_from_file(path.clone(), demultiply)
+        })?;
 
-                composition
-            });
+        benchmark.execute(Benchmark::add_blend_time, || {
+            blend_images(&mut bot, &layer, &algorithm_fn, &None)
+        });
+    }
 
     if demultiply {
         benchmark.execute(Benchmark::add_blend_time, || multiply_image(&mut bot));
@@ -98,8 +97,11 @@ pub fn compose(
 
     // writes the final composition to the file system
     let file_name = format!(
-        "result_{}_{}_{:#?}_{:#?}.png",
-        algorithm, background, compression, filter
+        "result_{}_{}_{}_{:#?}.png",
+        algorithm,
+        background,
+        compression_name(compression),
+        filter
     );
     let file_out = format!("{}{}", dir, file_name);
     benchmark.execute(Benchmark::add_write_png_time, || {
@@ -116,10 +118,11 @@ pub fn compose_parallel(
     dir: &str,
     algorithm: BlendAlgorithm,
     background: &Background,
-    compression: CompressionType,
+    compression: impl Into<Compression>,
     filter: FilterType,
     benchmark: &mut Benchmark,
 ) -> Result<String, PConvertError> {
+    let compression = compression.into();
     let demultiply = is_algorithm_multiplied(&algorithm);
     let algorithm_fn = get_blending_algorithm(&algorithm);
 
@@ -129,7 +132,7 @@ pub fn compose_parallel(
     // sends the PNG reading tasks to multiple threads
     // these values are hardcoded by the multiple layer files
     let background_file = format!("background_{}.png", background);
-    let png_file_names = vec![
+    let png_file_names = [
         "sole.png",
         "back.png",
         "front.png",
@@ -149,20 +152,12 @@ pub fn compose_parallel(
     // blending phase, will run the multiple layers operation
     // as expected by the proper execution
     let mut bot = benchmark.execute(Benchmark::add_read_png_time, || {
-        if let Ok(ResultMessage::ImageResult(result)) = result_channels[0].recv() {
-            result
-        } else {
-            panic!("failure reading 'sole.png'")
-        }
+        receive_image(&result_channels[0], png_file_names[0])
     })?;
 
     for i in 1..=3 {
         let top = benchmark.execute(Benchmark::add_read_png_time, || {
-            if let Ok(ResultMessage::ImageResult(result)) = result_channels[i].recv() {
-                result
-            } else {
-                panic!("failure reading '{}'", png_file_names[i])
-            }
+            receive_image(&result_channels[i], png_file_names[i])
         })?;
         benchmark.execute(Benchmark::add_blend_time, || {
             blend_images(&mut bot, &top, &algorithm_fn, &None)
@@ -174,11 +169,7 @@ pub fn compose_parallel(
     }
 
     let mut composition = benchmark.execute(Benchmark::add_read_png_time, || {
-        if let Ok(ResultMessage::ImageResult(result)) = result_channels[4].recv() {
-            result
-        } else {
-            panic!("failure reading '{}'", background_file)
-        }
+        receive_image(&result_channels[4], &background_file)
     })?;
     benchmark.execute(Benchmark::add_blend_time, || {
         blend_images(&mut composition, &bot, &algorithm_fn, &None)
@@ -187,8 +178,11 @@ pub fn compose_parallel(
     // writes the final composition PNG to the output file,
     // this is considered to be the most expensive operation
     let file_name = format!(
-        "result_{}_{}_{:#?}_{:#?}.png",
-        algorithm, background, compression, filter
+        "result_{}_{}_{}_{:#?}.png",
+        algorithm,
+        background,
+        compression_name(compression),
+        filter
     );
     let file_out = format!("{}{}", dir, file_name);
     benchmark.execute(Benchmark::add_write_png_time, || {
@@ -204,3 +198,38 @@ pub fn apply_blue_filter(pixel: &mut Rgba<u8>) {
     pixel[0] = 0;
     pixel[1] = pixel[2];
 }
+
+/// Waits for the result of reading the given PNG file in the thread pool.
+fn receive_image(
+    result_channel: &Receiver<ResultMessage>,
+    file_name: &str,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    match result_channel.recv() {
+        Ok(ResultMessage::ImageResult(result)) => result,
+        _ => Err(PConvertError::ThreadPoolError(format!(
+            "failure reading '{}'",
+            file_name
+        ))),
+    }
+}
+
+/// Name of the compression in the file names of the compositions, the
+/// (`Debug`) name of the compression type it comes from, as in the names
+/// from before the numeric levels (e.g. `Fast`), or its level otherwise.
+fn compression_name(compression: Compression) -> String {
+    #[allow(deprecated)]
+    let compression_types = [
+        CompressionType::Default,
+        CompressionType::Fast,
+        CompressionType::Best,
+        CompressionType::Huffman,
+        CompressionType::Rle,
+    ];
+    match compression_types
+        .iter()
+        .find(|compression_type| Compression::from(**compression_type) == compression)
+    {
+        Some(compression_type) => format!("{:?}", compression_type),
+        None => compression.to_string(),
+    }
+}
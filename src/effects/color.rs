@@ -0,0 +1,230 @@
+//! Colour adjustment effects (brightness, contrast, hue, saturation,
+//! levels, tint, invert and grayscale), applied in straight alpha and
+//! leaving the alpha channel untouched, as well as the alpha threshold
+//! and matte removal effects.
+
+use super::{ImageEffect, PixelEffect};
+use crate::alpha::{remove_matte, threshold_alpha};
+use crate::errors::PConvertError;
+use image::{ImageBuffer, Rgba};
+
+/// Adds `amount` (from `-1.0` to `1.0`) of white to every channel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Brightness {
+    pub amount: f32,
+}
+
+impl PixelEffect for Brightness {
+    fn apply_pixel(&self, pixel: &mut Rgba<u8>) {
+        let offset = self.amount * 255.0;
+        map_channels(pixel, |channel| channel + offset);
+    }
+}
+
+/// Scales the distance of every channel to the middle gray by
+/// `factor` (`1.0` keeps the layer unchanged, `0.0` makes it gray).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contrast {
+    pub factor: f32,
+}
+
+impl PixelEffect for Contrast {
+    fn apply_pixel(&self, pixel: &mut Rgba<u8>) {
+        map_channels(pixel, |channel| (channel - 127.5) * self.factor + 127.5);
+    }
+}
+
+/// Rotates the hue of every pixel by `degrees`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hue {
+    pub degrees: f32,
+}
+
+impl PixelEffect for Hue {
+    fn apply_pixel(&self, pixel: &mut Rgba<u8>) {
+        let (hue, saturation, lightness) = rgb_to_hsl(pixel[0], pixel[1], pixel[2]);
+        let hue = (hue + self.degrees / 360.0).rem_euclid(1.0);
+        let (red, green, blue) = hsl_to_rgb(hue, saturation, lightness);
+        pixel[0] = red;
+        pixel[1] = green;
+        pixel[2] = blue;
+    }
+}
+
+/// Scales the distance of every channel to the luma of the pixel by
+/// `factor` (`1.0` keeps the layer unchanged, `0.0` makes it grayscale).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Saturation {
+    pub factor: f32,
+}
+
+impl PixelEffect for Saturation {
+    fn apply_pixel(&self, pixel: &mut Rgba<u8>) {
+        let luma = luma(pixel);
+        map_channels(pixel, |channel| luma + (channel - luma) * self.factor);
+    }
+}
+
+/// Maps the `in_black` to `in_white` input range (with the `gamma`
+/// correction) into the `out_black` to `out_white` output range.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Levels {
+    pub in_black: f32,
+    pub in_white: f32,
+    pub gamma: f32,
+    pub out_black: f32,
+    pub out_white: f32,
+}
+
+impl ImageEffect for Levels {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError> {
+        // the mapping is the same for every channel, so it is
+        // computed once into a lookup table for all of the values
+        let range = (self.in_white - self.in_black).max(f32::EPSILON);
+        let mut table = [0u8; 256];
+        for (value, entry) in table.iter_mut().enumerate() {
+            let level = ((value as f32 - self.in_black) / range).clamp(0.0, 1.0);
+            let level = level.powf(1.0 / self.gamma);
+            *entry = to_channel(self.out_black + level * (self.out_white - self.out_black));
+        }
+        for pixel in image.pixels_mut() {
+            pixel[0] = table[pixel[0] as usize];
+            pixel[1] = table[pixel[1] as usize];
+            pixel[2] = table[pixel[2] as usize];
+        }
+        Ok(())
+    }
+}
+
+/// Colourises the layer with `color`, keeping the luma of each pixel,
+/// and mixes the result with the original by `amount` (`0.0` to `1.0`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tint {
+    pub color: [u8; 3],
+    pub amount: f32,
+}
+
+impl PixelEffect for Tint {
+    fn apply_pixel(&self, pixel: &mut Rgba<u8>) {
+        let luma = luma(pixel) / 255.0;
+        for (channel, color) in pixel.0.iter_mut().take(3).zip(self.color) {
+            let value = *channel as f32;
+            let tinted = color as f32 * luma;
+            *channel = to_channel(value + (tinted - value) * self.amount);
+        }
+    }
+}
+
+/// Inverts the colour channels of every pixel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invert;
+
+impl PixelEffect for Invert {
+    fn apply_pixel(&self, pixel: &mut Rgba<u8>) {
+        pixel[0] = 255 - pixel[0];
+        pixel[1] = 255 - pixel[1];
+        pixel[2] = 255 - pixel[2];
+    }
+}
+
+/// Replaces the colour channels of every pixel by its luma.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Grayscale;
+
+impl PixelEffect for Grayscale {
+    fn apply_pixel(&self, pixel: &mut Rgba<u8>) {
+        let luma = to_channel(luma(pixel));
+        pixel[0] = luma;
+        pixel[1] = luma;
+        pixel[2] = luma;
+    }
+}
+
+/// Makes the alpha channel binary (see `alpha::threshold_alpha`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlphaThreshold {
+    pub threshold: u8,
+}
+
+impl ImageEffect for AlphaThreshold {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError> {
+        threshold_alpha(image, self.threshold);
+        Ok(())
+    }
+}
+
+/// Removes the colour of the matte from the semi-transparent
+/// pixels (see `alpha::remove_matte`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoveMatte {
+    pub color: [u8; 3],
+}
+
+impl ImageEffect for RemoveMatte {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError> {
+        remove_matte(image, self.color);
+        Ok(())
+    }
+}
+
+/// Luma of the pixel (ITU-R BT.601), from `0.0` to `255.0`.
+fn luma(pixel: &Rgba<u8>) -> f32 {
+    0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32
+}
+
+fn to_channel(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+fn map_channels(pixel: &mut Rgba<u8>, mapping: impl Fn(f32) -> f32) {
+    for channel in pixel.0.iter_mut().take(3) {
+        *channel = to_channel(mapping(*channel as f32));
+    }
+}
+
+/// Converts the colour into hue, saturation and lightness (from `0.0` to `1.0`).
+fn rgb_to_hsl(red: u8, green: u8, blue: u8) -> (f32, f32, f32) {
+    let (red, green, blue) = (
+        red as f32 / 255.0,
+        green as f32 / 255.0,
+        blue as f32 / 255.0,
+    );
+    let max = red.max(green).max(blue);
+    let min = red.min(green).min(blue);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == red {
+        ((green - blue) / delta).rem_euclid(6.0)
+    } else if max == green {
+        (blue - red) / delta + 2.0
+    } else {
+        (red - green) / delta + 4.0
+    };
+    (hue / 6.0, saturation, lightness)
+}
+
+/// Converts the hue, saturation and lightness (from `0.0` to `1.0`) into a colour.
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (u8, u8, u8) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue * 6.0;
+    let x = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+    let (red, green, blue) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let offset = lightness - chroma / 2.0;
+    (
+        to_channel((red + offset) * 255.0),
+        to_channel((green + offset) * 255.0),
+        to_channel((blue + offset) * 255.0),
+    )
+}
@@ -0,0 +1,383 @@
+//! Convolution effects (Gaussian and box blur, unsharp mask) and the
+//! layer styles built on them (drop shadow and outer glow).
+//!
+//! Convolutions are done in premultiplied alpha, so that the colour of
+//! transparent pixels does not bleed into the visible ones, and larger
+//! kernels are split by bands of rows across the threads of a pool.
+
+use super::ImageEffect;
+use crate::errors::PConvertError;
+use crate::limits::Limits;
+#[cfg(not(feature = "wasm-extension"))]
+use crate::parallelism::{ResultMessage, ThreadPool};
+use crate::resize::{demultiplied, premultiplied};
+use image::{ImageBuffer, Rgba};
+use std::sync::Arc;
+#[cfg(not(feature = "wasm-extension"))]
+use std::sync::OnceLock;
+
+/// Half size of the kernel from which the convolution passes
+/// are executed in multiple threads.
+#[cfg(not(feature = "wasm-extension"))]
+const PARALLEL_RADIUS: usize = 8;
+
+/// Number of buffers of premultiplied channels (of 16 bytes per pixel)
+/// alive at once while a convolution effect is applied.
+const CHANNELS_BUFFERS: u64 = 4;
+
+/// Thread pool of the convolution passes (with a worker per available
+/// thread), kept apart from the pools of the compositions, whose tasks
+/// wait for these passes, or `None` when it could not be created.
+#[cfg(not(feature = "wasm-extension"))]
+static THREAD_POOL: OnceLock<Option<ThreadPool>> = OnceLock::new();
+
+/// Gaussian blur with the given `radius` (the standard deviation
+/// of the Gaussian function, in pixels).
+#[derive(Clone, Debug, PartialEq)]
+pub struct GaussianBlur {
+    pub radius: f32,
+}
+
+impl ImageEffect for GaussianBlur {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError> {
+        self.apply_with_limits(image, &Limits::default())
+    }
+
+    fn apply_with_limits(
+        &self,
+        image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+        limits: &Limits,
+    ) -> Result<(), PConvertError> {
+        let (width, height) = image.dimensions();
+        check_channels(width, height, limits)?;
+        let kernel = gaussian_kernel(self.radius);
+        let blurred = convolve(Arc::new(premultiplied(image)), width, height, &kernel)?;
+        *image = to_image(width, height, &blurred);
+        Ok(())
+    }
+}
+
+/// Box blur averaging the `2 * radius + 1` pixels around each pixel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoxBlur {
+    pub radius: u32,
+}
+
+impl ImageEffect for BoxBlur {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError> {
+        self.apply_with_limits(image, &Limits::default())
+    }
+
+    fn apply_with_limits(
+        &self,
+        image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+        limits: &Limits,
+    ) -> Result<(), PConvertError> {
+        let (width, height) = image.dimensions();
+        check_channels(width, height, limits)?;
+        let size = self.radius as usize * 2 + 1;
+        let kernel = vec![1.0 / size as f32; size];
+        let blurred = convolve(Arc::new(premultiplied(image)), width, height, &kernel)?;
+        *image = to_image(width, height, &blurred);
+        Ok(())
+    }
+}
+
+/// Unsharp mask, adds `amount` times the difference between the layer
+/// and its Gaussian blur (with `radius`) whenever such difference is
+/// above `threshold`, the alpha channel is kept as it is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnsharpMask {
+    pub radius: f32,
+    pub amount: f32,
+    pub threshold: f32,
+}
+
+impl ImageEffect for UnsharpMask {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError> {
+        self.apply_with_limits(image, &Limits::default())
+    }
+
+    fn apply_with_limits(
+        &self,
+        image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+        limits: &Limits,
+    ) -> Result<(), PConvertError> {
+        let (width, height) = image.dimensions();
+        check_channels(width, height, limits)?;
+        let kernel = gaussian_kernel(self.radius);
+        let channels = Arc::new(premultiplied(image));
+        let blurred = convolve(channels.clone(), width, height, &kernel)?;
+        let mut channels = Arc::try_unwrap(channels).unwrap_or_else(|channels| channels.to_vec());
+        for (pixel, blurred) in channels.iter_mut().zip(blurred) {
+            let alpha = pixel[3];
+            for (channel, blurred) in pixel.iter_mut().take(3).zip(blurred) {
+                let difference = *channel - blurred;
+                if difference.abs() > self.threshold {
+                    *channel = (*channel + difference * self.amount).clamp(0.0, alpha);
+                }
+            }
+        }
+        *image = to_image(width, height, &channels);
+        Ok(())
+    }
+}
+
+/// Shadow of the layer in `color`, moved by the given offset, blurred
+/// with `radius` and with `opacity`, placed under the layer (within its
+/// bounds, so the layer may need transparent margins to fit it).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DropShadow {
+    pub offset_x: i32,
+    pub offset_y: i32,
+    pub radius: f32,
+    pub color: [u8; 3],
+    pub opacity: f32,
+}
+
+impl ImageEffect for DropShadow {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError> {
+        self.apply_with_limits(image, &Limits::default())
+    }
+
+    fn apply_with_limits(
+        &self,
+        image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+        limits: &Limits,
+    ) -> Result<(), PConvertError> {
+        let (width, height) = image.dimensions();
+        check_channels(width, height, limits)?;
+        shadow(
+            image,
+            (self.offset_x, self.offset_y),
+            self.radius,
+            self.color,
+            self.opacity,
+        )
+    }
+}
+
+/// Glow around the layer in `color`, as a shadow that is not moved,
+/// blurred with `radius` and with `opacity`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OuterGlow {
+    pub radius: f32,
+    pub color: [u8; 3],
+    pub opacity: f32,
+}
+
+impl ImageEffect for OuterGlow {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError> {
+        self.apply_with_limits(image, &Limits::default())
+    }
+
+    fn apply_with_limits(
+        &self,
+        image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+        limits: &Limits,
+    ) -> Result<(), PConvertError> {
+        let (width, height) = image.dimensions();
+        check_channels(width, height, limits)?;
+        shadow(image, (0, 0), self.radius, self.color, self.opacity)
+    }
+}
+
+/// Places a blurred copy of the alpha of the layer, in the given colour,
+/// under the layer (blending the layer over it).
+fn shadow(
+    image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    offset: (i32, i32),
+    radius: f32,
+    color: [u8; 3],
+    opacity: f32,
+) -> Result<(), PConvertError> {
+    let (width, height) = image.dimensions();
+    let mut mask = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let (source_x, source_y) = (x - offset.0 as i64, y - offset.1 as i64);
+            if source_x < 0 || source_y < 0 || source_x >= width as i64 || source_y >= height as i64
+            {
+                mask.push([0.0; 4]);
+                continue;
+            }
+            let alpha = image.get_pixel(source_x as u32, source_y as u32)[3] as f32 * opacity;
+            let factor = alpha / 255.0;
+            mask.push([
+                color[0] as f32 * factor,
+                color[1] as f32 * factor,
+                color[2] as f32 * factor,
+                alpha,
+            ]);
+        }
+    }
+    let shadow = convolve(Arc::new(mask), width, height, &gaussian_kernel(radius))?;
+
+    let mut channels = premultiplied(image);
+    for (pixel, shadow) in channels.iter_mut().zip(shadow) {
+        let coverage = 1.0 - pixel[3] / 255.0;
+        for (channel, shadow) in pixel.iter_mut().zip(shadow) {
+            *channel += shadow * coverage;
+        }
+    }
+    *image = to_image(width, height, &channels);
+    Ok(())
+}
+
+/// Checks the bytes of the buffers of premultiplied channels needed to
+/// convolve a layer with the given dimensions against the bytes limit.
+fn check_channels(width: u32, height: u32, limits: &Limits) -> Result<(), PConvertError> {
+    let pixels = width as u64 * height as u64;
+    limits.check_bytes(pixels.saturating_mul(16 * CHANNELS_BUFFERS))
+}
+
+/// Normalized Gaussian kernel for the given standard deviation,
+/// covering three times the standard deviation on each side.
+fn gaussian_kernel(radius: f32) -> Vec<f32> {
+    if radius <= 0.0 {
+        return vec![1.0];
+    }
+    let half = (radius * 3.0).ceil() as i64;
+    let weights = (-half..=half)
+        .map(|offset| (-((offset * offset) as f32) / (2.0 * radius * radius)).exp())
+        .collect::<Vec<_>>();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|weight| weight / total).collect()
+}
+
+fn to_image(width: u32, height: u32, channels: &[[f32; 4]]) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut image = ImageBuffer::new(width, height);
+    for (pixel, channels) in image.pixels_mut().zip(channels) {
+        *pixel = demultiplied(*channels);
+    }
+    image
+}
+
+/// Convolves the channels with the (symmetric) kernel along the rows
+/// and then along the columns, the pixels at the edges are extended.
+fn convolve(
+    channels: Arc<Vec<[f32; 4]>>,
+    width: u32,
+    height: u32,
+    kernel: &[f32],
+) -> Result<Vec<[f32; 4]>, PConvertError> {
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 || kernel.len() <= 1 {
+        return Ok(channels.to_vec());
+    }
+    let kernel = Arc::from(kernel);
+    let rows = convolve_pass(channels, width, height, &kernel, true)?;
+    convolve_pass(Arc::new(rows), width, height, &kernel, false)
+}
+
+/// Convolves the rows of the channels in the thread pool (each task
+/// computing a band of rows) when the kernel is large enough.
+#[cfg(not(feature = "wasm-extension"))]
+fn convolve_pass(
+    channels: Arc<Vec<[f32; 4]>>,
+    width: usize,
+    height: usize,
+    kernel: &Arc<[f32]>,
+    horizontal: bool,
+) -> Result<Vec<[f32; 4]>, PConvertError> {
+    let num_threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let parallel = kernel.len() / 2 >= PARALLEL_RADIUS && num_threads >= 2 && height >= num_threads;
+    let thread_pool = match parallel {
+        true => THREAD_POOL
+            .get_or_init(|| start_thread_pool(num_threads))
+            .as_ref(),
+        false => None,
+    };
+    let thread_pool = match thread_pool {
+        Some(thread_pool) => thread_pool,
+        None => {
+            let mut output = vec![[0.0; 4]; width * height];
+            convolve_rows(&channels, &mut output, 0, width, height, kernel, horizontal);
+            return Ok(output);
+        }
+    };
+
+    let band_rows = height.div_ceil(num_threads);
+    let result_channels = (0..height)
+        .step_by(band_rows)
+        .map(|first_row| {
+            let (channels, kernel) = (channels.clone(), kernel.clone());
+            let rows = band_rows.min(height - first_row);
+            thread_pool.execute(move || {
+                let mut band = vec![[0.0; 4]; rows * width];
+                convolve_rows(
+                    &channels, &mut band, first_row, width, height, &kernel, horizontal,
+                );
+                ResultMessage::ChannelsResult(band)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut output = Vec::with_capacity(width * height);
+    for result_channel in result_channels {
+        match result_channel.recv() {
+            Ok(ResultMessage::ChannelsResult(band)) => output.extend(band),
+            _ => {
+                return Err(PConvertError::ThreadPoolError(
+                    "failure convolving a band of rows".to_string(),
+                ))
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Creates and starts the thread pool of the convolution passes.
+#[cfg(not(feature = "wasm-extension"))]
+fn start_thread_pool(num_threads: usize) -> Option<ThreadPool> {
+    let mut thread_pool = ThreadPool::new(num_threads).ok()?;
+    thread_pool.start();
+    Some(thread_pool)
+}
+
+/// [SUPPORTED IN WASM] WASM stub; single-threaded convolution of the rows.
+#[cfg(feature = "wasm-extension")]
+fn convolve_pass(
+    channels: Arc<Vec<[f32; 4]>>,
+    width: usize,
+    height: usize,
+    kernel: &Arc<[f32]>,
+    horizontal: bool,
+) -> Result<Vec<[f32; 4]>, PConvertError> {
+    let mut output = vec![[0.0; 4]; width * height];
+    convolve_rows(&channels, &mut output, 0, width, height, kernel, horizontal);
+    Ok(output)
+}
+
+/// Convolves the band of rows starting at `first_row` into `output`,
+/// either along the rows (`horizontal`) or along the columns.
+fn convolve_rows(
+    channels: &[[f32; 4]],
+    output: &mut [[f32; 4]],
+    first_row: usize,
+    width: usize,
+    height: usize,
+    kernel: &[f32],
+    horizontal: bool,
+) {
+    let half = (kernel.len() / 2) as isize;
+    for (index, row) in output.chunks_mut(width).enumerate() {
+        let y = first_row + index;
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let mut sum = [0.0; 4];
+            for (offset, weight) in (-half..=half).zip(kernel) {
+                let sample = if horizontal {
+                    let sample_x = (x as isize + offset).clamp(0, width as isize - 1) as usize;
+                    &channels[y * width + sample_x]
+                } else {
+                    let sample_y = (y as isize + offset).clamp(0, height as isize - 1) as usize;
+                    &channels[sample_y * width + x]
+                };
+                for (channel, value) in sum.iter_mut().zip(sample) {
+                    *channel += value * weight;
+                }
+            }
+            *pixel = sum;
+        }
+    }
+}
@@ -0,0 +1,404 @@
+//! Effects (e.g. colour adjustments) applied to the layers before they
+//! are blended, either pixel by pixel or to the whole layer at once.
+
+pub mod color;
+pub mod convolution;
+
+use crate::errors::PConvertError;
+use crate::limits::Limits;
+use crate::utils::parse_hex_color;
+use color::{
+    AlphaThreshold, Brightness, Contrast, Grayscale, Hue, Invert, Levels, RemoveMatte, Saturation,
+    Tint,
+};
+use convolution::{BoxBlur, DropShadow, GaussianBlur, OuterGlow, UnsharpMask};
+use image::{ImageBuffer, Rgba};
+use serde_json::Value as JSONValue;
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+use std::result;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Maximum radius (in pixels) of the convolution effects.
+const MAX_RADIUS: f32 = 1000.0;
+
+/// Maximum offset (in pixels) of the drop shadows.
+const MAX_OFFSET: f32 = 100_000.0;
+
+/// An effect applied to the whole of a layer (with straight alpha).
+pub trait ImageEffect {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError>;
+
+    /// Applies the effect within the given limits, for the effects that
+    /// allocate buffers larger than the layer (by default, as `apply`).
+    fn apply_with_limits(
+        &self,
+        image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+        _limits: &Limits,
+    ) -> Result<(), PConvertError> {
+        self.apply(image)
+    }
+}
+
+/// An effect that changes each pixel of a layer independently of
+/// the others, every pixel effect is also an image effect.
+pub trait PixelEffect {
+    fn apply_pixel(&self, pixel: &mut Rgba<u8>);
+}
+
+impl<T: PixelEffect> ImageEffect for T {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError> {
+        for pixel in image.pixels_mut() {
+            self.apply_pixel(pixel);
+        }
+        Ok(())
+    }
+}
+
+/// An effect defined outside of the crate, shared by the layers (and
+/// threads) it is given to, and only equal to the clones of itself.
+///
+/// ```
+/// use image::Rgba;
+/// use pconvert_rust::effects::{CustomEffect, Effect, PixelEffect};
+/// use pconvert_rust::layer::LayerOptions;
+///
+/// struct Sepia;
+///
+/// impl PixelEffect for Sepia {
+///     fn apply_pixel(&self, pixel: &mut Rgba<u8>) {
+///         let luma = (pixel[0] as u32 * 3 + pixel[1] as u32 * 6 + pixel[2] as u32) / 10;
+///         pixel[0] = (luma + 40).min(255) as u8;
+///         pixel[1] = (luma + 20).min(255) as u8;
+///         pixel[2] = luma as u8;
+///     }
+/// }
+///
+/// let layer_options = LayerOptions {
+///     transforms: vec![],
+///     effects: vec![Effect::Custom(CustomEffect::new(Sepia))],
+/// };
+/// let layer = image::ImageBuffer::from_pixel(2, 2, Rgba([100, 100, 100, 255]));
+/// let layer = layer_options.apply(layer).unwrap();
+/// assert_eq!(layer.get_pixel(0, 0), &Rgba([140, 120, 100, 255]));
+/// ```
+#[derive(Clone)]
+pub struct CustomEffect(pub Arc<dyn ImageEffect + Send + Sync>);
+
+impl CustomEffect {
+    pub fn new(effect: impl ImageEffect + Send + Sync + 'static) -> CustomEffect {
+        CustomEffect(Arc::new(effect))
+    }
+}
+
+impl Debug for CustomEffect {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "CustomEffect")
+    }
+}
+
+impl PartialEq for CustomEffect {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Enumeration of the effects that may be given to a layer.
+///
+/// Effects are given as strings such as `brightness:0.2`, `contrast:1.5`,
+/// `hue:90`, `saturation:0.5`, `levels:16,235,1.2`, `tint:#ff8800,0.5`,
+/// `invert`, `grayscale`, `blur:4`, `box_blur:3`, `sharpen:0.8,1.5`,
+/// `drop_shadow:10,10,8,#000000,0.6`, `outer_glow:12,#ffcc00`,
+/// `alpha_threshold:128` or `remove_matte:#ffffff`, except for the
+/// custom effects, that are only given in code (and shown as `custom`).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Effect {
+    Brightness(Brightness),
+    Contrast(Contrast),
+    Hue(Hue),
+    Saturation(Saturation),
+    Levels(Levels),
+    Tint(Tint),
+    Invert,
+    Grayscale,
+    Blur(GaussianBlur),
+    BoxBlur(BoxBlur),
+    Sharpen(UnsharpMask),
+    DropShadow(DropShadow),
+    OuterGlow(OuterGlow),
+    AlphaThreshold(AlphaThreshold),
+    RemoveMatte(RemoveMatte),
+    Custom(CustomEffect),
+}
+
+impl ImageEffect for Effect {
+    fn apply(&self, image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<(), PConvertError> {
+        match self {
+            Effect::Brightness(effect) => effect.apply(image),
+            Effect::Contrast(effect) => effect.apply(image),
+            Effect::Hue(effect) => effect.apply(image),
+            Effect::Saturation(effect) => effect.apply(image),
+            Effect::Levels(effect) => effect.apply(image),
+            Effect::Tint(effect) => effect.apply(image),
+            Effect::Invert => Invert.apply(image),
+            Effect::Grayscale => Grayscale.apply(image),
+            Effect::Blur(effect) => effect.apply(image),
+            Effect::BoxBlur(effect) => effect.apply(image),
+            Effect::Sharpen(effect) => effect.apply(image),
+            Effect::DropShadow(effect) => effect.apply(image),
+            Effect::OuterGlow(effect) => effect.apply(image),
+            Effect::AlphaThreshold(effect) => effect.apply(image),
+            Effect::RemoveMatte(effect) => effect.apply(image),
+            Effect::Custom(effect) => effect.0.apply(image),
+        }
+    }
+
+    fn apply_with_limits(
+        &self,
+        image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+        limits: &Limits,
+    ) -> Result<(), PConvertError> {
+        match self {
+            Effect::Blur(effect) => effect.apply_with_limits(image, limits),
+            Effect::BoxBlur(effect) => effect.apply_with_limits(image, limits),
+            Effect::Sharpen(effect) => effect.apply_with_limits(image, limits),
+            Effect::DropShadow(effect) => effect.apply_with_limits(image, limits),
+            Effect::OuterGlow(effect) => effect.apply_with_limits(image, limits),
+            Effect::Custom(effect) => effect.0.apply_with_limits(image, limits),
+            effect => effect.apply(image),
+        }
+    }
+}
+
+impl FromStr for Effect {
+    type Err = PConvertError;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let invalid = |reason: &str| {
+            PConvertError::invalid_parameter("effect", format!("'{}' {}", s, reason))
+        };
+        let (name, args) = match s.trim().split_once(':') {
+            Some((name, args)) => (name.trim(), Some(args)),
+            None => (s.trim(), None),
+        };
+        let args = args
+            .map(|args| args.split(',').map(str::trim).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let number = |value: &str, min: f32, max: f32| -> Result<f32, PConvertError> {
+            match value.parse::<f32>() {
+                Ok(number) if number >= min && number <= max => Ok(number),
+                _ => Err(invalid(&format!(
+                    "requires numeric arguments between {} and {}",
+                    min, max
+                ))),
+            }
+        };
+        let integer = |value: &str, min: f32, max: f32| -> Result<f32, PConvertError> {
+            match number(value, min, max)? {
+                number if number.fract() == 0.0 => Ok(number),
+                _ => Err(invalid("requires integer arguments")),
+            }
+        };
+        // optional colour and opacity of the layer styles (e.g. shadows)
+        let style = |rest: &[&str], color: [u8; 3], opacity: f32| {
+            let color = match rest.first() {
+                Some(color) => {
+                    parse_hex_color(color).ok_or_else(|| invalid("requires a '#rrggbb' colour"))?
+                }
+                None => color,
+            };
+            let opacity = match rest.get(1) {
+                Some(opacity) => number(opacity, 0.0, 1.0)?,
+                None => opacity,
+            };
+            Ok::<_, PConvertError>((color, opacity))
+        };
+
+        match (name.to_lowercase().as_str(), &args[..]) {
+            ("brightness", [amount]) => Ok(Effect::Brightness(Brightness {
+                amount: number(amount, -1.0, 1.0)?,
+            })),
+            ("contrast", [factor]) => Ok(Effect::Contrast(Contrast {
+                factor: number(factor, 0.0, f32::MAX)?,
+            })),
+            ("hue", [degrees]) => Ok(Effect::Hue(Hue {
+                degrees: number(degrees, -360.0, 360.0)?,
+            })),
+            ("saturation", [factor]) => Ok(Effect::Saturation(Saturation {
+                factor: number(factor, 0.0, f32::MAX)?,
+            })),
+            ("levels", [in_black, in_white, rest @ ..]) if matches!(rest.len(), 0 | 1 | 3) => {
+                let in_black = number(in_black, 0.0, 255.0)?;
+                let in_white = number(in_white, 0.0, 255.0)?;
+                if in_black >= in_white {
+                    return Err(invalid("requires the input black below the input white"));
+                }
+                let gamma = match rest.first() {
+                    Some(gamma) => number(gamma, 0.01, 100.0)?,
+                    None => 1.0,
+                };
+                let (out_black, out_white) = match rest {
+                    [_, out_black, out_white] => (
+                        number(out_black, 0.0, 255.0)?,
+                        number(out_white, 0.0, 255.0)?,
+                    ),
+                    _ => (0.0, 255.0),
+                };
+                Ok(Effect::Levels(Levels {
+                    in_black,
+                    in_white,
+                    gamma,
+                    out_black,
+                    out_white,
+                }))
+            }
+            ("tint", [color, rest @ ..]) if rest.len() <= 1 => Ok(Effect::Tint(Tint {
+                color: parse_hex_color(color)
+                    .ok_or_else(|| invalid("requires a '#rrggbb' colour"))?,
+                amount: match rest.first() {
+                    Some(amount) => number(amount, 0.0, 1.0)?,
+                    None => 1.0,
+                },
+            })),
+            ("invert", []) => Ok(Effect::Invert),
+            ("grayscale", []) | ("greyscale", []) => Ok(Effect::Grayscale),
+            ("blur", [radius]) => Ok(Effect::Blur(GaussianBlur {
+                radius: number(radius, 0.0, MAX_RADIUS)?,
+            })),
+            ("box_blur", [radius]) => Ok(Effect::BoxBlur(BoxBlur {
+                radius: integer(radius, 0.0, MAX_RADIUS)? as u32,
+            })),
+            ("sharpen", [amount, rest @ ..]) if rest.len() <= 2 => {
+                Ok(Effect::Sharpen(UnsharpMask {
+                    amount: number(amount, 0.0, 10.0)?,
+                    radius: match rest.first() {
+                        Some(radius) => number(radius, 0.0, MAX_RADIUS)?,
+                        None => 1.0,
+                    },
+                    threshold: match rest.get(1) {
+                        Some(threshold) => number(threshold, 0.0, 255.0)?,
+                        None => 0.0,
+                    },
+                }))
+            }
+            ("drop_shadow", [offset_x, offset_y, radius, rest @ ..]) if rest.len() <= 2 => {
+                let (color, opacity) = style(rest, [0, 0, 0], 0.5)?;
+                Ok(Effect::DropShadow(DropShadow {
+                    offset_x: integer(offset_x, -MAX_OFFSET, MAX_OFFSET)? as i32,
+                    offset_y: integer(offset_y, -MAX_OFFSET, MAX_OFFSET)? as i32,
+                    radius: number(radius, 0.0, MAX_RADIUS)?,
+                    color,
+                    opacity,
+                }))
+            }
+            ("outer_glow", [radius, rest @ ..]) if rest.len() <= 2 => {
+                let (color, opacity) = style(rest, [255, 255, 255], 0.75)?;
+                Ok(Effect::OuterGlow(OuterGlow {
+                    radius: number(radius, 0.0, MAX_RADIUS)?,
+                    color,
+                    opacity,
+                }))
+            }
+            ("alpha_threshold", [threshold]) => Ok(Effect::AlphaThreshold(AlphaThreshold {
+                threshold: integer(threshold, 0.0, 255.0)? as u8,
+            })),
+            ("remove_matte", rest) if rest.len() <= 1 => Ok(Effect::RemoveMatte(RemoveMatte {
+                color: style(rest, [255, 255, 255], 1.0)?.0,
+            })),
+            _ => Err(invalid("is not a valid effect")),
+        }
+    }
+}
+
+impl Display for Effect {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Effect::Brightness(effect) => write!(f, "brightness:{}", effect.amount),
+            Effect::Contrast(effect) => write!(f, "contrast:{}", effect.factor),
+            Effect::Hue(effect) => write!(f, "hue:{}", effect.degrees),
+            Effect::Saturation(effect) => write!(f, "saturation:{}", effect.factor),
+            Effect::Levels(effect) => write!(
+                f,
+                "levels:{},{},{},{},{}",
+                effect.in_black, effect.in_white, effect.gamma, effect.out_black, effect.out_white
+            ),
+            Effect::Tint(effect) => write!(
+                f,
+                "tint:#{:02x}{:02x}{:02x},{}",
+                effect.color[0], effect.color[1], effect.color[2], effect.amount
+            ),
+            Effect::Invert => write!(f, "invert"),
+            Effect::Grayscale => write!(f, "grayscale"),
+            Effect::Blur(effect) => write!(f, "blur:{}", effect.radius),
+            Effect::BoxBlur(effect) => write!(f, "box_blur:{}", effect.radius),
+            Effect::Sharpen(effect) => write!(
+                f,
+                "sharpen:{},{},{}",
+                effect.amount, effect.radius, effect.threshold
+            ),
+            Effect::DropShadow(effect) => write!(
+                f,
+                "drop_shadow:{},{},{},#{:02x}{:02x}{:02x},{}",
+                effect.offset_x,
+                effect.offset_y,
+                effect.radius,
+                effect.color[0],
+                effect.color[1],
+                effect.color[2],
+                effect.opacity
+            ),
+            Effect::OuterGlow(effect) => write!(
+                f,
+                "outer_glow:{},#{:02x}{:02x}{:02x},{}",
+                effect.radius, effect.color[0], effect.color[1], effect.color[2], effect.opacity
+            ),
+            Effect::AlphaThreshold(effect) => write!(f, "alpha_threshold:{}", effect.threshold),
+            Effect::RemoveMatte(effect) => write!(
+                f,
+                "remove_matte:#{:02x}{:02x}{:02x}",
+                effect.color[0], effect.color[1], effect.color[2]
+            ),
+            Effect::Custom(_) => write!(f, "custom"),
+        }
+    }
+}
+
+/// Applies the effects, in order, to the given layer.
+pub fn apply_effects(
+    layer: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    effects: &[Effect],
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    apply_effects_with_limits(layer, effects, &Limits::default())
+}
+
+/// Applies the effects, in order, to the given layer, within the given
+/// limits (that bound the buffers of the convolution effects).
+pub fn apply_effects_with_limits(
+    mut layer: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    effects: &[Effect],
+    limits: &Limits,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    for effect in effects {
+        effect.apply_with_limits(&mut layer, limits)?;
+    }
+    Ok(layer)
+}
+
+/// Parses the effects of a layer from a JSON array of
+/// effect strings (e.g. `["grayscale", "brightness:0.1"]`).
+pub fn parse_json_effects(value: &JSONValue) -> Result<Vec<Effect>, PConvertError> {
+    let invalid =
+        || PConvertError::invalid_parameter("effects", "expected an array of effect strings");
+    match value {
+        JSONValue::Array(effects) => effects
+            .iter()
+            .map(|effect| match effect {
+                JSONValue::String(effect) => Effect::from_str(effect),
+                _ => Err(invalid()),
+            })
+            .collect(),
+        JSONValue::Null => Ok(Vec::new()),
+        _ => Err(invalid()),
+    }
+}
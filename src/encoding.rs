@@ -0,0 +1,383 @@
+//! Settings of the PNG encodings, the compression (a zlib level from 0 to 9
+//! and a deflate strategy) and the row filter, parsed strictly from their
+//! names, and the encoder used for the levels and strategies that the
+//! `image` PNG encoder does not expose.
+
+use crate::errors::PConvertError;
+use crate::utils::{encode_error, png_encode_error};
+use image::codecs::png::{CompressionType, FilterType};
+use image::error::{EncodingError, ImageError, ImageFormatHint};
+use image::ImageFormat;
+use miniz_oxide::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressionStrategy, CompressorOxide,
+    TDEFLFlush, TDEFLStatus,
+};
+use serde_json::Value as JSONValue;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::str::FromStr;
+
+/// Maximum size of the data of an `IDAT` chunk written by the encoder.
+const IDAT_SIZE: usize = 1 << 20;
+
+/// Deflate strategy of an encoding (as in zlib).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    Default,
+    Filtered,
+    Rle,
+    HuffmanOnly,
+}
+
+impl Display for Strategy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Strategy::Default => write!(f, "default"),
+            Strategy::Filtered => write!(f, "filtered"),
+            Strategy::Rle => write!(f, "rle"),
+            Strategy::HuffmanOnly => write!(f, "huffman"),
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = PConvertError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "default" => Ok(Strategy::Default),
+            "filtered" => Ok(Strategy::Filtered),
+            "rle" => Ok(Strategy::Rle),
+            "huffman" => Ok(Strategy::HuffmanOnly),
+            s => Err(PConvertError::invalid_parameter(
+                "strategy",
+                format!(
+                    "unknown strategy '{}' (expected default, filtered, rle or huffman)",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+/// Compression of a PNG encoding, the zlib level (from 0, no compression,
+/// to 9, the best) and the deflate strategy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression {
+    level: u8,
+    strategy: Strategy,
+}
+
+impl Compression {
+    pub const MAX_LEVEL: u8 = 9;
+    pub const FAST: Compression = Compression::with_level(1);
+    pub const DEFAULT: Compression = Compression::with_level(6);
+    pub const BEST: Compression = Compression::with_level(9);
+
+    const fn with_level(level: u8) -> Self {
+        Compression {
+            level,
+            strategy: Strategy::Default,
+        }
+    }
+
+    /// Creates the compression of the given zlib level (from 0 to 9),
+    /// with the default strategy.
+    pub fn new(level: u8) -> Result<Self, PConvertError> {
+        match level <= Self::MAX_LEVEL {
+            true => Ok(Self::with_level(level)),
+            false => Err(PConvertError::invalid_parameter(
+                "compression",
+                format!("level {} is not between 0 and {}", level, Self::MAX_LEVEL),
+            )),
+        }
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// The same compression level with another deflate strategy.
+    pub fn with_strategy(self, strategy: Strategy) -> Self {
+        Compression { strategy, ..self }
+    }
+
+    /// Builds the compression from the (optional) values of a request or
+    /// of the options of the bindings, its level (either a name or a number)
+    /// and its strategy name, defaulting to the fast compression.
+    pub fn from_options(
+        level: Option<CompressionLevel>,
+        strategy: Option<&str>,
+    ) -> Result<Self, PConvertError> {
+        let compression = match level {
+            Some(CompressionLevel::Name(name)) => Compression::from_str(name)?,
+            Some(CompressionLevel::Number(level)) => Compression::try_from(level)?,
+            None => Compression::default(),
+        };
+        Ok(match strategy {
+            Some(strategy) => compression.with_strategy(Strategy::from_str(strategy)?),
+            None => compression,
+        })
+    }
+}
+
+/// The fast compression, the default of every encoding.
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::FAST
+    }
+}
+
+/// Displays the level by its name when it has one (as parsed).
+impl Display for Compression {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.level {
+            level if level == Self::FAST.level => write!(f, "fast"),
+            level if level == Self::DEFAULT.level => write!(f, "default"),
+            level if level == Self::BEST.level => write!(f, "best"),
+            level => write!(f, "{}", level),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = PConvertError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "fast" => Ok(Compression::FAST),
+            "default" => Ok(Compression::DEFAULT),
+            "best" => Ok(Compression::BEST),
+            s => match s.parse::<u8>() {
+                Ok(level) => Compression::new(level),
+                Err(_) => Err(PConvertError::invalid_parameter(
+                    "compression",
+                    format!(
+                        "unknown compression '{}' (expected fast, default, best or a level from 0 to {})",
+                        s,
+                        Self::MAX_LEVEL
+                    ),
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<i64> for Compression {
+    type Error = PConvertError;
+
+    fn try_from(level: i64) -> Result<Self, Self::Error> {
+        match u8::try_from(level) {
+            Ok(level) => Compression::new(level),
+            Err(_) => Err(PConvertError::invalid_parameter(
+                "compression",
+                format!("level {} is not between 0 and {}", level, Self::MAX_LEVEL),
+            )),
+        }
+    }
+}
+
+/// The compression types of the `image` encoder are the named levels,
+/// the (deprecated) Huffman and RLE ones being the fast level with their
+/// deflate strategy.
+impl From<CompressionType> for Compression {
+    fn from(compression: CompressionType) -> Self {
+        match compression {
+            CompressionType::Default => Compression::DEFAULT,
+            CompressionType::Best => Compression::BEST,
+            #[allow(deprecated)]
+            CompressionType::Huffman => Compression::FAST.with_strategy(Strategy::HuffmanOnly),
+            #[allow(deprecated)]
+            CompressionType::Rle => Compression::FAST.with_strategy(Strategy::Rle),
+            _ => Compression::FAST,
+        }
+    }
+}
+
+/// Level of a compression as given in a request, by name or by number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionLevel<'a> {
+    Name(&'a str),
+    Number(i64),
+}
+
+impl<'a> CompressionLevel<'a> {
+    /// The level of a JSON value, either a string (its name) or an integer.
+    pub fn from_json(value: &'a JSONValue) -> Result<Self, PConvertError> {
+        match value {
+            JSONValue::String(name) => Ok(CompressionLevel::Name(name)),
+            JSONValue::Number(number) => match number.as_i64() {
+                Some(number) => Ok(CompressionLevel::Number(number)),
+                None => Err(PConvertError::invalid_parameter(
+                    "compression",
+                    format!("level {} is not an integer", number),
+                )),
+            },
+            _ => Err(PConvertError::invalid_parameter(
+                "compression",
+                "expected a string or an integer",
+            )),
+        }
+    }
+}
+
+/// Parses the name of a row filter, failing on unknown names (instead of
+/// falling back into no filtering), `adaptive` picks the filter of every
+/// row by the smallest sum of its (signed) filtered bytes.
+pub fn parse_filter(filter: &str) -> Result<FilterType, PConvertError> {
+    match filter.trim().to_lowercase().as_str() {
+        "nofilter" => Ok(FilterType::NoFilter),
+        "sub" => Ok(FilterType::Sub),
+        "up" => Ok(FilterType::Up),
+        "avg" => Ok(FilterType::Avg),
+        "paeth" => Ok(FilterType::Paeth),
+        "adaptive" => Ok(FilterType::Adaptive),
+        s => Err(PConvertError::invalid_parameter(
+            "filter",
+            format!(
+                "unknown filter '{}' (expected nofilter, sub, up, avg, paeth or adaptive)",
+                s
+            ),
+        )),
+    }
+}
+
+/// Encodes the image data (with the rows packed as in the PNG) after the
+/// header (and palette) chunks of the `png` encoder, filtering and
+/// deflating the rows itself so that any zlib level and strategy is
+/// available.
+///
+/// # Arguments
+///
+/// * `encoder` - The `png` encoder with the color, depth and palette set.
+/// * `data` - The rows of the image.
+/// * `row_size` - The size in bytes of each row.
+/// * `bpp` - The size in bytes of a pixel (at least one), the distance of
+///   the left neighbour of a byte in the filters.
+/// * `compression` - Compression level and strategy of the deflate.
+/// * `filter` - Filter type of the rows.
+pub(crate) fn encode_rows<W: Write>(
+    encoder: png::Encoder<W>,
+    data: &[u8],
+    row_size: usize,
+    bpp: usize,
+    compression: Compression,
+    filter: FilterType,
+) -> Result<(), PConvertError> {
+    let strategy = match compression.strategy {
+        Strategy::Default => CompressionStrategy::Default,
+        Strategy::Filtered => CompressionStrategy::Filtered,
+        Strategy::Rle => CompressionStrategy::RLE,
+        Strategy::HuffmanOnly => CompressionStrategy::HuffmanOnly,
+    };
+    // positive window bits for the zlib header and checksum
+    let flags = create_comp_flags_from_zip_params(compression.level as i32, 15, strategy as i32);
+    let mut compressor = CompressorOxide::new(flags);
+
+    let mut zlib = Vec::new();
+    let mut deflate = |input: &[u8], flush: TDEFLFlush| -> Result<(), PConvertError> {
+        let (status, _) = compress_to_output(&mut compressor, input, flush, |output| {
+            zlib.extend_from_slice(output);
+            true
+        });
+        match status {
+            TDEFLStatus::Okay | TDEFLStatus::Done => Ok(()),
+            _ => Err(encode_error(ImageError::Encoding(EncodingError::new(
+                ImageFormatHint::Exact(ImageFormat::Png),
+                format!("failed to deflate the image data ({:?})", status),
+            )))),
+        }
+    };
+
+    let empty = vec![0u8; row_size];
+    let mut previous = &empty[..];
+    let mut filtered = Vec::with_capacity(row_size + 1);
+    for row in data.chunks_exact(row_size) {
+        filter_row(filter, bpp, previous, row, &mut filtered);
+        deflate(&filtered, TDEFLFlush::None)?;
+        previous = row;
+    }
+    deflate(&[], TDEFLFlush::Finish)?;
+
+    let mut writer = encoder.write_header().map_err(png_encode_error)?;
+    for chunk in zlib.chunks(IDAT_SIZE) {
+        writer
+            .write_chunk(png::chunk::IDAT, chunk)
+            .map_err(png_encode_error)?;
+    }
+    writer.finish().map_err(png_encode_error)
+}
+
+/// Filters the row into `filtered` (prefixed with the filter type), the
+/// adaptive filter tries every one and keeps the one with the smallest
+/// sum of the absolute (signed) values of the bytes.
+fn filter_row(filter: FilterType, bpp: usize, previous: &[u8], row: &[u8], filtered: &mut Vec<u8>) {
+    let method = match filter {
+        FilterType::Sub => 1,
+        FilterType::Up => 2,
+        FilterType::Avg => 3,
+        FilterType::Paeth => 4,
+        FilterType::Adaptive => {
+            let mut best = Vec::with_capacity(filtered.capacity());
+            let mut best_sum = u64::MAX;
+            for method in 0..5 {
+                filter_row_with(method, bpp, previous, row, filtered);
+                let sum = filtered[1..]
+                    .iter()
+                    .map(|&value| (value as i8).unsigned_abs() as u64)
+                    .sum();
+                if sum < best_sum {
+                    best_sum = sum;
+                    std::mem::swap(&mut best, filtered);
+                }
+            }
+            std::mem::swap(&mut best, filtered);
+            return;
+        }
+        _ => 0,
+    };
+    filter_row_with(method, bpp, previous, row, filtered)
+}
+
+fn filter_row_with(method: u8, bpp: usize, previous: &[u8], row: &[u8], filtered: &mut Vec<u8>) {
+    filtered.clear();
+    filtered.push(method);
+    for (index, (&value, &above)) in row.iter().zip(previous).enumerate() {
+        let left = match index >= bpp {
+            true => row[index - bpp],
+            false => 0,
+        };
+        let above_left = match index >= bpp {
+            true => previous[index - bpp],
+            false => 0,
+        };
+        let predictor = match method {
+            1 => left,
+            2 => above,
+            3 => ((left as u16 + above as u16) / 2) as u8,
+            4 => paeth(left, above, above_left),
+            _ => 0,
+        };
+        filtered.push(value.wrapping_sub(predictor));
+    }
+}
+
+fn paeth(left: u8, above: u8, above_left: u8) -> u8 {
+    let estimate = left as i16 + above as i16 - above_left as i16;
+    let distance_left = (estimate - left as i16).abs();
+    let distance_above = (estimate - above as i16).abs();
+    let distance_above_left = (estimate - above_left as i16).abs();
+    if distance_left <= distance_above && distance_left <= distance_above_left {
+        left
+    } else if distance_above <= distance_above_left {
+        above
+    } else {
+        above_left
+    }
+}
@@ -12,6 +12,97 @@ pub enum PConvertError {
     UnsupportedImageTypeError,
     IOError(io::Error),
     ImageLibError(ImageError),
+    /// The layer with the given index (in the stack of layers being
+    /// blended) does not have the same dimensions as the layers below it.
+    DimensionMismatchError {
+        layer: usize,
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+    UnknownAlgorithmError(String),
+    InvalidParameterError {
+        name: String,
+        reason: String,
+    },
+    /// Failure decoding an image, with the path and the index of the
+    /// layer being decoded (when known).
+    DecodeError {
+        path: Option<String>,
+        layer: Option<usize>,
+        source: ImageError,
+    },
+    /// Failure encoding an image, with the path of the file being
+    /// written (when known).
+    EncodeError {
+        path: Option<String>,
+        source: ImageError,
+    },
+    ThreadPoolError(String),
+    /// The given resource limit (e.g. the width of an image or the
+    /// number of layers of a composition) would be exceeded.
+    LimitExceededError {
+        limit: &'static str,
+        value: u64,
+        max: u64,
+    },
+}
+
+impl PConvertError {
+    /// Returns a stable identifier of the kind of error, to be used
+    /// by the external bindings (e.g. the `code` of JavaScript errors).
+    pub fn code(&self) -> &'static str {
+        match self {
+            PConvertError::ArgumentError(_) => "ARGUMENT_ERROR",
+            PConvertError::UnsupportedImageTypeError => "UNSUPPORTED_IMAGE_TYPE",
+            PConvertError::IOError(_) => "IO_ERROR",
+            PConvertError::ImageLibError(_) => "IMAGE_LIB_ERROR",
+            PConvertError::DimensionMismatchError { .. } => "DIMENSION_MISMATCH",
+            PConvertError::UnknownAlgorithmError(_) => "UNKNOWN_ALGORITHM",
+            PConvertError::InvalidParameterError { .. } => "INVALID_PARAMETER",
+            PConvertError::DecodeError { .. } => "DECODE_ERROR",
+            PConvertError::EncodeError { .. } => "ENCODE_ERROR",
+            PConvertError::ThreadPoolError(_) => "THREAD_POOL_ERROR",
+            PConvertError::LimitExceededError { .. } => "LIMIT_EXCEEDED",
+        }
+    }
+
+    /// Creates an error for an invalid value of the given parameter.
+    pub fn invalid_parameter(name: &str, reason: impl Into<String>) -> PConvertError {
+        PConvertError::InvalidParameterError {
+            name: name.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Attaches the path of the file being decoded or encoded to the
+    /// error, any other error is returned unchanged.
+    pub fn with_path(self, path: &str) -> PConvertError {
+        match self {
+            PConvertError::DecodeError { layer, source, .. } => PConvertError::DecodeError {
+                path: Some(path.to_string()),
+                layer,
+                source,
+            },
+            PConvertError::EncodeError { source, .. } => PConvertError::EncodeError {
+                path: Some(path.to_string()),
+                source,
+            },
+            err => err,
+        }
+    }
+
+    /// Attaches the index of the layer being decoded to the error,
+    /// any other error is returned unchanged.
+    pub fn with_layer(self, layer: usize) -> PConvertError {
+        match self {
+            PConvertError::DecodeError { path, source, .. } => PConvertError::DecodeError {
+                path,
+                layer: Some(layer),
+                source,
+            },
+            err => err,
+        }
+    }
 }
 
 impl Display for PConvertError {
@@ -23,7 +114,53 @@ impl Display for PConvertError {
             ),
             PConvertError::ImageLibError(err) => err.fmt(formatter),
             PConvertError::IOError(err) => err.fmt(formatter),
-            PConvertError::ArgumentError(msg) => write!(formatter, "{}", msg),
+            PConvertError::ArgumentError(msg) => write!(formatter, "ArgumentError: {}", msg),
+            PConvertError::DimensionMismatchError {
+                layer,
+                expected,
+                found,
+            } => write!(
+                formatter,
+                "DimensionMismatchError: layer {} is {}x{} but {}x{} was expected",
+                layer, found.0, found.1, expected.0, expected.1
+            ),
+            PConvertError::UnknownAlgorithmError(algorithm) => write!(
+                formatter,
+                "UnknownAlgorithmError: invalid algorithm '{}'",
+                algorithm
+            ),
+            PConvertError::InvalidParameterError { name, reason } => write!(
+                formatter,
+                "InvalidParameterError: invalid '{}' ({})",
+                name, reason
+            ),
+            PConvertError::DecodeError {
+                path,
+                layer,
+                source,
+            } => {
+                write!(formatter, "DecodeError: failure decoding")?;
+                if let Some(layer) = layer {
+                    write!(formatter, " layer {}", layer)?;
+                }
+                if let Some(path) = path {
+                    write!(formatter, " '{}'", path)?;
+                }
+                write!(formatter, " ({})", source)
+            }
+            PConvertError::EncodeError { path, source } => {
+                write!(formatter, "EncodeError: failure encoding")?;
+                if let Some(path) = path {
+                    write!(formatter, " '{}'", path)?;
+                }
+                write!(formatter, " ({})", source)
+            }
+            PConvertError::ThreadPoolError(msg) => write!(formatter, "ThreadPoolError: {}", msg),
+            PConvertError::LimitExceededError { limit, value, max } => write!(
+                formatter,
+                "LimitExceededError: {} is above the '{}' limit of {}",
+                value, limit, max
+            ),
         }
     }
 }
@@ -35,6 +172,13 @@ impl Error for PConvertError {
             PConvertError::ArgumentError(_) => None,
             PConvertError::ImageLibError(ref err) => Some(err),
             PConvertError::IOError(ref err) => Some(err),
+            PConvertError::DimensionMismatchError { .. } => None,
+            PConvertError::UnknownAlgorithmError(_) => None,
+            PConvertError::InvalidParameterError { .. } => None,
+            PConvertError::DecodeError { ref source, .. } => Some(source),
+            PConvertError::EncodeError { ref source, .. } => Some(source),
+            PConvertError::ThreadPoolError(_) => None,
+            PConvertError::LimitExceededError { .. } => None,
         }
     }
 }
@@ -0,0 +1,492 @@
+//! Input formats of the layers, sniffed from their magic bytes, and output
+//! formats of the images, PNG (optionally quantised into a palette and size
+//! optimized) and (behind their cargo features) JPEG, WebP and QOI. Lossy
+//! formats take a quality, and JPEGs (which have no alpha channel) are
+//! flattened over a background colour.
+
+#[cfg(feature = "jpeg")]
+use crate::alpha::flatten;
+use crate::encoding::Compression;
+use crate::errors::PConvertError;
+use crate::optimize::{encode_indexed_png_optimized, encode_png_optimized};
+use crate::palette::{encode_png8, quantize, MAX_COLORS};
+#[cfg(any(feature = "jpeg", feature = "webp", feature = "qoi"))]
+use crate::utils::encode_error;
+use crate::utils::{encode_png, parse_hex_color, write_png_parallel};
+use image::codecs::png::FilterType;
+#[cfg(any(feature = "jpeg", feature = "webp", feature = "qoi"))]
+use image::ColorType;
+use image::{ImageBuffer, ImageFormat, Rgba};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+#[cfg(any(feature = "jpeg", feature = "webp", feature = "qoi"))]
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::result;
+use std::str::FromStr;
+
+/// Default quality of the JPEG encoding (from 1 to 100).
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// Default colour JPEGs are flattened over, white.
+pub const DEFAULT_BACKGROUND: [u8; 3] = [255, 255, 255];
+
+/// Number of bytes at the start of an image that identify its format.
+pub const SIGNATURE_SIZE: usize = 12;
+
+/// Enumeration of the supported input formats, other than PNG they
+/// are decoded into RGBA (8 bits per channel) buffers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    Png,
+    Jpeg,
+    WebP,
+    Tiff,
+    Bmp,
+    Qoi,
+}
+
+impl InputFormat {
+    /// Identifies the format from the magic bytes at the start of the
+    /// image (the first `SIGNATURE_SIZE` bytes), if known.
+    pub fn sniff(signature: &[u8]) -> Option<InputFormat> {
+        match signature {
+            [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, ..] => Some(InputFormat::Png),
+            [0xff, 0xd8, 0xff, ..] => Some(InputFormat::Jpeg),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+                Some(InputFormat::WebP)
+            }
+            [b'I', b'I', 0x2a, 0x00, ..] | [b'M', b'M', 0x00, 0x2a, ..] => Some(InputFormat::Tiff),
+            [b'B', b'M', ..] => Some(InputFormat::Bmp),
+            [b'q', b'o', b'i', b'f', ..] => Some(InputFormat::Qoi),
+            _ => None,
+        }
+    }
+
+    /// Whether the support of the format was enabled at compile time
+    /// (by its cargo feature, PNG is always supported).
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            InputFormat::Png => true,
+            InputFormat::Jpeg => cfg!(feature = "jpeg"),
+            InputFormat::WebP => cfg!(feature = "webp"),
+            InputFormat::Tiff => cfg!(feature = "tiff"),
+            InputFormat::Bmp => cfg!(feature = "bmp"),
+            InputFormat::Qoi => cfg!(feature = "qoi"),
+        }
+    }
+
+    /// The format of the `image` crate decoding the format.
+    pub fn image_format(&self) -> ImageFormat {
+        match self {
+            InputFormat::Png => ImageFormat::Png,
+            InputFormat::Jpeg => ImageFormat::Jpeg,
+            InputFormat::WebP => ImageFormat::WebP,
+            InputFormat::Tiff => ImageFormat::Tiff,
+            InputFormat::Bmp => ImageFormat::Bmp,
+            InputFormat::Qoi => ImageFormat::Qoi,
+        }
+    }
+}
+
+impl Display for InputFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            InputFormat::Png => write!(f, "png"),
+            InputFormat::Jpeg => write!(f, "jpeg"),
+            InputFormat::WebP => write!(f, "webp"),
+            InputFormat::Tiff => write!(f, "tiff"),
+            InputFormat::Bmp => write!(f, "bmp"),
+            InputFormat::Qoi => write!(f, "qoi"),
+        }
+    }
+}
+
+/// Enumeration of the supported output formats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Jpeg,
+    WebP,
+    Qoi,
+}
+
+impl OutputFormat {
+    /// Infers the format from the extension of the path, if known.
+    pub fn from_path(path: &str) -> Option<OutputFormat> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        match extension.to_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "webp" => Some(OutputFormat::WebP),
+            "qoi" => Some(OutputFormat::Qoi),
+            _ => None,
+        }
+    }
+
+    /// Whether the support of the format was enabled at compile time
+    /// (by its cargo feature, PNG is always supported).
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            OutputFormat::Png => true,
+            OutputFormat::Jpeg => cfg!(feature = "jpeg"),
+            OutputFormat::WebP => cfg!(feature = "webp"),
+            OutputFormat::Qoi => cfg!(feature = "qoi"),
+        }
+    }
+
+    /// The MIME type of the images encoded in the format.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::WebP => "image/webp",
+            OutputFormat::Qoi => "image/qoi",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = PConvertError;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "webp" => Ok(OutputFormat::WebP),
+            "qoi" => Ok(OutputFormat::Qoi),
+            _ => Err(PConvertError::invalid_parameter(
+                "format",
+                format!("unknown format '{}'", s),
+            )),
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            OutputFormat::Png => write!(f, "png"),
+            OutputFormat::Jpeg => write!(f, "jpeg"),
+            OutputFormat::WebP => write!(f, "webp"),
+            OutputFormat::Qoi => write!(f, "qoi"),
+        }
+    }
+}
+
+/// Format (and its settings) in which an image is encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    /// Quality of the lossy formats (from 1 to 100), a WebP without
+    /// quality is encoded losslessly.
+    pub quality: Option<u8>,
+    /// Colour the image is flattened over, for formats without
+    /// an alpha channel (`DEFAULT_BACKGROUND` when not given).
+    pub background: Option<[u8; 3]>,
+    /// Number of colours PNGs are quantised into, encoding an indexed
+    /// PNG (PNG8), PNGs are encoded in true colour when not given.
+    pub colors: Option<u16>,
+    /// Whether the quantisation into the palette is dithered.
+    pub dither: bool,
+    /// Whether PNGs are optimized, keeping the smallest of several
+    /// encodings (see `optimize`) instead of the given compression
+    /// and filter.
+    pub optimize: bool,
+}
+
+/// The (optional and unchecked) output values, as given in the options of
+/// the external APIs, from which the `OutputOptions` are built.
+///
+/// # Examples
+///
+/// ```
+/// use pconvert_rust::format::{OutputFormat, OutputOptions, OutputValues};
+///
+/// let output = OutputOptions::from_options(OutputValues {
+///     quality: Some(80),
+///     path: Some("out.jpg"),
+///     ..Default::default()
+/// });
+/// # #[cfg(feature = "jpeg")]
+/// assert_eq!(output.unwrap().format, OutputFormat::Jpeg);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutputValues<'a> {
+    pub format: Option<&'a str>,
+    pub quality: Option<i64>,
+    pub background: Option<&'a str>,
+    pub colors: Option<i64>,
+    pub dither: Option<bool>,
+    pub optimize: Option<bool>,
+    /// Path of the output, whose extension gives the format when
+    /// the format is not given.
+    pub path: Option<&'a str>,
+}
+
+impl OutputOptions {
+    /// Builds the output options from the output values, checking them
+    /// against each other, the format is inferred from the extension of
+    /// the path when not given (and defaults to PNG).
+    pub fn from_options(values: OutputValues) -> Result<OutputOptions, PConvertError> {
+        let OutputValues {
+            format,
+            quality,
+            background,
+            colors,
+            dither,
+            optimize,
+            path,
+        } = values;
+        let format = match format {
+            Some(format) => OutputFormat::from_str(format)?,
+            None => path.and_then(OutputFormat::from_path).unwrap_or_default(),
+        };
+        if !format.is_enabled() {
+            return Err(PConvertError::invalid_parameter(
+                "format",
+                format!("support for '{}' is not enabled (cargo feature)", format),
+            ));
+        }
+
+        let quality = match quality {
+            Some(quality) if !(1..=100).contains(&quality) => {
+                return Err(PConvertError::invalid_parameter(
+                    "quality",
+                    format!("{} is not between 1 and 100", quality),
+                ))
+            }
+            Some(_) if !matches!(format, OutputFormat::Jpeg | OutputFormat::WebP) => {
+                return Err(PConvertError::invalid_parameter(
+                    "quality",
+                    format!("'{}' is not a lossy format", format),
+                ))
+            }
+            Some(_) if format == OutputFormat::WebP && !cfg!(feature = "webp-lossy") => {
+                return Err(PConvertError::invalid_parameter(
+                    "quality",
+                    "support for lossy WebP is not enabled (cargo feature 'webp-lossy')",
+                ))
+            }
+            quality => quality.map(|quality| quality as u8),
+        };
+
+        let background = match background {
+            Some(_) if format != OutputFormat::Jpeg => {
+                return Err(PConvertError::invalid_parameter(
+                    "background",
+                    format!("'{}' keeps the alpha channel", format),
+                ))
+            }
+            Some(background) => Some(parse_hex_color(background).ok_or_else(|| {
+                PConvertError::invalid_parameter(
+                    "background",
+                    format!("'{}' is not a '#rrggbb' colour", background),
+                )
+            })?),
+            None => None,
+        };
+
+        let colors = match colors {
+            Some(_) if format != OutputFormat::Png => {
+                return Err(PConvertError::invalid_parameter(
+                    "colors",
+                    format!("'{}' can not be quantised into a palette", format),
+                ))
+            }
+            Some(colors) if !(2..=MAX_COLORS as i64).contains(&colors) => {
+                return Err(PConvertError::invalid_parameter(
+                    "colors",
+                    format!("{} is not between 2 and {}", colors, MAX_COLORS),
+                ))
+            }
+            colors => colors.map(|colors| colors as u16),
+        };
+        if dither.is_some() && colors.is_none() {
+            return Err(PConvertError::invalid_parameter(
+                "dither",
+                "only applies to the quantisation into a palette ('colors')",
+            ));
+        }
+
+        if optimize.is_some() && format != OutputFormat::Png {
+            return Err(PConvertError::invalid_parameter(
+                "optimize",
+                format!("only applies to PNGs, not '{}'", format),
+            ));
+        }
+
+        Ok(OutputOptions {
+            format,
+            quality,
+            background,
+            colors,
+            dither: dither.unwrap_or(false),
+            optimize: optimize.unwrap_or(false),
+        })
+    }
+
+    /// Whether the images are encoded as plain PNGs (in true colour, with the
+    /// given compression and filter), the only output of the streaming and
+    /// animated compositions.
+    pub fn is_plain_png(&self) -> bool {
+        self.format == OutputFormat::Png && self.colors.is_none() && !self.optimize
+    }
+}
+
+/// Encodes an image in the given output format, the compression and
+/// filter settings only apply to PNGs (true colour or indexed), that
+/// are otherwise optimized.
+///
+/// # Arguments
+///
+/// * `writable_buff` - Any buffer structure that implements the `Write` trait.
+/// * `image` - A byte buffer with the image data (with straight alpha).
+/// * `compression` - Compression to use in the encoding of PNGs.
+/// * `filter` - Filter type to use in the encoding of PNGs.
+/// * `output` - The output format and its settings.
+pub fn encode_image(
+    writable_buff: impl Write,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    compression: Compression,
+    filter: FilterType,
+    output: &OutputOptions,
+) -> Result<(), PConvertError> {
+    match output.format {
+        OutputFormat::Png => match (output.colors, output.optimize) {
+            (Some(colors), true) => encode_indexed_png_optimized(
+                writable_buff,
+                &quantize(image, colors, output.dither)?,
+                compression,
+                filter,
+            )
+            .map(|_| ()),
+            (Some(colors), false) => encode_png8(
+                writable_buff,
+                image,
+                colors,
+                output.dither,
+                compression,
+                filter,
+            ),
+            (None, true) => {
+                encode_png_optimized(writable_buff, image, compression, filter).map(|_| ())
+            }
+            (None, false) => encode_png(writable_buff, image, compression, filter),
+        },
+        #[cfg(feature = "jpeg")]
+        OutputFormat::Jpeg => encode_jpeg(writable_buff, image, output),
+        #[cfg(feature = "webp")]
+        OutputFormat::WebP => encode_webp(writable_buff, image, output),
+        #[cfg(feature = "qoi")]
+        OutputFormat::Qoi => encode_qoi(writable_buff, image),
+        #[allow(unreachable_patterns)]
+        format => Err(PConvertError::invalid_parameter(
+            "format",
+            format!("support for '{}' is not enabled (cargo feature)", format),
+        )),
+    }
+}
+
+/// Writes an image to the local file system in the given output format.
+///
+/// # Arguments
+///
+/// * `file_out` - Local file system path where to write the image file.
+/// * `image` - A byte buffer with the image data (with straight alpha).
+/// * `compression` - Compression to use in the encoding of PNGs.
+/// * `filter` - Filter type to use in the encoding of PNGs.
+/// * `output` - The output format and its settings.
+pub fn write_image_to_file(
+    file_out: String,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    compression: Compression,
+    filter: FilterType,
+    output: &OutputOptions,
+) -> Result<(), PConvertError> {
+    let file = File::create(&file_out)?;
+    encode_image(file, image, compression, filter, output).map_err(|err| err.with_path(&file_out))
+}
+
+/// Writes an image to the local file system in the given output format, plain
+/// PNGs are written with the multi-threaded encoder (as `write_png_parallel`).
+///
+/// # Arguments
+///
+/// * `file_out` - Local file system path where to write the image file.
+/// * `image` - A byte buffer with the image data (with straight alpha).
+/// * `compression` - Compression to use in the encoding of PNGs.
+/// * `filter` - Filter type to use in the encoding of PNGs.
+/// * `output` - The output format and its settings.
+pub fn write_image_parallel(
+    file_out: String,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    compression: Compression,
+    filter: FilterType,
+    output: &OutputOptions,
+) -> Result<(), PConvertError> {
+    match output.is_plain_png() {
+        true => write_png_parallel(file_out, image, compression, filter),
+        false => write_image_to_file(file_out, image, compression, filter, output),
+    }
+}
+
+#[cfg(feature = "jpeg")]
+fn encode_jpeg(
+    writable_buff: impl Write,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    output: &OutputOptions,
+) -> Result<(), PConvertError> {
+    use image::codecs::jpeg::JpegEncoder;
+
+    let flattened = flatten(image, output.background.unwrap_or(DEFAULT_BACKGROUND));
+    let quality = output.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+    JpegEncoder::new_with_quality(BufWriter::new(writable_buff), quality)
+        .encode(
+            &flattened,
+            flattened.width(),
+            flattened.height(),
+            ColorType::Rgb8,
+        )
+        .map_err(encode_error)
+}
+
+#[cfg(feature = "webp")]
+fn encode_webp(
+    writable_buff: impl Write,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    output: &OutputOptions,
+) -> Result<(), PConvertError> {
+    use image::codecs::webp::WebPEncoder;
+
+    let buff = BufWriter::new(writable_buff);
+    let encoder = match output.quality {
+        // the lossy encoding (by libwebp) is deprecated in `image`,
+        // that plans to only keep its own lossless encoder
+        #[cfg(feature = "webp-lossy")]
+        #[allow(deprecated)]
+        Some(quality) => {
+            WebPEncoder::new_with_quality(buff, image::codecs::webp::WebPQuality::lossy(quality))
+        }
+        _ => WebPEncoder::new_lossless(buff),
+    };
+    encoder
+        .encode(image, image.width(), image.height(), ColorType::Rgba8)
+        .map_err(encode_error)
+}
+
+#[cfg(feature = "qoi")]
+fn encode_qoi(
+    writable_buff: impl Write,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> Result<(), PConvertError> {
+    use image::codecs::qoi::QoiEncoder;
+    use image::ImageEncoder;
+
+    QoiEncoder::new(BufWriter::new(writable_buff))
+        .write_image(image, image.width(), image.height(), ColorType::Rgba8)
+        .map_err(encode_error)
+}
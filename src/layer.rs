@@ -0,0 +1,62 @@
+//! Options of each of the layers of a composition (transforms and
+//! effects) applied to the layer before it is blended.
+
+use crate::effects::{apply_effects_with_limits, parse_json_effects, Effect};
+use crate::errors::PConvertError;
+use crate::limits::Limits;
+use crate::transform::{apply_transforms, parse_json_transforms, Transform};
+use image::{ImageBuffer, Rgba};
+use serde_json::Value as JSONValue;
+
+/// The transforms and effects of a layer, the transforms are
+/// applied first (in order) and then the effects (in order).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LayerOptions {
+    pub transforms: Vec<Transform>,
+    pub effects: Vec<Effect>,
+}
+
+impl LayerOptions {
+    /// Parses the (optional) `transforms` and `effects` of a JSON layer
+    /// object, layers given in any other way have no options.
+    pub fn from_json(layer: &JSONValue) -> Result<LayerOptions, PConvertError> {
+        let layer = match layer {
+            JSONValue::Object(layer) => layer,
+            _ => return Ok(LayerOptions::default()),
+        };
+        Ok(LayerOptions {
+            transforms: match layer.get("transforms") {
+                Some(transforms) => parse_json_transforms(transforms)?,
+                None => Vec::new(),
+            },
+            effects: match layer.get("effects") {
+                Some(effects) => parse_json_effects(effects)?,
+                None => Vec::new(),
+            },
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty() && self.effects.is_empty()
+    }
+
+    /// Applies the transforms and then the effects to the given
+    /// layer (with straight alpha).
+    pub fn apply(
+        &self,
+        layer: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+        self.apply_with_limits(layer, &Limits::default())
+    }
+
+    /// Applies the transforms and then the effects to the given
+    /// layer (with straight alpha), within the given limits.
+    pub fn apply_with_limits(
+        &self,
+        layer: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        limits: &Limits,
+    ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+        let layer = apply_transforms(layer, &self.transforms)?;
+        apply_effects_with_limits(layer, &self.effects, limits)
+    }
+}
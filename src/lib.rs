@@ -23,13 +23,30 @@
 //!
 //! P(NG)Convert Rust is currently licensed under the [Apache License, Version 2.0](http://www.apache.org/licenses/).
 
+pub mod alpha;
+pub mod apng;
+pub mod batch;
 pub mod benchmark;
 pub mod blending;
+pub mod cache;
+pub mod compare;
 pub mod compose;
 pub mod constants;
+pub mod effects;
+pub mod encoding;
 pub mod errors;
+pub mod format;
+pub mod layer;
+pub mod limits;
+pub mod optimize;
+pub mod palette;
 pub mod parallelism;
+pub mod resize;
+pub mod server;
+pub mod stream;
+pub mod transform;
 pub mod utils;
+pub mod watch;
 
 #[cfg(feature = "python-extension")]
 pub mod pymodule;
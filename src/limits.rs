@@ -0,0 +1,139 @@
+//! Limits on the resources used by decoding and composing images, so that
+//! untrusted inputs (e.g. a small PNG declaring a huge image) fail early
+//! instead of exhausting the memory of the process.
+
+use crate::errors::PConvertError;
+use image::io;
+use std::convert::TryFrom;
+
+/// Names of the limits, as used by the options of the external APIs
+/// (the command line flags use them with dashes, e.g. `--max-width`).
+pub const LIMIT_NAMES: [&str; 5] = [
+    "max_width",
+    "max_height",
+    "max_pixels",
+    "max_bytes",
+    "max_layers",
+];
+
+/// Default maximum width (and height) of an image, in pixels.
+pub const DEFAULT_MAX_DIMENSION: u32 = 32768;
+
+/// Default maximum number of pixels of an image, 256 megapixels.
+pub const DEFAULT_MAX_PIXELS: u64 = 256 * 1024 * 1024;
+
+/// Default maximum number of bytes of a decoded image, 1 GB.
+pub const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Default maximum number of layers of a composition.
+pub const DEFAULT_MAX_LAYERS: usize = 256;
+
+/// Resource limits enforced before allocating the buffers of an image
+/// (e.g. when the header of a PNG is decoded) and before the layers
+/// of a composition are read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+    pub max_bytes: u64,
+    pub max_layers: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_width: DEFAULT_MAX_DIMENSION,
+            max_height: DEFAULT_MAX_DIMENSION,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_layers: DEFAULT_MAX_LAYERS,
+        }
+    }
+}
+
+impl Limits {
+    /// Changes the limit with the given name (one of `LIMIT_NAMES`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pconvert_rust::limits::Limits;
+    ///
+    /// let mut limits = Limits::default();
+    /// limits.set("max_pixels", 1920 * 1080).unwrap();
+    /// assert!(limits.check_dimensions(1920, 1080).is_ok());
+    /// assert!(limits.check_dimensions(1920, 1081).is_err());
+    /// ```
+    pub fn set(&mut self, name: &str, value: u64) -> Result<(), PConvertError> {
+        let invalid = || PConvertError::invalid_parameter(name, format!("{} is too large", value));
+        match name {
+            "max_width" => self.max_width = u32::try_from(value).map_err(|_| invalid())?,
+            "max_height" => self.max_height = u32::try_from(value).map_err(|_| invalid())?,
+            "max_pixels" => self.max_pixels = value,
+            "max_bytes" => self.max_bytes = value,
+            "max_layers" => self.max_layers = usize::try_from(value).map_err(|_| invalid())?,
+            _ => {
+                return Err(PConvertError::ArgumentError(format!(
+                    "unknown limit '{}'",
+                    name
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the dimensions of an image (with 4 bytes per pixel, as
+    /// RGBA8) against the width, height, pixels and bytes limits.
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), PConvertError> {
+        let pixels = width as u64 * height as u64;
+        check("max_width", width as u64, self.max_width as u64)?;
+        check("max_height", height as u64, self.max_height as u64)?;
+        check("max_pixels", pixels, self.max_pixels)?;
+        check("max_bytes", pixels * 4, self.max_bytes)
+    }
+
+    /// Checks the dimensions of an image processed in bands of rows (e.g. by
+    /// the streaming composition), where the pixels and bytes limits apply
+    /// to a band, as a band is the largest buffer allocated for the image.
+    pub fn check_band_dimensions(
+        &self,
+        width: u32,
+        height: u32,
+        band_height: u32,
+    ) -> Result<(), PConvertError> {
+        let pixels = width as u64 * band_height.min(height) as u64;
+        check("max_width", width as u64, self.max_width as u64)?;
+        check("max_height", height as u64, self.max_height as u64)?;
+        check("max_pixels", pixels, self.max_pixels)?;
+        check("max_bytes", pixels * 4, self.max_bytes)
+    }
+
+    /// Checks the number of bytes of a decoded image against the bytes limit.
+    pub fn check_bytes(&self, bytes: u64) -> Result<(), PConvertError> {
+        check("max_bytes", bytes, self.max_bytes)
+    }
+
+    /// Checks the number of layers of a composition against the layers limit.
+    pub fn check_layers(&self, layers: usize) -> Result<(), PConvertError> {
+        check("max_layers", layers as u64, self.max_layers as u64)
+    }
+}
+
+/// Converts into the limits of the `image` decoders, so that the memory
+/// allocated while decoding (e.g. for the metadata chunks) is also bounded,
+/// the dimensions are left to `check_dimensions` (for a `LimitExceededError`).
+impl From<&Limits> for io::Limits {
+    fn from(limits: &Limits) -> io::Limits {
+        let mut image_limits = io::Limits::no_limits();
+        image_limits.max_alloc = Some(limits.max_bytes);
+        image_limits
+    }
+}
+
+fn check(limit: &'static str, value: u64, max: u64) -> Result<(), PConvertError> {
+    match value > max {
+        true => Err(PConvertError::LimitExceededError { limit, value, max }),
+        false => Ok(()),
+    }
+}
@@ -1,6 +1,9 @@
 mod cli;
 
-use cli::{pbenchmark, pcompose, pconvert, print_usage, pversion};
+use cli::{
+    pbatch, pbenchmark, pblend, pcompare, pcompose, pconvert, poptimize, presize, print_usage,
+    pserve, pversion, pwatch,
+};
 use pconvert_rust::errors::PConvertError;
 use std::env;
 
@@ -14,7 +17,14 @@ fn main() -> Result<(), PConvertError> {
     match args.next() {
         Some(action) => match &action[..] {
             "convert" => pconvert(&mut args)?,
+            "resize" => presize(&mut args)?,
             "compose" => pcompose(&mut args)?,
+            "blend" => pblend(&mut args)?,
+            "batch" => pbatch(&mut args)?,
+            "serve" => pserve(&mut args)?,
+            "watch" => pwatch(&mut args)?,
+            "compare" => pcompare(&mut args)?,
+            "optimize" => poptimize(&mut args)?,
             "benchmark" => pbenchmark(&mut args)?,
             "version" => pversion(),
             _ => print_usage(),
@@ -0,0 +1,301 @@
+//! PNG size optimizer, encoding an image with several combinations of row
+//! filtering (including the adaptive, per row, filtering) and deflate
+//! strategies and keeping the smallest output.
+
+use crate::apng::is_apng;
+use crate::encoding::{Compression, Strategy};
+use crate::errors::PConvertError;
+use crate::format::{InputFormat, SIGNATURE_SIZE};
+use crate::limits::Limits;
+use crate::palette::{encode_indexed_png, IndexedImage};
+use crate::utils::{decode_image_with_limits, encode_png, png_decode_error};
+#[cfg(not(feature = "wasm-extension"))]
+use crate::utils::{encode_error, mtpng_compression_from, mtpng_filter_from, mtpng_strategy_from};
+use image::codecs::png::FilterType;
+use image::{ImageBuffer, Rgba};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::Write;
+
+/// Settings of one of the encodings tried by the optimizer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    /// The level and the deflate strategy of the compression.
+    pub compression: Compression,
+    /// The filter of every row, or `FilterType::Adaptive` for
+    /// the one that best fits each row.
+    pub filter: FilterType,
+}
+
+impl Display for Candidate {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} compression, {:?} filter, {} strategy",
+            self.compression,
+            self.filter,
+            self.compression.strategy()
+        )
+    }
+}
+
+/// Outcome of an optimization, the size of the original encoding (the one
+/// with the requested settings, or the original file) and of the smallest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OptimizeReport {
+    pub original_size: usize,
+    pub size: usize,
+    /// Settings of the smallest encoding, `None` when the original
+    /// file was smaller than every candidate (and kept as is).
+    pub candidate: Option<Candidate>,
+}
+
+impl OptimizeReport {
+    /// Number of bytes saved over the original encoding.
+    pub fn saving(&self) -> usize {
+        self.original_size.saturating_sub(self.size)
+    }
+
+    /// Bytes saved as a percentage of the original encoding.
+    pub fn saving_percent(&self) -> f64 {
+        match self.original_size {
+            0 => 0.0,
+            original_size => self.saving() as f64 * 100.0 / original_size as f64,
+        }
+    }
+}
+
+impl Display for OptimizeReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} bytes (saved {} bytes, {:.1}%)",
+            self.original_size,
+            self.size,
+            self.saving(),
+            self.saving_percent()
+        )?;
+        match &self.candidate {
+            Some(candidate) => write!(f, " with {}", candidate),
+            None => write!(f, " keeping the original"),
+        }
+    }
+}
+
+/// The encodings tried for true colour images, the best compression with
+/// adaptive and no filtering (that suits flat images) and each strategy.
+pub fn candidates() -> Vec<Candidate> {
+    let strategies = [
+        Strategy::Default,
+        Strategy::Filtered,
+        Strategy::Rle,
+        Strategy::HuffmanOnly,
+    ];
+    [FilterType::Adaptive, FilterType::NoFilter]
+        .iter()
+        .flat_map(|&filter| {
+            strategies.iter().map(move |&strategy| Candidate {
+                compression: Compression::BEST.with_strategy(strategy),
+                filter,
+            })
+        })
+        .collect()
+}
+
+/// Encodes the image as a PNG with the requested settings and with each of
+/// the `candidates`, returning the smallest output and the report of the
+/// saving over the requested settings.
+///
+/// # Arguments
+///
+/// * `image` - The image buffer (with straight alpha).
+/// * `compression` - Compression of the original encoding.
+/// * `filter` - Filter type of the original encoding.
+pub fn optimize_png(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    compression: Compression,
+    filter: FilterType,
+) -> Result<(Vec<u8>, OptimizeReport), PConvertError> {
+    smallest(compression, filter, &candidates(), |candidate| {
+        encode_candidate(image, candidate)
+    })
+}
+
+/// Writes the smallest of the PNG encodings of the image (see `optimize_png`).
+///
+/// # Arguments
+///
+/// * `writable_buff` - Any buffer structure that implements the `Write` trait.
+/// * `image` - The image buffer (with straight alpha).
+/// * `compression` - Compression of the original encoding.
+/// * `filter` - Filter type of the original encoding.
+pub fn encode_png_optimized(
+    mut writable_buff: impl Write,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    compression: Compression,
+    filter: FilterType,
+) -> Result<OptimizeReport, PConvertError> {
+    let (data, report) = optimize_png(image, compression, filter)?;
+    writable_buff.write_all(&data)?;
+    Ok(report)
+}
+
+/// Writes the smallest of the indexed PNG encodings of the quantised image,
+/// with the best compression and either adaptive or no filtering.
+///
+/// # Arguments
+///
+/// * `writable_buff` - Any buffer structure that implements the `Write` trait.
+/// * `image` - The quantised image.
+/// * `compression` - Compression of the original encoding.
+/// * `filter` - Filter type of the original encoding.
+pub fn encode_indexed_png_optimized(
+    mut writable_buff: impl Write,
+    image: &IndexedImage,
+    compression: Compression,
+    filter: FilterType,
+) -> Result<OptimizeReport, PConvertError> {
+    let candidates: Vec<Candidate> = [FilterType::Adaptive, FilterType::NoFilter]
+        .iter()
+        .map(|&filter| Candidate {
+            compression: Compression::BEST,
+            filter,
+        })
+        .collect();
+    let (data, report) = smallest(compression, filter, &candidates, |candidate| {
+        let mut data = Vec::new();
+        encode_indexed_png(&mut data, image, candidate.compression, candidate.filter)?;
+        Ok(data)
+    })?;
+    writable_buff.write_all(&data)?;
+    Ok(report)
+}
+
+/// Optimizes an image file into a PNG, the original file is kept (copied)
+/// when it is already a PNG smaller than every candidate, so that the output
+/// is never larger. PNGs of any other color type (e.g. RGB, grey or palette)
+/// are optimized into RGBA PNGs, while animated and 16 bits PNGs are
+/// rejected, as they would lose their frames or precision.
+///
+/// # Arguments
+///
+/// * `file_in` - Local file system path of the image (in any input format).
+/// * `file_out` - Local file system path where to write the PNG (that may
+///   be the same as `file_in`).
+/// * `limits` - The resource limits of the decoding.
+pub fn optimize_file(
+    file_in: &str,
+    file_out: &str,
+    limits: &Limits,
+) -> Result<OptimizeReport, PConvertError> {
+    let original = fs::read(file_in)?;
+    let is_png = InputFormat::sniff(&original[..SIGNATURE_SIZE.min(original.len())])
+        == Some(InputFormat::Png);
+    if is_png {
+        if is_apng(&original[..]) {
+            return Err(PConvertError::ArgumentError(format!(
+                "'{}' is animated, its frames can not be optimized",
+                file_in
+            )));
+        }
+        let reader = png::Decoder::new(&original[..])
+            .read_info()
+            .map_err(|err| png_decode_error(err).with_path(file_in))?;
+        if reader.info().bit_depth == png::BitDepth::Sixteen {
+            return Err(PConvertError::ArgumentError(format!(
+                "'{}' has 16 bits per channel, that can not be optimized losslessly",
+                file_in
+            )));
+        }
+    }
+
+    let image = decode_image_with_limits(&original[..], false, limits)
+        .map_err(|err| err.with_path(file_in))?;
+    let (data, mut report) = optimize_png(&image, Compression::FAST, FilterType::NoFilter)?;
+    report.original_size = original.len();
+    let data = match is_png && data.len() >= original.len() {
+        true => {
+            report.size = original.len();
+            report.candidate = None;
+            original
+        }
+        false => data,
+    };
+    fs::write(file_out, data)?;
+    Ok(report)
+}
+
+/// Encodes with the requested settings (the original encoding) and with
+/// every candidate, keeping the smallest output.
+fn smallest(
+    compression: Compression,
+    filter: FilterType,
+    candidates: &[Candidate],
+    encode: impl Fn(&Candidate) -> Result<Vec<u8>, PConvertError>,
+) -> Result<(Vec<u8>, OptimizeReport), PConvertError> {
+    let requested = Candidate {
+        compression,
+        filter,
+    };
+    let mut best = encode(&requested)?;
+    let mut report = OptimizeReport {
+        original_size: best.len(),
+        size: best.len(),
+        candidate: Some(requested),
+    };
+    for candidate in candidates {
+        let data = encode(candidate)?;
+        if data.len() < best.len() {
+            report.size = data.len();
+            report.candidate = Some(*candidate);
+            best = data;
+        }
+    }
+    Ok((best, report))
+}
+
+/// Encodes the image with the candidate settings, using the multi-threaded
+/// encoder (with its adaptive filtering) for the levels it supports.
+#[cfg(not(feature = "wasm-extension"))]
+fn encode_candidate(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    candidate: &Candidate,
+) -> Result<Vec<u8>, PConvertError> {
+    let level = match mtpng_compression_from(candidate.compression) {
+        Some(level) => level,
+        None => {
+            let mut data = Vec::new();
+            encode_png(&mut data, image, candidate.compression, candidate.filter)?;
+            return Ok(data);
+        }
+    };
+    let encode = || -> Result<Vec<u8>, std::io::Error> {
+        let mut header = mtpng::Header::new();
+        header.set_size(image.width(), image.height())?;
+        header.set_color(mtpng::ColorType::TruecolorAlpha, 8)?;
+
+        let mut options = mtpng::encoder::Options::new();
+        options.set_compression_level(level)?;
+        options.set_filter_mode(mtpng_filter_from(candidate.filter))?;
+        options.set_strategy_mode(mtpng_strategy_from(candidate.compression))?;
+
+        let mut encoder = mtpng::encoder::Encoder::new(Vec::new(), &options);
+        encoder.write_header(&header)?;
+        encoder.write_image_rows(image)?;
+        encoder.finish()
+    };
+    encode().map_err(|err| encode_error(err.into()))
+}
+
+/// Encodes the image with the candidate settings, using the single-threaded
+/// encoder.
+#[cfg(feature = "wasm-extension")]
+fn encode_candidate(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    candidate: &Candidate,
+) -> Result<Vec<u8>, PConvertError> {
+    let mut data = Vec::new();
+    encode_png(&mut data, image, candidate.compression, candidate.filter)?;
+    Ok(data)
+}
@@ -0,0 +1,310 @@
+//! Palette quantisation of RGBA images into at most 256 colours (by median
+//! cut, optionally with Floyd–Steinberg dithering) and the encoding of the
+//! quantised images as indexed PNGs (PNG8), with the alpha of the colours
+//! in their `tRNS` chunk.
+
+use crate::encoding::{encode_rows, Compression};
+use crate::errors::PConvertError;
+use image::codecs::png::FilterType;
+use image::{ImageBuffer, Rgba};
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+
+/// Maximum number of colours of a palette (indexed by a byte).
+pub const MAX_COLORS: u16 = 256;
+
+/// Image with its pixels as indexes into a palette of (straight alpha) colours.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedImage {
+    pub width: u32,
+    pub height: u32,
+    /// Colours of the palette, the translucent ones come first (so that
+    /// the `tRNS` chunk only covers them).
+    pub palette: Vec<[u8; 4]>,
+    /// Index of the colour of each pixel, row by row.
+    pub indexes: Vec<u8>,
+}
+
+impl IndexedImage {
+    /// Expands the image back into an RGBA buffer.
+    pub fn to_rgba(&self) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let data = self
+            .indexes
+            .iter()
+            .flat_map(|&index| self.palette[index as usize])
+            .collect();
+        ImageBuffer::from_raw(self.width, self.height, data).unwrap()
+    }
+}
+
+/// Quantises the image into a palette of at most `colors` colours, chosen by
+/// median cut over its (distinct) colours, an image with no more distinct
+/// colours than that is kept as is. Fully transparent pixels are all mapped
+/// into the same (transparent) colour.
+///
+/// # Arguments
+///
+/// * `image` - The image buffer (with straight alpha).
+/// * `colors` - Maximum number of colours of the palette (from 2 to 256).
+/// * `dither` - Whether to diffuse the quantisation error of each pixel
+///   into its neighbours (Floyd–Steinberg), smoothing gradients.
+pub fn quantize(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    colors: u16,
+    dither: bool,
+) -> Result<IndexedImage, PConvertError> {
+    if !(2..=MAX_COLORS).contains(&colors) {
+        return Err(PConvertError::invalid_parameter(
+            "colors",
+            format!("{} is not between 2 and {}", colors, MAX_COLORS),
+        ));
+    }
+
+    let mut histogram: HashMap<[u8; 4], u32> = HashMap::new();
+    for pixel in image.pixels() {
+        *histogram.entry(normalize(pixel.0)).or_insert(0) += 1;
+    }
+    // sorts the colours so that the palette does not depend on
+    // the (random) iteration order of the histogram
+    let mut entries: Vec<([u8; 4], u32)> = histogram.into_iter().collect();
+    entries.sort_unstable();
+    let mut palette = match entries.len() <= colors as usize {
+        true => entries.into_iter().map(|(color, _)| color).collect(),
+        false => median_cut(entries, colors as usize),
+    };
+    palette.sort_by_key(|color| (color[3] == 255, *color));
+
+    let indexes = match dither {
+        true => map_dithered(image, &palette),
+        false => map_nearest(image, &palette),
+    };
+    Ok(IndexedImage {
+        width: image.width(),
+        height: image.height(),
+        palette,
+        indexes,
+    })
+}
+
+/// Encodes the quantised image as an indexed PNG, with the smallest bit depth
+/// that fits its palette and a `tRNS` chunk when any colour is translucent.
+///
+/// # Arguments
+///
+/// * `writable_buff` - Any buffer structure that implements the `Write` trait.
+/// * `image` - The quantised image.
+/// * `compression` - Compression to use in the encoding.
+/// * `filter` - Filter type to use in the encoding.
+pub fn encode_indexed_png(
+    writable_buff: impl Write,
+    image: &IndexedImage,
+    compression: Compression,
+    filter: FilterType,
+) -> Result<(), PConvertError> {
+    let depth = match image.palette.len() {
+        0..=2 => png::BitDepth::One,
+        3..=4 => png::BitDepth::Two,
+        5..=16 => png::BitDepth::Four,
+        _ => png::BitDepth::Eight,
+    };
+    let bits = depth as usize;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(writable_buff), image.width, image.height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(
+        image
+            .palette
+            .iter()
+            .flat_map(|color| [color[0], color[1], color[2]])
+            .collect::<Vec<u8>>(),
+    );
+    let trns: Vec<u8> = image
+        .palette
+        .iter()
+        .map(|color| color[3])
+        .take_while(|&alpha| alpha < 255)
+        .collect();
+    if !trns.is_empty() {
+        encoder.set_trns(trns);
+    }
+
+    // packs the indexes of each row into its bytes, most significant
+    // bits first, the rows starting at a byte boundary
+    let width = image.width as usize;
+    let row_size = (width * bits).div_ceil(8);
+    let mut data = vec![0u8; row_size * image.height as usize];
+    for (row, indexes) in data
+        .chunks_exact_mut(row_size)
+        .zip(image.indexes.chunks_exact(width))
+    {
+        for (x, &index) in indexes.iter().enumerate() {
+            let offset = x * bits;
+            row[offset / 8] |= index << (8 - bits - offset % 8);
+        }
+    }
+
+    encode_rows(encoder, &data, row_size, 1, compression, filter)
+}
+
+/// Quantises the image and encodes it as an indexed PNG (see `quantize`
+/// and `encode_indexed_png`).
+///
+/// # Arguments
+///
+/// * `writable_buff` - Any buffer structure that implements the `Write` trait.
+/// * `image` - The image buffer (with straight alpha).
+/// * `colors` - Maximum number of colours of the palette (from 2 to 256).
+/// * `dither` - Whether to dither the image (Floyd–Steinberg).
+/// * `compression` - Compression to use in the encoding.
+/// * `filter` - Filter type to use in the encoding.
+pub fn encode_png8(
+    writable_buff: impl Write,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    colors: u16,
+    dither: bool,
+    compression: Compression,
+    filter: FilterType,
+) -> Result<(), PConvertError> {
+    let indexed = quantize(image, colors, dither)?;
+    encode_indexed_png(writable_buff, &indexed, compression, filter)
+}
+
+/// Fully transparent pixels are all the same colour.
+fn normalize(color: [u8; 4]) -> [u8; 4] {
+    match color[3] {
+        0 => [0, 0, 0, 0],
+        _ => color,
+    }
+}
+
+/// Splits the colours (with their number of pixels) into `colors` boxes,
+/// always splitting the box with the widest channel range at the median
+/// (by pixels) of that channel, the palette being the (weighted) mean
+/// colour of every box.
+fn median_cut(entries: Vec<([u8; 4], u32)>, colors: usize) -> Vec<[u8; 4]> {
+    let mut boxes = vec![entries];
+    while boxes.len() < colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, entries)| entries.len() > 1)
+            .map(|(index, entries)| (index, widest_channel(entries)))
+            .max_by_key(|&(_, (_, range))| range);
+        let (index, channel) = match widest {
+            Some((index, (channel, _))) => (index, channel),
+            None => break,
+        };
+
+        let mut entries = boxes.swap_remove(index);
+        entries.sort_unstable_by_key(|(color, _)| color[channel]);
+        let total: u64 = entries.iter().map(|&(_, count)| count as u64).sum();
+        let mut accumulated = 0;
+        let mut median = entries.len() - 1;
+        for (position, &(_, count)) in entries.iter().enumerate() {
+            accumulated += count as u64;
+            if accumulated * 2 >= total {
+                median = position + 1;
+                break;
+            }
+        }
+        let upper = entries.split_off(median.min(entries.len() - 1));
+        boxes.push(entries);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|entries| mean_color(entries)).collect()
+}
+
+/// The channel with the widest range of values and that range.
+fn widest_channel(entries: &[([u8; 4], u32)]) -> (usize, u8) {
+    (0..4)
+        .map(|channel| {
+            let values = entries.iter().map(|(color, _)| color[channel]);
+            let range = values.clone().max().unwrap() - values.min().unwrap();
+            (channel, range)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+fn mean_color(entries: &[([u8; 4], u32)]) -> [u8; 4] {
+    let total: u64 = entries.iter().map(|&(_, count)| count as u64).sum();
+    let mut color = [0u8; 4];
+    for (channel, value) in color.iter_mut().enumerate() {
+        let sum: u64 = entries
+            .iter()
+            .map(|(color, count)| color[channel] as u64 * *count as u64)
+            .sum();
+        *value = ((sum + total / 2) / total) as u8;
+    }
+    color
+}
+
+/// Index of the palette colour closest (by squared distance) to the colour.
+fn nearest(palette: &[[u8; 4]], color: [i32; 4]) -> u8 {
+    let distance = |entry: &[u8; 4]| -> i32 {
+        (0..4)
+            .map(|channel| (entry[channel] as i32 - color[channel]).pow(2))
+            .sum()
+    };
+    (0..palette.len())
+        .min_by_key(|&index| distance(&palette[index]))
+        .unwrap() as u8
+}
+
+fn map_nearest(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, palette: &[[u8; 4]]) -> Vec<u8> {
+    let mut cache: HashMap<[u8; 4], u8> = HashMap::new();
+    image
+        .pixels()
+        .map(|pixel| {
+            let color = normalize(pixel.0);
+            *cache
+                .entry(color)
+                .or_insert_with(|| nearest(palette, color.map(|value| value as i32)))
+        })
+        .collect()
+}
+
+/// Maps every pixel (with the error diffused into it) into the closest colour
+/// and diffuses its own error into the following pixels, 7/16 to the right,
+/// 3/16 down left, 5/16 down and 1/16 down right. Fully transparent pixels
+/// neither take nor diffuse any error, keeping the transparent areas clean.
+fn map_dithered(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, palette: &[[u8; 4]]) -> Vec<u8> {
+    let width = image.width() as usize;
+
+    // errors (in sixteenths) of the current and the next row, with
+    // a padding pixel at each side
+    let mut current = vec![[0i32; 4]; width + 2];
+    let mut next = vec![[0i32; 4]; width + 2];
+    let mut indexes = Vec::with_capacity(width * image.height() as usize);
+    let mut cache: HashMap<[i32; 4], u8> = HashMap::new();
+
+    for row in image.rows() {
+        for (x, pixel) in row.enumerate() {
+            if pixel[3] == 0 {
+                indexes.push(nearest(palette, [0; 4]));
+                continue;
+            }
+            let mut color = [0i32; 4];
+            for channel in 0..4 {
+                color[channel] =
+                    (pixel[channel] as i32 + current[x + 1][channel] / 16).clamp(0, 255);
+            }
+            let index = *cache
+                .entry(color)
+                .or_insert_with(|| nearest(palette, color));
+            indexes.push(index);
+
+            for channel in 0..4 {
+                let error = color[channel] - palette[index as usize][channel] as i32;
+                current[x + 2][channel] += error * 7;
+                next[x][channel] += error * 3;
+                next[x + 1][channel] += error * 5;
+                next[x + 2][channel] += error;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|error| *error = [0; 4]);
+    }
+    indexes
+}
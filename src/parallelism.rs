@@ -2,10 +2,12 @@
 
 use crate::constants;
 use crate::errors::PConvertError;
+use crate::format::OutputFormat;
 use crate::utils::min;
 use image::{ImageBuffer, Rgba};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{mpsc, Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
 use std::thread::{spawn, JoinHandle};
 
 /// Thread pool used in multi-threaded pconvert calls.
@@ -20,8 +22,8 @@ impl ThreadPool {
     /// Creates a thread pool with `size` worker threads.
     pub fn new(size: usize) -> Result<ThreadPool, PConvertError> {
         if size == 0 {
-            return Err(PConvertError::ArgumentError(
-                "Thread Pool size should be a positive number".to_string(),
+            return Err(PConvertError::ThreadPoolError(
+                "size should be a positive number".to_string(),
             ));
         }
 
@@ -85,6 +87,7 @@ impl ThreadPool {
     /// let result_channel = thread_pool.execute(move || ResultMessage::ImageResult(read_png_from_file(path, demultiply)));
     /// let top = match result_channel.recv().unwrap() {
     ///     ResultMessage::ImageResult(result) => result,
+    ///     _ => panic!("unexpected result message"),
     /// }.unwrap();
     /// ```
     pub fn execute<F>(&self, func: F) -> mpsc::Receiver<ResultMessage>
@@ -145,16 +148,28 @@ impl Worker {
         receiver: Arc<Mutex<mpsc::Receiver<WorkMessage>>>,
     ) -> Worker {
         let thread = spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
+            // a poisoned lock only means that another worker panicked while
+            // holding it, the receiver itself remains usable, while a receive
+            // error means that the thread pool is gone
+            let message = match receiver
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .recv()
+            {
+                Ok(message) => message,
+                Err(_) => break,
+            };
 
             match message {
                 WorkMessage::NewTask(task, result_channel_sender) => {
                     thread_pool_status.dec_queued_count();
                     thread_pool_status.inc_active_count();
 
-                    let result = task();
-
-                    result_channel_sender.send(result).unwrap_or_default();
+                    // a panicking task must not take down the worker, the result
+                    // channel is dropped instead so that the receiver gets an error
+                    if let Ok(result) = catch_unwind(AssertUnwindSafe(task)) {
+                        result_channel_sender.send(result).unwrap_or_default();
+                    }
 
                     thread_pool_status.dec_active_count();
                 }
@@ -181,6 +196,9 @@ enum WorkMessage {
 /// Result message types for `self.execute()`.
 pub enum ResultMessage {
     ImageResult(Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError>),
+    CompositionResult(Result<(), PConvertError>),
+    EncodedResult(Result<(Vec<u8>, OutputFormat), PConvertError>),
+    ChannelsResult(Vec<[f32; 4]>),
 }
 
 /// Represents the status of the thread pool (e.g. size, queued jobs, active jobs).
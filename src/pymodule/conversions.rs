@@ -1,11 +1,15 @@
 //! From and to conversions for rust and python types.
 
 use crate::blending::params::Value;
+use crate::cache::CacheStats;
+use crate::compare::Comparison;
 use crate::errors::PConvertError;
+use crate::optimize::OptimizeReport;
 use crate::parallelism::ThreadPoolStatus;
+use crate::pymodule::exceptions;
 use pyo3::conversion::FromPyObject;
 use pyo3::exceptions::{
-    PyAttributeError, PyException, PyIOError, PyNotImplementedError, PyTypeError,
+    PyAttributeError, PyBaseException, PyIOError, PyNotImplementedError, PyTypeError,
 };
 use pyo3::prelude::*;
 use pyo3::types::{IntoPyDict, PyBool, PyDict, PyFloat, PyInt, PyLong, PyString};
@@ -13,17 +17,62 @@ use pyo3::PyErr;
 
 impl From<PConvertError> for PyErr {
     fn from(err: PConvertError) -> PyErr {
+        let message = err.to_string();
         match err {
-            PConvertError::ArgumentError(err) => PyAttributeError::new_err(err),
-            PConvertError::ImageLibError(err) => PyException::new_err(err.to_string()),
-            PConvertError::UnsupportedImageTypeError => {
-                PyNotImplementedError::new_err(err.to_string())
+            PConvertError::ArgumentError(_) => PyAttributeError::new_err(message),
+            PConvertError::ImageLibError(_) => exceptions::PConvertError::new_err(message),
+            PConvertError::UnsupportedImageTypeError => PyNotImplementedError::new_err(message),
+            PConvertError::IOError(_) => PyIOError::new_err(message),
+            PConvertError::DimensionMismatchError {
+                layer,
+                expected,
+                found,
+            } => with_context(
+                exceptions::DimensionMismatchError::new_err(message),
+                |value| {
+                    value.setattr("layer", layer)?;
+                    value.setattr("expected", expected)?;
+                    value.setattr("found", found)
+                },
+            ),
+            PConvertError::UnknownAlgorithmError(algorithm) => with_context(
+                exceptions::UnknownAlgorithmError::new_err(message),
+                |value| value.setattr("algorithm", algorithm),
+            ),
+            PConvertError::InvalidParameterError { name, .. } => with_context(
+                exceptions::InvalidParameterError::new_err(message),
+                |value| value.setattr("name", name),
+            ),
+            PConvertError::DecodeError { path, layer, .. } => {
+                with_context(exceptions::DecodeError::new_err(message), |value| {
+                    value.setattr("path", path)?;
+                    value.setattr("layer", layer)
+                })
+            }
+            PConvertError::EncodeError { path, .. } => {
+                with_context(exceptions::EncodeError::new_err(message), |value| {
+                    value.setattr("path", path)
+                })
+            }
+            PConvertError::ThreadPoolError(_) => exceptions::ThreadPoolError::new_err(message),
+            PConvertError::LimitExceededError { limit, .. } => {
+                with_context(exceptions::LimitExceededError::new_err(message), |value| {
+                    value.setattr("limit", limit)
+                })
             }
-            PConvertError::IOError(err) => PyIOError::new_err(err.to_string()),
         }
     }
 }
 
+/// Sets the context of the error as attributes of the exception
+/// value (e.g. the path of the file that failed decoding).
+fn with_context(err: PyErr, context: impl FnOnce(&PyBaseException) -> PyResult<()>) -> PyErr {
+    Python::with_gil(|py| match context(err.value(py)) {
+        Ok(()) => err,
+        Err(context_err) => context_err,
+    })
+}
+
 impl FromPyObject<'_> for Value {
     fn extract(ob: &'_ PyAny) -> PyResult<Self> {
         if let Ok(boolean) = ob.cast_as::<PyBool>() {
@@ -59,3 +108,57 @@ impl IntoPyDict for ThreadPoolStatus {
         py_dict
     }
 }
+
+impl IntoPyDict for CacheStats {
+    fn into_py_dict(self, py: Python<'_>) -> &PyDict {
+        let py_dict = PyDict::new(py);
+
+        py_dict.set_item("hits", self.hits()).unwrap();
+        py_dict.set_item("misses", self.misses()).unwrap();
+        py_dict.set_item("entries", self.entries()).unwrap();
+        py_dict.set_item("size", self.size()).unwrap();
+        py_dict.set_item("capacity", self.capacity()).unwrap();
+
+        py_dict
+    }
+}
+
+impl IntoPyDict for Comparison {
+    fn into_py_dict(self, py: Python<'_>) -> &PyDict {
+        let py_dict = PyDict::new(py);
+
+        py_dict
+            .set_item("max_difference", self.max_difference())
+            .unwrap();
+        py_dict
+            .set_item("mean_difference", self.mean_difference())
+            .unwrap();
+        py_dict.set_item("psnr", self.psnr()).unwrap();
+        py_dict.set_item("ssim", self.ssim()).unwrap();
+
+        py_dict
+    }
+}
+
+impl IntoPyDict for OptimizeReport {
+    fn into_py_dict(self, py: Python<'_>) -> &PyDict {
+        let py_dict = PyDict::new(py);
+
+        py_dict
+            .set_item("original_size", self.original_size)
+            .unwrap();
+        py_dict.set_item("size", self.size).unwrap();
+        py_dict.set_item("saving", self.saving()).unwrap();
+        py_dict
+            .set_item("saving_percent", self.saving_percent())
+            .unwrap();
+        py_dict
+            .set_item(
+                "settings",
+                self.candidate.map(|candidate| candidate.to_string()),
+            )
+            .unwrap();
+
+        py_dict
+    }
+}
@@ -0,0 +1,80 @@
+//! Python exception types raised by the extension.
+
+// the exception types created by the `pyo3` macro check the `addr_of`
+// configuration of `pyo3` itself, which is unknown to this crate
+#![allow(unexpected_cfgs)]
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+
+create_exception!(
+    pconvert_rust,
+    PConvertError,
+    PyException,
+    "Base class of the errors specific to pconvert."
+);
+create_exception!(
+    pconvert_rust,
+    DimensionMismatchError,
+    PConvertError,
+    "A layer does not have the same dimensions as the layers below it."
+);
+create_exception!(
+    pconvert_rust,
+    UnknownAlgorithmError,
+    PConvertError,
+    "The requested blending algorithm does not exist."
+);
+create_exception!(
+    pconvert_rust,
+    InvalidParameterError,
+    PConvertError,
+    "A parameter or option has an invalid value."
+);
+create_exception!(
+    pconvert_rust,
+    DecodeError,
+    PConvertError,
+    "Failure decoding an image (a layer)."
+);
+create_exception!(
+    pconvert_rust,
+    EncodeError,
+    PConvertError,
+    "Failure encoding an image."
+);
+create_exception!(
+    pconvert_rust,
+    ThreadPoolError,
+    PConvertError,
+    "Failure in the thread pool used for parallel operations."
+);
+create_exception!(
+    pconvert_rust,
+    LimitExceededError,
+    PConvertError,
+    "A resource limit (e.g. the dimensions of an image) would be exceeded."
+);
+
+/// Adds the exception types to the given python module.
+pub fn register(py: Python, module: &PyModule) -> PyResult<()> {
+    module.add("PConvertError", py.get_type::<PConvertError>())?;
+    module.add(
+        "DimensionMismatchError",
+        py.get_type::<DimensionMismatchError>(),
+    )?;
+    module.add(
+        "UnknownAlgorithmError",
+        py.get_type::<UnknownAlgorithmError>(),
+    )?;
+    module.add(
+        "InvalidParameterError",
+        py.get_type::<InvalidParameterError>(),
+    )?;
+    module.add("DecodeError", py.get_type::<DecodeError>())?;
+    module.add("EncodeError", py.get_type::<EncodeError>())?;
+    module.add("ThreadPoolError", py.get_type::<ThreadPoolError>())?;
+    module.add("LimitExceededError", py.get_type::<LimitExceededError>())?;
+    Ok(())
+}
@@ -2,33 +2,53 @@
 #![allow(clippy::borrow_deref_ref)]
 
 pub mod conversions;
+pub mod exceptions;
 pub mod utils;
 
+use crate::apng::{
+    blend_animations, is_apng_file, read_animation_from_file_with_limits, write_apng_to_file,
+};
+use crate::blending::dimensions::{conform_layer, DimensionPolicy};
 use crate::blending::params::{BlendAlgorithmParams, Options};
 use crate::blending::{
     blend_images, demultiply_image, get_blending_algorithm, is_algorithm_multiplied, BlendAlgorithm,
 };
+use crate::cache::{LayerCache, DEFAULT_CACHE_CAPACITY};
+use crate::compare::{compare, diff_heatmap};
 use crate::constants;
+use crate::encoding::Compression;
 use crate::errors::PConvertError;
+use crate::format::{write_image_parallel, write_image_to_file};
+use crate::layer::LayerOptions;
+use crate::limits::Limits;
+use crate::optimize::optimize_file;
 use crate::parallelism::{ResultMessage, ThreadPool};
-use crate::utils::{read_png_from_file, write_png_parallel, write_png_to_file};
+use crate::resize::resize_output;
+use crate::stream::blend_files_streaming;
+use crate::utils::{read_image_from_file, read_image_from_file_with_limits, write_png_to_file};
+use image::codecs::png::FilterType;
+use image::{ImageBuffer, Rgba};
 use pyo3::exceptions::PyException;
 use pyo3::prelude::*;
 use pyo3::types::{IntoPyDict, PyDict, PySequence};
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, OnceLock};
 use utils::{
-    build_algorithm, build_params, get_compression_type, get_filter_type, get_num_threads,
+    build_algorithm, build_layers, build_params, get_cache_enabled, get_cache_size,
+    get_compression_type, get_dimension_policy, get_filter_type, get_limits, get_num_threads,
+    get_output_options, get_resize_options, get_stream_band_height,
 };
 
 static mut THREAD_POOL: Option<ThreadPool> = None;
+static LAYER_CACHE: OnceLock<Arc<LayerCache>> = OnceLock::new();
 
 #[pymodule]
-fn pconvert_rust(_py: Python, module: &PyModule) -> PyResult<()> {
+fn pconvert_rust(py: Python, module: &PyModule) -> PyResult<()> {
     unsafe {
-        let mut thread_pool = ThreadPool::new(constants::DEFAULT_THREAD_POOL_SIZE).unwrap();
+        let mut thread_pool = ThreadPool::new(constants::DEFAULT_THREAD_POOL_SIZE)?;
         thread_pool.start();
         THREAD_POOL = Some(thread_pool);
     }
+    LAYER_CACHE.get_or_init(|| Arc::new(LayerCache::new(DEFAULT_CACHE_CAPACITY)));
 
     module.add("COMPILATION_DATE", constants::COMPILATION_DATE)?;
     module.add("COMPILATION_TIME", constants::COMPILATION_TIME)?;
@@ -54,6 +74,8 @@ fn pconvert_rust(_py: Python, module: &PyModule) -> PyResult<()> {
         .collect();
     module.add("COMPRESSION_TYPES", compressions)?;
 
+    exceptions::register(py, module)?;
+
     #[pyfunction]
     #[pyo3(name = "blend_images")]
     fn blend_images_py(
@@ -68,6 +90,17 @@ fn pconvert_rust(_py: Python, module: &PyModule) -> PyResult<()> {
         // blends two images using either the single-threaded or the multiple-threaded version
         // taking into consideration the requested number of thread in options
         py.allow_threads(|| -> PyResult<()> {
+            if is_apng_file(&bot_path) || is_apng_file(&top_path) {
+                let algorithm = algorithm.unwrap_or_else(|| String::from("multiplicative"));
+                return blend_animated(
+                    vec![bot_path, top_path],
+                    vec![LayerOptions::default(); 2],
+                    target_path,
+                    vec![(build_algorithm(&algorithm)?, None)],
+                    options,
+                );
+            }
+
             let num_threads = get_num_threads(&options);
             if num_threads == 0 {
                 blend_images_single_thread(
@@ -106,23 +139,75 @@ fn pconvert_rust(_py: Python, module: &PyModule) -> PyResult<()> {
         options: Option<Options>,
     ) -> PyResult<()> {
         // parses python types to rust owned values so that they are safely shared between threads
-        let img_paths: Vec<String> = img_paths.extract()?;
+        let (img_paths, layers_options) = build_layers(img_paths)?;
         let num_images = img_paths.len();
 
         let algorithms_to_apply: Vec<(BlendAlgorithm, Option<BlendAlgorithmParams>)> =
             match (algorithms, algorithm) {
-                (Some(algorithms), _) if algorithms.len().unwrap() > 0 => build_params(algorithms)?,
-                (_, Some(algorithm)) => vec![(build_algorithm(&algorithm)?, None); num_images - 1],
-                _ => vec![(BlendAlgorithm::Multiplicative, None); num_images - 1],
+                (Some(algorithms), _) if algorithms.len()? > 0 => build_params(algorithms)?,
+                (_, Some(algorithm)) => {
+                    vec![(build_algorithm(&algorithm)?, None); num_images.saturating_sub(1)]
+                }
+                _ => vec![(BlendAlgorithm::Multiplicative, None); num_images.saturating_sub(1)],
             };
 
+        // the streaming composition blends the layers band by band, so it is only
+        // possible when the whole layers are not needed (e.g. to be transformed)
+        if let Some(band_height) = get_stream_band_height(&options)? {
+            if get_dimension_policy(&options)? != DimensionPolicy::Error
+                || get_resize_options(&options)?.is_some()
+                || layers_options.iter().any(|options| !options.is_empty())
+            {
+                return Err(PyErr::from(PConvertError::ArgumentError(
+                    "'stream' does not support transforms, effects, resizing or dimension policies"
+                        .to_string(),
+                )));
+            }
+            if !get_output_options(&options, &out_path)?.is_plain_png() {
+                return Err(PyErr::from(PConvertError::ArgumentError(
+                    "'stream' only supports the PNG format (without 'colors' or 'optimize')"
+                        .to_string(),
+                )));
+            }
+            if img_paths.iter().any(|path| is_apng_file(path)) {
+                return Err(PyErr::from(PConvertError::ArgumentError(
+                    "'stream' does not support animated layers".to_string(),
+                )));
+            }
+            let limits = get_limits(&options)?;
+            let compression_type = get_compression_type(&options)?;
+            let filter_type = get_filter_type(&options)?;
+            return py.allow_threads(|| -> PyResult<()> {
+                Ok(blend_files_streaming(
+                    &img_paths,
+                    out_path,
+                    &algorithms_to_apply,
+                    compression_type,
+                    filter_type,
+                    band_height,
+                    &limits,
+                )?)
+            });
+        }
+
         // blends multiple images using either the single-threaded or the multiple-threaded version
         // taking into consideration the requested number of thread in options
         py.allow_threads(|| -> PyResult<()> {
+            if img_paths.iter().any(|path| is_apng_file(path)) {
+                return blend_animated(
+                    img_paths,
+                    layers_options,
+                    out_path,
+                    algorithms_to_apply,
+                    options,
+                );
+            }
+
             let num_threads = get_num_threads(&options);
             if num_threads == 0 {
                 blend_multiple_single_thread(
                     img_paths,
+                    layers_options,
                     out_path,
                     algorithms_to_apply,
                     is_inline,
@@ -132,6 +217,7 @@ fn pconvert_rust(_py: Python, module: &PyModule) -> PyResult<()> {
                 unsafe {
                     blend_multiple_multi_thread(
                         img_paths,
+                        layers_options,
                         out_path,
                         algorithms_to_apply,
                         is_inline,
@@ -143,6 +229,56 @@ fn pconvert_rust(_py: Python, module: &PyModule) -> PyResult<()> {
         })
     }
 
+    #[pyfunction]
+    #[pyo3(name = "compare")]
+    fn compare_py(
+        py: Python<'_>,
+        first_path: String,
+        second_path: String,
+        tolerance: Option<u8>,
+        heatmap_path: Option<String>,
+    ) -> PyResult<&PyDict> {
+        // compares both images (and writes the heatmap of the differences
+        // when requested), the matches entry takes the tolerance into account
+        let comparison = py.allow_threads(|| -> PyResult<_> {
+            let first = read_image_from_file(first_path, false)?;
+            let second = read_image_from_file(second_path, false)?;
+            let comparison = compare(&first, &second)?;
+            if let Some(heatmap_path) = heatmap_path {
+                let heatmap = diff_heatmap(&first, &second)?;
+                write_png_to_file(
+                    heatmap_path,
+                    &heatmap,
+                    Compression::FAST,
+                    FilterType::NoFilter,
+                )?;
+            }
+            Ok(comparison)
+        })?;
+        let matches = comparison.matches(tolerance.unwrap_or(0));
+        let comparison_dict = comparison.into_py_dict(py);
+        comparison_dict.set_item("matches", matches)?;
+        Ok(comparison_dict)
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "optimize")]
+    fn optimize_py(
+        py: Python<'_>,
+        file_in: String,
+        file_out: Option<String>,
+        options: Option<Options>,
+    ) -> PyResult<&PyDict> {
+        // optimizes the image in place unless an output path is given,
+        // never writing an output larger than an original PNG
+        let report = py.allow_threads(|| -> PyResult<_> {
+            let file_out = file_out.unwrap_or_else(|| file_in.clone());
+            let limits = get_limits(&options)?;
+            Ok(optimize_file(&file_in, &file_out, &limits)?)
+        })?;
+        Ok(report.into_py_dict(py))
+    }
+
     #[pyfunction]
     #[pyo3(name = "get_thread_pool_status")]
     fn get_thread_pool_status(py: Python) -> PyResult<&PyDict> {
@@ -159,9 +295,38 @@ fn pconvert_rust(_py: Python, module: &PyModule) -> PyResult<()> {
         }
     }
 
+    #[pyfunction]
+    #[pyo3(name = "get_cache_stats")]
+    fn get_cache_stats(py: Python<'_>) -> PyResult<&PyDict> {
+        match LAYER_CACHE.get() {
+            Some(cache) => Ok(cache.get_stats().into_py_dict(py)),
+            None => Err(PyException::new_err(
+                "Accessing global layer cache".to_string(),
+            )),
+        }
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "clear_cache")]
+    fn clear_cache() -> PyResult<()> {
+        match LAYER_CACHE.get() {
+            Some(cache) => {
+                cache.clear();
+                Ok(())
+            }
+            None => Err(PyException::new_err(
+                "Accessing global layer cache".to_string(),
+            )),
+        }
+    }
+
     module.add_function(wrap_pyfunction!(blend_images_py, module)?)?;
     module.add_function(wrap_pyfunction!(blend_multiple_py, module)?)?;
+    module.add_function(wrap_pyfunction!(compare_py, module)?)?;
+    module.add_function(wrap_pyfunction!(optimize_py, module)?)?;
     module.add_function(wrap_pyfunction!(get_thread_pool_status, module)?)?;
+    module.add_function(wrap_pyfunction!(get_cache_stats, module)?)?;
+    module.add_function(wrap_pyfunction!(clear_cache, module)?)?;
 
     Ok(())
 }
@@ -182,14 +347,26 @@ fn blend_images_single_thread(
     let demultiply = is_algorithm_multiplied(&algorithm);
     let algorithm_fn = get_blending_algorithm(&algorithm);
 
-    let mut bot = read_png_from_file(bot_path, demultiply)?;
-    let top = read_png_from_file(top_path, demultiply)?;
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    let limits = get_limits(&options)?;
+    let output = get_output_options(&options, &target_path)?;
+
+    let cache = get_layer_cache(&options);
+    let mut bot = read_layer(bot_path, false, &cache, &limits).map_err(|err| err.with_layer(0))?;
+    let top = read_layer(top_path, false, &cache, &limits).map_err(|err| err.with_layer(1))?;
+    let mut top = conform_layer(top, bot.dimensions(), 1, &dimension_policy)?;
+    if demultiply {
+        demultiply_image(&mut bot);
+        demultiply_image(&mut top);
+    }
 
     blend_images(&mut bot, &top, &algorithm_fn, &None);
 
-    let compression_type = get_compression_type(&options);
-    let filter_type = get_filter_type(&options);
-    write_png_to_file(target_path, &bot, compression_type, filter_type)?;
+    let compression_type = get_compression_type(&options)?;
+    let filter_type = get_filter_type(&options)?;
+    let bot = resize_output(bot, &resize, &limits)?;
+    write_image_to_file(target_path, &bot, compression_type, filter_type, &output)?;
 
     Ok(())
 }
@@ -208,38 +385,54 @@ unsafe fn blend_images_multi_thread(
     let _is_inline = is_inline.unwrap_or(false);
     let demultiply = is_algorithm_multiplied(&algorithm);
     let algorithm_fn = get_blending_algorithm(&algorithm);
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    let limits = get_limits(&options)?;
+    let output = get_output_options(&options, &target_path)?;
 
     let thread_pool = match &mut THREAD_POOL {
         Some(thread_pool) => thread_pool,
-        None => panic!("Unable to access global pconvert thread pool"),
+        None => {
+            return Err(PyErr::from(PConvertError::ThreadPoolError(
+                "unable to access global pconvert thread pool".to_string(),
+            )))
+        }
     };
 
     // expands thread pool to the desired number of threads/parallelism (if necessary and possible)
     thread_pool.expand_to(num_threads);
 
-    let bot_result_channel = thread_pool
-        .execute(move || ResultMessage::ImageResult(read_png_from_file(bot_path, demultiply)));
-    let top_result_channel = thread_pool
-        .execute(move || ResultMessage::ImageResult(read_png_from_file(top_path, demultiply)));
-
-    let mut bot = match bot_result_channel.recv().unwrap() {
-        ResultMessage::ImageResult(result) => result,
-    }?;
-    let top = match top_result_channel.recv().unwrap() {
-        ResultMessage::ImageResult(result) => result,
-    }?;
+    let bot_cache = get_layer_cache(&options);
+    let top_cache = bot_cache.clone();
+    let (bot_limits, top_limits) = (limits.clone(), limits.clone());
+    let bot_result_channel = thread_pool.execute(move || {
+        ResultMessage::ImageResult(read_layer(bot_path, false, &bot_cache, &bot_limits))
+    });
+    let top_result_channel = thread_pool.execute(move || {
+        ResultMessage::ImageResult(read_layer(top_path, false, &top_cache, &top_limits))
+    });
+
+    let mut bot = receive_layer(&bot_result_channel, 0)?;
+    let top = receive_layer(&top_result_channel, 1)?;
+    let mut top = conform_layer(top, bot.dimensions(), 1, &dimension_policy)?;
+    if demultiply {
+        demultiply_image(&mut bot);
+        demultiply_image(&mut top);
+    }
 
     blend_images(&mut bot, &top, &algorithm_fn, &None);
 
-    let compression_type = get_compression_type(&options);
-    let filter_type = get_filter_type(&options);
-    write_png_parallel(target_path, &bot, compression_type, filter_type)?;
+    let compression_type = get_compression_type(&options)?;
+    let filter_type = get_filter_type(&options)?;
+    let bot = resize_output(bot, &resize, &limits)?;
+    write_image_parallel(target_path, &bot, compression_type, filter_type, &output)?;
 
     Ok(())
 }
 
 fn blend_multiple_single_thread(
     img_paths: Vec<String>,
+    layers_options: Vec<LayerOptions>,
     out_path: String,
     algorithms: Vec<(BlendAlgorithm, Option<BlendAlgorithmParams>)>,
     is_inline: Option<bool>,
@@ -249,18 +442,23 @@ fn blend_multiple_single_thread(
 
     if num_images < 1 {
         return Err(PyErr::from(PConvertError::ArgumentError(
-            "ArgumentError: 'img_paths' must contain at least one path".to_string(),
+            "'img_paths' must contain at least one path".to_string(),
         )));
     }
 
     if algorithms.len() != num_images - 1 {
         return Err(PyErr::from(PConvertError::ArgumentError(format!(
-            "ArgumentError: 'algorithms' must be of size {} (one per blending operation)",
+            "'algorithms' must be of size {} (one per blending operation)",
             num_images - 1
         ))));
     };
 
     let _is_inline = is_inline.unwrap_or(false);
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    let limits = get_limits(&options)?;
+    limits.check_layers(num_images)?;
+    let output = get_output_options(&options, &out_path)?;
 
     // loops through the algorithms to apply and blends the
     // current composition with the next layer
@@ -271,14 +469,31 @@ fn blend_multiple_single_thread(
     } else {
         false
     };
-    let mut composition = read_png_from_file(first_path, first_demultiply)?;
+    let cache = get_layer_cache(&options);
+    let composition =
+        read_layer(first_path, false, &cache, &limits).map_err(|err| err.with_layer(0))?;
+    let mut composition = layers_options[0].apply_with_limits(composition, &limits)?;
+    if first_demultiply {
+        demultiply_image(&mut composition);
+    }
     let zip_iter = img_paths_iter.zip(algorithms.iter());
-    for pair in zip_iter {
+    for (index, pair) in zip_iter.enumerate() {
         let path = pair.0.to_string();
         let (algorithm, algorithm_params) = pair.1;
         let demultiply = is_algorithm_multiplied(algorithm);
         let algorithm_fn = get_blending_algorithm(algorithm);
-        let current_layer = read_png_from_file(path, demultiply)?;
+        let current_layer =
+            read_layer(path, false, &cache, &limits).map_err(|err| err.with_layer(index + 1))?;
+        let current_layer = layers_options[index + 1].apply_with_limits(current_layer, &limits)?;
+        let mut current_layer = conform_layer(
+            current_layer,
+            composition.dimensions(),
+            index + 1,
+            &dimension_policy,
+        )?;
+        if demultiply {
+            demultiply_image(&mut current_layer);
+        }
         blend_images(
             &mut composition,
             &current_layer,
@@ -287,15 +502,23 @@ fn blend_multiple_single_thread(
         );
     }
 
-    let compression_type = get_compression_type(&options);
-    let filter_type = get_filter_type(&options);
-    write_png_to_file(out_path, &composition, compression_type, filter_type)?;
+    let compression_type = get_compression_type(&options)?;
+    let filter_type = get_filter_type(&options)?;
+    let composition = resize_output(composition, &resize, &limits)?;
+    write_image_to_file(
+        out_path,
+        &composition,
+        compression_type,
+        filter_type,
+        &output,
+    )?;
 
     Ok(())
 }
 
 unsafe fn blend_multiple_multi_thread(
     img_paths: Vec<String>,
+    layers_options: Vec<LayerOptions>,
     out_path: String,
     algorithms: Vec<(BlendAlgorithm, Option<BlendAlgorithmParams>)>,
     is_inline: Option<bool>,
@@ -306,31 +529,46 @@ unsafe fn blend_multiple_multi_thread(
 
     if num_images < 1 {
         return Err(PyErr::from(PConvertError::ArgumentError(
-            "ArgumentError: 'img_paths' must contain at least one path".to_string(),
+            "'img_paths' must contain at least one path".to_string(),
         )));
     }
 
     if algorithms.len() != num_images - 1 {
         return Err(PyErr::from(PConvertError::ArgumentError(format!(
-            "ArgumentError: 'algorithms' must be of size {} (one per blending operation)",
+            "'algorithms' must be of size {} (one per blending operation)",
             num_images - 1
         ))));
     };
 
     let _is_inline = is_inline.unwrap_or(false);
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    let limits = get_limits(&options)?;
+    limits.check_layers(num_images)?;
+    let output = get_output_options(&options, &out_path)?;
 
     let thread_pool = match &mut THREAD_POOL {
         Some(thread_pool) => thread_pool,
-        None => panic!("Unable to access global pconvert thread pool"),
+        None => {
+            return Err(PyErr::from(PConvertError::ThreadPoolError(
+                "unable to access global pconvert thread pool".to_string(),
+            )))
+        }
     };
 
     // expands thread pool to the desired number of threads/parallelism (if necessary and possible)
     thread_pool.expand_to(num_threads);
 
+    let cache = get_layer_cache(&options);
     let mut png_channels: Vec<mpsc::Receiver<ResultMessage>> = Vec::with_capacity(num_images);
-    for path in img_paths.into_iter() {
+    for (path, layer_options) in img_paths.into_iter().zip(layers_options) {
+        let cache = cache.clone();
+        let limits = limits.clone();
         let result_channel = thread_pool.execute(move || -> ResultMessage {
-            ResultMessage::ImageResult(read_png_from_file(path, false))
+            ResultMessage::ImageResult(
+                read_layer(path, false, &cache, &limits)
+                    .and_then(|layer| layer_options.apply_with_limits(layer, &limits)),
+            )
         });
         png_channels.push(result_channel);
     }
@@ -341,9 +579,7 @@ unsafe fn blend_multiple_multi_thread(
         false
     };
 
-    let mut composition = match png_channels[0].recv().unwrap() {
-        ResultMessage::ImageResult(result) => result,
-    }?;
+    let mut composition = receive_layer(&png_channels[0], 0)?;
     if first_demultiply {
         demultiply_image(&mut composition)
     }
@@ -355,9 +591,13 @@ unsafe fn blend_multiple_multi_thread(
         let (algorithm, algorithm_params) = &algorithms[i - 1];
         let demultiply = is_algorithm_multiplied(algorithm);
         let algorithm_fn = get_blending_algorithm(algorithm);
-        let mut current_layer = match png_channels[i].recv().unwrap() {
-            ResultMessage::ImageResult(result) => result,
-        }?;
+        let current_layer = receive_layer(&png_channels[i], i)?;
+        let mut current_layer = conform_layer(
+            current_layer,
+            composition.dimensions(),
+            i,
+            &dimension_policy,
+        )?;
         if demultiply {
             demultiply_image(&mut current_layer)
         }
@@ -370,9 +610,112 @@ unsafe fn blend_multiple_multi_thread(
         );
     }
 
-    let compression_type = get_compression_type(&options);
-    let filter_type = get_filter_type(&options);
-    write_png_parallel(out_path, &composition, compression_type, filter_type)?;
+    let compression_type = get_compression_type(&options)?;
+    let filter_type = get_filter_type(&options)?;
+    let composition = resize_output(composition, &resize, &limits)?;
+    write_image_parallel(
+        out_path,
+        &composition,
+        compression_type,
+        filter_type,
+        &output,
+    )?;
+
+    Ok(())
+}
+
+/// Blends the layers frame by frame into an animated PNG, for compositions
+/// with any animated layer (static layers being the same in every frame).
+fn blend_animated(
+    img_paths: Vec<String>,
+    layers_options: Vec<LayerOptions>,
+    out_path: String,
+    algorithms: Vec<(BlendAlgorithm, Option<BlendAlgorithmParams>)>,
+    options: Option<Options>,
+) -> PyResult<()> {
+    if algorithms.len() != img_paths.len() - 1 {
+        return Err(PyErr::from(PConvertError::ArgumentError(format!(
+            "'algorithms' must be of size {} (one per blending operation)",
+            img_paths.len() - 1
+        ))));
+    };
+    if !get_output_options(&options, &out_path)?.is_plain_png() {
+        return Err(PyErr::from(PConvertError::ArgumentError(
+            "animated compositions only support the PNG format (without 'colors' or 'optimize')"
+                .to_string(),
+        )));
+    }
+
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    let limits = get_limits(&options)?;
+    limits.check_layers(img_paths.len())?;
+
+    let layers = img_paths
+        .into_iter()
+        .zip(layers_options.iter())
+        .enumerate()
+        .map(|(index, (path, layer_options))| {
+            read_animation_from_file_with_limits(path, &limits)
+                .and_then(|layer| {
+                    layer.map_frames(|frame| layer_options.apply_with_limits(frame, &limits))
+                })
+                .map_err(|err| err.with_layer(index))
+        })
+        .collect::<Result<Vec<_>, PConvertError>>()?;
+    let composition = blend_animations(layers, &algorithms, &dimension_policy, &limits)?;
+    let composition = composition.map_frames(|frame| resize_output(frame, &resize, &limits))?;
+    write_apng_to_file(
+        out_path,
+        &composition,
+        get_compression_type(&options)?,
+        get_filter_type(&options)?,
+    )?;
 
     Ok(())
 }
+
+/// Returns the global layer cache if its usage was requested in the
+/// options, updating its capacity when `cache_size` is given.
+fn get_layer_cache(options: &Option<Options>) -> Option<Arc<LayerCache>> {
+    if !get_cache_enabled(options) {
+        return None;
+    }
+
+    let cache = LAYER_CACHE.get()?.clone();
+    if let Some(cache_size) = get_cache_size(options) {
+        cache.set_capacity(cache_size);
+    }
+    Some(cache)
+}
+
+/// Reads a PNG from the local file system, within the given limits,
+/// going through the layer cache when one is provided.
+fn read_layer(
+    path: String,
+    demultiply: bool,
+    cache: &Option<Arc<LayerCache>>,
+    limits: &Limits,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    match cache {
+        Some(cache) => Ok((*cache.read_image_with_limits(&path, demultiply, limits)?).clone()),
+        None => read_image_from_file_with_limits(path, demultiply, limits),
+    }
+}
+
+/// Waits for the decoded layer with the given index from its
+/// thread pool result channel.
+fn receive_layer(
+    channel: &mpsc::Receiver<ResultMessage>,
+    index: usize,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    match channel.recv() {
+        Ok(ResultMessage::ImageResult(result)) => result.map_err(|err| err.with_layer(index)),
+        Ok(_) => Err(PConvertError::ThreadPoolError(
+            "unexpected thread pool result message".to_string(),
+        )),
+        Err(_) => Err(PConvertError::ThreadPoolError(
+            "thread pool worker disconnected".to_string(),
+        )),
+    }
+}
@@ -1,24 +1,76 @@
 //! Utility functions for argument parsing from python input to inner-crate rust types.
 
+use crate::blending::dimensions::DimensionPolicy;
 use crate::blending::params::{BlendAlgorithmParams, Options, Value};
 use crate::blending::BlendAlgorithm;
+use crate::effects::Effect;
+use crate::encoding::{parse_filter, Compression, CompressionLevel};
 use crate::errors::PConvertError;
-use crate::utils::{image_compression_from, image_filter_from};
-use image::codecs::png::{CompressionType, FilterType};
+use crate::format::{OutputOptions, OutputValues};
+use crate::layer::LayerOptions;
+use crate::limits::{Limits, LIMIT_NAMES};
+use crate::resize::ResizeOptions;
+use crate::stream::DEFAULT_BAND_HEIGHT;
+use crate::transform::Transform;
+use image::codecs::png::FilterType;
 use pyo3::prelude::*;
-use pyo3::types::{PySequence, PyString};
+use pyo3::types::{PyDict, PySequence, PyString};
+use std::convert::TryFrom;
 use std::str::FromStr;
 
 /// Attempts to parse a `&String` to a `BlendAlgorithm`.
 /// Returns the enum variant if it succeeds. Otherwise it returns a `PyErr`.
 pub fn build_algorithm(algorithm: &str) -> Result<BlendAlgorithm, PyErr> {
-    match BlendAlgorithm::from_str(algorithm) {
-        Ok(algorithm) => Ok(algorithm),
-        Err(algorithm) => Err(PyErr::from(PConvertError::ArgumentError(format!(
-            "ArgumentError: invalid algorithm '{}'",
-            algorithm
-        )))),
+    Ok(BlendAlgorithm::from_str(algorithm)?)
+}
+
+/// Attempts to build the paths and the options of the layers from a python sequence where
+/// each layer is either a path or a dict with the `path` and (optional) `transforms` and
+/// `effects` (sequences of strings such as `"rotate:90"` or `"grayscale"`) of the layer.
+pub fn build_layers(layers: &PySequence) -> Result<(Vec<String>, Vec<LayerOptions>), PyErr> {
+    let mut paths = Vec::new();
+    let mut layers_options = Vec::new();
+
+    for i in 0..layers.len()? {
+        let element = layers.get_item(i)?;
+
+        if let Ok(layer) = element.cast_as::<PyDict>() {
+            let path = match layer.get_item("path") {
+                Some(path) => path.extract::<String>()?,
+                None => {
+                    return Err(PyErr::from(PConvertError::ArgumentError(
+                        "layers must have a 'path'".to_string(),
+                    )))
+                }
+            };
+            let transforms = match layer.get_item("transforms") {
+                Some(transforms) => transforms
+                    .extract::<Vec<String>>()?
+                    .iter()
+                    .map(|transform| Transform::from_str(transform))
+                    .collect::<Result<Vec<_>, PConvertError>>()?,
+                None => Vec::new(),
+            };
+            let effects = match layer.get_item("effects") {
+                Some(effects) => effects
+                    .extract::<Vec<String>>()?
+                    .iter()
+                    .map(|effect| Effect::from_str(effect))
+                    .collect::<Result<Vec<_>, PConvertError>>()?,
+                None => Vec::new(),
+            };
+            paths.push(path);
+            layers_options.push(LayerOptions {
+                transforms,
+                effects,
+            });
+        } else {
+            paths.push(element.extract::<String>()?);
+            layers_options.push(LayerOptions::default());
+        }
     }
+
+    Ok((paths, layers_options))
 }
 
 /// Attempts to build a vector of blending operations and extra parameters.
@@ -53,8 +105,9 @@ pub fn build_params(
                     }
                 }
             } else {
-                return Err(PyErr::from(PConvertError::ArgumentError(
-                    "Parameters should be given as a python sequence object".to_string(),
+                return Err(PyErr::from(PConvertError::invalid_parameter(
+                    "params",
+                    "should be given as a python sequence object",
                 )));
             }
 
@@ -65,30 +118,50 @@ pub fn build_params(
     Ok(result)
 }
 
-/// Retrieves the `image::codecs::png::CompressionType` value from the `Options` map if it exists.
-/// Otherwise it returns the default value: `CompressionType::Fast`.
-pub fn get_compression_type(options: &Option<Options>) -> CompressionType {
-    options.clone().map_or(CompressionType::Fast, |options| {
-        options
-            .get("compression")
-            .map_or(CompressionType::Fast, |compression| match compression {
-                Value::Str(compression) => image_compression_from(compression.to_string()),
-                _ => CompressionType::Fast,
-            })
-    })
+/// Retrieves the `Compression` from the `compression` (a name or a level from
+/// 0 to 9) and `strategy` values of the `Options` map if they exist.
+/// Otherwise it returns the default value: `Compression::FAST`.
+pub fn get_compression_type(options: &Option<Options>) -> Result<Compression, PConvertError> {
+    let get = |name: &str| options.as_ref().and_then(|options| options.get(name));
+    let level = match get("compression") {
+        Some(Value::Str(name)) => Some(CompressionLevel::Name(name)),
+        Some(Value::Int(level)) => Some(CompressionLevel::Number(*level as i64)),
+        Some(Value::Long(level)) => Some(CompressionLevel::Number(*level)),
+        Some(Value::UInt(level)) => Some(CompressionLevel::Number(
+            i64::try_from(*level).unwrap_or(i64::MAX),
+        )),
+        Some(_) => {
+            return Err(PConvertError::invalid_parameter(
+                "compression",
+                "expected a string or an integer",
+            ))
+        }
+        None => None,
+    };
+    let strategy = match get("strategy") {
+        Some(Value::Str(strategy)) => Some(&strategy[..]),
+        Some(_) => {
+            return Err(PConvertError::invalid_parameter(
+                "strategy",
+                "expected a string",
+            ))
+        }
+        None => None,
+    };
+    Compression::from_options(level, strategy)
 }
 
 /// Retrieves the `image::codecs::png::FilterType` value from the `Options` map if it exists.
 /// Otherwise it returns the default value: `FilterType::NoFilter`.
-pub fn get_filter_type(options: &Option<Options>) -> FilterType {
-    options.clone().map_or(FilterType::NoFilter, |options| {
-        options
-            .get("filter")
-            .map_or(FilterType::NoFilter, |filter| match filter {
-                Value::Str(filter) => image_filter_from(filter.to_string()),
-                _ => FilterType::NoFilter,
-            })
-    })
+pub fn get_filter_type(options: &Option<Options>) -> Result<FilterType, PConvertError> {
+    match options.as_ref().and_then(|options| options.get("filter")) {
+        Some(Value::Str(filter)) => parse_filter(filter),
+        Some(_) => Err(PConvertError::invalid_parameter(
+            "filter",
+            "expected a string",
+        )),
+        None => Ok(FilterType::NoFilter),
+    }
 }
 
 /// Retrieves the number of threads value from the `Options` map if it exists.
@@ -103,3 +176,169 @@ pub fn get_num_threads(options: &Option<Options>) -> usize {
             })
     })
 }
+
+/// Retrieves whether the layer cache should be used from the `Options` map if it exists.
+/// Otherwise it returns the default value: `false`.
+pub fn get_cache_enabled(options: &Option<Options>) -> bool {
+    matches!(
+        options.as_ref().and_then(|options| options.get("cache")),
+        Some(Value::Bool(true))
+    )
+}
+
+/// Retrieves the layer cache capacity (in bytes) from the `Options` map if it exists.
+pub fn get_cache_size(options: &Option<Options>) -> Option<usize> {
+    options.as_ref().and_then(|options| {
+        options
+            .get("cache_size")
+            .and_then(|cache_size| match cache_size {
+                Value::Int(cache_size) => TryFrom::try_from(*cache_size).ok(),
+                Value::Long(cache_size) => TryFrom::try_from(*cache_size).ok(),
+                Value::UInt(cache_size) => Some(*cache_size),
+                _ => None,
+            })
+    })
+}
+
+/// Retrieves the `DimensionPolicy` from the `dimension_policy` and `gravity` values of
+/// the `Options` map if they exist. Otherwise it returns the default value:
+/// `DimensionPolicy::Error`.
+pub fn get_dimension_policy(options: &Option<Options>) -> Result<DimensionPolicy, PConvertError> {
+    let get_str = |name: &str| -> Result<Option<&str>, PConvertError> {
+        match options.as_ref().and_then(|options| options.get(name)) {
+            Some(Value::Str(value)) => Ok(Some(value)),
+            Some(_) => Err(PConvertError::invalid_parameter(name, "expected a string")),
+            None => Ok(None),
+        }
+    };
+    DimensionPolicy::from_options(get_str("dimension_policy")?, get_str("gravity")?)
+}
+
+/// Retrieves the `ResizeOptions` from the `size`, `scale` and `resize_filter` values of
+/// the `Options` map if they exist. Otherwise it returns `None` (no resizing).
+pub fn get_resize_options(
+    options: &Option<Options>,
+) -> Result<Option<ResizeOptions>, PConvertError> {
+    let get = |name: &str| options.as_ref().and_then(|options| options.get(name));
+    let get_str = |name: &str| -> Result<Option<&str>, PConvertError> {
+        match get(name) {
+            Some(Value::Str(value)) => Ok(Some(value)),
+            Some(_) => Err(PConvertError::invalid_parameter(name, "expected a string")),
+            None => Ok(None),
+        }
+    };
+    let scale = match get("scale") {
+        Some(Value::Float(scale)) => Some(*scale),
+        Some(Value::Int(scale)) => Some(*scale as f64),
+        Some(Value::Long(scale)) => Some(*scale as f64),
+        Some(Value::UInt(scale)) => Some(*scale as f64),
+        Some(_) => {
+            return Err(PConvertError::invalid_parameter(
+                "scale",
+                "expected a number",
+            ))
+        }
+        None => None,
+    };
+    ResizeOptions::from_options(get_str("size")?, scale, get_str("resize_filter")?)
+}
+
+/// Retrieves the `OutputOptions` from the `format`, `quality`, `background`,
+/// `colors`, `dither` and `optimize` values of the `Options` map if they exist,
+/// inferring the format from the extension of `path` otherwise.
+pub fn get_output_options(
+    options: &Option<Options>,
+    path: &str,
+) -> Result<OutputOptions, PConvertError> {
+    let get = |name: &str| options.as_ref().and_then(|options| options.get(name));
+    let get_str = |name: &str| -> Result<Option<&str>, PConvertError> {
+        match get(name) {
+            Some(Value::Str(value)) => Ok(Some(value)),
+            Some(_) => Err(PConvertError::invalid_parameter(name, "expected a string")),
+            None => Ok(None),
+        }
+    };
+    let get_int = |name: &str| -> Result<Option<i64>, PConvertError> {
+        match get(name) {
+            Some(Value::Int(value)) => Ok(Some(*value as i64)),
+            Some(Value::Long(value)) => Ok(Some(*value)),
+            Some(Value::UInt(value)) => Ok(Some(i64::try_from(*value).unwrap_or(i64::MAX))),
+            Some(_) => Err(PConvertError::invalid_parameter(
+                name,
+                "expected an integer",
+            )),
+            None => Ok(None),
+        }
+    };
+    let get_bool = |name: &str| -> Result<Option<bool>, PConvertError> {
+        match get(name) {
+            Some(Value::Bool(value)) => Ok(Some(*value)),
+            Some(_) => Err(PConvertError::invalid_parameter(name, "expected a boolean")),
+            None => Ok(None),
+        }
+    };
+    OutputOptions::from_options(OutputValues {
+        format: get_str("format")?,
+        quality: get_int("quality")?,
+        background: get_str("background")?,
+        colors: get_int("colors")?,
+        dither: get_bool("dither")?,
+        optimize: get_bool("optimize")?,
+        path: Some(path),
+    })
+}
+
+/// Retrieves the resource `Limits` from the `max_width`, `max_height`, `max_pixels`,
+/// `max_bytes` and `max_layers` values of the `Options` map if they exist, using
+/// the default value for every limit not given.
+pub fn get_limits(options: &Option<Options>) -> Result<Limits, PConvertError> {
+    let mut limits = Limits::default();
+    let options = match options {
+        Some(options) => options,
+        None => return Ok(limits),
+    };
+    for name in LIMIT_NAMES {
+        let value = match options.get(name) {
+            Some(Value::Int(value)) => u64::try_from(*value).ok(),
+            Some(Value::Long(value)) => u64::try_from(*value).ok(),
+            Some(Value::UInt(value)) => u64::try_from(*value).ok(),
+            Some(_) => None,
+            None => continue,
+        };
+        let value = value.ok_or_else(|| {
+            PConvertError::invalid_parameter(name, "expected a non-negative integer")
+        })?;
+        limits.set(name, value)?;
+    }
+    Ok(limits)
+}
+
+/// Retrieves the height of the bands of the streaming composition when the `stream`
+/// value of the `Options` map is `true`, from the `band_height` value if it exists
+/// (otherwise `DEFAULT_BAND_HEIGHT`). Returns `None` when not streaming.
+pub fn get_stream_band_height(options: &Option<Options>) -> Result<Option<u32>, PConvertError> {
+    let options = match options {
+        Some(options) => options,
+        None => return Ok(None),
+    };
+    match options.get("stream") {
+        Some(Value::Bool(true)) => (),
+        Some(Value::Bool(false)) | None => return Ok(None),
+        Some(_) => {
+            return Err(PConvertError::invalid_parameter(
+                "stream",
+                "expected a boolean",
+            ))
+        }
+    }
+    let band_height = match options.get("band_height") {
+        Some(Value::Int(value)) => u32::try_from(*value).ok(),
+        Some(Value::Long(value)) => u32::try_from(*value).ok(),
+        Some(Value::UInt(value)) => u32::try_from(*value).ok(),
+        Some(_) => None,
+        None => Some(DEFAULT_BAND_HEIGHT),
+    };
+    band_height.map(Some).ok_or_else(|| {
+        PConvertError::invalid_parameter("band_height", "expected a non-negative integer")
+    })
+}
@@ -0,0 +1,424 @@
+//! Resampling of images to other dimensions, with quality filters
+//! applied in premultiplied alpha to avoid dark fringes.
+
+use crate::errors::PConvertError;
+use crate::limits::Limits;
+use image::{ImageBuffer, Rgba};
+use std::f32::consts::PI;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::result;
+use std::str::FromStr;
+
+/// Enumeration of the supported resampling filters, from the
+/// fastest (and lowest quality) to the slowest.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResizeFilter {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    #[default]
+    Lanczos,
+}
+
+impl ResizeFilter {
+    /// Returns the radius (in source pixels, when upscaling) of the
+    /// kernel of the filter.
+    fn support(&self) -> f32 {
+        match self {
+            ResizeFilter::Nearest => 0.5,
+            ResizeFilter::Bilinear => 1.0,
+            ResizeFilter::Bicubic => 2.0,
+            ResizeFilter::Lanczos => 3.0,
+        }
+    }
+
+    /// Returns the weight of the kernel of the filter at the given
+    /// distance from the center of the sample.
+    fn kernel(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            ResizeFilter::Nearest => {
+                if x < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            ResizeFilter::Bilinear => (1.0 - x).max(0.0),
+            // Catmull-Rom spline (cubic convolution with a = -0.5)
+            ResizeFilter::Bicubic => {
+                if x < 1.0 {
+                    (1.5 * x - 2.5) * x * x + 1.0
+                } else if x < 2.0 {
+                    ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0
+                } else {
+                    0.0
+                }
+            }
+            ResizeFilter::Lanczos => {
+                if x < 1e-6 {
+                    1.0
+                } else if x < 3.0 {
+                    let pi_x = PI * x;
+                    3.0 * pi_x.sin() * (pi_x / 3.0).sin() / (pi_x * pi_x)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for ResizeFilter {
+    type Err = PConvertError;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "nearest" => Ok(ResizeFilter::Nearest),
+            "bilinear" | "linear" => Ok(ResizeFilter::Bilinear),
+            "bicubic" | "cubic" => Ok(ResizeFilter::Bicubic),
+            "lanczos" | "lanczos3" => Ok(ResizeFilter::Lanczos),
+            _ => Err(PConvertError::invalid_parameter(
+                "resize_filter",
+                format!("unknown filter '{}'", s),
+            )),
+        }
+    }
+}
+
+impl Display for ResizeFilter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ResizeFilter::Nearest => write!(f, "nearest"),
+            ResizeFilter::Bilinear => write!(f, "bilinear"),
+            ResizeFilter::Bicubic => write!(f, "bicubic"),
+            ResizeFilter::Lanczos => write!(f, "lanczos"),
+        }
+    }
+}
+
+/// Requested size of a resized image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputSize {
+    /// Width and height of the image, when only one of them is given
+    /// the other one is computed keeping the aspect ratio.
+    Dimensions(Option<u32>, Option<u32>),
+    /// Factor applied to both the width and the height of the image.
+    Scale(f64),
+}
+
+impl OutputSize {
+    /// Computes the dimensions of an image with the given dimensions
+    /// once resized to this size.
+    pub fn dimensions(&self, dimensions: (u32, u32)) -> Result<(u32, u32), PConvertError> {
+        let (width, height) = (dimensions.0 as f64, dimensions.1 as f64);
+        let (target_width, target_height) = match *self {
+            OutputSize::Dimensions(Some(target_width), Some(target_height)) => {
+                (target_width as f64, target_height as f64)
+            }
+            OutputSize::Dimensions(Some(target_width), None) => {
+                let target_width = target_width as f64;
+                (target_width, height * target_width / width.max(1.0))
+            }
+            OutputSize::Dimensions(None, Some(target_height)) => {
+                let target_height = target_height as f64;
+                (width * target_height / height.max(1.0), target_height)
+            }
+            OutputSize::Dimensions(None, None) => (width, height),
+            OutputSize::Scale(scale) => (width * scale, height * scale),
+        };
+
+        if !(1.0..=u32::MAX as f64).contains(&target_width.round())
+            || !(1.0..=u32::MAX as f64).contains(&target_height.round())
+        {
+            return Err(PConvertError::invalid_parameter(
+                "size",
+                format!(
+                    "resizing {}x{} results in an empty or too large image",
+                    dimensions.0, dimensions.1
+                ),
+            ));
+        }
+        Ok((target_width.round() as u32, target_height.round() as u32))
+    }
+}
+
+/// Parses sizes as `<width>x<height>`, where either the width
+/// or the height may be omitted (e.g. `200x` or `x100`).
+impl FromStr for OutputSize {
+    type Err = PConvertError;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let invalid = || {
+            PConvertError::invalid_parameter(
+                "size",
+                format!("'{}' is not a valid size (expected <width>x<height>)", s),
+            )
+        };
+        let parse = |value: &str| -> Result<Option<u32>, PConvertError> {
+            match value.trim() {
+                "" => Ok(None),
+                value => match value.parse::<u32>() {
+                    Ok(value) if value > 0 => Ok(Some(value)),
+                    _ => Err(invalid()),
+                },
+            }
+        };
+
+        let (width, height) = s.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+        match (parse(width)?, parse(height)?) {
+            (None, None) => Err(invalid()),
+            (width, height) => Ok(OutputSize::Dimensions(width, height)),
+        }
+    }
+}
+
+/// Resizing to apply to the result of an operation (e.g. to the
+/// composition of a blend call), before it is encoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResizeOptions {
+    pub size: OutputSize,
+    pub filter: ResizeFilter,
+}
+
+impl ResizeOptions {
+    /// Builds the resize options from the (optional) `size`, `scale` and
+    /// `resize_filter` values, as given in the options of the external APIs.
+    /// Returns `None` when no resizing was requested.
+    pub fn from_options(
+        size: Option<&str>,
+        scale: Option<f64>,
+        filter: Option<&str>,
+    ) -> Result<Option<ResizeOptions>, PConvertError> {
+        let size = match (size, scale) {
+            (Some(_), Some(_)) => {
+                return Err(PConvertError::invalid_parameter(
+                    "size",
+                    "'size' and 'scale' can not be used together",
+                ))
+            }
+            (Some(size), None) => OutputSize::from_str(size)?,
+            (None, Some(scale)) if scale.is_finite() && scale > 0.0 => OutputSize::Scale(scale),
+            (None, Some(scale)) => {
+                return Err(PConvertError::invalid_parameter(
+                    "scale",
+                    format!("'{}' is not a positive number", scale),
+                ))
+            }
+            (None, None) => return Ok(None),
+        };
+        let filter = match filter {
+            Some(filter) => ResizeFilter::from_str(filter)?,
+            None => ResizeFilter::default(),
+        };
+        Ok(Some(ResizeOptions { size, filter }))
+    }
+
+    /// Resizes the image according to these options.
+    pub fn apply(
+        &self,
+        image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+        let (width, height) = self.size.dimensions(image.dimensions())?;
+        Ok(resize(image, width, height, self.filter))
+    }
+}
+
+/// Applies the (optional) resize options to the image, which is returned
+/// unchanged when no resizing was requested, failing before the resized
+/// image (and the buffers of the resampling) is allocated when it exceeds
+/// the given limits.
+pub fn resize_output(
+    image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    resize: &Option<ResizeOptions>,
+    limits: &Limits,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    match resize {
+        Some(resize) => {
+            let (width, height) = resize.size.dimensions(image.dimensions())?;
+            limits.check_dimensions(width, height)?;
+            check_channels(image.dimensions(), width, resize.filter, limits)?;
+            Ok(self::resize(&image, width, height, resize.filter))
+        }
+        None => Ok(image),
+    }
+}
+
+/// Checks the bytes of the buffers of premultiplied channels (16 bytes per
+/// pixel) needed to resample an image with the given source dimensions to
+/// the given width, the premultiplied source and its resampled rows, against
+/// the bytes limit. Nearest neighbour copies the pixels without them.
+fn check_channels(
+    (source_width, source_height): (u32, u32),
+    width: u32,
+    filter: ResizeFilter,
+    limits: &Limits,
+) -> Result<(), PConvertError> {
+    if filter == ResizeFilter::Nearest {
+        return Ok(());
+    }
+    let pixels = (source_width as u64 + width as u64) * source_height as u64;
+    limits.check_bytes(pixels.saturating_mul(16))
+}
+
+/// Resizes an image (with straight alpha) to the given dimensions.
+///
+/// Resampling is done in premultiplied alpha, so that the colour of
+/// transparent pixels does not bleed into the visible ones, and the
+/// filter is widened when downscaling so that every source pixel
+/// contributes to the result.
+///
+/// # Arguments
+///
+/// * `image` - The image buffer to resize.
+/// * `width` - The width of the resized image.
+/// * `height` - The height of the resized image.
+/// * `filter` - The resampling filter to use.
+///
+/// # Examples
+///
+/// ```no_run
+/// use pconvert_rust::resize::{resize, ResizeFilter};
+/// use pconvert_rust::utils::read_png_from_file;
+///
+/// let image = read_png_from_file("image.png".to_string(), false).unwrap();
+/// let thumbnail = resize(&image, 128, 128, ResizeFilter::Lanczos);
+/// ```
+pub fn resize(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    width: u32,
+    height: u32,
+    filter: ResizeFilter,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (source_width, source_height) = image.dimensions();
+    if (source_width, source_height) == (width, height) {
+        return image.clone();
+    }
+    if source_width == 0 || source_height == 0 || width == 0 || height == 0 {
+        return ImageBuffer::new(width, height);
+    }
+
+    // nearest neighbour only copies pixels, so there's no need
+    // to go through the (premultiplied) floating point buffers
+    if filter == ResizeFilter::Nearest {
+        let xs = (0..width)
+            .map(|x| nearest_index(x, width, source_width))
+            .collect::<Vec<_>>();
+        return ImageBuffer::from_fn(width, height, |x, y| {
+            let source_y = nearest_index(y, height, source_height);
+            *image.get_pixel(xs[x as usize], source_y)
+        });
+    }
+
+    let premultiplied = premultiplied(image);
+
+    // resamples the rows first and then the columns, as the
+    // filters are separable
+    let horizontal = sample_weights(source_width, width, filter);
+    let mut rows = vec![[0.0f32; 4]; width as usize * source_height as usize];
+    for y in 0..source_height as usize {
+        let source_row = &premultiplied[y * source_width as usize..][..source_width as usize];
+        let row = &mut rows[y * width as usize..][..width as usize];
+        for (pixel, (start, weights)) in row.iter_mut().zip(horizontal.iter()) {
+            *pixel = convolve(source_row[*start..].iter(), weights);
+        }
+    }
+
+    let vertical = sample_weights(source_height, height, filter);
+    let mut resized = ImageBuffer::new(width, height);
+    for (y, (start, weights)) in vertical.iter().enumerate() {
+        for x in 0..width as usize {
+            let column = rows[start * width as usize + x..]
+                .iter()
+                .step_by(width as usize);
+            let channels = convolve(column, weights);
+            resized.put_pixel(x as u32, y as u32, demultiplied(channels));
+        }
+    }
+
+    resized
+}
+
+/// Returns the index of the source sample nearest to the center
+/// of the given target sample.
+fn nearest_index(index: u32, size: u32, source_size: u32) -> u32 {
+    let ratio = source_size as f64 / size as f64;
+    (((index as f64 + 0.5) * ratio) as u32).min(source_size - 1)
+}
+
+/// Computes, for each target sample, the index of the first source sample
+/// it depends on and the (normalized) weights of the source samples.
+fn sample_weights(source_size: u32, size: u32, filter: ResizeFilter) -> Vec<(usize, Vec<f32>)> {
+    let ratio = source_size as f32 / size as f32;
+    let filter_scale = ratio.max(1.0);
+    let support = filter.support() * filter_scale;
+
+    (0..size)
+        .map(|index| {
+            let center = (index as f32 + 0.5) * ratio;
+            let start =
+                ((center - support).floor().max(0.0) as usize).min(source_size as usize - 1);
+            let end = ((center + support).ceil() as usize).clamp(start + 1, source_size as usize);
+            let mut weights = (start..end)
+                .map(|source| filter.kernel((source as f32 + 0.5 - center) / filter_scale))
+                .collect::<Vec<_>>();
+
+            let total: f32 = weights.iter().sum();
+            if total.abs() > f32::EPSILON {
+                weights.iter_mut().for_each(|weight| *weight /= total);
+            } else {
+                // the kernel is zero on every sample in range, which may happen
+                // for the tiniest of images, so the nearest sample is used
+                let nearest = (center as usize).clamp(start, end - 1);
+                weights.iter_mut().for_each(|weight| *weight = 0.0);
+                weights[nearest - start] = 1.0;
+            }
+            (start, weights)
+        })
+        .collect()
+}
+
+fn convolve<'a>(samples: impl Iterator<Item = &'a [f32; 4]>, weights: &[f32]) -> [f32; 4] {
+    let mut channels = [0.0; 4];
+    for (sample, weight) in samples.zip(weights.iter()) {
+        for (channel, value) in channels.iter_mut().zip(sample.iter()) {
+            *channel += value * weight;
+        }
+    }
+    channels
+}
+
+/// Converts the pixels of the image (with straight alpha) into
+/// premultiplied floating point channels.
+pub(crate) fn premultiplied(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<[f32; 4]> {
+    image
+        .pixels()
+        .map(|pixel| {
+            let alpha = pixel[3] as f32 / 255.0;
+            [
+                pixel[0] as f32 * alpha,
+                pixel[1] as f32 * alpha,
+                pixel[2] as f32 * alpha,
+                pixel[3] as f32,
+            ]
+        })
+        .collect()
+}
+
+/// Converts premultiplied floating point channels back into a pixel
+/// with straight alpha, clamping the overshoot of sharper filters.
+pub(crate) fn demultiplied(channels: [f32; 4]) -> Rgba<u8> {
+    let alpha = channels[3].round().clamp(0.0, 255.0);
+    if alpha <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let factor = 255.0 / channels[3];
+    let color = |value: f32| (value.max(0.0) * factor).round().clamp(0.0, 255.0) as u8;
+    Rgba([
+        color(channels[0]),
+        color(channels[1]),
+        color(channels[2]),
+        alpha as u8,
+    ])
+}
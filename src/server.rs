@@ -0,0 +1,737 @@
+//! Minimal HTTP server that exposes the composition API, each connection
+//! being read and answered in its own thread while the compositions run in
+//! the thread pool (so that slow clients never hold its workers).
+//!
+//! Endpoints:
+//!
+//! * `GET /status` - Health check with the thread pool status (size, queued
+//!   and active jobs).
+//! * `POST /compose` - Blends the given layers, bottom to top, and replies
+//!   with the image (a PNG unless another `format`, with its `quality` and
+//!   `background`, is given, or an indexed PNG with `colors` and `dither`,
+//!   optionally size optimized with `optimize`). Accepts either a JSON body
+//!   such as `{"layers": [{"data": "<base64>"}, {"path": "top.png",
+//!   "transforms": ["rotate:90"], "effects": ["grayscale"]}], "algorithm":
+//!   "source_over"}` or a `multipart/form-data` body with one file part per
+//!   layer (in order) and optional `algorithm`, `compression`, `strategy`,
+//!   `filter`, `dimension_policy`, `gravity`, `size`, `scale`,
+//!   `resize_filter`, `format`, `quality`, `background`, `colors`, `dither`
+//!   and `optimize` fields.
+//!
+//! Compositions exceeding the resource limits of the server (e.g. the
+//! number of layers or the dimensions of a layer) get a `413` response,
+//! while a server with too many open connections or queued compositions
+//! replies with a `503` response.
+
+use crate::batch::{build_json_algorithms, parse_algorithm};
+use crate::blending::dimensions::DimensionPolicy;
+use crate::blending::params::BlendAlgorithmParams;
+use crate::blending::{blend_multiple_images, BlendAlgorithm};
+use crate::constants;
+use crate::encoding::{parse_filter, Compression, CompressionLevel};
+use crate::errors::PConvertError;
+use crate::format::{encode_image, OutputFormat, OutputOptions, OutputValues};
+use crate::layer::LayerOptions;
+use crate::limits::Limits;
+use crate::parallelism::{ResultMessage, ThreadPool};
+use crate::resize::{resize_output, ResizeOptions};
+use crate::utils::{decode_image_with_limits, read_image_from_file_with_limits};
+use image::codecs::png::FilterType;
+use image::{ImageBuffer, Rgba};
+use serde::Deserialize;
+use serde_json::{json, Value as JSONValue};
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Maximum size (in bytes) of the request line and headers.
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Maximum size (in bytes) of a request body, further bounded
+/// by the bytes limit of the server.
+const MAX_BODY_SIZE: usize = 256 * 1024 * 1024;
+
+/// Timeout applied to reads and writes of client connections.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of connections handled at the same time.
+const MAX_CONNECTIONS: usize = 256;
+
+/// Maximum number of compositions queued in the thread pool per worker,
+/// beyond which compositions are rejected until the queue drains.
+const MAX_QUEUED_PER_THREAD: usize = 4;
+
+/// Options that control the behaviour of the server.
+#[derive(Clone, Debug, Default)]
+pub struct ServerOptions {
+    /// Whether layers may be given as paths in the local file system
+    /// of the server (disabled by default).
+    pub allow_paths: bool,
+    /// Resource limits of the compositions, exceeding them results
+    /// in a `413 Payload Too Large` response.
+    pub limits: Limits,
+}
+
+/// HTTP compositing server bound to a local address.
+pub struct Server {
+    listener: TcpListener,
+    thread_pool: Arc<ThreadPool>,
+    options: ServerOptions,
+    connections: Arc<AtomicUsize>,
+}
+
+impl Server {
+    /// Binds the server to `address` (e.g. `127.0.0.1:8080`), creating
+    /// a thread pool with `num_threads` workers to handle the requests.
+    pub fn bind(
+        address: &str,
+        num_threads: usize,
+        options: ServerOptions,
+    ) -> Result<Server, PConvertError> {
+        let listener = TcpListener::bind(address)?;
+        let mut thread_pool = ThreadPool::new(num_threads)?;
+        thread_pool.start();
+
+        Ok(Server {
+            listener,
+            thread_pool: Arc::new(thread_pool),
+            options,
+            connections: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Returns the local address the server is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, PConvertError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections forever, handling each of them in its own thread,
+    /// that submits the composition (if any) to the thread pool.
+    pub fn run(&self) -> Result<(), PConvertError> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            if self.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                self.connections.fetch_sub(1, Ordering::SeqCst);
+                let _ = stream.set_write_timeout(Some(CONNECTION_TIMEOUT));
+                let _ = write_response(stream, Response::error(503, "Too many connections"));
+                continue;
+            }
+
+            let thread_pool = self.thread_pool.clone();
+            let options = self.options.clone();
+            let connections = self.connections.clone();
+            thread::spawn(move || {
+                let _ = handle_connection(stream, &thread_pool, &options);
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    code: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(code: u16, value: JSONValue) -> Response {
+        Response {
+            code,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn error(code: u16, message: &str) -> Response {
+        Response::json(code, json!({ "error": message }))
+    }
+}
+
+#[derive(Deserialize)]
+struct ComposeRequest {
+    layers: Vec<JSONValue>,
+    algorithm: Option<String>,
+    algorithms: Option<Vec<JSONValue>>,
+    compression: Option<JSONValue>,
+    strategy: Option<String>,
+    filter: Option<String>,
+    dimension_policy: Option<String>,
+    gravity: Option<String>,
+    size: Option<String>,
+    scale: Option<f64>,
+    resize_filter: Option<String>,
+    format: Option<String>,
+    quality: Option<i64>,
+    background: Option<String>,
+    colors: Option<i64>,
+    dither: Option<bool>,
+    optimize: Option<bool>,
+}
+
+struct Composition {
+    layers: Vec<ImageBuffer<Rgba<u8>, Vec<u8>>>,
+    algorithms: Vec<(BlendAlgorithm, Option<BlendAlgorithmParams>)>,
+    dimension_policy: DimensionPolicy,
+    resize: Option<ResizeOptions>,
+    compression: Compression,
+    filter: FilterType,
+    output: OutputOptions,
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    thread_pool: &ThreadPool,
+    options: &ServerOptions,
+) -> Result<(), PConvertError> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+
+    let max_body_size = usize::try_from(options.limits.max_bytes).unwrap_or(usize::MAX);
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader, MAX_BODY_SIZE.min(max_body_size)) {
+        Ok(request) => route(request, thread_pool, options),
+        Err(response) => response,
+    };
+    write_response(stream, response)
+}
+
+fn route(request: Request, thread_pool: &ThreadPool, options: &ServerOptions) -> Response {
+    let status = thread_pool.get_status();
+    match (&request.method[..], &request.path[..]) {
+        ("GET", "/status") | ("GET", "/health") => Response::json(
+            200,
+            json!({
+                "status": "ok",
+                "version": constants::VERSION,
+                "thread_pool": {
+                    "size": status.size(),
+                    "queued": status.queued(),
+                    "active": status.active()
+                }
+            }),
+        ),
+        ("POST", "/compose") if status.queued() >= status.size() * MAX_QUEUED_PER_THREAD => {
+            Response::error(503, "Too many queued compositions")
+        }
+        ("POST", "/compose") => match run_composition(request, thread_pool, options) {
+            Ok((body, format)) => Response {
+                code: 200,
+                content_type: format.mime_type(),
+                body,
+            },
+            Err(err) => Response::json(
+                error_code(&err),
+                json!({ "error": err.to_string(), "code": err.code() }),
+            ),
+        },
+        (_, "/status") | (_, "/health") | (_, "/compose") => {
+            Response::error(405, "Method not allowed")
+        }
+        _ => Response::error(404, "Not found"),
+    }
+}
+
+/// Runs the composition of the request in the thread pool, waiting
+/// for its (encoded) result.
+fn run_composition(
+    request: Request,
+    thread_pool: &ThreadPool,
+    options: &ServerOptions,
+) -> Result<(Vec<u8>, OutputFormat), PConvertError> {
+    let options = options.clone();
+    let result_channel =
+        thread_pool.execute(move || ResultMessage::EncodedResult(compose(request, &options)));
+    match result_channel.recv() {
+        Ok(ResultMessage::EncodedResult(result)) => result,
+        _ => Err(PConvertError::ThreadPoolError(
+            "composition task did not complete".to_string(),
+        )),
+    }
+}
+
+fn compose(
+    request: Request,
+    options: &ServerOptions,
+) -> Result<(Vec<u8>, OutputFormat), PConvertError> {
+    let is_multipart = matches!(
+        &request.content_type,
+        Some(content_type) if content_type.starts_with("multipart/form-data")
+    );
+
+    let composition = if is_multipart {
+        parse_multipart_composition(&request, options)?
+    } else {
+        parse_json_composition(&request.body, options)?
+    };
+
+    let result = blend_multiple_images(
+        composition.layers,
+        &composition.algorithms,
+        &composition.dimension_policy,
+    )?;
+    let result = resize_output(result, &composition.resize, &options.limits)?;
+    let mut body = Vec::new();
+    encode_image(
+        &mut body,
+        &result,
+        composition.compression,
+        composition.filter,
+        &composition.output,
+    )?;
+    Ok((body, composition.output.format))
+}
+
+fn parse_json_composition(
+    body: &[u8],
+    options: &ServerOptions,
+) -> Result<Composition, PConvertError> {
+    let request: ComposeRequest = serde_json::from_slice(body)
+        .map_err(|err| PConvertError::ArgumentError(format!("invalid request ({})", err)))?;
+    options.limits.check_layers(request.layers.len())?;
+
+    let layers = request
+        .layers
+        .into_iter()
+        .enumerate()
+        .map(|(index, layer)| {
+            let layer_options = LayerOptions::from_json(&layer)?;
+            let layer = load_json_layer(layer, options).map_err(|err| err.with_layer(index))?;
+            layer_options.apply_with_limits(layer, &options.limits)
+        })
+        .collect::<Result<Vec<_>, PConvertError>>()?;
+    let algorithms = build_json_algorithms(request.algorithm, request.algorithms, layers.len())?;
+    let dimension_policy = DimensionPolicy::from_options(
+        request.dimension_policy.as_deref(),
+        request.gravity.as_deref(),
+    )?;
+    let resize = ResizeOptions::from_options(
+        request.size.as_deref(),
+        request.scale,
+        request.resize_filter.as_deref(),
+    )?;
+    let output = OutputOptions::from_options(OutputValues {
+        format: request.format.as_deref(),
+        quality: request.quality,
+        background: request.background.as_deref(),
+        colors: request.colors,
+        dither: request.dither,
+        optimize: request.optimize,
+        path: None,
+    })?;
+
+    Ok(Composition {
+        layers,
+        algorithms,
+        dimension_policy,
+        resize,
+        compression: Compression::from_options(
+            request
+                .compression
+                .as_ref()
+                .map(CompressionLevel::from_json)
+                .transpose()?,
+            request.strategy.as_deref(),
+        )?,
+        filter: request
+            .filter
+            .as_deref()
+            .map_or(Ok(FilterType::NoFilter), parse_filter)?,
+        output,
+    })
+}
+
+fn load_json_layer(
+    layer: JSONValue,
+    options: &ServerOptions,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    let path = match &layer {
+        JSONValue::String(path) => Some(path.clone()),
+        JSONValue::Object(object) => match object.get("path") {
+            Some(JSONValue::String(path)) => Some(path.clone()),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    if let Some(path) = path {
+        if !options.allow_paths {
+            return Err(PConvertError::ArgumentError(
+                "layers given as paths are not allowed".to_string(),
+            ));
+        }
+        return read_image_from_file_with_limits(path, false, &options.limits);
+    }
+
+    match layer.get("data") {
+        Some(JSONValue::String(data)) => {
+            decode_image_with_limits(&decode_base64(data)?[..], false, &options.limits)
+        }
+        _ => Err(PConvertError::ArgumentError(
+            "layers must have either a 'path' or 'data'".to_string(),
+        )),
+    }
+}
+
+fn parse_multipart_composition(
+    request: &Request,
+    options: &ServerOptions,
+) -> Result<Composition, PConvertError> {
+    let boundary = request
+        .content_type
+        .as_ref()
+        .and_then(|content_type| {
+            content_type
+                .split(';')
+                .map(|param| param.trim())
+                .find_map(|param| param.strip_prefix("boundary="))
+        })
+        .map(|boundary| boundary.trim_matches('"').to_string())
+        .ok_or_else(|| PConvertError::ArgumentError("missing multipart boundary".to_string()))?;
+
+    let mut layers = Vec::new();
+    let mut algorithm = None;
+    let mut compression = None;
+    let mut strategy = None;
+    let mut filter = FilterType::NoFilter;
+    let mut dimension_policy = None;
+    let mut gravity = None;
+    let mut size = None;
+    let mut scale = None;
+    let mut resize_filter = None;
+    let mut format = None;
+    let mut quality = None;
+    let mut background = None;
+    let mut colors = None;
+    let mut dither = None;
+    let mut optimize = None;
+
+    let parts = parse_multipart(&request.body, &boundary)?;
+    let num_layers = parts
+        .iter()
+        .filter(|(_, filename, _)| filename.is_some())
+        .count();
+    options.limits.check_layers(num_layers)?;
+
+    for (name, filename, content) in parts {
+        if let Some(filename) = filename {
+            let layer = decode_image_with_limits(content, false, &options.limits)
+                .map_err(|err| err.with_path(&filename).with_layer(layers.len()))?;
+            layers.push(layer);
+            continue;
+        }
+
+        let value = String::from_utf8_lossy(content).trim().to_string();
+        match &name[..] {
+            "algorithm" => algorithm = Some(parse_algorithm(&value)?),
+            "compression" => compression = Some(value),
+            "strategy" => strategy = Some(value),
+            "filter" => filter = parse_filter(&value)?,
+            "dimension_policy" => dimension_policy = Some(value),
+            "gravity" => gravity = Some(value),
+            "size" => size = Some(value),
+            "scale" => {
+                scale = Some(value.parse::<f64>().map_err(|_| {
+                    PConvertError::invalid_parameter(
+                        "scale",
+                        format!("'{}' is not a number", value),
+                    )
+                })?)
+            }
+            "resize_filter" => resize_filter = Some(value),
+            "format" => format = Some(value),
+            "quality" => {
+                quality = Some(value.parse::<i64>().map_err(|_| {
+                    PConvertError::invalid_parameter(
+                        "quality",
+                        format!("'{}' is not a number", value),
+                    )
+                })?)
+            }
+            "background" => background = Some(value),
+            "colors" => {
+                colors = Some(value.parse::<i64>().map_err(|_| {
+                    PConvertError::invalid_parameter(
+                        "colors",
+                        format!("'{}' is not a number", value),
+                    )
+                })?)
+            }
+            "dither" => {
+                dither = Some(value.parse::<bool>().map_err(|_| {
+                    PConvertError::invalid_parameter(
+                        "dither",
+                        format!("'{}' is not a boolean", value),
+                    )
+                })?)
+            }
+            "optimize" => {
+                optimize = Some(value.parse::<bool>().map_err(|_| {
+                    PConvertError::invalid_parameter(
+                        "optimize",
+                        format!("'{}' is not a boolean", value),
+                    )
+                })?)
+            }
+            _ => {}
+        }
+    }
+
+    let algorithm = algorithm.unwrap_or(BlendAlgorithm::Multiplicative);
+    Ok(Composition {
+        algorithms: vec![(algorithm, None); layers.len().saturating_sub(1)],
+        dimension_policy: DimensionPolicy::from_options(
+            dimension_policy.as_deref(),
+            gravity.as_deref(),
+        )?,
+        resize: ResizeOptions::from_options(size.as_deref(), scale, resize_filter.as_deref())?,
+        layers,
+        compression: Compression::from_options(
+            compression.as_deref().map(CompressionLevel::Name),
+            strategy.as_deref(),
+        )?,
+        filter,
+        output: OutputOptions::from_options(OutputValues {
+            format: format.as_deref(),
+            quality,
+            background: background.as_deref(),
+            colors,
+            dither,
+            optimize,
+            path: None,
+        })?,
+    })
+}
+
+/// A part of a `multipart/form-data` body, as a tuple of field
+/// name, optional file name and content.
+type MultipartPart<'a> = (String, Option<String>, &'a [u8]);
+
+/// Splits a `multipart/form-data` body into its parts.
+fn parse_multipart<'a>(
+    body: &'a [u8],
+    boundary: &str,
+) -> Result<Vec<MultipartPart<'a>>, PConvertError> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+
+    let mut position = find(body, &delimiter, 0).ok_or_else(invalid_multipart)? + delimiter.len();
+    loop {
+        // the closing delimiter is followed by `--`, otherwise every
+        // delimiter is followed by the part headers and content
+        if body[position..].starts_with(b"--") {
+            break;
+        }
+        let start = find(body, b"\r\n", position).ok_or_else(invalid_multipart)? + 2;
+        let end = find(body, &delimiter, start).ok_or_else(invalid_multipart)?;
+        let part = &body[start..end.saturating_sub(2).max(start)];
+
+        let headers_end = find(part, b"\r\n\r\n", 0).ok_or_else(invalid_multipart)?;
+        let headers = String::from_utf8_lossy(&part[..headers_end]);
+        let content = &part[headers_end + 4..];
+
+        let mut name = String::new();
+        let mut filename = None;
+        for header in headers.lines() {
+            let (key, value) = match header.split_once(':') {
+                Some(header) => header,
+                None => continue,
+            };
+            if !key.trim().eq_ignore_ascii_case("content-disposition") {
+                continue;
+            }
+            for param in value.split(';').map(|param| param.trim()) {
+                if let Some(value) = param.strip_prefix("name=") {
+                    name = value.trim_matches('"').to_string();
+                } else if let Some(value) = param.strip_prefix("filename=") {
+                    filename = Some(value.trim_matches('"').to_string());
+                }
+            }
+        }
+
+        parts.push((name, filename, content));
+        position = end + delimiter.len();
+    }
+
+    Ok(parts)
+}
+
+fn invalid_multipart() -> PConvertError {
+    PConvertError::ArgumentError("invalid multipart body".to_string())
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from > haystack.len() {
+        return None;
+    }
+    haystack[from..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+/// Decodes a standard (RFC 4648) base64 string, padding is optional
+/// and whitespace is ignored.
+fn decode_base64(data: &str) -> Result<Vec<u8>, PConvertError> {
+    let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for byte in data.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => {
+                return Err(PConvertError::ArgumentError(
+                    "invalid base64 layer data".to_string(),
+                ))
+            }
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn read_request(reader: &mut impl BufRead, max_body_size: usize) -> Result<Request, Response> {
+    let mut request_line = String::new();
+    read_line(reader, &mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+    if method.is_empty() || path.is_empty() {
+        return Err(Response::error(400, "Invalid request line"));
+    }
+
+    let mut header_size = request_line.len();
+    let mut content_length = 0;
+    let mut content_type = None;
+    loop {
+        let mut header = String::new();
+        read_line(reader, &mut header)?;
+        header_size += header.len();
+        if header_size > MAX_HEADER_SIZE {
+            return Err(Response::error(431, "Request headers too large"));
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        let (key, value) = match header.split_once(':') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+            None => return Err(Response::error(400, "Invalid header")),
+        };
+        match &key[..] {
+            "content-length" => {
+                content_length = value
+                    .parse::<usize>()
+                    .map_err(|_| Response::error(400, "Invalid content length"))?
+            }
+            "content-type" => content_type = Some(value.to_string()),
+            "transfer-encoding" => return Err(Response::error(411, "Length required")),
+            _ => {}
+        }
+    }
+
+    if content_length > max_body_size {
+        return Err(Response::error(413, "Request body too large"));
+    }
+
+    // the body grows as its bytes arrive, instead of being allocated
+    // upfront from the (untrusted) content length
+    let mut body = Vec::new();
+    reader
+        .take(content_length as u64)
+        .read_to_end(&mut body)
+        .map_err(|_| Response::error(400, "Incomplete request body"))?;
+    if body.len() < content_length {
+        return Err(Response::error(400, "Incomplete request body"));
+    }
+
+    // strips the query string (if any) from the path
+    let path = path.split('?').next().unwrap_or_default().to_string();
+
+    Ok(Request {
+        method,
+        path,
+        content_type,
+        body,
+    })
+}
+
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> Result<(), Response> {
+    let mut bytes = Vec::new();
+    reader
+        .take(MAX_HEADER_SIZE as u64)
+        .read_until(b'\n', &mut bytes)
+        .map_err(|_| Response::error(400, "Invalid request"))?;
+    *line = String::from_utf8(bytes).map_err(|_| Response::error(400, "Invalid request"))?;
+    Ok(())
+}
+
+fn write_response(mut stream: TcpStream, response: Response) -> Result<(), PConvertError> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.code,
+        reason_phrase(response.code),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn error_code(err: &PConvertError) -> u16 {
+    match err {
+        PConvertError::IOError(err) if err.kind() == io::ErrorKind::NotFound => 404,
+        PConvertError::IOError(_) => 500,
+        PConvertError::EncodeError { .. } => 500,
+        PConvertError::ThreadPoolError(_) => 500,
+        PConvertError::LimitExceededError { .. } => 413,
+        _ => 400,
+    }
+}
+
+fn reason_phrase(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
@@ -0,0 +1,417 @@
+//! Streaming composition of (huge) layers, which are decoded, blended and
+//! encoded in bands of rows, so that the memory used is bounded by the width
+//! of the composition times the height of a band, instead of by the dimensions
+//! of the whole composition (in WASM, without the multi-threaded encoder, the
+//! bands are gathered into the whole composition before being encoded).
+
+use crate::blending::params::BlendAlgorithmParams;
+use crate::blending::{
+    blend_images, demultiply_image, get_blending_algorithm, is_algorithm_multiplied, BlendAlgorithm,
+};
+use crate::encoding::Compression;
+use crate::errors::PConvertError;
+use crate::limits::Limits;
+#[cfg(feature = "wasm-extension")]
+use crate::utils::encode_png;
+use crate::utils::{
+    check_png_header, decode_error, expand_row_to_rgba8, png_decode_error, png_samples,
+};
+#[cfg(not(feature = "wasm-extension"))]
+use crate::utils::{encode_error, mtpng_compression_from, mtpng_filter_from, mtpng_strategy_from};
+use image::codecs::png::FilterType;
+use image::error::ImageError;
+use image::{ImageBuffer, Rgba};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+
+/// Default number of rows of the bands in which layers are blended.
+pub const DEFAULT_BAND_HEIGHT: u32 = 64;
+
+/// Blends a stack of PNGs into a single PNG, from the bottom-most layer (the
+/// first one) to the top-most one (the last one), one band of rows at a time.
+///
+/// Only a band of each layer is decoded at a time and the blended bands are
+/// encoded as they are done (with multi-threaded compression), the result
+/// is the same as blending the whole layers with `blend_multiple_images`.
+/// Every layer must have the dimensions of the bottom layer, as the layers
+/// are never fully in memory to be conformed (interlaced PNGs are fully
+/// decoded when opened, so they do not benefit from the streaming). PNGs of
+/// any color type are expanded into RGBA8, as when decoded in memory.
+///
+/// # Arguments
+///
+/// * `layers` - The streams of the PNGs to blend, bottom to top.
+/// * `algorithms` - The blending algorithm and optional parameters to use
+///   in each blending operation, one per layer except the first.
+/// * `writable_buff` - Any buffer structure that implements the `Write` trait.
+/// * `compression` - Compression to use in the encoding, only the fast,
+///   default and best levels are supported by the multi-threaded encoder.
+/// * `filter` - Filter type to use in the encoding.
+/// * `band_height` - The number of rows blended at a time.
+/// * `limits` - The resource limits, where the pixels and bytes limits
+///   apply to a band instead of to a whole layer (in WASM, they also apply
+///   to the whole composition, that is gathered before being encoded).
+pub fn blend_multiple_streaming(
+    layers: Vec<impl Read>,
+    algorithms: &[(BlendAlgorithm, Option<BlendAlgorithmParams>)],
+    writable_buff: impl Write,
+    compression: Compression,
+    filter: FilterType,
+    band_height: u32,
+    limits: &Limits,
+) -> Result<(), PConvertError> {
+    let (mut readers, dimensions) = open_layers(layers, algorithms, band_height, limits)?;
+    encode_bands(
+        &mut readers,
+        algorithms,
+        dimensions,
+        band_height,
+        writable_buff,
+        compression,
+        filter,
+    )
+}
+
+/// Blends a stack of PNGs read from the local file system into a single
+/// PNG written to the local file system, one band of rows at a time
+/// (as `blend_multiple_streaming`).
+///
+/// # Arguments
+///
+/// * `files_in` - Local file system paths of the PNGs to blend, bottom to top.
+/// * `file_out` - Local file system path where to write the PNG file.
+/// * `algorithms` - The blending algorithm and optional parameters to use
+///   in each blending operation, one per layer except the first.
+/// * `compression` - Compression to use in the encoding (see `blend_multiple_streaming`).
+/// * `filter` - Filter type to use in the encoding.
+/// * `band_height` - The number of rows blended at a time.
+/// * `limits` - The resource limits, where the pixels and bytes limits
+///   apply to a band instead of to a whole layer.
+///
+/// # Examples
+///
+/// ```no_run
+/// use image::codecs::png::FilterType;
+/// use pconvert_rust::blending::BlendAlgorithm;
+/// use pconvert_rust::encoding::Compression;
+/// use pconvert_rust::limits::Limits;
+/// use pconvert_rust::stream::{blend_files_streaming, DEFAULT_BAND_HEIGHT};
+///
+/// blend_files_streaming(
+///     &["bot.png".to_string(), "top.png".to_string()],
+///     "banner.png".to_string(),
+///     &[(BlendAlgorithm::SourceOver, None)],
+///     Compression::FAST,
+///     FilterType::NoFilter,
+///     DEFAULT_BAND_HEIGHT,
+///     &Limits::default(),
+/// )
+/// .unwrap();
+/// ```
+pub fn blend_files_streaming(
+    files_in: &[String],
+    file_out: String,
+    algorithms: &[(BlendAlgorithm, Option<BlendAlgorithmParams>)],
+    compression: Compression,
+    filter: FilterType,
+    band_height: u32,
+    limits: &Limits,
+) -> Result<(), PConvertError> {
+    let layers = files_in
+        .iter()
+        .map(|path| Ok(BufReader::new(File::open(path)?)))
+        .collect::<Result<Vec<_>, PConvertError>>()?;
+    let with_path = |err: PConvertError| match err {
+        PConvertError::DecodeError {
+            layer: Some(layer), ..
+        } => err.with_path(&files_in[layer]),
+        err => err.with_path(&file_out),
+    };
+
+    // the output file is only created once every layer is opened and
+    // checked, so that an invalid layer does not leave an empty file
+    let (mut readers, dimensions) =
+        open_layers(layers, algorithms, band_height, limits).map_err(with_path)?;
+    let file = File::create(&file_out)?;
+
+    encode_bands(
+        &mut readers,
+        algorithms,
+        dimensions,
+        band_height,
+        file,
+        compression,
+        filter,
+    )
+    .map_err(with_path)
+}
+
+/// Opens the readers of the rows of the layers, checking the arguments
+/// of the blending and that every layer has the dimensions of the bottom
+/// one, returning the readers with those dimensions.
+fn open_layers(
+    layers: Vec<impl Read>,
+    algorithms: &[(BlendAlgorithm, Option<BlendAlgorithmParams>)],
+    band_height: u32,
+    limits: &Limits,
+) -> Result<(Vec<LayerReader<impl Read>>, (u32, u32)), PConvertError> {
+    let num_layers = layers.len();
+
+    if num_layers < 1 {
+        return Err(PConvertError::ArgumentError(
+            "'layers' must contain at least one image".to_string(),
+        ));
+    }
+
+    if algorithms.len() != num_layers - 1 {
+        return Err(PConvertError::ArgumentError(format!(
+            "'algorithms' must be of size {} (one per blending operation)",
+            num_layers - 1
+        )));
+    }
+
+    if band_height == 0 {
+        return Err(PConvertError::invalid_parameter(
+            "band_height",
+            "must be at least one row",
+        ));
+    }
+
+    limits.check_layers(num_layers)?;
+
+    let mut readers = Vec::with_capacity(num_layers);
+    let mut dimensions = (0, 0);
+    for (index, layer) in layers.into_iter().enumerate() {
+        let (reader, layer_dimensions) =
+            open_layer(layer, band_height, limits).map_err(|err| err.with_layer(index))?;
+        if index == 0 {
+            dimensions = layer_dimensions;
+        } else if layer_dimensions != dimensions {
+            return Err(PConvertError::DimensionMismatchError {
+                layer: index,
+                expected: dimensions,
+                found: layer_dimensions,
+            });
+        }
+        readers.push(reader);
+    }
+    // in WASM the bands are gathered into the whole composition, which
+    // is then bound by the limits of a whole image
+    #[cfg(feature = "wasm-extension")]
+    limits.check_dimensions(dimensions.0, dimensions.1)?;
+    Ok((readers, dimensions))
+}
+
+/// Blends the layers band by band, writing each blended band into the
+/// multi-threaded encoder as soon as it is done.
+#[cfg(not(feature = "wasm-extension"))]
+fn encode_bands(
+    readers: &mut [LayerReader<impl Read>],
+    algorithms: &[(BlendAlgorithm, Option<BlendAlgorithmParams>)],
+    (width, height): (u32, u32),
+    band_height: u32,
+    writable_buff: impl Write,
+    compression: Compression,
+    filter: FilterType,
+) -> Result<(), PConvertError> {
+    let level = mtpng_compression_from(compression).ok_or_else(|| {
+        PConvertError::invalid_parameter(
+            "compression",
+            format!(
+                "level {} is not supported when streaming (only fast, default and best are)",
+                compression.level()
+            ),
+        )
+    })?;
+    let encode_io_error = |err: std::io::Error| encode_error(ImageError::IoError(err));
+    let mut header = mtpng::Header::new();
+    header.set_size(width, height).map_err(encode_io_error)?;
+    header
+        .set_color(mtpng::ColorType::TruecolorAlpha, 8)
+        .map_err(encode_io_error)?;
+
+    // the streaming mode writes each compressed chunk as it is done,
+    // instead of keeping the whole compressed image in memory
+    let mut options = mtpng::encoder::Options::new();
+    options
+        .set_compression_level(level)
+        .map_err(encode_io_error)?;
+    options
+        .set_strategy_mode(mtpng_strategy_from(compression))
+        .map_err(encode_io_error)?;
+    options
+        .set_filter_mode(mtpng_filter_from(filter))
+        .map_err(encode_io_error)?;
+    options.set_streaming(true).map_err(encode_io_error)?;
+
+    let mut encoder = mtpng::encoder::Encoder::new(writable_buff, &options);
+    encoder.write_header(&header).map_err(encode_io_error)?;
+
+    // the compression jobs of the encoder (in the global thread pool) abort
+    // the process if the encoder is dropped while they are running, so
+    // they are waited for before failing on an error of a band
+    let result = blend_bands(readers, algorithms, (width, height), band_height, |band| {
+        encoder.write_image_rows(band).map_err(encode_io_error)
+    });
+    if let Err(err) = result {
+        let _ = encoder.flush();
+        return Err(err);
+    }
+
+    encoder.finish().map_err(encode_io_error)?;
+    Ok(())
+}
+
+/// Blends the layers band by band into the whole composition, which is
+/// then encoded (as there is no multi-threaded encoder in WASM).
+#[cfg(feature = "wasm-extension")]
+fn encode_bands(
+    readers: &mut [LayerReader<impl Read>],
+    algorithms: &[(BlendAlgorithm, Option<BlendAlgorithmParams>)],
+    (width, height): (u32, u32),
+    band_height: u32,
+    writable_buff: impl Write,
+    compression: Compression,
+    filter: FilterType,
+) -> Result<(), PConvertError> {
+    let mut bytes = Vec::new();
+    blend_bands(readers, algorithms, (width, height), band_height, |band| {
+        bytes.extend_from_slice(band);
+        Ok(())
+    })?;
+    let composition = ImageBuffer::from_raw(width, height, bytes).ok_or_else(|| {
+        PConvertError::ArgumentError("the blended bands do not fill the composition".to_string())
+    })?;
+    encode_png(writable_buff, &composition, compression, filter)
+}
+
+/// Blends the layers band by band, handing each blended band to `write_band`.
+fn blend_bands(
+    readers: &mut [LayerReader<impl Read>],
+    algorithms: &[(BlendAlgorithm, Option<BlendAlgorithmParams>)],
+    (width, height): (u32, u32),
+    band_height: u32,
+    mut write_band: impl FnMut(&[u8]) -> Result<(), PConvertError>,
+) -> Result<(), PConvertError> {
+    // the buffers of the bands are reused for every band, the
+    // blended band is the composition buffer once it is written
+    let mut composition_bytes = Vec::new();
+    let mut layer_bytes = Vec::new();
+    for band_start in (0..height).step_by(band_height as usize) {
+        let rows = band_height.min(height - band_start);
+        let mut composition = read_band(&mut readers[0], width, rows, composition_bytes)
+            .map_err(|err| err.with_layer(0))?;
+        if !algorithms.is_empty() && is_algorithm_multiplied(&algorithms[0].0) {
+            demultiply_image(&mut composition);
+        }
+
+        for (index, (algorithm, algorithm_params)) in algorithms.iter().enumerate() {
+            let mut layer = read_band(&mut readers[index + 1], width, rows, layer_bytes)
+                .map_err(|err| err.with_layer(index + 1))?;
+            let algorithm_fn = get_blending_algorithm(algorithm);
+            if is_algorithm_multiplied(algorithm) {
+                demultiply_image(&mut layer);
+            }
+            blend_images(&mut composition, &layer, &algorithm_fn, algorithm_params);
+            layer_bytes = layer.into_raw();
+        }
+
+        write_band(&composition)?;
+        composition_bytes = composition.into_raw();
+    }
+    Ok(())
+}
+
+/// Reader of the rows of a PNG layer, expanded into RGBA8 pixels.
+struct LayerReader<R: Read> {
+    reader: png::Reader<R>,
+    samples: (usize, usize),
+    /// The whole decoded image of an interlaced PNG, whose rows do not
+    /// come in order, with the offset of the next row in it.
+    interlaced: Option<(Vec<u8>, usize)>,
+}
+
+impl<R: Read> LayerReader<R> {
+    /// Reads the next row of the layer, expanded into the given pixels.
+    fn read_row(&mut self, pixels: &mut [u8]) -> Result<(), PConvertError> {
+        let samples = self.samples;
+        let missing_row = || decode_error(ImageError::IoError(ErrorKind::UnexpectedEof.into()));
+        match &mut self.interlaced {
+            Some((image, offset)) => {
+                let row_size = pixels.len() / 4 * samples.0 * samples.1;
+                let row = image
+                    .get(*offset..*offset + row_size)
+                    .ok_or_else(missing_row)?;
+                *offset += row_size;
+                expand_row_to_rgba8(row, samples, pixels);
+            }
+            None => {
+                let row = self
+                    .reader
+                    .next_row()
+                    .map_err(png_decode_error)?
+                    .ok_or_else(missing_row)?;
+                expand_row_to_rgba8(row.data(), samples, pixels);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Creates the reader of the rows of the PNG (of any color type, expanded
+/// into RGBA8 as when decoding the whole PNG), returning it with the
+/// dimensions of the PNG.
+fn open_layer<R: Read>(
+    layer: R,
+    band_height: u32,
+    limits: &Limits,
+) -> Result<(LayerReader<impl Read>, (u32, u32)), PConvertError> {
+    // the dimensions are checked from the header before creating the
+    // decoder, as the decoder already allocates its rows from them
+    let layer = check_png_header(layer, |width, height| {
+        limits.check_band_dimensions(width, height, band_height)
+    })?;
+
+    let png_limits = png::Limits {
+        bytes: usize::try_from(limits.max_bytes).unwrap_or(usize::MAX),
+    };
+    let mut decoder = png::Decoder::new_with_limits(layer, png_limits);
+    decoder.set_ignore_text_chunk(true);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(png_decode_error)?;
+    let dimensions = reader.info().size();
+    limits.check_band_dimensions(dimensions.0, dimensions.1, band_height)?;
+    let samples = png_samples(reader.output_color_type())?;
+
+    // interlaced PNGs are fully decoded, as their rows come in passes
+    let interlaced = match reader.info().interlaced {
+        true => {
+            let mut image = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut image).map_err(png_decode_error)?;
+            Some((image, 0))
+        }
+        false => None,
+    };
+    let reader = LayerReader {
+        reader,
+        samples,
+        interlaced,
+    };
+    Ok((reader, dimensions))
+}
+
+/// Reads the next `rows` rows of the layer into the given buffer,
+/// which is (re)sized to fit them.
+fn read_band(
+    reader: &mut LayerReader<impl Read>,
+    width: u32,
+    rows: u32,
+    mut bytes: Vec<u8>,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    bytes.resize(width as usize * rows as usize * 4, 0);
+    for pixels in bytes.chunks_exact_mut(width as usize * 4) {
+        reader.read_row(pixels)?;
+    }
+    ImageBuffer::from_raw(width, rows, bytes).ok_or(PConvertError::UnsupportedImageTypeError)
+}
@@ -1,16 +1,65 @@
+mod properties;
+
 use crate::{
+    alpha::{bounding_box, flatten, remove_matte, threshold_alpha, trim},
+    apng::{
+        blend_animations, decode_apng_with_limits, encode_apng, is_apng, Animation, Delay, Frame,
+    },
+    batch::{compose_item, read_manifest, run_batch, ManifestFormat, SharedLayers},
     benchmark::Benchmark,
-    blending::BlendAlgorithm,
+    blending::dimensions::{conform_layer, DimensionPolicy, Gravity},
+    blending::params::BlendAlgorithmParams,
+    blending::{blend_images, blend_multiple_images, get_blending_algorithm, BlendAlgorithm},
+    cache::{LayerCache, DEFAULT_CACHE_CAPACITY},
+    compare::{compare, diff_heatmap},
     compose::{apply_blue_filter, compose, compose_parallel, Background},
+    effects::{
+        apply_effects, apply_effects_with_limits, CustomEffect, Effect, ImageEffect, PixelEffect,
+    },
+    encoding::{parse_filter, Compression, Strategy},
+    errors::PConvertError,
+    format::{encode_image, InputFormat, OutputFormat, OutputOptions, OutputValues},
+    layer::LayerOptions,
+    limits::Limits,
+    optimize::{candidates, optimize_file, optimize_png, OptimizeReport},
+    palette::{encode_indexed_png, quantize},
+    parallelism::{ResultMessage, ThreadPool},
+    resize::{resize, resize_output, OutputSize, ResizeFilter, ResizeOptions},
+    server::{Server, ServerOptions},
+    stream::{blend_files_streaming, blend_multiple_streaming, DEFAULT_BAND_HEIGHT},
+    transform::{apply_transforms, Transform},
+    utils::{
+        decode_image, decode_image_with_limits, decode_png, decode_png_with_limits, encode_png,
+        read_image_from_file, write_png_parallel, write_png_to_file, write_png_to_file_d,
+    },
+    watch::Watcher,
 };
-use crate::{constants, utils::read_png_from_file};
-use image::codecs::png::{CompressionType, FilterType};
-use image::ImageFormat;
+use crate::{batch::BatchItem, constants, utils::read_png_from_file};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{ColorType, DynamicImage, ImageBuffer, ImageEncoder, ImageFormat, Rgba};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
 const TEST_DIR: &str = "assets/test/";
 const TEST_FILE: &str = "tux.png";
 const TEST_FILE_OUT: &str = "result_tux.png";
+const GOLDEN_DIR: &str = "assets/test/golden/";
+
+/// Golden images of `compose_parallel`, only for the compositions where it
+/// differs from `compose` (that blends the sole onto itself), the images of
+/// `GOLDEN_DIR` being used for every other composition.
+const GOLDEN_PARALLEL_DIR: &str = "assets/test/golden/parallel/";
+
+/// Largest channel difference accepted against the golden images (so that
+/// the tests pass across platforms with slightly different float rounding).
+const GOLDEN_TOLERANCE: u8 = 1;
+
+/// Environment variable that, when set, (re)writes the golden images from
+/// the current compositions instead of only checking them.
+const GOLDEN_REGENERATE: &str = "PCONVERT_REGENERATE_GOLDEN";
 
 #[test]
 fn test_benchmark() {
@@ -45,15 +94,19 @@ fn test_compose() {
     // composes with different combinations of blending algorithms and backgrounds
     for background in &backgrounds {
         for algorithm in constants::ALGORITHMS.iter() {
-            compose(
-                &TEST_DIR,
+            let file_name = compose(
+                TEST_DIR,
                 BlendAlgorithm::from_str(algorithm).unwrap(),
                 background,
-                CompressionType::Fast,
+                Compression::FAST,
                 FilterType::NoFilter,
                 &mut benchmark,
             )
             .unwrap_or_else(|_| panic!("failed composing with algorithm={} background={} compression=Fast filter=NoFilter", algorithm, background));
+            assert_eq!(
+                file_name,
+                format!("result_{}_{}_Fast_NoFilter.png", algorithm, background)
+            );
         }
     }
 }
@@ -72,10 +125,10 @@ fn test_compose_parallel() {
     for background in backgrounds {
         for algorithm in constants::ALGORITHMS.iter() {
             compose_parallel(
-                &TEST_DIR,
+                TEST_DIR,
                 BlendAlgorithm::from_str(algorithm).unwrap(),
                 &background,
-                CompressionType::Fast,
+                Compression::FAST,
                 FilterType::NoFilter,
                 &mut benchmark,
             )
@@ -84,6 +137,154 @@ fn test_compose_parallel() {
     }
 }
 
+type ComposeFn = fn(
+    &str,
+    BlendAlgorithm,
+    &Background,
+    Compression,
+    FilterType,
+    &mut Benchmark,
+) -> Result<String, PConvertError>;
+
+#[test]
+fn test_golden() {
+    // composes in a scratch directory (with copies of the layers) so that
+    // the results are not overwritten by the other composition tests
+    let dir = ScratchDir::new("pconvert_test_golden");
+    let backgrounds = vec![
+        Background::Alpha,
+        Background::Blue,
+        Background::Texture,
+        Background::White,
+    ];
+    let layers = ["sole.png", "back.png", "front.png", "shoelace.png"]
+        .iter()
+        .map(|name| name.to_string())
+        .chain(
+            backgrounds
+                .iter()
+                .map(|background| format!("background_{}.png", background)),
+        );
+    for name in layers {
+        fs::copy(format!("{}{}", TEST_DIR, name), dir.0.join(&name)).unwrap();
+    }
+    let dir_path = format!("{}/", dir.0.display());
+
+    let regenerate = std::env::var_os(GOLDEN_REGENERATE).is_some();
+    if regenerate {
+        fs::create_dir_all(GOLDEN_PARALLEL_DIR).unwrap();
+    }
+
+    let mut benchmark = Benchmark::new();
+    let mut failures = Vec::new();
+    for background in &backgrounds {
+        for algorithm in constants::ALGORITHMS.iter() {
+            let algorithm = BlendAlgorithm::from_str(algorithm).unwrap();
+            let results = [compose as ComposeFn, compose_parallel].map(|compose_fn| {
+                let file_name = compose_fn(
+                    &dir_path,
+                    algorithm.clone(),
+                    background,
+                    Compression::FAST,
+                    FilterType::NoFilter,
+                    &mut benchmark,
+                )
+                .unwrap();
+                read_png_from_file(format!("{}{}", dir_path, file_name), false).unwrap()
+            });
+
+            let name = format!("{}_{}", algorithm, background);
+            let golden_paths = [GOLDEN_DIR, GOLDEN_PARALLEL_DIR]
+                .map(|golden_dir| format!("{}{}.png", golden_dir, name));
+            if regenerate {
+                write_golden(&golden_paths[0], &results[0]);
+                let parallel_matches = compare(&results[0], &results[1])
+                    .unwrap()
+                    .matches(GOLDEN_TOLERANCE);
+                match parallel_matches {
+                    true => {
+                        let _ = fs::remove_file(&golden_paths[1]);
+                    }
+                    false => write_golden(&golden_paths[1], &results[1]),
+                }
+            }
+
+            // the differences are written as heatmaps into the temporary
+            // directory (out of the scratch one), so that they may be inspected
+            for (index, kind) in ["compose", "compose_parallel"].iter().enumerate() {
+                let golden_path = match index > 0 && fs::metadata(&golden_paths[1]).is_ok() {
+                    true => &golden_paths[1],
+                    false => &golden_paths[0],
+                };
+                let golden = read_png_from_file(golden_path.clone(), false).unwrap_or_else(|_| {
+                    panic!(
+                        "missing golden image {} (set {} to generate it)",
+                        golden_path, GOLDEN_REGENERATE
+                    )
+                });
+                let result = &results[index];
+                let comparison = compare(&golden, result).unwrap();
+                if !comparison.matches(GOLDEN_TOLERANCE) {
+                    let heatmap = diff_heatmap(&golden, result).unwrap();
+                    let heatmap_path = std::env::temp_dir().join(format!(
+                        "pconvert_golden_{}_{}_{}.png",
+                        std::process::id(),
+                        name,
+                        kind
+                    ));
+                    let heatmap_path = heatmap_path.display().to_string();
+                    write_png_to_file_d(heatmap_path.clone(), &heatmap).unwrap();
+                    failures.push(format!(
+                        "{} ({}) max_difference={} psnr={:.2} heatmap={}",
+                        name,
+                        kind,
+                        comparison.max_difference(),
+                        comparison.psnr(),
+                        heatmap_path
+                    ));
+                }
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "compositions differ from the golden images (set {} to regenerate them \
+        when the change is deliberate):\n{}",
+        GOLDEN_REGENERATE,
+        failures.join("\n")
+    );
+}
+
+/// Scratch directory of a test, unique to the process (so that concurrent
+/// test runs do not collide), removed when dropped, even on a panic.
+struct ScratchDir(std::path::PathBuf);
+
+impl ScratchDir {
+    fn new(name: &str) -> ScratchDir {
+        let path = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        ScratchDir(path)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Writes a golden image, with the best compression as it is tracked.
+fn write_golden(path: &str, image: &ImageBuffer<Rgba<u8>, Vec<u8>>) {
+    write_png_to_file(
+        path.to_string(),
+        image,
+        Compression::BEST,
+        FilterType::Adaptive,
+    )
+    .unwrap();
+}
+
 #[test]
 fn test_convert() {
     let file_in = format!("{}{}", TEST_DIR, TEST_FILE);
@@ -98,3 +299,2253 @@ fn test_convert() {
     img.save_with_format(out.clone(), ImageFormat::Png)
         .unwrap_or_else(|_| panic!("failure writing {}", out));
 }
+
+#[test]
+fn test_blend_multiple_images() {
+    let layers = ["sole.png", "back.png", "front.png"]
+        .iter()
+        .map(|name| read_png_from_file(format!("{}{}", TEST_DIR, name), false).unwrap())
+        .collect::<Vec<_>>();
+
+    // blends the layers one at a time, which is the expected
+    // behaviour for the multiple layers blending
+    let algorithm_fn = get_blending_algorithm(&BlendAlgorithm::SourceOver);
+    let mut expected = layers[0].clone();
+    blend_images(&mut expected, &layers[1], &algorithm_fn, &None);
+    blend_images(&mut expected, &layers[2], &algorithm_fn, &None);
+
+    let algorithms = vec![(BlendAlgorithm::SourceOver, None); 2];
+    let composition =
+        blend_multiple_images(layers.clone(), &algorithms, &DimensionPolicy::default()).unwrap();
+    assert!(composition == expected);
+
+    let algorithms = vec![(BlendAlgorithm::SourceOver, None); 1];
+    assert!(blend_multiple_images(layers, &algorithms, &DimensionPolicy::default()).is_err());
+    assert!(blend_multiple_images(vec![], &[], &DimensionPolicy::default()).is_err());
+}
+
+#[test]
+fn test_errors() {
+    let err = BlendAlgorithm::from_str("unknown").unwrap_err();
+    assert!(
+        matches!(&err, PConvertError::UnknownAlgorithmError(algorithm) if algorithm == "unknown")
+    );
+    assert_eq!(err.code(), "UNKNOWN_ALGORITHM");
+
+    // blending layers with different dimensions reports the offending layer
+    let layers = ["sole.png", "tux.png"]
+        .iter()
+        .map(|name| read_png_from_file(format!("{}{}", TEST_DIR, name), false).unwrap())
+        .collect::<Vec<_>>();
+    let algorithms = vec![(BlendAlgorithm::Alpha, None)];
+    match blend_multiple_images(layers, &algorithms, &DimensionPolicy::default()) {
+        Err(PConvertError::DimensionMismatchError {
+            layer,
+            expected,
+            found,
+        }) => {
+            assert_eq!(layer, 1);
+            assert_eq!(expected, (750, 750));
+            assert_eq!(found, (265, 314));
+        }
+        _ => panic!("expected a dimension mismatch error"),
+    }
+
+    // decoding a truncated file reports the path of the file
+    let data = fs::read(format!("{}{}", TEST_DIR, TEST_FILE)).unwrap();
+    let path = std::env::temp_dir().join("pconvert_test_truncated.png");
+    let path = path.to_str().unwrap().to_string();
+    fs::write(&path, &data[..data.len() / 2]).unwrap();
+    match read_png_from_file(path.clone(), false).map_err(|err| err.with_layer(2)) {
+        Err(err @ PConvertError::DecodeError { .. }) => {
+            assert_eq!(err.code(), "DECODE_ERROR");
+            assert!(err.to_string().contains(&path));
+            assert!(
+                matches!(err, PConvertError::DecodeError { path: Some(ref err_path), layer: Some(2), .. } if *err_path == path)
+            );
+        }
+        _ => panic!("expected a decode error"),
+    }
+}
+
+#[test]
+fn test_dimension_policies() {
+    let sole = read_png_from_file(format!("{}{}", TEST_DIR, "sole.png"), false).unwrap();
+    let tux = read_png_from_file(format!("{}{}", TEST_DIR, "tux.png"), false).unwrap();
+
+    // layers are conformed to the dimensions of the bottom layer
+    let algorithms = vec![(BlendAlgorithm::Alpha, None)];
+    for policy in ["anchor", "stretch", "fit"].iter() {
+        let policy = DimensionPolicy::from_str(policy).unwrap();
+        let layers = vec![sole.clone(), tux.clone()];
+        let composition = blend_multiple_images(layers, &algorithms, &policy).unwrap();
+        assert_eq!(composition.dimensions(), (750, 750));
+    }
+
+    // anchoring places the layer as is at the gravity
+    let policy = DimensionPolicy::from_options(Some("anchor"), Some("top-left")).unwrap();
+    assert_eq!(policy, DimensionPolicy::Anchor(Gravity::TopLeft));
+    let anchored = conform_layer(tux.clone(), (750, 750), 1, &policy).unwrap();
+    assert_eq!(anchored.get_pixel(100, 100), tux.get_pixel(100, 100));
+    assert_eq!(anchored.get_pixel(749, 749)[3], 0);
+    let policy = DimensionPolicy::Anchor(Gravity::BottomRight);
+    let anchored = conform_layer(tux.clone(), (100, 100), 1, &policy).unwrap();
+    assert_eq!(anchored.get_pixel(99, 99), tux.get_pixel(264, 313));
+
+    // fitting keeps the aspect ratio, leaving the sides transparent
+    let policy = DimensionPolicy::Fit(Gravity::Center);
+    let fitted = conform_layer(tux.clone(), (750, 750), 1, &policy).unwrap();
+    assert_eq!(fitted.get_pixel(0, 375)[3], 0);
+    let stretched = conform_layer(tux, (750, 750), 1, &DimensionPolicy::Stretch).unwrap();
+    assert_eq!(stretched.dimensions(), (750, 750));
+
+    assert!(DimensionPolicy::from_str("unknown").is_err());
+    assert!(matches!(
+        DimensionPolicy::from_options(Some("fit"), Some("middle")),
+        Err(PConvertError::InvalidParameterError { .. })
+    ));
+}
+
+#[test]
+fn test_resize() {
+    let tux = read_png_from_file(format!("{}{}", TEST_DIR, "tux.png"), false).unwrap();
+    let filters = [
+        ResizeFilter::Nearest,
+        ResizeFilter::Bilinear,
+        ResizeFilter::Bicubic,
+        ResizeFilter::Lanczos,
+    ];
+    for filter in filters.iter() {
+        assert_eq!(resize(&tux, 530, 628, *filter).dimensions(), (530, 628));
+        assert_eq!(resize(&tux, 66, 78, *filter).dimensions(), (66, 78));
+    }
+
+    // nearest neighbour upscaling only replicates the pixels
+    let upscaled = resize(&tux, 530, 628, ResizeFilter::Nearest);
+    assert_eq!(upscaled.get_pixel(200, 300), tux.get_pixel(100, 150));
+
+    // a uniform image remains uniform, whatever the filter
+    let uniform = ImageBuffer::from_pixel(10, 10, Rgba([200, 100, 50, 255]));
+    for filter in filters.iter() {
+        let resized = resize(&uniform, 7, 13, *filter);
+        assert!(resized
+            .pixels()
+            .all(|pixel| *pixel == Rgba([200, 100, 50, 255])));
+    }
+
+    // transparent pixels do not darken the visible ones (premultiplied alpha)
+    let half = ImageBuffer::from_fn(8, 8, |x, _| {
+        if x < 4 {
+            Rgba([0, 0, 0, 0])
+        } else {
+            Rgba([255, 0, 0, 255])
+        }
+    });
+    for filter in filters.iter() {
+        let resized = resize(&half, 13, 5, *filter);
+        for pixel in resized.pixels().filter(|pixel| pixel[3] > 0) {
+            assert_eq!((pixel[0], pixel[1], pixel[2]), (255, 0, 0));
+        }
+    }
+
+    // missing dimensions keep the aspect ratio
+    let size = OutputSize::from_str("200x").unwrap();
+    assert_eq!(size.dimensions(tux.dimensions()).unwrap(), (200, 237));
+    let size = OutputSize::from_str("x157").unwrap();
+    assert_eq!(size.dimensions(tux.dimensions()).unwrap(), (133, 157));
+    let resize = ResizeOptions::from_options(None, Some(0.5), Some("bicubic"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(resize.filter, ResizeFilter::Bicubic);
+    assert_eq!(resize.apply(&tux).unwrap().dimensions(), (133, 157));
+
+    assert!(OutputSize::from_str("x").is_err());
+    assert!(OutputSize::from_str("0x10").is_err());
+    assert!(ResizeOptions::from_options(None, None, None)
+        .unwrap()
+        .is_none());
+    assert!(ResizeOptions::from_options(Some("10x10"), Some(2.0), None).is_err());
+    assert!(ResizeOptions::from_options(None, Some(-1.0), None).is_err());
+    assert!(ResizeOptions::from_options(None, Some(2.0), Some("unknown")).is_err());
+}
+
+#[test]
+fn test_transforms() {
+    let tux = read_png_from_file(format!("{}{}", TEST_DIR, "tux.png"), false).unwrap();
+    let (width, height) = tux.dimensions();
+    let transform = |transform: &str| {
+        Transform::from_str(transform)
+            .and_then(|transform| transform.apply(&tux))
+            .unwrap()
+    };
+
+    let rotated = transform("rotate:90");
+    assert_eq!(rotated.dimensions(), (height, width));
+    assert_eq!(
+        rotated.get_pixel(height - 1 - 150, 100),
+        tux.get_pixel(100, 150)
+    );
+    let flipped = transform("flip_h");
+    assert_eq!(
+        flipped.get_pixel(width - 1 - 100, 150),
+        tux.get_pixel(100, 150)
+    );
+    let flipped = transform("flip_v");
+    assert_eq!(
+        flipped.get_pixel(100, height - 1 - 150),
+        tux.get_pixel(100, 150)
+    );
+    let cropped = transform("crop:10,20,100,50");
+    assert_eq!(cropped.dimensions(), (100, 50));
+    assert_eq!(cropped.get_pixel(0, 0), tux.get_pixel(10, 20));
+
+    // affine transforms keep the dimensions, translating by whole
+    // pixels moves them as they are, leaving the rest transparent
+    assert!(transform("affine:1,0,0,0,1,0") == tux);
+    let translated = transform("affine:1,0,10,0,1,5");
+    assert_eq!(translated.dimensions(), (width, height));
+    assert_eq!(translated.get_pixel(110, 155), tux.get_pixel(100, 150));
+    assert_eq!(translated.get_pixel(0, 0)[3], 0);
+    let translated = transform("affine:1,0,1e45,0,1,-1e45");
+    assert_eq!(translated.get_pixel(0, 0)[3], 0);
+
+    let transforms = [Transform::Rotate90, Transform::Rotate270];
+    assert!(apply_transforms(tux.clone(), &transforms).unwrap() == tux);
+
+    for transform in [
+        "rotate:45",
+        "crop:1,2,3",
+        "crop:-1,0,10,10",
+        "skew",
+        "flip_h:1",
+    ]
+    .iter()
+    {
+        assert!(matches!(
+            Transform::from_str(transform),
+            Err(PConvertError::InvalidParameterError { .. })
+        ));
+    }
+    let singular = Transform::Affine([1.0, 2.0, 0.0, 2.0, 4.0, 0.0]);
+    assert!(singular.apply(&tux).is_err());
+    let outside = Transform::Crop {
+        x: 200,
+        y: 0,
+        width: 100,
+        height: 10,
+    };
+    assert!(outside.apply(&tux).is_err());
+
+    // layers of manifest entries may be given with their transforms
+    let item = crate::batch::parse_json_entry(
+        r#"{"out": "out.png", "layers": ["a.png", {"path": "b.png", "transforms": ["flip_h", "rotate:180"]}]}"#,
+    )
+    .unwrap();
+    assert_eq!(item.layers, vec!["a.png", "b.png"]);
+    assert!(item.layers_options[0].is_empty());
+    assert_eq!(
+        item.layers_options[1].transforms,
+        vec![Transform::FlipHorizontal, Transform::Rotate180]
+    );
+}
+
+#[test]
+fn test_effects() {
+    let effect = |effect: &str, pixel: [u8; 4]| {
+        let mut image = ImageBuffer::from_pixel(1, 1, Rgba(pixel));
+        Effect::from_str(effect).unwrap().apply(&mut image).unwrap();
+        image.get_pixel(0, 0).0
+    };
+
+    // the colour channels are adjusted while the alpha is kept
+    assert_eq!(effect("invert", [10, 20, 30, 40]), [245, 235, 225, 40]);
+    assert_eq!(effect("grayscale", [255, 0, 0, 128]), [76, 76, 76, 128]);
+    assert_eq!(
+        effect("brightness:0.1", [10, 250, 128, 255]),
+        [36, 255, 154, 255]
+    );
+    assert_eq!(
+        effect("contrast:0", [10, 250, 128, 255]),
+        [128, 128, 128, 255]
+    );
+    assert_eq!(
+        effect("contrast:2", [100, 200, 128, 255]),
+        [73, 255, 129, 255]
+    );
+    assert_eq!(
+        effect("saturation:1", [10, 200, 30, 255]),
+        [10, 200, 30, 255]
+    );
+    assert_eq!(effect("saturation:0", [0, 0, 255, 255]), [29, 29, 29, 255]);
+    assert_eq!(effect("hue:120", [255, 0, 0, 255]), [0, 255, 0, 255]);
+    assert_eq!(effect("hue:-120", [255, 0, 0, 255]), [0, 0, 255, 255]);
+    assert_eq!(effect("hue:360", [12, 34, 56, 255]), [12, 34, 56, 255]);
+    assert_eq!(effect("levels:0,255", [12, 34, 56, 255]), [12, 34, 56, 255]);
+    assert_eq!(
+        effect("levels:50,150", [40, 100, 200, 255]),
+        [0, 128, 255, 255]
+    );
+    assert_eq!(
+        effect("levels:0,255,1,100,200", [0, 255, 128, 255]),
+        [100, 200, 150, 255]
+    );
+    assert_eq!(
+        effect("tint:#ff0000", [255, 255, 255, 255]),
+        [255, 0, 0, 255]
+    );
+    assert_eq!(effect("tint:#ff0000,0", [1, 2, 3, 4]), [1, 2, 3, 4]);
+
+    // effects are applied in order, after the transforms of the layer
+    let tux = read_png_from_file(format!("{}{}", TEST_DIR, TEST_FILE), false).unwrap();
+    let effects = [Effect::Invert, Effect::Invert];
+    assert!(apply_effects(tux.clone(), &effects).unwrap() == tux);
+    let layer_options = LayerOptions {
+        transforms: vec![Transform::Rotate90],
+        effects: vec![Effect::Grayscale],
+    };
+    let layer = layer_options.apply(tux.clone()).unwrap();
+    assert_eq!(layer.dimensions(), (tux.height(), tux.width()));
+    assert!(layer
+        .pixels()
+        .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]));
+
+    // effects defined outside of the crate are given to the layers as
+    // custom effects, applied in order with the other ones
+    struct SwapRedBlue;
+    impl PixelEffect for SwapRedBlue {
+        fn apply_pixel(&self, pixel: &mut Rgba<u8>) {
+            pixel.0.swap(0, 2);
+        }
+    }
+    let swap = Effect::Custom(CustomEffect::new(SwapRedBlue));
+    assert_eq!(swap, swap.clone());
+    assert_ne!(swap, Effect::Custom(CustomEffect::new(SwapRedBlue)));
+    assert_eq!(swap.to_string(), "custom");
+    let layer_options = LayerOptions {
+        transforms: vec![],
+        effects: vec![swap.clone(), Effect::Invert, swap],
+    };
+    let layer = layer_options
+        .apply_with_limits(
+            ImageBuffer::from_pixel(2, 2, Rgba([10, 20, 30, 40])),
+            &Limits::default(),
+        )
+        .unwrap();
+    assert!(layer.pixels().all(|pixel| pixel.0 == [245, 235, 225, 40]));
+
+    for effect in [
+        "brightness:2",
+        "contrast:-1",
+        "levels:200,100",
+        "levels:0,255,1,0",
+        "tint:red",
+        "tint:#ff0000,2",
+        "blur",
+        "invert:1",
+    ]
+    .iter()
+    {
+        assert!(matches!(
+            Effect::from_str(effect),
+            Err(PConvertError::InvalidParameterError { .. })
+        ));
+    }
+    let effect = Effect::from_str("levels:16,235").unwrap();
+    assert_eq!(effect.to_string(), "levels:16,235,1,0,255");
+    assert_eq!(Effect::from_str(&effect.to_string()).unwrap(), effect);
+
+    // layers of manifest entries may be given with their effects
+    let item = crate::batch::parse_json_entry(
+        r#"{"out": "out.png", "layers": [{"path": "a.png", "effects": ["grayscale", "hue:30"]}, "b.png"]}"#,
+    )
+    .unwrap();
+    assert_eq!(item.layers_options[0].effects.len(), 2);
+    assert!(item.layers_options[0].transforms.is_empty());
+    assert!(item.layers_options[1].is_empty());
+}
+
+#[test]
+fn test_convolution_effects() {
+    let effect = |effect: &str, image: &ImageBuffer<Rgba<u8>, Vec<u8>>| {
+        let mut image = image.clone();
+        Effect::from_str(effect).unwrap().apply(&mut image).unwrap();
+        image
+    };
+
+    // blurring and sharpening keep uniform layers as they are
+    let uniform = ImageBuffer::from_pixel(64, 64, Rgba([20, 120, 220, 255]));
+    for name in ["blur:3", "box_blur:2", "sharpen:1.5,2"].iter() {
+        assert!(effect(name, &uniform) == uniform);
+    }
+
+    // the colour of transparent pixels does not bleed into the visible ones
+    let half = ImageBuffer::from_fn(16, 1, |x, _| match x < 8 {
+        true => Rgba([255, 0, 0, 0]),
+        false => Rgba([0, 0, 255, 255]),
+    });
+    let blurred = effect("blur:2", &half);
+    assert!(blurred
+        .pixels()
+        .all(|pixel| pixel[3] == 0 || (pixel[0] == 0 && pixel[2] == 255)));
+    assert!(blurred.get_pixel(7, 0)[3] > 0 && blurred.get_pixel(8, 0)[3] < 255);
+
+    let mut dot = ImageBuffer::from_pixel(3, 3, Rgba([0, 0, 0, 0]));
+    dot.put_pixel(1, 1, Rgba([255, 255, 255, 255]));
+    assert_eq!(
+        effect("box_blur:1", &dot).get_pixel(1, 1).0,
+        [255, 255, 255, 28]
+    );
+
+    // large radii run by bands of rows (in multiple threads), which
+    // must give the same result in every row for identical rows
+    let gradient = ImageBuffer::from_fn(40, 300, |x, _| Rgba([(x * 6) as u8, 0, 0, 255]));
+    let blurred = effect("blur:12", &gradient);
+    assert!((1..300).all(|y| (0..40).all(|x| blurred.get_pixel(x, y) == blurred.get_pixel(x, 0))));
+    assert!(blurred.get_pixel(0, 0)[0] > gradient.get_pixel(0, 0)[0]);
+
+    // sharpening increases the contrast of the edges
+    let step = ImageBuffer::from_fn(16, 1, |x, _| match x < 8 {
+        true => Rgba([100, 100, 100, 255]),
+        false => Rgba([150, 150, 150, 255]),
+    });
+    let sharpened = effect("sharpen:1", &step);
+    assert!(sharpened.get_pixel(7, 0)[0] < 100 && sharpened.get_pixel(8, 0)[0] > 150);
+    assert_eq!(sharpened.get_pixel(0, 0), step.get_pixel(0, 0));
+    assert!(effect("sharpen:1,1,50", &step) == step);
+
+    // shadows are placed under the layer, which is kept as it is
+    let square = ImageBuffer::from_fn(20, 20, |x, y| {
+        match (2..6).contains(&x) && (2..6).contains(&y) {
+            true => Rgba([200, 10, 10, 255]),
+            false => Rgba([0, 0, 0, 0]),
+        }
+    });
+    let shadowed = effect("drop_shadow:5,5,0", &square);
+    assert_eq!(shadowed.get_pixel(3, 3), square.get_pixel(3, 3));
+    assert_eq!(shadowed.get_pixel(8, 8).0, [0, 0, 0, 128]);
+    assert_eq!(shadowed.get_pixel(1, 1)[3], 0);
+    let shadowed = effect("drop_shadow:-2,0,0,#ff0000,1", &square);
+    assert_eq!(shadowed.get_pixel(0, 3).0, [255, 0, 0, 255]);
+    let glowing = effect("outer_glow:2,#ffcc00", &square);
+    assert_eq!(glowing.get_pixel(3, 3), square.get_pixel(3, 3));
+    assert_eq!(&glowing.get_pixel(7, 3).0[..3], &[255, 204, 0]);
+    assert!(glowing.get_pixel(7, 3)[3] > 0 && glowing.get_pixel(19, 19)[3] == 0);
+
+    for effect in [
+        "blur:-1",
+        "box_blur:1.5",
+        "sharpen:1,2,3,4",
+        "drop_shadow:1,2",
+        "drop_shadow:1.5,2,3",
+        "outer_glow:2,#12345",
+        "outer_glow:2,#123456,2",
+    ]
+    .iter()
+    {
+        assert!(matches!(
+            Effect::from_str(effect),
+            Err(PConvertError::InvalidParameterError { .. })
+        ));
+    }
+    let effect = Effect::from_str("drop_shadow:4,-6,3").unwrap();
+    assert_eq!(effect.to_string(), "drop_shadow:4,-6,3,#000000,0.5");
+    assert_eq!(Effect::from_str(&effect.to_string()).unwrap(), effect);
+
+    // the buffers of the convolutions (16 bytes per pixel) are bounded
+    // by the limits, while the other effects are not affected by them
+    let limits = Limits {
+        max_bytes: 64 * 64 * 16,
+        ..Limits::default()
+    };
+    for name in [
+        "blur:3",
+        "box_blur:2",
+        "sharpen:1",
+        "drop_shadow:1,1,1",
+        "outer_glow:2",
+    ]
+    .iter()
+    {
+        let mut image = uniform.clone();
+        assert!(matches!(
+            Effect::from_str(name)
+                .unwrap()
+                .apply_with_limits(&mut image, &limits),
+            Err(PConvertError::LimitExceededError {
+                limit: "max_bytes",
+                value: 262144,
+                ..
+            })
+        ));
+    }
+    let effects = vec![Effect::from_str("invert").unwrap()];
+    assert!(apply_effects_with_limits(uniform.clone(), &effects, &limits).is_ok());
+    let effects = vec![Effect::from_str("blur:1").unwrap()];
+    assert!(apply_effects_with_limits(uniform.clone(), &effects, &Limits::default()).is_ok());
+    assert!(apply_effects_with_limits(uniform, &effects, &limits).is_err());
+}
+
+#[test]
+fn test_alpha() {
+    let mut image = ImageBuffer::from_pixel(10, 8, Rgba([255, 255, 255, 0]));
+    assert_eq!(bounding_box(&image, 0), None);
+    assert_eq!(trim(&image, 0).dimensions(), (1, 1));
+
+    image.put_pixel(2, 3, Rgba([10, 20, 30, 255]));
+    image.put_pixel(6, 5, Rgba([10, 20, 30, 40]));
+    assert_eq!(bounding_box(&image, 0), Some((2, 3, 5, 3)));
+    assert_eq!(bounding_box(&image, 40), Some((2, 3, 1, 1)));
+    let trimmed = trim(&image, 0);
+    assert_eq!(trimmed.dimensions(), (5, 3));
+    assert_eq!(trimmed.get_pixel(0, 0), image.get_pixel(2, 3));
+    assert_eq!(trimmed.get_pixel(4, 2), image.get_pixel(6, 5));
+
+    let mut thresholded = image.clone();
+    threshold_alpha(&mut thresholded, 40);
+    assert_eq!(thresholded.get_pixel(6, 5)[3], 255);
+    assert_eq!(thresholded.get_pixel(0, 0)[3], 0);
+    threshold_alpha(&mut thresholded, 255);
+    assert_eq!(bounding_box(&thresholded, 0), Some((2, 3, 5, 3)));
+
+    // the colour of a red pixel blended at half opacity with a white
+    // matte is restored, while opaque pixels are kept as they are
+    let mut matted = ImageBuffer::from_fn(2, 1, |x, _| match x {
+        0 => Rgba([255, 127, 127, 128]),
+        _ => Rgba([10, 20, 30, 255]),
+    });
+    remove_matte(&mut matted, [255, 255, 255]);
+    assert_eq!(matted.get_pixel(0, 0).0, [255, 0, 0, 128]);
+    assert_eq!(matted.get_pixel(1, 0).0, [10, 20, 30, 255]);
+
+    // flattening is the inverse, blending the colour over the background
+    let flattened = flatten(&matted, [255, 255, 255]);
+    assert_eq!(flattened.get_pixel(0, 0).0, [255, 127, 127]);
+    assert_eq!(flattened.get_pixel(1, 0).0, [10, 20, 30]);
+    let flattened = flatten(&image, [1, 2, 3]);
+    assert_eq!(flattened.get_pixel(0, 0).0, [1, 2, 3]);
+
+    // the alpha utilities are also available as layer effects
+    let mut layer = image.clone();
+    Effect::from_str("alpha_threshold:50")
+        .unwrap()
+        .apply(&mut layer)
+        .unwrap();
+    assert_eq!(bounding_box(&layer, 0), Some((2, 3, 1, 1)));
+    assert_eq!(
+        Effect::from_str("remove_matte").unwrap().to_string(),
+        "remove_matte:#ffffff"
+    );
+    assert!(Effect::from_str("remove_matte:#000000,1").is_err());
+    assert!(Effect::from_str("alpha_threshold:256").is_err());
+}
+
+#[test]
+fn test_compare() {
+    let reference = read_png_from_file(format!("{}{}", TEST_DIR, TEST_FILE), false).unwrap();
+    let comparison = compare(&reference, &reference).unwrap();
+    assert_eq!(comparison.max_difference(), 0);
+    assert_eq!(comparison.mean_difference(), 0.0);
+    assert_eq!(comparison.psnr(), f64::INFINITY);
+    assert!((comparison.ssim() - 1.0).abs() < 1e-9);
+    assert!(comparison.matches(0));
+
+    // a single channel (of an opaque pixel) that is slightly off
+    // only matches when compared with a tolerance
+    let mut result = reference.clone();
+    let (x, y, _) = reference
+        .enumerate_pixels()
+        .find(|(_, _, pixel)| pixel[3] == 255 && pixel[0] < 250)
+        .unwrap();
+    result.get_pixel_mut(x, y)[0] += 2;
+    let comparison = compare(&reference, &result).unwrap();
+    assert_eq!(comparison.max_difference(), 2);
+    assert!(comparison.mean_difference() > 0.0);
+    assert!(comparison.psnr().is_finite());
+    assert!(comparison.ssim() < 1.0);
+    assert!(!comparison.matches(1));
+    assert!(comparison.matches(2));
+
+    // the colour of fully transparent pixels is not a difference
+    let transparent = ImageBuffer::from_pixel(4, 4, Rgba([255, 0, 0, 0]));
+    let other = ImageBuffer::from_pixel(4, 4, Rgba([0, 0, 255, 0]));
+    assert!(compare(&transparent, &other).unwrap().matches(0));
+
+    let heatmap = diff_heatmap(&reference, &result).unwrap();
+    assert_eq!(heatmap.dimensions(), reference.dimensions());
+    assert_eq!(heatmap.get_pixel(x, y).0, [255, 255, 255, 255]);
+    assert_ne!(heatmap.get_pixel(0, 0).0, [255, 255, 255, 255]);
+
+    let smaller = ImageBuffer::new(reference.width() - 1, reference.height());
+    assert!(matches!(
+        compare(&reference, &smaller),
+        Err(PConvertError::DimensionMismatchError { .. })
+    ));
+    assert!(diff_heatmap(&reference, &smaller).is_err());
+}
+
+#[test]
+fn test_corrupt_pngs() {
+    let data = fs::read(format!("{}{}", TEST_DIR, TEST_FILE)).unwrap();
+
+    // truncated files (including the empty file and the signature
+    // alone) must fail decoding with an error instead of panicking
+    for length in [0, 8, 16, 33, 100, data.len() / 2, data.len() - 12] {
+        assert!(
+            decode_png(&data[..length], false).is_err(),
+            "truncated at {} bytes",
+            length
+        );
+    }
+
+    // corrupts bytes across the header and image data, the decoding
+    // may succeed (the corruption may go unnoticed) but never panic
+    for offset in (8..data.len()).step_by(97) {
+        let mut corrupt = data.clone();
+        corrupt[offset] ^= 0xff;
+        let _ = decode_png(&corrupt[..], true);
+    }
+
+    // dimensions too large to be allocated (from the header) are
+    // reported as an error instead of aborting the process, either
+    // by the limits or by the failure to allocate the image
+    let huge = with_dimensions(&data, 0xffff_01bf, 0xffff);
+    assert!(matches!(
+        decode_png(&huge[..], false),
+        Err(PConvertError::LimitExceededError { .. })
+    ));
+    let unlimited = Limits {
+        max_width: u32::MAX,
+        max_height: u32::MAX,
+        max_pixels: u64::MAX,
+        max_bytes: u64::MAX,
+        max_layers: usize::MAX,
+    };
+    assert!(matches!(
+        decode_png_with_limits(&huge[..], false, &unlimited),
+        Err(PConvertError::DecodeError { .. })
+    ));
+
+    // images that are not RGBA8 are reported as unsupported
+    let mut rgb = Vec::new();
+    PngEncoder::new(&mut rgb)
+        .write_image(&[0; 4 * 4 * 3], 4, 4, ColorType::Rgb8)
+        .unwrap();
+    assert!(matches!(
+        decode_png(&rgb[..], false),
+        Err(PConvertError::UnsupportedImageTypeError)
+    ));
+
+    // corrupt layers read by the thread pool are reported as errors
+    let path = std::env::temp_dir().join("pconvert_test_corrupt.png");
+    let path = path.to_str().unwrap().to_string();
+    fs::write(&path, &data[..data.len() / 3]).unwrap();
+    let mut thread_pool = ThreadPool::new(1).unwrap();
+    thread_pool.start();
+    let result_channel =
+        thread_pool.execute(move || ResultMessage::ImageResult(read_png_from_file(path, false)));
+    assert!(matches!(
+        result_channel.recv(),
+        Ok(ResultMessage::ImageResult(Err(
+            PConvertError::DecodeError { .. }
+        )))
+    ));
+}
+
+/// Changes the dimensions in the header (IHDR chunk) of the PNG, updating
+/// its CRC so that the header is still valid.
+fn with_dimensions(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut data = data.to_vec();
+    data[16..20].copy_from_slice(&width.to_be_bytes());
+    data[20..24].copy_from_slice(&height.to_be_bytes());
+    let mut crc = 0xffff_ffff_u32;
+    for byte in &data[12..29] {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    data[29..33].copy_from_slice(&(!crc).to_be_bytes());
+    data
+}
+
+#[test]
+fn test_limits() {
+    let path = format!("{}{}", TEST_DIR, TEST_FILE);
+    let data = fs::read(&path).unwrap();
+    let (width, height) = decode_png(&data[..], false).unwrap().dimensions();
+
+    // every limit is checked from the header, before decoding the image
+    let cases = [
+        ("max_width", width as u64 - 1, width as u64),
+        ("max_height", height as u64 - 1, height as u64),
+        (
+            "max_pixels",
+            (width * height) as u64 - 1,
+            (width * height) as u64,
+        ),
+        (
+            "max_bytes",
+            (width * height * 4) as u64 - 1,
+            (width * height * 4) as u64,
+        ),
+    ];
+    for (name, max, value) in cases {
+        let mut limits = Limits::default();
+        limits.set(name, max).unwrap();
+        match decode_png_with_limits(&data[..], false, &limits) {
+            Err(PConvertError::LimitExceededError {
+                limit,
+                value: found,
+                max: found_max,
+            }) => assert_eq!((limit, found, found_max), (name, value, max)),
+            result => panic!("unexpected result for {}: {:?}", name, result.err()),
+        }
+        limits.set(name, value).unwrap();
+        assert!(decode_png_with_limits(&data[..], false, &limits).is_ok());
+    }
+
+    let mut limits = Limits::default();
+    assert!(matches!(
+        limits.set("max_depth", 1),
+        Err(PConvertError::ArgumentError(_))
+    ));
+    assert!(matches!(
+        limits.set("max_width", u64::MAX),
+        Err(PConvertError::InvalidParameterError { .. })
+    ));
+
+    // cached images are checked against the limits of each read
+    limits.max_pixels = 1;
+    let cache = LayerCache::new(usize::MAX);
+    cache.read_image(&path, false).unwrap();
+    assert!(matches!(
+        cache.read_image_with_limits(&path, false, &limits),
+        Err(PConvertError::LimitExceededError {
+            limit: "max_pixels",
+            ..
+        })
+    ));
+
+    // the resized image is checked before being allocated
+    let image = decode_png(&data[..], false).unwrap();
+    let resize = Some(ResizeOptions {
+        size: OutputSize::Scale(2.0),
+        filter: ResizeFilter::default(),
+    });
+    let mut limits = Limits {
+        max_width: width * 2 - 1,
+        ..Limits::default()
+    };
+    assert!(matches!(
+        resize_output(image.clone(), &resize, &limits),
+        Err(PConvertError::LimitExceededError {
+            limit: "max_width",
+            ..
+        })
+    ));
+    limits.max_width = width * 2;
+    assert_eq!(
+        resize_output(image.clone(), &resize, &limits)
+            .unwrap()
+            .dimensions(),
+        (width * 2, height * 2)
+    );
+
+    // and so are the (premultiplied) buffers of the resampling, that
+    // nearest neighbour does without
+    let (width, height) = (width as u64, height as u64);
+    limits.max_bytes = (width * height + width * 2 * height) * 16 - 1;
+    assert!(matches!(
+        resize_output(image.clone(), &resize, &limits),
+        Err(PConvertError::LimitExceededError {
+            limit: "max_bytes",
+            ..
+        })
+    ));
+    let nearest = Some(ResizeOptions {
+        size: OutputSize::Scale(2.0),
+        filter: ResizeFilter::Nearest,
+    });
+    assert!(resize_output(image, &nearest, &limits).is_ok());
+
+    // the number of layers is checked before any layer is read
+    let limits = Limits {
+        max_layers: 1,
+        ..Limits::default()
+    };
+    let item = BatchItem::from_str(&format!(
+        "{{\"out\": \"{0}result_limits.png\", \"layers\": [\"{0}missing.png\", \"{0}missing.png\"]}}",
+        TEST_DIR
+    ))
+    .unwrap();
+    let mut thread_pool = ThreadPool::new(1).unwrap();
+    thread_pool.start();
+    let results = run_batch(vec![(1, Ok(item.clone()))], &thread_pool, &limits);
+    assert!(matches!(
+        results[0].result,
+        Err(PConvertError::LimitExceededError {
+            limit: "max_layers",
+            value: 2,
+            max: 1
+        })
+    ));
+    assert!(matches!(
+        Watcher::new(item, limits),
+        Err(PConvertError::LimitExceededError { .. })
+    ));
+}
+
+#[test]
+fn test_stream() {
+    let paths = ["sole.png", "back.png", "front.png"]
+        .iter()
+        .map(|name| format!("{}{}", TEST_DIR, name))
+        .collect::<Vec<_>>();
+    let datas = paths
+        .iter()
+        .map(|path| fs::read(path).unwrap())
+        .collect::<Vec<_>>();
+    let stream = |algorithms: &[(BlendAlgorithm, Option<BlendAlgorithmParams>)],
+                  band_height: u32,
+                  limits: &Limits| {
+        let layers = datas.iter().map(|data| &data[..]).collect::<Vec<_>>();
+        let mut bytes = Vec::new();
+        blend_multiple_streaming(
+            layers,
+            algorithms,
+            &mut bytes,
+            Compression::FAST,
+            FilterType::NoFilter,
+            band_height,
+            limits,
+        )
+        .map(|_| bytes)
+    };
+
+    // the streamed composition matches the in-memory one exactly,
+    // for bands that do not divide the height of the layers as well
+    for algorithm in ["multiplicative", "source_over", "disjoint_over", "mask_top"].iter() {
+        let algorithm = BlendAlgorithm::from_str(algorithm).unwrap();
+        let algorithms = vec![(algorithm, None); 2];
+        let layers = datas
+            .iter()
+            .map(|data| decode_png(&data[..], false).unwrap())
+            .collect::<Vec<_>>();
+        let expected = blend_multiple_images(layers, &algorithms, &DimensionPolicy::Error).unwrap();
+        for band_height in [1, 7, 64, 10000].iter() {
+            let bytes = stream(&algorithms, *band_height, &Limits::default()).unwrap();
+            let composition = decode_png(&bytes[..], false).unwrap();
+            assert_eq!(composition, expected, "algorithm={:?}", algorithms[0].0);
+        }
+    }
+
+    // layers of other color types (e.g. opaque RGB backgrounds) are
+    // expanded into RGBA8 as in the in-memory path
+    let sole = decode_png(&datas[0][..], false).unwrap();
+    let (width, height) = sole.dimensions();
+    let mut rgb = Vec::new();
+    let rgb_image = DynamicImage::ImageRgba8(sole).into_rgb8();
+    PngEncoder::new(&mut rgb)
+        .write_image(&rgb_image, width, height, ColorType::Rgb8)
+        .unwrap();
+    let mut grey = Vec::new();
+    let grey_image = DynamicImage::ImageRgb8(rgb_image).into_luma_alpha16();
+    let grey_bytes = grey_image
+        .as_raw()
+        .iter()
+        .flat_map(|value| value.to_ne_bytes())
+        .collect::<Vec<_>>();
+    PngEncoder::new(&mut grey)
+        .write_image(&grey_bytes, width, height, ColorType::La16)
+        .unwrap();
+    let algorithms = vec![(BlendAlgorithm::SourceOver, None)];
+    for bottom in [rgb, grey].iter() {
+        let layers = vec![&bottom[..], &datas[1][..]];
+        let expected = blend_multiple_images(
+            layers
+                .iter()
+                .map(|data| decode_image(*data, false).unwrap())
+                .collect(),
+            &algorithms,
+            &DimensionPolicy::Error,
+        )
+        .unwrap();
+        let mut bytes = Vec::new();
+        blend_multiple_streaming(
+            layers,
+            &algorithms,
+            &mut bytes,
+            Compression::FAST,
+            FilterType::NoFilter,
+            7,
+            &Limits::default(),
+        )
+        .unwrap();
+        assert_eq!(decode_png(&bytes[..], false).unwrap(), expected);
+    }
+
+    // the pixels and bytes limits apply to a band, not to the whole layer
+    let algorithms = vec![(BlendAlgorithm::SourceOver, None); 2];
+    let limits = Limits {
+        max_pixels: 750 * 8,
+        ..Limits::default()
+    };
+    assert!(stream(&algorithms, 8, &limits).is_ok());
+    assert!(matches!(
+        stream(&algorithms, 9, &limits),
+        Err(PConvertError::LimitExceededError {
+            limit: "max_pixels",
+            ..
+        })
+    ));
+    assert!(matches!(
+        stream(&algorithms, 0, &Limits::default()),
+        Err(PConvertError::InvalidParameterError { .. })
+    ));
+    assert!(matches!(
+        stream(&algorithms[1..], 8, &Limits::default()),
+        Err(PConvertError::ArgumentError(_))
+    ));
+
+    // the layers are never conformed, so they must all have the same
+    // dimensions, checked before the output file is even created
+    let tux = format!("{}{}", TEST_DIR, TEST_FILE);
+    let mismatch_path = format!("{}result_stream_mismatch.png", TEST_DIR);
+    let result = blend_files_streaming(
+        &[paths[0].clone(), tux],
+        mismatch_path.clone(),
+        &algorithms[1..],
+        Compression::FAST,
+        FilterType::NoFilter,
+        DEFAULT_BAND_HEIGHT,
+        &Limits::default(),
+    );
+    assert!(matches!(
+        result,
+        Err(PConvertError::DimensionMismatchError { layer: 1, .. })
+    ));
+    assert!(fs::metadata(&mismatch_path).is_err());
+
+    // decoding errors name the path of the layer that failed
+    let mut corrupt = datas[1].clone();
+    corrupt.truncate(corrupt.len() / 2);
+    let corrupt_path = format!("{}result_stream_corrupt.png", TEST_DIR);
+    fs::write(&corrupt_path, corrupt).unwrap();
+    let result = blend_files_streaming(
+        &[paths[0].clone(), corrupt_path.clone()],
+        format!("{}result_stream.png", TEST_DIR),
+        &algorithms[1..],
+        Compression::FAST,
+        FilterType::NoFilter,
+        DEFAULT_BAND_HEIGHT,
+        &Limits::default(),
+    );
+    fs::remove_file(&corrupt_path).unwrap();
+    match result {
+        Err(PConvertError::DecodeError { path, layer, .. }) => {
+            assert_eq!((path, layer), (Some(corrupt_path), Some(1)))
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_thread_pool_panic() {
+    let mut thread_pool = ThreadPool::new(1).unwrap();
+    thread_pool.start();
+
+    // a panicking task drops its result channel, leaving
+    // the (single) worker available for the next tasks
+    let result_channel = thread_pool.execute(|| panic!("task failure"));
+    assert!(result_channel.recv().is_err());
+
+    let path = format!("{}{}", TEST_DIR, TEST_FILE);
+    let result_channel =
+        thread_pool.execute(move || ResultMessage::ImageResult(read_png_from_file(path, false)));
+    assert!(matches!(
+        result_channel.recv(),
+        Ok(ResultMessage::ImageResult(Ok(_)))
+    ));
+}
+
+#[test]
+fn test_batch() {
+    let manifest = std::env::temp_dir().join("pconvert_test_batch.jsonl");
+    let manifest = manifest.to_str().unwrap().to_string();
+    let lines = [
+        format!(
+            "{{\"out\": \"{0}result_batch_1.png\", \"layers\": [\"{0}sole.png\", \"{0}back.png\"], \"algorithm\": \"source_over\"}}",
+            TEST_DIR
+        ),
+        "# comments and blank lines are ignored".to_string(),
+        String::new(),
+        "{\"out\": \"invalid.png\"".to_string(),
+        format!(
+            "{{\"out\": \"{0}result_batch_2.png\", \"layers\": [\"{0}sole.png\", \"{0}missing.png\"]}}",
+            TEST_DIR
+        ),
+        format!(
+            "{{\"out\": \"{0}result_batch_3.png\", \"layers\": [\"{0}sole.png\", \"{0}back.png\"], \"algorithms\": [{{\"algorithm\": \"mask_top\", \"params\": {{\"factor\": 0.5}}}}]}}",
+            TEST_DIR
+        ),
+        format!(
+            "{{\"out\": \"{0}result_batch_7.png\", \"layers\": [\"{0}sole.png\", \"{0}back.png\"], \"algorithms\": [{{\"algorithm\": \"mask_top\", \"params\": 0.5}}]}}",
+            TEST_DIR
+        ),
+    ];
+    fs::write(&manifest, lines.join("\n")).unwrap();
+
+    // parameters that are not an object are an error of their entry
+    let entries = read_manifest(&manifest, &ManifestFormat::JsonLines).unwrap();
+    assert_eq!(entries.len(), 5);
+    match &entries[4].1 {
+        Err(PConvertError::InvalidParameterError { name, .. }) => assert_eq!(name, "params"),
+        _ => panic!("expected an invalid 'params' error"),
+    }
+
+    let mut thread_pool = ThreadPool::new(2).unwrap();
+    thread_pool.start();
+    let results = run_batch(entries, &thread_pool, &Limits::default());
+
+    let lines = results.iter().map(|result| result.line).collect::<Vec<_>>();
+    assert_eq!(lines, vec![1, 4, 5, 6, 7]);
+    assert!(results[0].result.is_ok());
+    assert!(results[1].result.is_err() && results[1].out.is_none());
+    assert!(results[2].result.is_err());
+    assert!(results[3].result.is_ok());
+    assert!(results[4].result.is_err());
+
+    let composition = read_png_from_file(format!("{}result_batch_1.png", TEST_DIR), false).unwrap();
+    let layers = ["sole.png", "back.png"]
+        .iter()
+        .map(|name| read_png_from_file(format!("{}{}", TEST_DIR, name), false).unwrap())
+        .collect::<Vec<_>>();
+    let algorithms = vec![(BlendAlgorithm::SourceOver, None)];
+    assert!(
+        composition
+            == blend_multiple_images(layers.clone(), &algorithms, &DimensionPolicy::default())
+                .unwrap()
+    );
+
+    // the CSV fields may be quoted (holding commas and doubled quotes),
+    // while unterminated or misplaced quotes are errors of their row
+    let csv_manifest = std::env::temp_dir().join("pconvert_test_batch.csv");
+    let csv_manifest = csv_manifest.to_str().unwrap().to_string();
+    let csv_out = format!("{}result_batch_4, \"quoted\".png", TEST_DIR);
+    let lines = [
+        format!(
+            "\"{0}result_batch_4, \"\"quoted\"\".png\" ,source_over, \"{0}sole.png\",{0}back.png",
+            TEST_DIR
+        ),
+        format!("\"{}result_batch_5.png,source_over", TEST_DIR),
+        format!("{}result_batch_\"5\".png,source_over", TEST_DIR),
+    ];
+    fs::write(&csv_manifest, lines.join("\n")).unwrap();
+
+    let entries = read_manifest(&csv_manifest, &ManifestFormat::Csv).unwrap();
+    assert_eq!(entries.len(), 3);
+    match &entries[0].1 {
+        Ok(item) => assert_eq!(
+            item.layers,
+            vec![
+                format!("{}sole.png", TEST_DIR),
+                format!("{}back.png", TEST_DIR)
+            ]
+        ),
+        Err(err) => panic!("unexpected error: {:?}", err),
+    }
+    assert!(entries[1].1.is_err() && entries[2].1.is_err());
+    let results = run_batch(entries, &thread_pool, &Limits::default());
+    assert_eq!(results[0].out, Some(csv_out.clone()));
+    assert!(results[0].result.is_ok());
+    let composition = read_png_from_file(csv_out.clone(), false).unwrap();
+    assert!(
+        composition
+            == blend_multiple_images(layers, &algorithms, &DimensionPolicy::default()).unwrap()
+    );
+
+    // the compositions failing before fetching their layers (on the
+    // limits or on a previous layer) release them from the shared set
+    let lines = [
+        format!(
+            "{{\"out\": \"{0}result_batch_6.png\", \"layers\": [\"{0}missing.png\", \"{0}back.png\"]}}",
+            TEST_DIR
+        ),
+        format!(
+            "{{\"out\": \"{0}result_batch_6.png\", \"layers\": [\"{0}sole.png\", \"{0}back.png\", \"{0}front.png\"]}}",
+            TEST_DIR
+        ),
+        format!(
+            "{{\"out\": \"{0}result_batch_6.png\", \"layers\": [\"{0}sole.png\"]}}",
+            TEST_DIR
+        ),
+    ];
+    fs::write(&manifest, lines.join("\n")).unwrap();
+    let entries = read_manifest(&manifest, &ManifestFormat::JsonLines).unwrap();
+    let limits = Limits {
+        max_layers: 2,
+        ..Limits::default()
+    };
+    let shared = SharedLayers::new(&entries, &limits);
+    assert_eq!(shared.len(), 2);
+    let mut items = entries.into_iter().map(|(_, item)| item.unwrap());
+    assert!(compose_item(items.next().unwrap(), &shared).is_err());
+    assert!(compose_item(items.next().unwrap(), &shared).is_err());
+    assert_eq!(shared.len(), 1);
+    compose_item(items.next().unwrap(), &shared).unwrap();
+    assert_eq!(shared.len(), 0);
+    fs::remove_file(format!("{}result_batch_6.png", TEST_DIR)).unwrap();
+
+    fs::remove_file(csv_out).unwrap();
+    fs::remove_file(csv_manifest).unwrap();
+    fs::remove_file(manifest).unwrap();
+}
+
+#[test]
+fn test_layer_cache() {
+    let sole = format!("{}sole.png", TEST_DIR);
+    let back = format!("{}back.png", TEST_DIR);
+    let image_size = read_png_from_file(sole.clone(), false)
+        .unwrap()
+        .as_raw()
+        .len();
+
+    // the cache only has room for one of the layers, so reading
+    // a different layer evicts the least recently used one
+    let cache = LayerCache::new(image_size);
+    let first = cache.read_image(&sole, false).unwrap();
+    let second = cache.read_image(&sole, false).unwrap();
+    assert!(first == second);
+    assert_eq!(cache.get_stats().hits(), 1);
+    assert_eq!(cache.get_stats().misses(), 1);
+
+    cache.read_image(&sole, true).unwrap();
+    cache.read_image(&back, false).unwrap();
+    cache.read_image(&sole, false).unwrap();
+    let stats = cache.get_stats();
+    assert_eq!(stats.hits(), 1);
+    assert_eq!(stats.misses(), 4);
+    assert_eq!(stats.entries(), 1);
+    assert_eq!(stats.size(), image_size);
+
+    cache.clear();
+    assert_eq!(cache.get_stats().entries(), 0);
+    assert!(cache.read_image("missing.png", false).is_err());
+}
+
+#[test]
+fn test_server() {
+    let options = ServerOptions {
+        allow_paths: true,
+        limits: Limits {
+            max_layers: 2,
+            ..Limits::default()
+        },
+    };
+    let server = Server::bind("127.0.0.1:0", 2, options).unwrap();
+    let address = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+
+    let (code, body) = http_request(address, "GET", "/status", "", &[]);
+    assert_eq!(code, 200);
+    let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status["status"], "ok");
+    assert!(status["thread_pool"]["size"].as_u64().unwrap() >= 1);
+
+    // composes with one layer given as a local path and the other
+    // one given as a (multipart) upload, both have to match
+    let layers = ["sole.png", "back.png"]
+        .iter()
+        .map(|name| read_png_from_file(format!("{}{}", TEST_DIR, name), false).unwrap())
+        .collect::<Vec<_>>();
+    let algorithms = vec![(BlendAlgorithm::SourceOver, None)];
+    let expected = blend_multiple_images(layers, &algorithms, &DimensionPolicy::default()).unwrap();
+
+    let request = format!(
+        "{{\"layers\": [\"{0}sole.png\", {{\"path\": \"{0}back.png\"}}], \"algorithm\": \"source_over\"}}",
+        TEST_DIR
+    );
+    let (code, body) = http_request(
+        address,
+        "POST",
+        "/compose",
+        "application/json",
+        request.as_bytes(),
+    );
+    assert_eq!(code, 200);
+    assert!(decode_png(&body[..], false).unwrap() == expected);
+
+    let mut multipart = Vec::new();
+    for name in ["sole.png", "back.png"] {
+        multipart.extend_from_slice(format!("--boundary\r\nContent-Disposition: form-data; name=\"layer\"; filename=\"{}\"\r\nContent-Type: image/png\r\n\r\n", name).as_bytes());
+        multipart.extend_from_slice(&fs::read(format!("{}{}", TEST_DIR, name)).unwrap());
+        multipart.extend_from_slice(b"\r\n");
+    }
+    multipart.extend_from_slice(b"--boundary\r\nContent-Disposition: form-data; name=\"algorithm\"\r\n\r\nsource_over\r\n--boundary--\r\n");
+    let (code, body) = http_request(
+        address,
+        "POST",
+        "/compose",
+        "multipart/form-data; boundary=boundary",
+        &multipart,
+    );
+    assert_eq!(code, 200);
+    assert!(decode_png(&body[..], false).unwrap() == expected);
+
+    // the format of the composition is selectable, as long as enabled
+    if cfg!(feature = "qoi") {
+        let request = format!(
+            "{{\"layers\": [\"{0}sole.png\", \"{0}back.png\"], \"algorithm\": \"source_over\", \"format\": \"qoi\"}}",
+            TEST_DIR
+        );
+        let (code, body) = http_request(
+            address,
+            "POST",
+            "/compose",
+            "application/json",
+            request.as_bytes(),
+        );
+        assert_eq!(code, 200);
+        let decoded = image::load_from_memory_with_format(&body, ImageFormat::Qoi).unwrap();
+        assert!(decoded.to_rgba8() == expected);
+    }
+    let request = format!(
+        "{{\"layers\": [\"{}sole.png\"], \"format\": \"png\", \"quality\": 50}}",
+        TEST_DIR
+    );
+    let (code, _) = http_request(
+        address,
+        "POST",
+        "/compose",
+        "application/json",
+        request.as_bytes(),
+    );
+    assert_eq!(code, 400);
+
+    let (code, _) = http_request(
+        address,
+        "POST",
+        "/compose",
+        "application/json",
+        b"{\"layers\": [{\"data\": \"AAAA\"}]}",
+    );
+    assert_eq!(code, 400);
+    let (code, _) = http_request(address, "POST", "/compose", "application/json", b"{");
+    assert_eq!(code, 400);
+
+    // compositions beyond the limits of the server are too large
+    let request = format!(
+        "{{\"layers\": [\"{0}sole.png\", \"{0}back.png\", \"{0}front.png\"]}}",
+        TEST_DIR
+    );
+    let (code, _) = http_request(
+        address,
+        "POST",
+        "/compose",
+        "application/json",
+        request.as_bytes(),
+    );
+    assert_eq!(code, 413);
+    let request = format!(
+        "{{\"layers\": [\"{}sole.png\"], \"size\": \"100000x\"}}",
+        TEST_DIR
+    );
+    let (code, _) = http_request(
+        address,
+        "POST",
+        "/compose",
+        "application/json",
+        request.as_bytes(),
+    );
+    assert_eq!(code, 413);
+    let (code, _) = http_request(address, "GET", "/compose", "", &[]);
+    assert_eq!(code, 405);
+    let (code, _) = http_request(address, "GET", "/unknown", "", &[]);
+    assert_eq!(code, 404);
+
+    // the body size is bounded by the bytes limit, rejected from the
+    // content length alone (before any byte of the body is sent)
+    let options = ServerOptions {
+        allow_paths: false,
+        limits: Limits {
+            max_bytes: 1024,
+            ..Limits::default()
+        },
+    };
+    let server = Server::bind("127.0.0.1:0", 1, options).unwrap();
+    let address = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+
+    // idle clients do not hold the (only) worker of the thread pool,
+    // so the health check is still answered while they are connected
+    let idle = [
+        TcpStream::connect(address).unwrap(),
+        TcpStream::connect(address).unwrap(),
+    ];
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(b"GET /health HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200"));
+    drop(idle);
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"POST /compose HTTP/1.1\r\nContent-Length: 2048\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 413"));
+}
+
+fn http_request(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    content_type: &str,
+    body: &[u8],
+) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        method,
+        path,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).unwrap();
+    stream.write_all(body).unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let head_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap();
+    let code = String::from_utf8_lossy(&response[9..12]).parse().unwrap();
+    (code, response[head_end + 4..].to_vec())
+}
+
+#[test]
+fn test_watcher() {
+    let dir = std::env::temp_dir().join("pconvert_test_watcher");
+    fs::create_dir_all(&dir).unwrap();
+    let layers = ["sole.png", "back.png", "front.png"]
+        .iter()
+        .map(|name| {
+            let path = dir.join(name).to_str().unwrap().to_string();
+            fs::copy(format!("{}{}", TEST_DIR, name), &path).unwrap();
+            path
+        })
+        .collect::<Vec<_>>();
+    let out = dir.join("out.png").to_str().unwrap().to_string();
+
+    let algorithms = vec![(BlendAlgorithm::DisjointOver, None); 2];
+    let item = BatchItem {
+        layers: layers.clone(),
+        out: out.clone(),
+        algorithms: algorithms.clone(),
+        compression: Compression::FAST,
+        filter: FilterType::NoFilter,
+        dimension_policy: DimensionPolicy::default(),
+        resize: None,
+        layers_options: Vec::new(),
+        output: OutputOptions::default(),
+    };
+
+    // the cache holds every watched layer within the bytes limit, up to
+    // the default capacity of the cache
+    let limits = Limits {
+        max_bytes: 1024,
+        ..Limits::default()
+    };
+    let watcher = Watcher::new(item.clone(), limits).unwrap();
+    assert_eq!(watcher.get_cache_stats().capacity(), 3 * 1024);
+
+    let mut watcher = Watcher::new(item, Limits::default()).unwrap();
+    assert_eq!(watcher.get_cache_stats().capacity(), DEFAULT_CACHE_CAPACITY);
+    assert_eq!(watcher.update().unwrap(), Some(0));
+    assert_eq!(watcher.get_cache_stats().misses(), 3);
+    assert_eq!(watcher.update().unwrap(), None);
+
+    // replaces the middle layer, which must be the only one decoded
+    // again, making sure the modification time is a different one
+    fs::copy(format!("{}shoelace.png", TEST_DIR), &layers[1]).unwrap();
+    let file = fs::File::options().write(true).open(&layers[1]).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+
+    assert_eq!(watcher.update().unwrap(), Some(1));
+    assert_eq!(watcher.get_cache_stats().misses(), 4);
+    assert_eq!(watcher.get_cache_stats().hits(), 1);
+
+    let expected = layers
+        .iter()
+        .map(|path| read_png_from_file(path.clone(), false).unwrap())
+        .collect::<Vec<_>>();
+    let expected =
+        blend_multiple_images(expected, &algorithms, &DimensionPolicy::default()).unwrap();
+    assert!(read_png_from_file(out, false).unwrap() == expected);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_output_formats() {
+    assert_eq!(OutputFormat::from_path("out.PNG"), Some(OutputFormat::Png));
+    assert_eq!(OutputFormat::from_path("out.jpg"), Some(OutputFormat::Jpeg));
+    assert_eq!(
+        OutputFormat::from_path("a/out.webp"),
+        Some(OutputFormat::WebP)
+    );
+    assert_eq!(OutputFormat::from_path("out.qoi"), Some(OutputFormat::Qoi));
+    assert_eq!(OutputFormat::from_path("out.gif"), None);
+    assert_eq!(OutputFormat::from_path("out"), None);
+    assert_eq!(OutputFormat::from_str("JPG").unwrap(), OutputFormat::Jpeg);
+    assert!(OutputFormat::from_str("gif").is_err());
+
+    // the format is inferred from the path unless given, defaulting to PNG
+    let output = output_options(OutputValues {
+        path: Some("out.gif"),
+        ..Default::default()
+    });
+    assert_eq!(output, OutputOptions::default());
+    let output = output_options(OutputValues {
+        format: Some("png"),
+        path: Some("out.qoi"),
+        ..Default::default()
+    });
+    assert_eq!(output.format, OutputFormat::Png);
+
+    let lossy = |format, quality, background| OutputValues {
+        format: Some(format),
+        quality,
+        background,
+        ..Default::default()
+    };
+    assert_eq!(invalid_output(lossy("gif", None, None)), "format");
+    assert_eq!(invalid_output(lossy("png", Some(50), None)), "quality");
+    if cfg!(feature = "qoi") {
+        assert_eq!(invalid_output(lossy("qoi", Some(50), None)), "quality");
+    } else {
+        assert_eq!(invalid_output(lossy("qoi", None, None)), "format");
+    }
+    assert_eq!(
+        invalid_output(lossy("png", None, Some("#ffffff"))),
+        "background"
+    );
+    if cfg!(feature = "jpeg") {
+        assert_eq!(invalid_output(lossy("jpeg", Some(0), None)), "quality");
+        assert_eq!(invalid_output(lossy("jpeg", Some(101), None)), "quality");
+        assert_eq!(
+            invalid_output(lossy("jpeg", None, Some("white"))),
+            "background"
+        );
+        let output = output_options(lossy("jpeg", Some(80), Some("#000000")));
+        assert_eq!(output.quality, Some(80));
+        assert_eq!(output.background, Some([0, 0, 0]));
+    }
+    if cfg!(feature = "webp") && !cfg!(feature = "webp-lossy") {
+        assert_eq!(invalid_output(lossy("webp", Some(80), None)), "quality");
+    }
+
+    // the lossless formats decode back to the very same pixels, while a
+    // JPEG (without alpha) is flattened over the background
+    let image = read_png_from_file(format!("{}{}", TEST_DIR, TEST_FILE), false).unwrap();
+    let encode = |format, quality, background| {
+        encode_output(&image, &output_options(lossy(format, quality, background)))
+    };
+    let decoded = decode_png(&encode("png", None, None)[..], false).unwrap();
+    assert!(decoded == image);
+    if cfg!(feature = "qoi") {
+        let decoded =
+            image::load_from_memory_with_format(&encode("qoi", None, None), ImageFormat::Qoi);
+        assert!(decoded.unwrap().to_rgba8() == image);
+    }
+    if cfg!(feature = "webp") {
+        let decoded =
+            image::load_from_memory_with_format(&encode("webp", None, None), ImageFormat::WebP);
+        assert!(decoded.unwrap().to_rgba8() == image);
+    }
+    if cfg!(feature = "webp-lossy") {
+        let decoded =
+            image::load_from_memory_with_format(&encode("webp", Some(75), None), ImageFormat::WebP);
+        assert_eq!(decoded.unwrap().to_rgba8().dimensions(), image.dimensions());
+    }
+    if cfg!(feature = "jpeg") {
+        let decoded = image::load_from_memory_with_format(
+            &encode("jpeg", Some(95), Some("#ff0000")),
+            ImageFormat::Jpeg,
+        )
+        .unwrap()
+        .to_rgb8();
+        assert_eq!(decoded.dimensions(), image.dimensions());
+        let (x, y) = (0..image.width())
+            .flat_map(|x| (0..image.height()).map(move |y| (x, y)))
+            .find(|&(x, y)| image.get_pixel(x, y)[3] == 0)
+            .unwrap();
+        let pixel = decoded.get_pixel(x, y);
+        assert!(pixel[0] > 200 && pixel[1] < 50 && pixel[2] < 50);
+        assert!(encode("jpeg", Some(10), None).len() < encode("jpeg", Some(95), None).len());
+    }
+}
+
+/// Builds the output options of the given (valid) values.
+fn output_options(values: OutputValues) -> OutputOptions {
+    OutputOptions::from_options(values).unwrap()
+}
+
+/// Returns the name of the parameter by which the output values are
+/// invalid, failing when they are valid (or invalid otherwise).
+fn invalid_output(values: OutputValues) -> String {
+    match OutputOptions::from_options(values) {
+        Err(PConvertError::InvalidParameterError { name, .. }) => name,
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+/// Encodes the image with the given output options, with the fast
+/// compression and no filter.
+fn encode_output(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, output: &OutputOptions) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    encode_image(
+        &mut bytes,
+        image,
+        Compression::FAST,
+        FilterType::NoFilter,
+        output,
+    )
+    .unwrap();
+    bytes
+}
+
+#[test]
+fn test_input_formats() {
+    assert_eq!(
+        InputFormat::sniff(b"\x89PNG\r\n\x1a\n\0\0\0\0"),
+        Some(InputFormat::Png)
+    );
+    assert_eq!(
+        InputFormat::sniff(b"\xff\xd8\xff\xe0"),
+        Some(InputFormat::Jpeg)
+    );
+    assert_eq!(
+        InputFormat::sniff(b"RIFF\0\0\0\0WEBPVP8L"),
+        Some(InputFormat::WebP)
+    );
+    assert_eq!(InputFormat::sniff(b"RIFF\0\0\0\0WAVE"), None);
+    assert_eq!(InputFormat::sniff(b"II*\0"), Some(InputFormat::Tiff));
+    assert_eq!(InputFormat::sniff(b"MM\0*"), Some(InputFormat::Tiff));
+    assert_eq!(InputFormat::sniff(b"BM"), Some(InputFormat::Bmp));
+    assert_eq!(InputFormat::sniff(b"qoif"), Some(InputFormat::Qoi));
+    assert_eq!(InputFormat::sniff(b"GIF89a"), None);
+    assert_eq!(InputFormat::sniff(b""), None);
+
+    // every enabled format is decoded into the same RGBA buffer as the PNG
+    // (the lossless ones), images of unknown formats fail as PNGs do
+    let image = read_png_from_file(format!("{}{}", TEST_DIR, TEST_FILE), false).unwrap();
+    let (width, height) = image.dimensions();
+    let formats = [
+        (InputFormat::Png, ImageFormat::Png),
+        (InputFormat::WebP, ImageFormat::WebP),
+        (InputFormat::Tiff, ImageFormat::Tiff),
+        (InputFormat::Bmp, ImageFormat::Bmp),
+        (InputFormat::Qoi, ImageFormat::Qoi),
+    ];
+    for (format, image_format) in formats.iter().filter(|(format, _)| format.is_enabled()) {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, *image_format).unwrap();
+        let bytes = bytes.into_inner();
+        assert_eq!(InputFormat::sniff(&bytes), Some(*format));
+        assert!(
+            decode_image(&bytes[..], false).unwrap() == image,
+            "{}",
+            format
+        );
+
+        let limits = Limits {
+            max_width: width - 1,
+            ..Limits::default()
+        };
+        assert!(matches!(
+            decode_image_with_limits(&bytes[..], false, &limits),
+            Err(PConvertError::LimitExceededError {
+                limit: "max_width",
+                ..
+            })
+        ));
+    }
+    assert!(matches!(
+        decode_image(&b"GIF89a\0\0\0\0"[..], false),
+        Err(PConvertError::DecodeError { .. })
+    ));
+    assert!(decode_image(&b"BM\0\0"[..], false).is_err());
+
+    // PNGs of other color types and bit depths (RGB, grey, palette and
+    // 16 bits) are converted into RGBA8 as the other formats are
+    let opaque = image::DynamicImage::ImageRgba8(image.clone());
+    let pngs = [
+        image::DynamicImage::ImageRgb8(opaque.to_rgb8()),
+        image::DynamicImage::ImageLuma8(opaque.to_luma8()),
+        image::DynamicImage::ImageRgba16(opaque.to_rgba16()),
+    ];
+    for png in pngs.iter() {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        png.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        let bytes = bytes.into_inner();
+        assert!(decode_image(&bytes[..], false).unwrap() == png.to_rgba8());
+    }
+    let mut indexed = Vec::new();
+    let palette = quantize(&image, 16, false).unwrap();
+    encode_indexed_png(
+        &mut indexed,
+        &palette,
+        Compression::FAST,
+        FilterType::NoFilter,
+    )
+    .unwrap();
+    assert!(decode_image(&indexed[..], false).unwrap() == palette.to_rgba());
+
+    // the JPEG (without alpha) is decoded as opaque, from a file, and
+    // can be blended with layers of the other formats
+    if InputFormat::Jpeg.is_enabled() {
+        let path = std::env::temp_dir().join("pconvert_test_input.jpg");
+        let path = path.to_str().unwrap().to_string();
+        image::DynamicImage::ImageRgba8(image.clone())
+            .to_rgb8()
+            .save_with_format(&path, ImageFormat::Jpeg)
+            .unwrap();
+        let jpeg = read_image_from_file(path.clone(), false).unwrap();
+        assert_eq!(jpeg.dimensions(), (width, height));
+        assert!(jpeg.pixels().all(|pixel| pixel[3] == 255));
+
+        let algorithms = vec![(BlendAlgorithm::SourceOver, None)];
+        let composition =
+            blend_multiple_images(vec![jpeg, image], &algorithms, &DimensionPolicy::default())
+                .unwrap();
+        assert_eq!(composition.dimensions(), (width, height));
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_apng() {
+    // an animation of a moving square, encoded (as the changed regions)
+    // and decoded back into the very same frames and timing
+    let frames = (0..4)
+        .map(|index| {
+            let mut image = ImageBuffer::from_pixel(16, 12, Rgba([0, 0, 255, 128]));
+            for y in 2..6 {
+                for x in index * 3..index * 3 + 4 {
+                    image.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+                }
+            }
+            Frame {
+                image,
+                delay: Delay {
+                    numerator: index as u16 + 1,
+                    denominator: 25,
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    let animation = Animation {
+        frames,
+        num_plays: 3,
+    };
+    let mut bytes = Vec::new();
+    encode_apng(
+        &mut bytes,
+        &animation,
+        Compression::FAST,
+        FilterType::NoFilter,
+    )
+    .unwrap();
+    assert!(is_apng(&bytes[..]));
+    let decoded = decode_apng_with_limits(&bytes[..], &Limits::default()).unwrap();
+    assert_eq!(decoded.num_plays, 3);
+    assert_eq!(decoded.frames.len(), 4);
+    for (decoded, frame) in decoded.frames.iter().zip(animation.frames.iter()) {
+        assert!(decoded.image == frame.image);
+        assert_eq!(decoded.delay, frame.delay);
+    }
+    assert_eq!(decoded.frames[1].delay.as_millis(), 80);
+
+    // the first frame of the APNG is its default image
+    assert!(decode_png(&bytes[..], false).unwrap() == animation.frames[0].image);
+    let limits = Limits {
+        max_bytes: 16 * 12 * 4 * 3,
+        ..Limits::default()
+    };
+    assert!(matches!(
+        decode_apng_with_limits(&bytes[..], &limits),
+        Err(PConvertError::LimitExceededError {
+            limit: "max_bytes",
+            ..
+        })
+    ));
+
+    // animations without alpha (e.g. RGB turntables) are expanded into
+    // RGBA8, as static PNGs of those color types are
+    let mut rgb = Vec::new();
+    let mut encoder = png::Encoder::new(&mut rgb, 2, 1);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(2, 0).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&[255, 0, 0, 0, 255, 0]).unwrap();
+    writer.set_frame_dimension(1, 1).unwrap();
+    writer.set_frame_position(1, 0).unwrap();
+    writer.write_image_data(&[0, 0, 255]).unwrap();
+    drop(writer);
+    let decoded = decode_apng_with_limits(&rgb[..], &Limits::default()).unwrap();
+    let pixels = decoded
+        .frames
+        .iter()
+        .map(|frame| frame.image.pixels().copied().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        pixels,
+        vec![
+            vec![Rgba([255, 0, 0, 255]), Rgba([0, 255, 0, 255])],
+            vec![Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])],
+        ]
+    );
+
+    // a tiny animation declaring a huge number of frames is rejected
+    // by the limits instead of allocating memory for those frames
+    let mut bomb = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bomb, 1, 1);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(1 << 28, 0).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&[0, 0, 0, 0]).unwrap();
+    drop(writer);
+    assert!(bomb.len() < 256);
+    assert!(matches!(
+        decode_apng_with_limits(&bomb[..], &Limits::default()),
+        Err(PConvertError::LimitExceededError {
+            limit: "max_bytes",
+            ..
+        })
+    ));
+
+    // static PNGs are not animated, being decoded as a single frame
+    let png = fs::read(format!("{}{}", TEST_DIR, TEST_FILE)).unwrap();
+    assert!(!is_apng(&png[..]));
+    assert!(!is_apng(&b"not a png"[..]));
+    let still = decode_apng_with_limits(&png[..], &Limits::default()).unwrap();
+    assert_eq!(still.frames.len(), 1);
+    assert!(still.frames[0].image == decode_png(&png[..], false).unwrap());
+    assert!(decode_apng_with_limits(&png[..png.len() / 2], &Limits::default()).is_err());
+
+    // the dispose and blend operations of the frames are applied, with a
+    // default image that is not part of the animation (red), a frame
+    // blended over it (a white region, disposed of to the background)
+    // and a frame replacing a region (restored after being shown)
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, 4, 4);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(3, 0).unwrap();
+    encoder.set_sep_def_img(true).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    writer
+        .write_image_data(&[255, 0, 0, 255].repeat(16))
+        .unwrap();
+    writer.set_frame_delay(1, 10).unwrap();
+    writer
+        .write_image_data(&[0, 0, 255, 255].repeat(16))
+        .unwrap();
+    writer.set_blend_op(png::BlendOp::Over).unwrap();
+    writer.set_dispose_op(png::DisposeOp::Background).unwrap();
+    writer.set_frame_dimension(2, 2).unwrap();
+    writer.set_frame_position(1, 1).unwrap();
+    writer
+        .write_image_data(&[[255, 255, 255, 255], [0, 0, 0, 0]].concat().repeat(2))
+        .unwrap();
+    writer.set_blend_op(png::BlendOp::Source).unwrap();
+    writer.set_dispose_op(png::DisposeOp::Previous).unwrap();
+    writer.set_frame_position(0, 0).unwrap();
+    writer
+        .write_image_data(&[0, 255, 0, 128].repeat(4))
+        .unwrap();
+    writer.finish().unwrap();
+
+    let decoded = decode_apng_with_limits(&bytes[..], &Limits::default()).unwrap();
+    assert_eq!(decoded.frames.len(), 3);
+    let pixel = |frame: usize, x, y| decoded.frames[frame].image.get_pixel(x, y).0;
+    assert_eq!(pixel(0, 0, 0), [0, 0, 255, 255]);
+    assert_eq!(pixel(1, 1, 1), [255, 255, 255, 255]);
+    assert_eq!(pixel(1, 2, 1), [0, 0, 255, 255]);
+    assert_eq!(pixel(1, 0, 0), [0, 0, 255, 255]);
+    assert_eq!(pixel(2, 0, 0), [0, 255, 0, 128]);
+    assert_eq!(pixel(2, 1, 1), [0, 255, 0, 128]);
+    assert_eq!(pixel(2, 2, 2), [0, 0, 0, 0]);
+    assert_eq!(pixel(2, 3, 3), [0, 0, 255, 255]);
+
+    // the timelines of the layers are merged by their delays, a frame
+    // starting at every frame boundary of any layer, looping the shorter
+    // animations and repeating the static layers
+    let overlay = Animation::from_image(ImageBuffer::from_fn(16, 12, |x, _| match x {
+        0..=7 => Rgba([0, 255, 0, 200]),
+        _ => Rgba([0, 0, 0, 0]),
+    }));
+    let blink = Animation {
+        frames: animation.frames[..2].to_vec(),
+        num_plays: 0,
+    };
+    let algorithms = vec![(BlendAlgorithm::DestinationOver, None); 2];
+    let composition = blend_animations(
+        vec![blink.clone(), animation.clone(), overlay.clone()],
+        &algorithms,
+        &DimensionPolicy::default(),
+        &Limits::default(),
+    )
+    .unwrap();
+    // the blink (40 and 80 ms) plays over the 40, 80, 120 and 160 ms frames
+    let expected = [
+        (0, 0, 40),
+        (1, 1, 80),
+        (0, 2, 40),
+        (1, 2, 80),
+        (0, 3, 40),
+        (1, 3, 80),
+        (0, 3, 40),
+    ];
+    assert_eq!(composition.frames.len(), expected.len());
+    assert_eq!(composition.num_plays, 3);
+    for (frame, &(blink_index, index, millis)) in composition.frames.iter().zip(expected.iter()) {
+        let layers = vec![
+            blink.frames[blink_index].image.clone(),
+            animation.frames[index].image.clone(),
+            overlay.frames[0].image.clone(),
+        ];
+        let expected =
+            blend_multiple_images(layers, &algorithms, &DimensionPolicy::default()).unwrap();
+        assert!(frame.image == expected);
+        assert_eq!(frame.delay.as_millis(), millis);
+    }
+
+    // a slow layer (2 frames of 500 ms, on the first column) over a fast
+    // one (10 frames of 100 ms, on the second column) keeps its timing,
+    // changing after the fifth frame
+    let column = |column: u32, value: u8, delay: Delay| Frame {
+        image: ImageBuffer::from_fn(2, 2, |x, _| match x == column {
+            true => Rgba([value, 0, 0, 255]),
+            false => Rgba([0, 0, 0, 0]),
+        }),
+        delay,
+    };
+    let slow = Animation {
+        frames: (0..2)
+            .map(|index| column(0, index, Delay::from_millis(500)))
+            .collect(),
+        num_plays: 0,
+    };
+    let fast = Animation {
+        frames: (0..10)
+            .map(|index| column(1, index, Delay::from_millis(100)))
+            .collect(),
+        num_plays: 0,
+    };
+    let composition = blend_animations(
+        vec![fast, slow],
+        &[(BlendAlgorithm::Alpha, None)],
+        &DimensionPolicy::default(),
+        &Limits::default(),
+    )
+    .unwrap();
+    assert_eq!(composition.frames.len(), 10);
+    for (index, frame) in composition.frames.iter().enumerate() {
+        assert_eq!(frame.image.get_pixel(0, 0)[0], (index / 5) as u8);
+        assert_eq!(frame.image.get_pixel(1, 0)[0], index as u8);
+        assert_eq!(frame.delay.as_millis(), 100);
+    }
+
+    let limits = Limits {
+        max_bytes: 16 * 12 * 4 * 3,
+        ..Limits::default()
+    };
+    assert!(blend_animations(
+        vec![animation.clone(), overlay],
+        &algorithms[..1],
+        &DimensionPolicy::default(),
+        &limits,
+    )
+    .is_err());
+    assert!(
+        blend_animations(vec![], &[], &DimensionPolicy::default(), &Limits::default()).is_err()
+    );
+
+    let mut frames = animation.frames;
+    frames[2].image = ImageBuffer::new(4, 4);
+    let invalid = Animation {
+        frames,
+        num_plays: 0,
+    };
+    assert!(encode_apng(
+        Vec::new(),
+        &invalid,
+        Compression::FAST,
+        FilterType::NoFilter
+    )
+    .is_err());
+}
+
+#[test]
+fn test_palette() {
+    let palette = |format, colors, dither| OutputValues {
+        format: Some(format),
+        colors,
+        dither,
+        ..Default::default()
+    };
+    assert_eq!(invalid_output(palette("png", Some(1), None)), "colors");
+    assert_eq!(invalid_output(palette("png", Some(257), None)), "colors");
+    assert_eq!(invalid_output(palette("png", None, Some(true))), "dither");
+    if cfg!(feature = "qoi") {
+        assert_eq!(invalid_output(palette("qoi", Some(16), None)), "colors");
+    }
+    let output = output_options(OutputValues {
+        colors: Some(16),
+        dither: Some(true),
+        path: Some("a.png"),
+        ..Default::default()
+    });
+    assert_eq!((output.colors, output.dither), (Some(16), true));
+    assert!(!output.is_plain_png());
+    assert!(quantize(&ImageBuffer::new(1, 1), 1, false).is_err());
+
+    // an image with no more colours than the palette is kept as is, in the
+    // smallest bit depth, with the transparent colour in the tRNS chunk
+    let mut image = ImageBuffer::from_pixel(5, 3, Rgba([0, 0, 0, 0]));
+    image.put_pixel(1, 1, Rgba([255, 0, 0, 255]));
+    image.put_pixel(2, 1, Rgba([0, 0, 255, 128]));
+    image.put_pixel(3, 1, Rgba([10, 20, 30, 0]));
+    let indexed = quantize(&image, 256, true).unwrap();
+    assert_eq!(indexed.palette.len(), 3);
+    assert_eq!(indexed.palette[0], [0, 0, 0, 0]);
+    assert_eq!(indexed.palette[2], [255, 0, 0, 255]);
+    let mut bytes = Vec::new();
+    encode_indexed_png(
+        &mut bytes,
+        &indexed,
+        Compression::FAST,
+        FilterType::NoFilter,
+    )
+    .unwrap();
+    assert_eq!((bytes[24], bytes[25]), (2, 3));
+    assert!(bytes.windows(4).any(|chunk| chunk == b"tRNS"));
+    let mut expected = image.clone();
+    expected.put_pixel(3, 1, Rgba([0, 0, 0, 0]));
+    assert!(decode_png(&bytes[..], false).unwrap() == expected);
+
+    // a quantised image decodes into its palette colours and is
+    // smaller than the true colour encoding
+    let image = read_png_from_file(format!("{}{}", TEST_DIR, TEST_FILE), false).unwrap();
+    let indexed = quantize(&image, 16, false).unwrap();
+    assert!(indexed.palette.len() <= 16);
+    let translucent = indexed
+        .palette
+        .iter()
+        .filter(|color| color[3] < 255)
+        .count();
+    assert!(indexed.palette[..translucent]
+        .iter()
+        .all(|color| color[3] < 255));
+    let encode = |output: &OutputOptions| encode_output(&image, output);
+    let png8 = encode(&OutputOptions {
+        colors: Some(16),
+        ..OutputOptions::default()
+    });
+    assert!(decode_png(&png8[..], false).unwrap() == indexed.to_rgba());
+    assert!(png8.len() < encode(&OutputOptions::default()).len());
+
+    // dithering a grey gradient into two colours keeps the mean level of
+    // each region, that is otherwise collapsed into the closest colour
+    let gradient = ImageBuffer::from_fn(256, 16, |x, _| Rgba([x as u8, x as u8, x as u8, 255]));
+    let mean = |image: &ImageBuffer<Rgba<u8>, Vec<u8>>| {
+        let region: Vec<u64> = (96..128)
+            .flat_map(|x| (0..16).map(move |y| (x, y)))
+            .map(|(x, y)| image.get_pixel(x, y)[0] as u64)
+            .collect();
+        region.iter().sum::<u64>() / region.len() as u64
+    };
+    let plain = quantize(&gradient, 2, false).unwrap().to_rgba();
+    let dithered = quantize(&gradient, 2, true).unwrap().to_rgba();
+    assert!((mean(&plain) as i64 - 112).abs() > 8);
+    assert!((mean(&dithered) as i64 - 112).abs() <= 8);
+}
+
+#[test]
+fn test_optimize() {
+    let report = OptimizeReport {
+        original_size: 200,
+        size: 150,
+        candidate: None,
+    };
+    assert_eq!(report.saving(), 50);
+    assert_eq!(report.saving_percent(), 25.0);
+    assert!(report.to_string().starts_with("200 -> 150 bytes"));
+    assert!(candidates()
+        .iter()
+        .any(|candidate| candidate.filter == FilterType::Adaptive));
+
+    // the smallest encoding is lossless and never larger than the
+    // one with the requested settings
+    let image = read_png_from_file(format!("{}{}", TEST_DIR, TEST_FILE), false).unwrap();
+    let (data, report) = optimize_png(&image, Compression::FAST, FilterType::NoFilter).unwrap();
+    assert_eq!(report.size, data.len());
+    assert!(report.size < report.original_size);
+    assert!(report.candidate.unwrap().compression.level() == Compression::BEST.level());
+    assert!(decode_png(&data[..], false).unwrap() == image);
+
+    let encode = |output: &OutputOptions| encode_output(&image, output);
+    let optimized = encode(&OutputOptions {
+        optimize: true,
+        ..OutputOptions::default()
+    });
+    assert_eq!(optimized, data);
+    let png8 = encode(&OutputOptions {
+        colors: Some(16),
+        optimize: true,
+        ..OutputOptions::default()
+    });
+    let expected = quantize(&image, 16, false).unwrap().to_rgba();
+    assert!(decode_png(&png8[..], false).unwrap() == expected);
+    let output = output_options(OutputValues {
+        optimize: Some(true),
+        ..Default::default()
+    });
+    assert!(output.optimize && !output.is_plain_png());
+    if cfg!(feature = "qoi") {
+        let values = OutputValues {
+            format: Some("qoi"),
+            optimize: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(invalid_output(values), "optimize");
+    }
+
+    // files are optimized (in place) into the smallest PNG, an original
+    // PNG that is already smaller being kept as is
+    let path = std::env::temp_dir().join("pconvert_test_optimize.png");
+    let path = path.to_str().unwrap();
+    write_png_to_file(
+        path.to_string(),
+        &image,
+        Compression::FAST,
+        FilterType::NoFilter,
+    )
+    .unwrap();
+    let original_size = fs::metadata(path).unwrap().len() as usize;
+    let report = optimize_file(path, path, &Limits::default()).unwrap();
+    assert_eq!(report.original_size, original_size);
+    assert_eq!(report.size, fs::metadata(path).unwrap().len() as usize);
+    assert!(report.saving() > 0);
+    assert!(read_png_from_file(path.to_string(), false).unwrap() == image);
+    let report = optimize_file(path, path, &Limits::default()).unwrap();
+    assert_eq!((report.saving(), report.candidate), (0, None));
+
+    let limits = Limits {
+        max_width: 10,
+        ..Limits::default()
+    };
+    assert!(matches!(
+        optimize_file(path, path, &limits),
+        Err(PConvertError::LimitExceededError { .. })
+    ));
+    let turntable = format!("{}{}", TEST_DIR, "turntable.png");
+    assert!(matches!(
+        optimize_file(&turntable, path, &Limits::default()),
+        Err(PConvertError::ArgumentError(_))
+    ));
+
+    // PNGs of other color types (e.g. RGB) are optimized as well, into
+    // an RGBA PNG with the same pixels or kept when that is not smaller
+    let rgb = image::DynamicImage::ImageRgba8(image.clone()).to_rgb8();
+    rgb.save_with_format(path, ImageFormat::Png).unwrap();
+    let report = optimize_file(path, path, &Limits::default()).unwrap();
+    assert!(report.size <= report.original_size);
+    let expected = image::DynamicImage::ImageRgb8(rgb).to_rgba8();
+    assert!(read_image_from_file(path.to_string(), false).unwrap() == expected);
+
+    let deep: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::new(2, 2);
+    deep.save_with_format(path, ImageFormat::Png).unwrap();
+    assert!(matches!(
+        optimize_file(path, path, &Limits::default()),
+        Err(PConvertError::ArgumentError(_))
+    ));
+}
+
+#[test]
+fn test_compression() {
+    // names and levels are parsed strictly, unknown values are errors
+    // instead of silently falling back into the fast compression
+    assert_eq!(Compression::from_str("Best").unwrap(), Compression::BEST);
+    assert_eq!(Compression::from_str("3").unwrap().level(), 3);
+    assert_eq!(Compression::from_str("6").unwrap(), Compression::DEFAULT);
+    assert_eq!(Compression::default(), Compression::FAST);
+    assert_eq!(Compression::new(0).unwrap().to_string(), "0");
+    assert_eq!(Compression::BEST.to_string(), "best");
+    for value in ["10", "-1", "medium", ""].iter() {
+        assert!(matches!(
+            Compression::from_str(value),
+            Err(PConvertError::InvalidParameterError { .. })
+        ));
+    }
+    assert!(Compression::new(10).is_err());
+    assert_eq!(
+        Strategy::from_str("huffman").unwrap(),
+        Strategy::HuffmanOnly
+    );
+    assert!(Strategy::from_str("lz77").is_err());
+    #[allow(deprecated)]
+    let types = [
+        (CompressionType::Huffman, Strategy::HuffmanOnly),
+        (CompressionType::Rle, Strategy::Rle),
+    ];
+    for (compression_type, strategy) in types.iter() {
+        let compression = Compression::from(*compression_type);
+        assert_eq!(compression, Compression::FAST.with_strategy(*strategy));
+    }
+    assert_eq!(parse_filter("adaptive").unwrap(), FilterType::Adaptive);
+    assert_eq!(parse_filter("Paeth").unwrap(), FilterType::Paeth);
+    assert!(parse_filter("median").is_err());
+
+    // the (deprecated) string conversions are kept as they were, falling
+    // back into the fast compression and no filtering
+    #[allow(deprecated)]
+    {
+        use crate::utils::{image_compression_from, image_filter_from};
+        assert!(matches!(
+            image_compression_from("best".to_string()),
+            CompressionType::Best
+        ));
+        assert!(matches!(
+            image_compression_from("medium".to_string()),
+            CompressionType::Fast
+        ));
+        assert_eq!(image_filter_from("up".to_string()), FilterType::Up);
+        assert_eq!(
+            image_filter_from("median".to_string()),
+            FilterType::NoFilter
+        );
+    }
+
+    // every level, strategy and filter is a lossless encoding, the
+    // levels further apart giving different sizes
+    let image = read_png_from_file(format!("{}{}", TEST_DIR, TEST_FILE), false).unwrap();
+    let encode = |compression: Compression, filter: FilterType| {
+        let mut bytes = Vec::new();
+        encode_png(&mut bytes, &image, compression, filter).unwrap();
+        bytes
+    };
+    let filters = [
+        FilterType::NoFilter,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Avg,
+        FilterType::Paeth,
+        FilterType::Adaptive,
+    ];
+    let strategies = [
+        Strategy::Default,
+        Strategy::Filtered,
+        Strategy::Rle,
+        Strategy::HuffmanOnly,
+    ];
+    for level in 0..=Compression::MAX_LEVEL {
+        for filter in filters.iter() {
+            let bytes = encode(Compression::new(level).unwrap(), *filter);
+            assert!(decode_png(&bytes[..], false).unwrap() == image);
+        }
+    }
+    for strategy in strategies.iter() {
+        let compression = Compression::new(4).unwrap().with_strategy(*strategy);
+        let bytes = encode(compression, FilterType::Adaptive);
+        assert!(decode_png(&bytes[..], false).unwrap() == image);
+    }
+    let mut bytes = Vec::new();
+    encode_png(&mut bytes, &image, CompressionType::Best, FilterType::Sub).unwrap();
+    assert_eq!(bytes, encode(Compression::BEST, FilterType::Sub));
+    let stored = encode(Compression::new(0).unwrap(), FilterType::NoFilter);
+    assert!(stored.len() > image.as_raw().len());
+    assert!(encode(Compression::new(9).unwrap(), FilterType::Adaptive).len() < stored.len());
+
+    // the serial and the parallel writes honour the same settings, with
+    // the levels the multi-threaded encoder lacks written serially
+    let path = std::env::temp_dir().join("pconvert_test_compression.png");
+    let path = path.to_str().unwrap().to_string();
+    let settings = [
+        (Compression::new(3).unwrap(), FilterType::Adaptive),
+        (
+            Compression::BEST.with_strategy(Strategy::Rle),
+            FilterType::Paeth,
+        ),
+        (Compression::DEFAULT, FilterType::Adaptive),
+    ];
+    for (compression, filter) in settings.iter() {
+        write_png_parallel(path.clone(), &image, *compression, *filter).unwrap();
+        assert!(read_png_from_file(path.clone(), false).unwrap() == image);
+        write_png_to_file(path.clone(), &image, *compression, *filter).unwrap();
+        assert!(read_png_from_file(path.clone(), false).unwrap() == image);
+    }
+    let level = Compression::new(3).unwrap();
+    write_png_parallel(path.clone(), &image, level, FilterType::NoFilter).unwrap();
+    assert_eq!(
+        fs::read(&path).unwrap(),
+        encode(level, FilterType::NoFilter)
+    );
+
+    // indexed PNGs take any level, animated ones only the default
+    // strategy and, as the streaming, only the fast, default and best levels
+    let indexed = quantize(&image, 16, false).unwrap();
+    for level in [0, 5, 9].iter() {
+        let mut bytes = Vec::new();
+        let compression = Compression::new(*level).unwrap();
+        encode_indexed_png(&mut bytes, &indexed, compression, FilterType::Adaptive).unwrap();
+        assert!(decode_png(&bytes[..], false).unwrap() == indexed.to_rgba());
+    }
+    let animation = Animation::from_image(image.clone());
+    let rle = Compression::DEFAULT.with_strategy(Strategy::Rle);
+    assert!(matches!(
+        encode_apng(Vec::new(), &animation, rle, FilterType::NoFilter),
+        Err(PConvertError::InvalidParameterError { .. })
+    ));
+    assert!(matches!(
+        encode_apng(
+            Vec::new(),
+            &animation,
+            Compression::new(3).unwrap(),
+            FilterType::NoFilter
+        ),
+        Err(PConvertError::InvalidParameterError { .. })
+    ));
+    encode_apng(
+        Vec::new(),
+        &animation,
+        Compression::BEST,
+        FilterType::NoFilter,
+    )
+    .unwrap();
+    let layer = fs::read(format!("{}{}", TEST_DIR, TEST_FILE)).unwrap();
+    let result = blend_multiple_streaming(
+        vec![&layer[..], &layer[..]],
+        &[(BlendAlgorithm::SourceOver, None)],
+        Vec::new(),
+        Compression::new(3).unwrap(),
+        FilterType::NoFilter,
+        DEFAULT_BAND_HEIGHT,
+        &Limits::default(),
+    );
+    assert!(
+        matches!(result, Err(PConvertError::InvalidParameterError { .. }))
+            == cfg!(not(feature = "wasm-extension"))
+    );
+}
@@ -0,0 +1,204 @@
+//! Property based tests of the invariants of the blending algorithms,
+//! checked over random pairs of pixels for every `BlendAlgorithm`.
+
+use crate::blending::{
+    demultiply_image, get_blending_algorithm, is_algorithm_multiplied, multiply_image,
+    BlendAlgorithm,
+};
+use crate::constants;
+use image::{ImageBuffer, Rgba};
+use proptest::prelude::*;
+use std::str::FromStr;
+
+/// Difference allowed between channels, from the float rounding of the
+/// algorithms (e.g. dividing a channel by an alpha it was multiplied by).
+const TOLERANCE: u8 = 1;
+
+/// Invariants held by a blending algorithm, the ones an algorithm
+/// does not hold are documented (with the reason) in `invariants`.
+struct Invariants {
+    /// A fully transparent top pixel leaves the bottom pixel unchanged.
+    transparent_top: bool,
+    /// A fully transparent bottom pixel is replaced by the top pixel.
+    transparent_bottom: bool,
+    /// The alpha of the result is at least the alpha of both pixels.
+    covering_alpha: bool,
+}
+
+fn invariants(algorithm: &BlendAlgorithm) -> Invariants {
+    match algorithm {
+        // the colours are mixed by the top alpha (as the mask top), regardless
+        // of the bottom alpha, while the alphas are added together
+        BlendAlgorithm::Multiplicative => Invariants {
+            transparent_top: true,
+            transparent_bottom: false,
+            covering_alpha: true,
+        },
+        // the top alpha (scaled by the factor) mixes every channel,
+        // the alpha included, so the result is between both pixels
+        BlendAlgorithm::MaskTop => Invariants {
+            transparent_top: true,
+            transparent_bottom: false,
+            covering_alpha: false,
+        },
+        // the first visible pixel (from the top or the bottom)
+        // is kept as it is, so its alpha may be the lowest one
+        BlendAlgorithm::FirstTop | BlendAlgorithm::FirstBottom => Invariants {
+            transparent_top: true,
+            transparent_bottom: true,
+            covering_alpha: false,
+        },
+        // as in the original P(NG)Convert, the bottom channels are divided by the
+        // bottom alpha when both pixels do not cover the whole pixel together,
+        // so a fully transparent bottom (a zero alpha) yields no colour of the
+        // top, but the (NaN) result of that division
+        BlendAlgorithm::DisjointOver => Invariants {
+            transparent_top: false,
+            transparent_bottom: false,
+            covering_alpha: true,
+        },
+        // the colour shows whether both pixels cover the whole pixel together
+        // (red) or not (green), instead of the colours of the pixels
+        BlendAlgorithm::DisjointDebug => Invariants {
+            transparent_top: false,
+            transparent_bottom: false,
+            covering_alpha: true,
+        },
+        _ => Invariants {
+            transparent_top: true,
+            transparent_bottom: true,
+            covering_alpha: true,
+        },
+    }
+}
+
+fn algorithms() -> Vec<BlendAlgorithm> {
+    constants::ALGORITHMS
+        .iter()
+        .map(|algorithm| BlendAlgorithm::from_str(algorithm).unwrap())
+        .collect()
+}
+
+/// Converts the (straight alpha) pixel into the alpha representation
+/// used by the algorithm, premultiplied for the multiplied algorithms.
+fn prepare(algorithm: &BlendAlgorithm, pixel: [u8; 4]) -> Rgba<u8> {
+    let mut image = ImageBuffer::from_pixel(1, 1, Rgba(pixel));
+    if is_algorithm_multiplied(algorithm) {
+        demultiply_image(&mut image);
+    }
+    *image.get_pixel(0, 0)
+}
+
+fn blend(algorithm: &BlendAlgorithm, bot: Rgba<u8>, top: Rgba<u8>) -> Rgba<u8> {
+    let algorithm_fn = get_blending_algorithm(algorithm);
+    let mut result = bot;
+    algorithm_fn((&mut result, &top), &None);
+    result
+}
+
+/// The visible channels of the pixel, as premultiplied by its alpha (or
+/// the pixel as it is when already premultiplied), so that the colour
+/// of fully transparent pixels is ignored.
+fn visible(algorithm: &BlendAlgorithm, pixel: Rgba<u8>) -> [u8; 4] {
+    match is_algorithm_multiplied(algorithm) {
+        true => pixel.0,
+        false => prepare(&BlendAlgorithm::DisjointOver, pixel.0).0,
+    }
+}
+
+fn assert_close(
+    algorithm: &BlendAlgorithm,
+    result: Rgba<u8>,
+    expected: Rgba<u8>,
+) -> Result<(), TestCaseError> {
+    let (result, expected) = (visible(algorithm, result), visible(algorithm, expected));
+    for (result, expected) in result.iter().zip(expected) {
+        prop_assert!(
+            result.abs_diff(expected) <= TOLERANCE,
+            "algorithm={} result={:?} expected={:?}",
+            algorithm,
+            result,
+            expected
+        );
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn transparent_top(bot in any::<[u8; 4]>(), top in any::<[u8; 3]>()) {
+        for algorithm in algorithms() {
+            if !invariants(&algorithm).transparent_top {
+                continue;
+            }
+            let bot = prepare(&algorithm, bot);
+            let top = prepare(&algorithm, [top[0], top[1], top[2], 0]);
+            assert_close(&algorithm, blend(&algorithm, bot, top), bot)?;
+        }
+    }
+
+    #[test]
+    fn transparent_bottom(bot in any::<[u8; 3]>(), top in any::<[u8; 4]>()) {
+        for algorithm in algorithms() {
+            if !invariants(&algorithm).transparent_bottom {
+                continue;
+            }
+            let bot = prepare(&algorithm, [bot[0], bot[1], bot[2], 0]);
+            let top = prepare(&algorithm, top);
+            assert_close(&algorithm, blend(&algorithm, bot, top), top)?;
+        }
+    }
+
+    #[test]
+    fn transparent_pixels(bot in any::<[u8; 3]>(), top in any::<[u8; 3]>()) {
+        // two fully transparent pixels always yield a fully transparent
+        // pixel (dividing by their alphas must not produce any colour)
+        for algorithm in algorithms() {
+            let bot = prepare(&algorithm, [bot[0], bot[1], bot[2], 0]);
+            let top = prepare(&algorithm, [top[0], top[1], top[2], 0]);
+            let result = blend(&algorithm, bot, top);
+            prop_assert_eq!(result[3], 0, "algorithm={}", algorithm);
+        }
+    }
+
+    #[test]
+    fn alpha_range(bot in any::<[u8; 4]>(), top in any::<[u8; 4]>()) {
+        for algorithm in algorithms() {
+            let bot = prepare(&algorithm, bot);
+            let top = prepare(&algorithm, top);
+            let alpha = blend(&algorithm, bot, top)[3];
+
+            // no algorithm creates more coverage than both pixels together
+            let (min, max) = (bot[3].min(top[3]), bot[3].max(top[3]));
+            prop_assert!(alpha <= bot[3].saturating_add(top[3]), "algorithm={}", algorithm);
+            prop_assert!(alpha >= min.saturating_sub(TOLERANCE), "algorithm={}", algorithm);
+            if invariants(&algorithm).covering_alpha {
+                prop_assert!(alpha >= max.saturating_sub(TOLERANCE), "algorithm={}", algorithm);
+            }
+        }
+    }
+
+    #[test]
+    fn multiply_round_trip(pixel in any::<[u8; 4]>()) {
+        let mut image = ImageBuffer::from_pixel(1, 1, Rgba(pixel));
+        demultiply_image(&mut image);
+        let premultiplied = *image.get_pixel(0, 0);
+        multiply_image(&mut image);
+        let restored = *image.get_pixel(0, 0);
+
+        // the colour is restored up to the precision left by the
+        // premultiplication (which is lower for lower alphas)
+        let alpha = pixel[3];
+        prop_assert_eq!(restored[3], alpha);
+        if alpha > 0 {
+            let tolerance = (127.5 / alpha as f32 + 0.5).floor() as u8;
+            for (restored, channel) in restored.0.iter().zip(pixel).take(3) {
+                prop_assert!(restored.abs_diff(channel) <= tolerance);
+            }
+        }
+
+        // while the premultiplied channels are restored exactly
+        demultiply_image(&mut image);
+        prop_assert_eq!(*image.get_pixel(0, 0), premultiplied);
+    }
+}
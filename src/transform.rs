@@ -0,0 +1,263 @@
+//! Geometric transforms (crop, flips, rotations and affine transforms)
+//! applied to the layers before they are blended.
+
+use crate::errors::PConvertError;
+use image::imageops::{crop_imm, flip_horizontal, flip_vertical, rotate180, rotate270, rotate90};
+use image::{ImageBuffer, Rgba};
+use serde_json::Value as JSONValue;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::result;
+use std::str::FromStr;
+
+/// Enumeration of the supported layer transforms.
+///
+/// Transforms are given as strings such as `crop:10,10,200,100`, `flip_h`,
+/// `flip_v`, `rotate:90` or `affine:1,0,20,0,1,30`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transform {
+    /// Keeps only the given rectangle of the layer.
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    FlipHorizontal,
+    FlipVertical,
+    /// Rotation by 90 degrees clockwise.
+    Rotate90,
+    Rotate180,
+    /// Rotation by 270 degrees clockwise (90 counter-clockwise).
+    Rotate270,
+    /// Affine transform `[a, b, c, d, e, f]` that maps the point `(x, y)`
+    /// of the layer into `(a * x + b * y + c, d * x + e * y + f)`, keeping
+    /// the dimensions of the layer and sampling it bilinearly.
+    Affine([f64; 6]),
+}
+
+impl Transform {
+    /// Applies the transform to the given layer (with straight alpha).
+    pub fn apply(
+        &self,
+        layer: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+        match *self {
+            Transform::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let fits = |start: u32, size: u32, limit: u32| {
+                    size > 0 && matches!(start.checked_add(size), Some(end) if end <= limit)
+                };
+                if !fits(x, width, layer.width()) || !fits(y, height, layer.height()) {
+                    return Err(PConvertError::invalid_parameter(
+                        "transform",
+                        format!(
+                            "crop {}x{} at ({}, {}) is not within the {}x{} layer",
+                            width,
+                            height,
+                            x,
+                            y,
+                            layer.width(),
+                            layer.height()
+                        ),
+                    ));
+                }
+                Ok(crop_imm(layer, x, y, width, height).to_image())
+            }
+            Transform::FlipHorizontal => Ok(flip_horizontal(layer)),
+            Transform::FlipVertical => Ok(flip_vertical(layer)),
+            Transform::Rotate90 => Ok(rotate90(layer)),
+            Transform::Rotate180 => Ok(rotate180(layer)),
+            Transform::Rotate270 => Ok(rotate270(layer)),
+            Transform::Affine(matrix) => affine(layer, &matrix),
+        }
+    }
+}
+
+impl FromStr for Transform {
+    type Err = PConvertError;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let invalid = |reason: &str| {
+            PConvertError::invalid_parameter("transform", format!("'{}' {}", s, reason))
+        };
+        let (name, args) = match s.trim().split_once(':') {
+            Some((name, args)) => (name.trim(), Some(args)),
+            None => (s.trim(), None),
+        };
+        let numbers = |count: usize| -> Result<Vec<f64>, PConvertError> {
+            let numbers = args
+                .unwrap_or_default()
+                .split(',')
+                .map(|value| value.trim().parse::<f64>())
+                .collect::<result::Result<Vec<_>, _>>()
+                .map_err(|_| invalid("has invalid arguments"))?;
+            match numbers.len() == count && numbers.iter().all(|value| value.is_finite()) {
+                true => Ok(numbers),
+                false => Err(invalid(&format!("requires {} numeric arguments", count))),
+            }
+        };
+
+        match (name.to_lowercase().as_str(), args) {
+            ("crop", Some(_)) => {
+                let values = numbers(4)?;
+                if values
+                    .iter()
+                    .any(|value| value.fract() != 0.0 || *value < 0.0 || *value > u32::MAX as f64)
+                {
+                    return Err(invalid("requires non negative integer arguments"));
+                }
+                Ok(Transform::Crop {
+                    x: values[0] as u32,
+                    y: values[1] as u32,
+                    width: values[2] as u32,
+                    height: values[3] as u32,
+                })
+            }
+            ("flip_h", None) | ("flip_horizontal", None) => Ok(Transform::FlipHorizontal),
+            ("flip_v", None) | ("flip_vertical", None) => Ok(Transform::FlipVertical),
+            ("rotate", Some(_)) => {
+                let angle = numbers(1)?[0];
+                match angle as i64 {
+                    90 | -270 if angle.fract() == 0.0 => Ok(Transform::Rotate90),
+                    180 | -180 if angle.fract() == 0.0 => Ok(Transform::Rotate180),
+                    270 | -90 if angle.fract() == 0.0 => Ok(Transform::Rotate270),
+                    _ => Err(invalid("must rotate by 90, 180 or 270 degrees")),
+                }
+            }
+            ("affine", Some(_)) => {
+                let values = numbers(6)?;
+                let mut matrix = [0.0; 6];
+                matrix.copy_from_slice(&values);
+                Ok(Transform::Affine(matrix))
+            }
+            _ => Err(invalid("is not a valid transform")),
+        }
+    }
+}
+
+impl Display for Transform {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Transform::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => write!(f, "crop:{},{},{},{}", x, y, width, height),
+            Transform::FlipHorizontal => write!(f, "flip_h"),
+            Transform::FlipVertical => write!(f, "flip_v"),
+            Transform::Rotate90 => write!(f, "rotate:90"),
+            Transform::Rotate180 => write!(f, "rotate:180"),
+            Transform::Rotate270 => write!(f, "rotate:270"),
+            Transform::Affine(matrix) => write!(
+                f,
+                "affine:{},{},{},{},{},{}",
+                matrix[0], matrix[1], matrix[2], matrix[3], matrix[4], matrix[5]
+            ),
+        }
+    }
+}
+
+/// Applies the transforms, in order, to the given layer.
+pub fn apply_transforms(
+    layer: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    transforms: &[Transform],
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    transforms
+        .iter()
+        .try_fold(layer, |layer, transform| transform.apply(&layer))
+}
+
+/// Parses the transforms of a layer from a JSON array of
+/// transform strings (e.g. `["rotate:90", "flip_h"]`).
+pub fn parse_json_transforms(value: &JSONValue) -> Result<Vec<Transform>, PConvertError> {
+    let invalid =
+        || PConvertError::invalid_parameter("transforms", "expected an array of transform strings");
+    match value {
+        JSONValue::Array(transforms) => transforms
+            .iter()
+            .map(|transform| match transform {
+                JSONValue::String(transform) => Transform::from_str(transform),
+                _ => Err(invalid()),
+            })
+            .collect(),
+        JSONValue::Null => Ok(Vec::new()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Maps every pixel of the layer through the inverse of the affine
+/// transform, sampling bilinearly in premultiplied alpha (the area of
+/// the result outside of the transformed layer is transparent).
+fn affine(
+    layer: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    matrix: &[f64; 6],
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    let [a, b, c, d, e, f] = *matrix;
+    let determinant = a * e - b * d;
+    if determinant.abs() < 1e-12 {
+        return Err(PConvertError::invalid_parameter(
+            "transform",
+            "affine transform is not invertible",
+        ));
+    }
+    let (ia, ib, id, ie) = (
+        e / determinant,
+        -b / determinant,
+        -d / determinant,
+        a / determinant,
+    );
+
+    let (width, height) = layer.dimensions();
+    let sample = |x: i64, y: i64| -> [f64; 4] {
+        if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            return [0.0; 4];
+        }
+        let pixel = layer.get_pixel(x as u32, y as u32);
+        let alpha = pixel[3] as f64 / 255.0;
+        [
+            pixel[0] as f64 * alpha,
+            pixel[1] as f64 * alpha,
+            pixel[2] as f64 * alpha,
+            pixel[3] as f64,
+        ]
+    };
+
+    Ok(ImageBuffer::from_fn(width, height, |x, y| {
+        // maps the center of the target pixel back into the layer
+        let (tx, ty) = (x as f64 + 0.5 - c, y as f64 + 0.5 - f);
+        let source_x = ia * tx + ib * ty - 0.5;
+        let source_y = id * tx + ie * ty - 0.5;
+        let (x0, y0) = (source_x.floor(), source_y.floor());
+        let (fx, fy) = (source_x - x0, source_y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        // saturates as the source position may be far outside of the layer
+        let (x1, y1) = (x0.saturating_add(1), y0.saturating_add(1));
+        let (p00, p10) = (sample(x0, y0), sample(x1, y0));
+        let (p01, p11) = (sample(x0, y1), sample(x1, y1));
+        let mut channels = [0.0; 4];
+        for (index, channel) in channels.iter_mut().enumerate() {
+            let top = p00[index] * (1.0 - fx) + p10[index] * fx;
+            let bottom = p01[index] * (1.0 - fx) + p11[index] * fx;
+            *channel = top * (1.0 - fy) + bottom * fy;
+        }
+
+        let alpha = channels[3].round();
+        if alpha <= 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let factor = 255.0 / channels[3];
+        Rgba([
+            (channels[0] * factor).round().clamp(0.0, 255.0) as u8,
+            (channels[1] * factor).round().clamp(0.0, 255.0) as u8,
+            (channels[2] * factor).round().clamp(0.0, 255.0) as u8,
+            alpha.clamp(0.0, 255.0) as u8,
+        ])
+    }))
+}
@@ -1,16 +1,29 @@
-//! PNG decode/encode and read/write functions, external crate type
-//! conversions and other utility functions.
+//! PNG decode/encode and read/write functions, decoding of the other input
+//! formats, external crate type conversions and other utility functions.
 
 use crate::blending::demultiply_image;
+use crate::encoding::{encode_rows, Compression, Strategy};
 use crate::errors::PConvertError;
+use crate::format::{InputFormat, SIGNATURE_SIZE};
+use crate::limits::Limits;
 use image::codecs::png::{CompressionType, FilterType, PngDecoder, PngEncoder};
+use image::error::{
+    DecodingError, EncodingError, ImageError, ImageFormatHint, LimitError, LimitErrorKind,
+};
+use image::io::Reader;
 use image::ImageDecoder;
 use image::ImageEncoder;
-use image::{ColorType, ImageBuffer, Rgba};
+use image::{ColorType, DynamicImage, ImageBuffer, ImageFormat, Rgba};
+use std::convert::TryFrom;
 use std::fs::File;
-use std::io::{BufWriter, Read, Write};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 
-/// Decodes and returns a PNG.
+/// Size of the start of a PNG up to the dimensions in its header, the
+/// signature (8 bytes), the IHDR chunk length and type (8 bytes) and
+/// the width and height (4 bytes each).
+const PNG_HEADER_SIZE: usize = 24;
+
+/// Decodes and returns a PNG, within the default resource limits.
 ///
 /// # Arguments
 ///
@@ -20,16 +33,151 @@ pub fn decode_png(
     readable_stream: impl Read,
     demultiply: bool,
 ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
-    let decoder = PngDecoder::new(readable_stream)?;
+    decode_png_with_limits(readable_stream, demultiply, &Limits::default())
+}
+
+/// Decodes and returns a PNG, failing with a `LimitExceededError` before
+/// allocating the image when its header exceeds the given limits.
+///
+/// # Arguments
+///
+/// * `readable_stream` - Any structure that implements the `Read` trait.
+/// * `demultiply` - Whether or not to demultiply the PNG.
+/// * `limits` - The resource limits the image must be within.
+pub fn decode_png_with_limits(
+    readable_stream: impl Read,
+    demultiply: bool,
+    limits: &Limits,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    decode_png_as_rgba8(readable_stream, demultiply, false, limits)
+}
+
+/// Decodes a PNG within the limits, the PNGs that are not RGBA8 are either
+/// converted into RGBA8 (`convert`) or reported as unsupported.
+fn decode_png_as_rgba8(
+    readable_stream: impl Read,
+    demultiply: bool,
+    convert: bool,
+    limits: &Limits,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    // the dimensions are checked from the header before creating the decoder,
+    // as the decoder already allocates buffers (e.g. rows) from them
+    let readable_stream = check_png_header(readable_stream, |width, height| {
+        limits.check_dimensions(width, height)
+    })?;
+
+    let decoder = PngDecoder::with_limits(readable_stream, limits.into()).map_err(decode_error)?;
     let (width, height) = decoder.dimensions();
+    limits.check_dimensions(width, height)?;
+    limits.check_bytes(decoder.total_bytes())?;
+    if decoder.color_type() != ColorType::Rgba8 {
+        if !convert {
+            return Err(PConvertError::UnsupportedImageTypeError);
+        }
+        let mut img = DynamicImage::from_decoder(decoder)
+            .map_err(decode_error)?
+            .into_rgba8();
+        if demultiply {
+            demultiply_image(&mut img)
+        }
+        return Ok(img);
+    }
+
+    // the buffer is allocated fallibly as its size comes from the (untrusted)
+    // header, failing with an error instead of aborting the process
+    let mut bytes = Vec::new();
+    usize::try_from(decoder.total_bytes())
+        .ok()
+        .and_then(|total_bytes| {
+            bytes.try_reserve_exact(total_bytes).ok()?;
+            bytes.resize(total_bytes, 0);
+            Some(())
+        })
+        .ok_or_else(|| {
+            decode_error(ImageError::Limits(LimitError::from_kind(
+                LimitErrorKind::InsufficientMemory,
+            )))
+        })?;
+    decoder.read_image(&mut bytes).map_err(decode_error)?;
+
+    let mut img = ImageBuffer::from_vec(width, height, bytes)
+        .ok_or(PConvertError::UnsupportedImageTypeError)?;
+
+    if demultiply {
+        demultiply_image(&mut img)
+    }
+
+    Ok(img)
+}
+
+/// Decodes and returns an image in any of the supported input formats (as
+/// sniffed from its magic bytes), within the default resource limits.
+///
+/// # Arguments
+///
+/// * `readable_stream` - Any structure that implements the `Read` trait.
+/// * `demultiply` - Whether or not to demultiply the image.
+pub fn decode_image(
+    readable_stream: impl Read,
+    demultiply: bool,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    decode_image_with_limits(readable_stream, demultiply, &Limits::default())
+}
 
-    let mut reader = decoder.into_reader()?;
+/// Decodes and returns an image in any of the supported input formats (as
+/// sniffed from its magic bytes), normalised to RGBA with 8 bits per channel
+/// (PNGs of any color type and bit depth included),
+/// failing with a `LimitExceededError` before allocating the image when its
+/// header exceeds the given limits. Images of unknown formats are decoded
+/// as PNGs (failing as such).
+///
+/// # Arguments
+///
+/// * `readable_stream` - Any structure that implements the `Read` trait.
+/// * `demultiply` - Whether or not to demultiply the image.
+/// * `limits` - The resource limits the image must be within.
+pub fn decode_image_with_limits(
+    mut readable_stream: impl Read,
+    demultiply: bool,
+    limits: &Limits,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    let mut signature = Vec::with_capacity(SIGNATURE_SIZE);
+    readable_stream
+        .by_ref()
+        .take(SIGNATURE_SIZE as u64)
+        .read_to_end(&mut signature)?;
+    let format = InputFormat::sniff(&signature);
+    let readable_stream = io::Cursor::new(signature).chain(readable_stream);
 
-    let mut bytes = Vec::<u8>::new();
-    reader.read_to_end(&mut bytes)?;
+    match format {
+        None | Some(InputFormat::Png) => {
+            decode_png_as_rgba8(readable_stream, demultiply, true, limits)
+        }
+        Some(format) => decode_with_format(readable_stream, format, demultiply, limits),
+    }
+}
 
-    let mut img = ImageBuffer::from_vec(width, height, bytes).unwrap();
+/// Decodes an image in a format other than PNG, with the (generic) reader of
+/// the `image` crate, which fails when the format is not enabled.
+fn decode_with_format(
+    mut readable_stream: impl Read,
+    format: InputFormat,
+    demultiply: bool,
+    limits: &Limits,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    // the decoders need to seek, so the (encoded) image is read into memory,
+    // where its dimensions are checked before decoding it
+    let mut bytes = Vec::new();
+    readable_stream.read_to_end(&mut bytes)?;
+    let reader = || {
+        let mut reader = Reader::with_format(io::Cursor::new(&bytes[..]), format.image_format());
+        reader.limits(limits.into());
+        reader
+    };
+    let (width, height) = reader().into_dimensions().map_err(decode_error)?;
+    limits.check_dimensions(width, height)?;
 
+    let mut img = reader().decode().map_err(decode_error)?.into_rgba8();
     if demultiply {
         demultiply_image(&mut img)
     }
@@ -37,7 +185,27 @@ pub fn decode_png(
     Ok(img)
 }
 
-/// Reads a PNG from the local file system.
+/// Checks the dimensions read from the header (IHDR chunk) of a PNG, when they
+/// can be read, returning a stream of the whole PNG (header included).
+pub(crate) fn check_png_header<R: Read>(
+    mut readable_stream: R,
+    check: impl FnOnce(u32, u32) -> Result<(), PConvertError>,
+) -> Result<io::Chain<io::Cursor<Vec<u8>>, R>, PConvertError> {
+    let mut header = Vec::with_capacity(PNG_HEADER_SIZE);
+    readable_stream
+        .by_ref()
+        .take(PNG_HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+    if header.len() == PNG_HEADER_SIZE && &header[12..16] == b"IHDR" {
+        check(
+            u32::from_be_bytes([header[16], header[17], header[18], header[19]]),
+            u32::from_be_bytes([header[20], header[21], header[22], header[23]]),
+        )?;
+    }
+    Ok(io::Cursor::new(header).chain(readable_stream))
+}
+
+/// Reads a PNG from the local file system, within the default resource limits.
 ///
 /// # Arguments
 ///
@@ -47,8 +215,54 @@ pub fn read_png_from_file(
     file_in: String,
     demultiply: bool,
 ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
-    let file = File::open(file_in)?;
-    decode_png(file, demultiply)
+    read_png_from_file_with_limits(file_in, demultiply, &Limits::default())
+}
+
+/// Reads a PNG from the local file system, within the given resource limits.
+///
+/// # Arguments
+///
+/// * `file_in` - Local file system path to the PNG file.
+/// * `demultiply` - Whether or not to demultiply the PNG.
+/// * `limits` - The resource limits the image must be within.
+pub fn read_png_from_file_with_limits(
+    file_in: String,
+    demultiply: bool,
+    limits: &Limits,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    let file = File::open(&file_in)?;
+    decode_png_with_limits(file, demultiply, limits).map_err(|err| err.with_path(&file_in))
+}
+
+/// Reads an image in any of the supported input formats from the local
+/// file system, within the default resource limits.
+///
+/// # Arguments
+///
+/// * `file_in` - Local file system path to the image file.
+/// * `demultiply` - Whether or not to demultiply the image.
+pub fn read_image_from_file(
+    file_in: String,
+    demultiply: bool,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    read_image_from_file_with_limits(file_in, demultiply, &Limits::default())
+}
+
+/// Reads an image in any of the supported input formats from the local
+/// file system, within the given resource limits.
+///
+/// # Arguments
+///
+/// * `file_in` - Local file system path to the image file.
+/// * `demultiply` - Whether or not to demultiply the image.
+/// * `limits` - The resource limits the image must be within.
+pub fn read_image_from_file_with_limits(
+    file_in: String,
+    demultiply: bool,
+    limits: &Limits,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
+    let file = BufReader::new(File::open(&file_in)?);
+    decode_image_with_limits(file, demultiply, limits).map_err(|err| err.with_path(&file_in))
 }
 
 /// Encodes a PNG and writes it to a buffer.
@@ -62,12 +276,26 @@ pub fn read_png_from_file(
 pub fn encode_png(
     writable_buff: impl Write,
     png: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-    compression: CompressionType,
+    compression: impl Into<Compression>,
     filter: FilterType,
 ) -> Result<(), PConvertError> {
+    let compression = compression.into();
     let buff = BufWriter::new(writable_buff);
-    let encoder = PngEncoder::new_with_quality(buff, compression, filter);
-    Ok(encoder.write_image(png, png.width(), png.height(), ColorType::Rgba8)?)
+    match image_compression_type_from(compression) {
+        Some(compression) => {
+            let encoder = PngEncoder::new_with_quality(buff, compression, filter);
+            encoder
+                .write_image(png, png.width(), png.height(), ColorType::Rgba8)
+                .map_err(encode_error)
+        }
+        None => {
+            let mut encoder = png::Encoder::new(buff, png.width(), png.height());
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let row_size = png.width() as usize * 4;
+            encode_rows(encoder, png, row_size, 4, compression, filter)
+        }
+    }
 }
 
 /// Writes a PNG to the local file system using the provided compression
@@ -82,11 +310,11 @@ pub fn encode_png(
 pub fn write_png_to_file(
     file_out: String,
     png: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-    compression: CompressionType,
+    compression: impl Into<Compression>,
     filter: FilterType,
 ) -> Result<(), PConvertError> {
-    let file = File::create(file_out)?;
-    encode_png(file, png, compression, filter)
+    let file = File::create(&file_out)?;
+    encode_png(file, png, compression, filter).map_err(|err| err.with_path(&file_out))
 }
 
 /// Writes a PNG to the local file system using the default
@@ -102,12 +330,15 @@ pub fn write_png_to_file_d(
     file_out: String,
     png: &ImageBuffer<Rgba<u8>, Vec<u8>>,
 ) -> Result<(), PConvertError> {
-    let file = File::create(file_out)?;
-    encode_png(file, png, CompressionType::Fast, FilterType::NoFilter)
+    let file = File::create(&file_out)?;
+    encode_png(file, png, Compression::FAST, FilterType::NoFilter)
+        .map_err(|err| err.with_path(&file_out))
 }
 
 /// [NOT SUPPORTED IN WASM] Multi-threaded write version of a
-/// PNG to the local file system.
+/// PNG to the local file system, the levels other than the fast, default
+/// and best ones (that the multi-threaded encoder does not support) are
+/// written single-threaded.
 ///
 /// # Arguments
 ///
@@ -119,25 +350,33 @@ pub fn write_png_to_file_d(
 pub fn write_png_parallel(
     file_out: String,
     png: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-    compression: CompressionType,
+    compression: impl Into<Compression>,
     filter: FilterType,
 ) -> Result<(), PConvertError> {
-    let writer = File::create(file_out)?;
-
-    let mut header = mtpng::Header::new();
-    header.set_size(png.width(), png.height())?;
-    header.set_color(mtpng::ColorType::TruecolorAlpha, 8)?;
+    let compression = compression.into();
+    let level = match mtpng_compression_from(compression) {
+        Some(level) => level,
+        None => return write_png_to_file(file_out, png, compression, filter),
+    };
+    let writer = File::create(&file_out)?;
+    let encode = || -> Result<(), std::io::Error> {
+        let mut header = mtpng::Header::new();
+        header.set_size(png.width(), png.height())?;
+        header.set_color(mtpng::ColorType::TruecolorAlpha, 8)?;
 
-    let mut options = mtpng::encoder::Options::new();
-    options.set_compression_level(mtpng_compression_from(compression))?;
-    options.set_filter_mode(mtpng::Mode::Fixed(mtpng_filter_from(filter)))?;
+        let mut options = mtpng::encoder::Options::new();
+        options.set_compression_level(level)?;
+        options.set_strategy_mode(mtpng_strategy_from(compression))?;
+        options.set_filter_mode(mtpng_filter_from(filter))?;
 
-    let mut encoder = mtpng::encoder::Encoder::new(writer, &options);
-    encoder.write_header(&header)?;
-    encoder.write_image_rows(png)?;
-    encoder.finish()?;
+        let mut encoder = mtpng::encoder::Encoder::new(writer, &options);
+        encoder.write_header(&header)?;
+        encoder.write_image_rows(png)?;
+        encoder.finish()?;
+        Ok(())
+    };
 
-    Ok(())
+    encode().map_err(|err| encode_error(err.into()).with_path(&file_out))
 }
 
 /// [SUPPORTED IN WASM] WASM stub; single-threaded write PNG to the
@@ -153,15 +392,45 @@ pub fn write_png_parallel(
 pub fn write_png_parallel(
     file_out: String,
     png: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-    compression: CompressionType,
+    compression: impl Into<Compression>,
     filter: FilterType,
 ) -> Result<(), PConvertError> {
     write_png_to_file(file_out, png, compression, filter)
 }
 
+pub(crate) fn decode_error(err: ImageError) -> PConvertError {
+    PConvertError::DecodeError {
+        path: None,
+        layer: None,
+        source: err,
+    }
+}
+
+pub(crate) fn encode_error(err: ImageError) -> PConvertError {
+    PConvertError::EncodeError {
+        path: None,
+        source: err,
+    }
+}
+
+pub(crate) fn png_decode_error(err: png::DecodingError) -> PConvertError {
+    decode_error(ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Png),
+        err,
+    )))
+}
+
+pub(crate) fn png_encode_error(err: png::EncodingError) -> PConvertError {
+    encode_error(ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Png),
+        err,
+    )))
+}
+
 /// Converts a `String` to a `image::codecs::png::CompressionType`.
 /// This can not be done by implementing the trait `From<String> for CompressionType` due to Rust's.
 /// [orphan rule](https://doc.rust-lang.org/book/ch10-02-traits.html#implementing-a-trait-on-a-type).
+#[deprecated(note = "parse a `Compression` with `Compression::from_str` instead")]
 pub fn image_compression_from(compression: String) -> CompressionType {
     match compression.trim().to_lowercase().as_str() {
         "best" => CompressionType::Best,
@@ -174,6 +443,7 @@ pub fn image_compression_from(compression: String) -> CompressionType {
 /// Converts a `String` to a `image::codecs::png::FilterType`.
 /// This can not be done by implementing the trait `From<String> for FilterType` due to Rust's
 /// [orphan rule](https://doc.rust-lang.org/book/ch10-02-traits.html#implementing-a-trait-on-a-type).
+#[deprecated(note = "use `encoding::parse_filter` instead")]
 pub fn image_filter_from(filter: String) -> FilterType {
     match filter.trim().to_lowercase().as_str() {
         "avg" => FilterType::Avg,
@@ -185,26 +455,147 @@ pub fn image_filter_from(filter: String) -> FilterType {
     }
 }
 
-#[cfg(not(feature = "wasm-extension"))]
-fn mtpng_compression_from(compression: CompressionType) -> mtpng::CompressionLevel {
+/// Returns the number of channels and the size in bytes of a sample of the
+/// rows of a PNG decoded with the `EXPAND` transformation of the `png` crate
+/// (greyscale or RGB, with or without alpha, of 8 or 16 bits), the ones that
+/// can be expanded into RGBA8 (as the `image` decoder does).
+pub(crate) fn png_samples(
+    (color_type, bit_depth): (png::ColorType, png::BitDepth),
+) -> Result<(usize, usize), PConvertError> {
+    let channels = match color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => return Err(PConvertError::UnsupportedImageTypeError),
+    };
+    match bit_depth {
+        png::BitDepth::Eight => Ok((channels, 1)),
+        png::BitDepth::Sixteen => Ok((channels, 2)),
+        _ => Err(PConvertError::UnsupportedImageTypeError),
+    }
+}
+
+/// Expands a row of samples (laid out as given by `png_samples`) into RGBA8
+/// pixels, the 16 bits samples (big endian, as in the PNG) are rounded into
+/// 8 bits as in the `image` crate, so that the pixels are the ones of the
+/// `into_rgba8` conversion of the decoded image.
+pub(crate) fn expand_row_to_rgba8(
+    row: &[u8],
+    (channels, sample_size): (usize, usize),
+    pixels: &mut [u8],
+) {
+    let sample = |bytes: &[u8]| match sample_size {
+        2 => ((u16::from_be_bytes([bytes[0], bytes[1]]) as u32 + 128) / 257) as u8,
+        _ => bytes[0],
+    };
+    for (source, pixel) in row
+        .chunks_exact(channels * sample_size)
+        .zip(pixels.chunks_exact_mut(4))
+    {
+        let mut samples = source.chunks_exact(sample_size).map(sample);
+        let mut next = || samples.next().unwrap_or(u8::MAX);
+        let rgba = match channels {
+            1 | 2 => {
+                let luma = next();
+                [luma, luma, luma, next()]
+            }
+            _ => [next(), next(), next(), next()],
+        };
+        pixel.copy_from_slice(&rgba);
+    }
+}
+
+/// Maps the compression into the compression type of the `image` encoder,
+/// that only has the fast, default and best levels (with the default
+/// strategy), `None` for any other compression.
+pub(crate) fn image_compression_type_from(compression: Compression) -> Option<CompressionType> {
+    if compression.strategy() != Strategy::Default {
+        return None;
+    }
     match compression {
-        CompressionType::Default => mtpng::CompressionLevel::Default,
-        CompressionType::Best => mtpng::CompressionLevel::High,
-        CompressionType::Fast => mtpng::CompressionLevel::Fast,
-        _ => mtpng::CompressionLevel::Fast,
+        compression if compression == Compression::FAST => Some(CompressionType::Fast),
+        compression if compression == Compression::DEFAULT => Some(CompressionType::Default),
+        compression if compression == Compression::BEST => Some(CompressionType::Best),
+        _ => None,
+    }
+}
+
+/// Maps the compression into the level of the multi-threaded encoder, that
+/// only has the fast, default and best levels, `None` for any other level.
+#[cfg(not(feature = "wasm-extension"))]
+pub(crate) fn mtpng_compression_from(compression: Compression) -> Option<mtpng::CompressionLevel> {
+    match compression.level() {
+        level if level == Compression::FAST.level() => Some(mtpng::CompressionLevel::Fast),
+        level if level == Compression::DEFAULT.level() => Some(mtpng::CompressionLevel::Default),
+        level if level == Compression::BEST.level() => Some(mtpng::CompressionLevel::High),
+        _ => None,
     }
 }
 
+/// Maps the strategy of the compression into the (fixed) strategy of the
+/// multi-threaded encoder, as it otherwise picks one by the filter of the
+/// rows, unlike the single-threaded encoders.
+#[cfg(not(feature = "wasm-extension"))]
+pub(crate) fn mtpng_strategy_from(compression: Compression) -> mtpng::Mode<mtpng::Strategy> {
+    mtpng::Mode::Fixed(match compression.strategy() {
+        Strategy::Default => mtpng::Strategy::Default,
+        Strategy::Filtered => mtpng::Strategy::Filtered,
+        Strategy::Rle => mtpng::Strategy::RLE,
+        Strategy::HuffmanOnly => mtpng::Strategy::HuffmanOnly,
+    })
+}
+
+/// Maps the filter type into the filter mode of the multi-threaded
+/// encoder, the adaptive filter being its adaptive mode.
 #[cfg(not(feature = "wasm-extension"))]
-fn mtpng_filter_from(filter: FilterType) -> mtpng::Filter {
+pub(crate) fn mtpng_filter_from(filter: FilterType) -> mtpng::Mode<mtpng::Filter> {
+    match filter {
+        FilterType::Avg => mtpng::Mode::Fixed(mtpng::Filter::Average),
+        FilterType::Paeth => mtpng::Mode::Fixed(mtpng::Filter::Paeth),
+        FilterType::Sub => mtpng::Mode::Fixed(mtpng::Filter::Sub),
+        FilterType::Up => mtpng::Mode::Fixed(mtpng::Filter::Up),
+        FilterType::Adaptive => mtpng::Mode::Adaptive,
+        _ => mtpng::Mode::Fixed(mtpng::Filter::None),
+    }
+}
+
+/// Maps the compression into the level of the `png` crate (used directly
+/// for APNGs), that only has the fast, default and best levels, `None`
+/// for any other level.
+pub(crate) fn png_compression_from(compression: Compression) -> Option<png::Compression> {
+    match compression.level() {
+        level if level == Compression::FAST.level() => Some(png::Compression::Fast),
+        level if level == Compression::DEFAULT.level() => Some(png::Compression::Default),
+        level if level == Compression::BEST.level() => Some(png::Compression::Best),
+        _ => None,
+    }
+}
+
+/// Maps the filter type into the (adaptive) filter of the `png` crate (used
+/// directly for APNGs), as the `image` PNG encoder does.
+pub(crate) fn png_filter_from(filter: FilterType) -> (png::FilterType, png::AdaptiveFilterType) {
     match filter {
-        FilterType::Avg => mtpng::Filter::Average,
-        FilterType::Paeth => mtpng::Filter::Paeth,
-        FilterType::Sub => mtpng::Filter::Sub,
-        FilterType::Up => mtpng::Filter::Up,
-        FilterType::NoFilter => mtpng::Filter::None,
-        _ => mtpng::Filter::None,
+        FilterType::Sub => (png::FilterType::Sub, png::AdaptiveFilterType::NonAdaptive),
+        FilterType::Up => (png::FilterType::Up, png::AdaptiveFilterType::NonAdaptive),
+        FilterType::Avg => (png::FilterType::Avg, png::AdaptiveFilterType::NonAdaptive),
+        FilterType::Paeth => (png::FilterType::Paeth, png::AdaptiveFilterType::NonAdaptive),
+        FilterType::Adaptive => (png::FilterType::Sub, png::AdaptiveFilterType::Adaptive),
+        _ => (
+            png::FilterType::NoFilter,
+            png::AdaptiveFilterType::NonAdaptive,
+        ),
+    }
+}
+
+/// Parses a `#rrggbb` (or `rrggbb`) colour.
+pub fn parse_hex_color(value: &str) -> Option<[u8; 3]> {
+    let value = value.strip_prefix('#').unwrap_or(value);
+    if value.len() != 6 || !value.chars().all(|char| char.is_ascii_hexdigit()) {
+        return None;
     }
+    let channel = |index: usize| u8::from_str_radix(&value[index..index + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
 }
 
 /// Maximum of two values that implement the `PartialOrd` trait.
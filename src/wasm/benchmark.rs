@@ -1,9 +1,13 @@
 //! Benchmarking functions of the WASM API exposed
 
+use crate::blending::dimensions::DimensionPolicy;
 use crate::constants;
-use crate::wasm::utils::{encode_file, load_png, log_benchmark, log_benchmark_header};
+use crate::encoding::Compression;
+use crate::errors::PConvertError;
+use crate::format::OutputOptions;
+use crate::wasm::utils::{encode_file, load_image, log_benchmark, log_benchmark_header};
 use crate::wasm::{blend_image_buffers, blend_multiple_buffers};
-use image::codecs::png::{CompressionType, FilterType};
+use image::codecs::png::FilterType;
 use js_sys::try_iter;
 use wasm_bindgen::prelude::*;
 use web_sys::File;
@@ -26,7 +30,7 @@ pub async fn blend_images_benchmark_all_js(
                     "".to_string(),
                     Some(algorithm.to_string()),
                     is_inline,
-                    *compression,
+                    (*compression).into(),
                     *filter,
                 )
                 .await?;
@@ -53,7 +57,7 @@ pub async fn blend_multiple_benchmark_all_js(
                     Some(algorithm.to_string()),
                     None,
                     is_inline,
-                    *compression,
+                    (*compression).into(),
                     *filter,
                 )
                 .await?;
@@ -69,21 +73,33 @@ async fn blend_images_benchmark_js(
     target_file_name: String,
     algorithm: Option<String>,
     is_inline: Option<bool>,
-    compression: CompressionType,
+    compression: Compression,
     filter: FilterType,
 ) -> Result<File, JsValue> {
     let start_read = js_sys::Date::now();
 
-    let mut bot = load_png(bot, false).await?;
-    let mut top = load_png(top, false).await?;
+    let mut bot = load_image(bot, false).await?;
+    let mut top = load_image(top, false).await?;
 
     let start_blend = js_sys::Date::now();
 
-    blend_image_buffers(&mut bot, &mut top, algorithm.clone(), is_inline)?;
+    blend_image_buffers(
+        &mut bot,
+        &mut top,
+        algorithm.clone(),
+        is_inline,
+        &DimensionPolicy::default(),
+    )?;
 
     let start_write = js_sys::Date::now();
 
-    let file = encode_file(bot, compression, filter, target_file_name)?;
+    let file = encode_file(
+        bot,
+        compression,
+        filter,
+        &OutputOptions::default(),
+        target_file_name,
+    )?;
 
     let end = js_sys::Date::now();
 
@@ -109,28 +125,41 @@ async fn blend_multiple_benchmark_js(
     algorithm: Option<String>,
     algorithms: Option<Vec<JsValue>>,
     is_inline: Option<bool>,
-    compression: CompressionType,
+    compression: Compression,
     filter: FilterType,
 ) -> Result<File, JsValue> {
     let start_read = js_sys::Date::now();
 
     let mut image_buffers = Vec::new();
-    let image_files = try_iter(&image_files).unwrap().unwrap();
+    let image_files = try_iter(&image_files)?.ok_or_else(|| {
+        PConvertError::ArgumentError("'image_files' must be iterable".to_string())
+    })?;
     for file in image_files {
         let file = file?;
-        let img = load_png(file.into(), false).await?;
+        let img = load_image(file.into(), false).await?;
 
         image_buffers.push(img);
     }
 
     let start_blend = js_sys::Date::now();
 
-    let composition =
-        blend_multiple_buffers(image_buffers, algorithm.clone(), algorithms, is_inline)?;
+    let composition = blend_multiple_buffers(
+        image_buffers,
+        algorithm.clone(),
+        algorithms,
+        is_inline,
+        &DimensionPolicy::default(),
+    )?;
 
     let start_write = js_sys::Date::now();
 
-    let file = encode_file(composition, compression, filter, target_file_name)?;
+    let file = encode_file(
+        composition,
+        compression,
+        filter,
+        &OutputOptions::default(),
+        target_file_name,
+    )?;
 
     let end = js_sys::Date::now();
 
@@ -2,6 +2,7 @@
 
 use crate::blending::params::Value;
 use crate::errors::PConvertError;
+use js_sys::{Array, Error, Reflect};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JSONValue;
 use std::collections::HashMap;
@@ -16,15 +17,75 @@ pub struct JSONParams {
 
 impl From<PConvertError> for JsValue {
     fn from(err: PConvertError) -> JsValue {
-        match err {
-            PConvertError::ArgumentError(err) => JsValue::from_str(&err),
-            PConvertError::ImageLibError(err) => JsValue::from_str(&err.to_string()),
-            PConvertError::UnsupportedImageTypeError => JsValue::from_str(&err.to_string()),
-            PConvertError::IOError(err) => JsValue::from_str(&err.to_string()),
+        let error = Error::new(&err.to_string());
+        set_property(&error, "code", &err.code().into());
+
+        // attaches the context of the error as extra properties
+        // of the javascript error (e.g. the path of the file)
+        match &err {
+            PConvertError::DimensionMismatchError {
+                layer,
+                expected,
+                found,
+            } => {
+                set_property(&error, "layer", &(*layer as u32).into());
+                set_property(
+                    &error,
+                    "expected",
+                    &Array::of2(&expected.0.into(), &expected.1.into()),
+                );
+                set_property(
+                    &error,
+                    "found",
+                    &Array::of2(&found.0.into(), &found.1.into()),
+                );
+            }
+            PConvertError::UnknownAlgorithmError(algorithm) => {
+                set_property(&error, "algorithm", &algorithm.into());
+            }
+            PConvertError::InvalidParameterError { name, .. } => {
+                set_property(&error, "name", &name.into());
+            }
+            PConvertError::DecodeError { path, layer, .. } => {
+                if let Some(path) = path {
+                    set_property(&error, "path", &path.into());
+                }
+                if let Some(layer) = layer {
+                    set_property(&error, "layer", &(*layer as u32).into());
+                }
+            }
+            PConvertError::EncodeError {
+                path: Some(path), ..
+            } => {
+                set_property(&error, "path", &path.into());
+            }
+            PConvertError::LimitExceededError { limit, .. } => {
+                set_property(&error, "limit", &(*limit).into());
+            }
+            _ => {}
         }
+
+        error.into()
     }
 }
 
+/// Attaches the index of the layer to a decode error raised while
+/// loading that layer, any other value is returned unchanged.
+pub fn with_layer(err: JsValue, layer: usize) -> JsValue {
+    let is_decode_error = Reflect::get(&err, &"code".into()).map_or(false, |code| {
+        code.as_string().as_deref() == Some("DECODE_ERROR")
+    });
+    if err.is_instance_of::<Error>() && is_decode_error {
+        set_property(&err, "layer", &(layer as u32).into());
+    }
+    err
+}
+
+fn set_property(target: &JsValue, name: &str, value: &JsValue) {
+    // setting a property of a plain error object never fails
+    let _ = Reflect::set(target, &name.into(), value);
+}
+
 impl From<JSONValue> for Value {
     fn from(value: JSONValue) -> Value {
         match value {
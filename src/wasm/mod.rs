@@ -6,22 +6,27 @@ pub mod utils;
 pub mod benchmark;
 pub mod conversions;
 
+use crate::blending::dimensions::{conform_layer, DimensionPolicy};
 use crate::blending::params::BlendAlgorithmParams;
 use crate::blending::{
     blend_images, demultiply_image, get_blending_algorithm, is_algorithm_multiplied, BlendAlgorithm,
 };
 use crate::constants;
 use crate::errors::PConvertError;
-use crate::utils::{decode_png, encode_png};
+use crate::format::encode_image;
+use crate::resize::resize_output;
+use crate::utils::decode_image_with_limits;
+use conversions::with_layer;
 use image::{ImageBuffer, Rgba, RgbaImage};
 use js_sys::try_iter;
 use serde::Serialize;
 use serde_json::json;
 use serde_wasm_bindgen::Serializer;
 use utils::{
-    build_algorithm, build_params, encode_file, encode_image_data, get_compression_type,
-    get_filter_type, load_png, node_read_file_async, node_read_file_sync, node_require,
-    node_write_file_sync,
+    build_algorithm, build_layer, build_params, encode_file, encode_image_data,
+    get_compression_type, get_dimension_policy, get_filter_type, get_limits, get_output_options,
+    get_resize_options, load_image_with_limits, node_read_file_async, node_read_file_sync,
+    node_require, node_write_file_sync,
 };
 use wasm_bindgen::prelude::*;
 use web_sys::{File, ImageData};
@@ -42,15 +47,25 @@ pub async fn blend_images_js(
         false => None,
     };
 
-    let mut bot = load_png(bot, false).await?;
-    let mut top = load_png(top, false).await?;
+    let limits = get_limits(&options)?;
+    let output = get_output_options(&options, &target_file_name)?;
+    let mut bot = load_image_with_limits(bot, false, &limits)
+        .await
+        .map_err(|err| with_layer(err, 0))?;
+    let mut top = load_image_with_limits(top, false, &limits)
+        .await
+        .map_err(|err| with_layer(err, 1))?;
 
-    blend_image_buffers(&mut bot, &mut top, algorithm, is_inline)?;
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    blend_image_buffers(&mut bot, &mut top, algorithm, is_inline, &dimension_policy)?;
+    let bot = resize_output(bot, &resize, &limits)?;
 
     encode_file(
         bot,
-        get_compression_type(&options),
-        get_filter_type(&options),
+        get_compression_type(&options)?,
+        get_filter_type(&options)?,
+        &output,
         target_file_name,
     )
 }
@@ -70,28 +85,34 @@ pub fn blend_images_data_js(
         false => None,
     };
 
-    let (width, height) = (bot.width(), bot.height());
-    let mut bot = ImageBuffer::from_vec(width, height, bot.data().to_vec())
+    let mut bot = ImageBuffer::from_vec(bot.width(), bot.height(), bot.data().to_vec())
         .ok_or_else(|| PConvertError::ArgumentError("Could not parse \"bot\"".to_string()))?;
-    let mut top = ImageBuffer::from_vec(width, height, top.data().to_vec())
+    let mut top = ImageBuffer::from_vec(top.width(), top.height(), top.data().to_vec())
         .ok_or_else(|| PConvertError::ArgumentError("Could not parse \"top\"".to_string()))?;
 
-    blend_image_buffers(&mut bot, &mut top, algorithm, is_inline)?;
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    let limits = get_limits(&options)?;
+    blend_image_buffers(&mut bot, &mut top, algorithm, is_inline, &dimension_policy)?;
+    let bot = resize_output(bot, &resize, &limits)?;
 
     encode_image_data(
         bot,
-        get_compression_type(&options),
-        get_filter_type(&options),
+        get_compression_type(&options)?,
+        get_filter_type(&options)?,
     )
 }
 
 /// Blends two image buffers using `algorithm` and the extra
 /// `options` given. Algorithm defaults to `BlendAlgorithm::Multiplicative`.
+/// The `top` buffer is conformed to the dimensions of `bot` according
+/// to the given `dimension_policy`.
 pub fn blend_image_buffers(
     bot: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
     top: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
     algorithm: Option<String>,
     is_inline: Option<bool>,
+    dimension_policy: &DimensionPolicy,
 ) -> Result<(), PConvertError> {
     let algorithm = algorithm.unwrap_or_else(|| String::from("multiplicative"));
     let algorithm = build_algorithm(&algorithm)?;
@@ -99,6 +120,8 @@ pub fn blend_image_buffers(
     let demultiply = is_algorithm_multiplied(&algorithm);
     let _is_inline = is_inline.unwrap_or(false);
 
+    *top = conform_layer(std::mem::take(top), bot.dimensions(), 1, dimension_policy)?;
+
     if demultiply {
         demultiply_image(bot);
         demultiply_image(top);
@@ -124,20 +147,37 @@ pub async fn blend_multiple_js(
         false => None,
     };
 
+    let limits = get_limits(&options)?;
+    let output = get_output_options(&options, &target_file_name)?;
     let mut image_buffers = Vec::new();
-    let image_files = try_iter(&image_files).unwrap().unwrap();
-    for file in image_files {
-        let file = file?;
-        let img = load_png(file.into(), false).await?;
-
-        image_buffers.push(img);
+    let image_files = try_iter(&image_files)?.ok_or_else(|| {
+        PConvertError::ArgumentError("'image_files' must be iterable".to_string())
+    })?;
+    for (index, file) in image_files.enumerate() {
+        limits.check_layers(index + 1)?;
+        let (file, layer_options) = build_layer(file?, "file")?;
+        let img = load_image_with_limits(file.into(), false, &limits)
+            .await
+            .map_err(|err| with_layer(err, index))?;
+
+        image_buffers.push(layer_options.apply_with_limits(img, &limits)?);
     }
 
-    let composition = blend_multiple_buffers(image_buffers, algorithm, algorithms, is_inline)?;
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    let composition = blend_multiple_buffers(
+        image_buffers,
+        algorithm,
+        algorithms,
+        is_inline,
+        &dimension_policy,
+    )?;
+    let composition = resize_output(composition, &resize, &limits)?;
     encode_file(
         composition,
-        get_compression_type(&options),
-        get_filter_type(&options),
+        get_compression_type(&options)?,
+        get_filter_type(&options)?,
+        &output,
         target_file_name,
     )
 }
@@ -157,9 +197,13 @@ pub fn blend_multiple_data_js(
         false => None,
     };
 
+    let limits = get_limits(&options)?;
     let mut image_buffers: Vec<RgbaImage> = Vec::new();
-    let mut images = try_iter(images).unwrap().unwrap();
+    let mut images = try_iter(images)?
+        .ok_or_else(|| PConvertError::ArgumentError("'images' must be iterable".to_string()))?;
     while let Some(Ok(img_data)) = images.next() {
+        limits.check_layers(image_buffers.len() + 1)?;
+        let (img_data, layer_options) = build_layer(img_data, "data")?;
         let img_data: ImageData = img_data.into();
         let img_buffer: RgbaImage = ImageBuffer::from_vec(
             img_data.width(),
@@ -168,14 +212,23 @@ pub fn blend_multiple_data_js(
         )
         .ok_or_else(|| PConvertError::ArgumentError("Could not parse \"bot\"".to_string()))?;
 
-        image_buffers.push(img_buffer);
+        image_buffers.push(layer_options.apply_with_limits(img_buffer, &limits)?);
     }
 
-    let composition = blend_multiple_buffers(image_buffers, algorithm, algorithms, is_inline)?;
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    let composition = blend_multiple_buffers(
+        image_buffers,
+        algorithm,
+        algorithms,
+        is_inline,
+        &dimension_policy,
+    )?;
+    let composition = resize_output(composition, &resize, &limits)?;
     encode_image_data(
         composition,
-        get_compression_type(&options),
-        get_filter_type(&options),
+        get_compression_type(&options)?,
+        get_filter_type(&options)?,
     )
 }
 
@@ -227,7 +280,7 @@ pub fn blend_multiple_fs(
 
     if num_images < 1 {
         return Err(PConvertError::ArgumentError(
-            "ArgumentError: 'img_paths' must contain at least one path".to_string(),
+            "'img_paths' must contain at least one path".to_string(),
         )
         .into());
     }
@@ -249,22 +302,30 @@ pub fn blend_multiple_fs(
 
     if algorithms_to_apply.len() != num_images - 1 {
         return Err(PConvertError::ArgumentError(format!(
-            "ArgumentError: 'algorithms' must be of size {} (one per blending operation)",
+            "'algorithms' must be of size {} (one per blending operation)",
             num_images - 1
         ))
         .into());
     };
 
     let _is_inline = is_inline.unwrap_or(false);
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    let output = get_output_options(&options, &out_path)?;
+    let limits = get_limits(&options)?;
+    limits.check_layers(num_images)?;
+
+    let mut paths = Vec::with_capacity(num_images);
+    let mut layers_options = Vec::with_capacity(num_images);
+    for value in image_paths {
+        let (path, layer_options) = build_layer(value, "path")?;
+        paths.push(path.as_string().ok_or_else(path_error)?);
+        layers_options.push(layer_options);
+    }
 
     // loops through the algorithms to apply and blends the
     // current composition with the next layer
-    let mut img_paths_iter = image_paths.iter();
-    let first_path = img_paths_iter
-        .next()
-        .unwrap()
-        .as_string()
-        .expect("path must be a string");
+    let first_path = &paths[0];
 
     let node_fs = node_require("fs");
 
@@ -273,17 +334,31 @@ pub fn blend_multiple_fs(
     } else {
         false
     };
-    let composition = node_read_file_sync(&node_fs, &first_path);
-    let mut composition = decode_png(&composition[..], first_demultiply)?;
+    let composition = node_read_file_sync(&node_fs, first_path);
+    let composition = decode_image_with_limits(&composition[..], false, &limits)
+        .map_err(|err| err.with_path(first_path).with_layer(0))?;
+    let mut composition = layers_options[0].apply_with_limits(composition, &limits)?;
+    if first_demultiply {
+        demultiply_image(&mut composition);
+    }
 
-    let zip_iter = img_paths_iter.zip(algorithms_to_apply.iter());
-    for pair in zip_iter {
-        let path = pair.0.as_string().expect("path must be a string");
-        let (algorithm, algorithm_params) = pair.1;
+    let zip_iter = paths.iter().skip(1).zip(algorithms_to_apply.iter());
+    for (index, (path, (algorithm, algorithm_params))) in zip_iter.enumerate() {
         let demultiply = is_algorithm_multiplied(algorithm);
         let algorithm_fn = get_blending_algorithm(algorithm);
-        let current_layer = node_read_file_sync(&node_fs, &path);
-        let current_layer = decode_png(&current_layer[..], demultiply)?;
+        let current_layer = node_read_file_sync(&node_fs, path);
+        let current_layer = decode_image_with_limits(&current_layer[..], false, &limits)
+            .map_err(|err| err.with_path(path).with_layer(index + 1))?;
+        let current_layer = layers_options[index + 1].apply_with_limits(current_layer, &limits)?;
+        let mut current_layer = conform_layer(
+            current_layer,
+            composition.dimensions(),
+            index + 1,
+            &dimension_policy,
+        )?;
+        if demultiply {
+            demultiply_image(&mut current_layer);
+        }
         blend_images(
             &mut composition,
             &current_layer,
@@ -292,15 +367,17 @@ pub fn blend_multiple_fs(
         );
     }
 
-    let compression_type = get_compression_type(&options);
-    let filter_type = get_filter_type(&options);
+    let composition = resize_output(composition, &resize, &limits)?;
+    let compression_type = get_compression_type(&options)?;
+    let filter_type = get_filter_type(&options)?;
 
     let mut encoded_data = Vec::<u8>::with_capacity(composition.to_vec().capacity());
-    encode_png(
+    encode_image(
         &mut encoded_data,
         &composition,
         compression_type,
         filter_type,
+        &output,
     )?;
 
     node_write_file_sync(&node_fs, &out_path, &encoded_data);
@@ -324,7 +401,7 @@ pub async fn blend_multiple_fs_async(
 
     if num_images < 1 {
         return Err(PConvertError::ArgumentError(
-            "ArgumentError: 'img_paths' must contain at least one path".to_string(),
+            "'img_paths' must contain at least one path".to_string(),
         )
         .into());
     }
@@ -346,22 +423,32 @@ pub async fn blend_multiple_fs_async(
 
     if algorithms_to_apply.len() != num_images - 1 {
         return Err(PConvertError::ArgumentError(format!(
-            "ArgumentError: 'algorithms' must be of size {} (one per blending operation)",
+            "'algorithms' must be of size {} (one per blending operation)",
             num_images - 1
         ))
         .into());
     };
 
     let _is_inline = is_inline.unwrap_or(false);
+    let dimension_policy = get_dimension_policy(&options)?;
+    let resize = get_resize_options(&options)?;
+    let output = get_output_options(&options, &out_path)?;
+    let limits = get_limits(&options)?;
+    limits.check_layers(num_images)?;
 
     let node_fs = node_require("fs");
 
     let mut png_futures: Vec<Option<wasm_bindgen_futures::JsFuture>> =
         Vec::with_capacity(num_images);
-    for path in image_paths.iter() {
-        let path = path.as_string().expect("path must be a string");
+    let mut paths = Vec::with_capacity(num_images);
+    let mut layers_options = Vec::with_capacity(num_images);
+    for value in image_paths {
+        let (path, layer_options) = build_layer(value, "path")?;
+        let path = path.as_string().ok_or_else(path_error)?;
         let png_future = node_read_file_async(&node_fs, &path);
         png_futures.push(Some(png_future));
+        paths.push(path);
+        layers_options.push(layer_options);
     }
 
     let first_demultiply = if !algorithms_to_apply.is_empty() {
@@ -371,7 +458,12 @@ pub async fn blend_multiple_fs_async(
     };
     let composition = png_futures[0].take().unwrap().await?;
     let composition = js_sys::Uint8Array::from(composition).to_vec();
-    let mut composition = decode_png(&composition[..], first_demultiply)?;
+    let composition = decode_image_with_limits(&composition[..], false, &limits)
+        .map_err(|err| err.with_path(&paths[0]).with_layer(0))?;
+    let mut composition = layers_options[0].apply_with_limits(composition, &limits)?;
+    if first_demultiply {
+        demultiply_image(&mut composition);
+    }
 
     // loops through the algorithms to apply and blends the
     // current composition with the next layer
@@ -382,7 +474,18 @@ pub async fn blend_multiple_fs_async(
         let algorithm_fn = get_blending_algorithm(algorithm);
         let current_layer = png_futures[i].take().unwrap().await?;
         let current_layer = js_sys::Uint8Array::from(current_layer).to_vec();
-        let current_layer = decode_png(&current_layer[..], demultiply)?;
+        let current_layer = decode_image_with_limits(&current_layer[..], false, &limits)
+            .map_err(|err| err.with_path(&paths[i]).with_layer(i))?;
+        let current_layer = layers_options[i].apply_with_limits(current_layer, &limits)?;
+        let mut current_layer = conform_layer(
+            current_layer,
+            composition.dimensions(),
+            i,
+            &dimension_policy,
+        )?;
+        if demultiply {
+            demultiply_image(&mut current_layer);
+        }
 
         blend_images(
             &mut composition,
@@ -392,15 +495,17 @@ pub async fn blend_multiple_fs_async(
         );
     }
 
-    let compression_type = get_compression_type(&options);
-    let filter_type = get_filter_type(&options);
+    let composition = resize_output(composition, &resize, &limits)?;
+    let compression_type = get_compression_type(&options)?;
+    let filter_type = get_filter_type(&options)?;
 
     let mut encoded_data = Vec::<u8>::with_capacity(composition.to_vec().capacity());
-    encode_png(
+    encode_image(
         &mut encoded_data,
         &composition,
         compression_type,
         filter_type,
+        &output,
     )?;
 
     node_write_file_sync(&node_fs, &out_path, &encoded_data);
@@ -413,6 +518,7 @@ fn blend_multiple_buffers(
     algorithm: Option<String>,
     algorithms: Option<Vec<JsValue>>,
     is_inline: Option<bool>,
+    dimension_policy: &DimensionPolicy,
 ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, PConvertError> {
     let num_images = image_buffers.len();
     if num_images < 1 {
@@ -451,12 +557,18 @@ fn blend_multiple_buffers(
         demultiply_image(&mut composition);
     }
     let zip_iter = image_buffers_iter.zip(algorithms_to_apply.iter());
-    for pair in zip_iter {
-        let mut current_layer = pair.0.to_owned();
+    for (index, pair) in zip_iter.enumerate() {
         let (algorithm, algorithm_params) = pair.1;
         let demultiply = is_algorithm_multiplied(algorithm);
         let algorithm_fn = get_blending_algorithm(algorithm);
 
+        let mut current_layer = conform_layer(
+            pair.0.to_owned(),
+            composition.dimensions(),
+            index + 1,
+            dimension_policy,
+        )?;
+
         if demultiply {
             demultiply_image(&mut current_layer);
         }
@@ -471,3 +583,7 @@ fn blend_multiple_buffers(
 
     Ok(composition)
 }
+
+fn path_error() -> PConvertError {
+    PConvertError::ArgumentError("path must be a string".to_string())
+}
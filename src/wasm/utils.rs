@@ -2,22 +2,29 @@
 //! argument parsing from javascript input to inner-crate rust types
 //! and other utility functions.
 
+use crate::blending::dimensions::DimensionPolicy;
 use crate::blending::params::{BlendAlgorithmParams, Value};
 use crate::blending::BlendAlgorithm;
+use crate::effects::Effect;
+use crate::encoding::{parse_filter, Compression, CompressionLevel};
 use crate::errors::PConvertError;
-use crate::utils::{decode_png, encode_png};
-use crate::utils::{image_compression_from, image_filter_from};
+use crate::format::{encode_image, OutputOptions, OutputValues};
+use crate::layer::LayerOptions;
+use crate::limits::{Limits, LIMIT_NAMES};
+use crate::resize::ResizeOptions;
+use crate::transform::Transform;
+use crate::utils::{decode_image_with_limits, encode_png};
 use crate::wasm::conversions::JSONParams;
-use image::codecs::png::{CompressionType, FilterType};
+use image::codecs::png::FilterType;
 use image::{ImageBuffer, Rgba};
-use js_sys::{Array, Uint8Array};
+use js_sys::{Array, Reflect, Uint8Array};
 use serde_json::Value as JSONValue;
 use std::collections::HashMap;
 use std::str::FromStr;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::Clamped;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{File, ImageData};
+use web_sys::{File, FilePropertyBag, ImageData};
 
 #[wasm_bindgen]
 extern "C" {
@@ -44,39 +51,64 @@ macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
-/// Receives a `File` and returns the decoded PNG byte buffer.
-pub async fn load_png(
+/// Receives a `File` (in any of the supported input formats) and
+/// returns the decoded image byte buffer.
+pub async fn load_image(
     file: File,
     demultiply: bool,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, JsValue> {
+    load_image_with_limits(file, demultiply, &Limits::default()).await
+}
+
+/// Receives a `File` (in any of the supported input formats) and returns
+/// the decoded image byte buffer, within the given resource limits.
+pub async fn load_image_with_limits(
+    file: File,
+    demultiply: bool,
+    limits: &Limits,
 ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, JsValue> {
     let array_buffer = JsFuture::from(file.array_buffer()).await?;
     let uint8_array = Uint8Array::new(&array_buffer);
-    let png = decode_png(&uint8_array.to_vec()[..], demultiply)?;
-    Ok(png)
+    let image = decode_image_with_limits(&uint8_array.to_vec()[..], demultiply, limits)
+        .map_err(|err| err.with_path(&file.name()))?;
+    Ok(image)
 }
 
-/// Receives png buffer data and encodes it as a `File` with specified
-/// `CompressionType` and `FilterType`.
+/// Receives png buffer data and encodes it as a `File` in the given output
+/// format, with specified `Compression` and `FilterType` (for PNGs).
 pub fn encode_file(
     image_buffer: ImageBuffer<Rgba<u8>, Vec<u8>>,
-    compression: CompressionType,
+    compression: Compression,
     filter: FilterType,
+    output: &OutputOptions,
     target_file_name: String,
 ) -> Result<File, JsValue> {
     let mut encoded_data = Vec::<u8>::with_capacity(image_buffer.to_vec().capacity());
-    encode_png(&mut encoded_data, &image_buffer, compression, filter)?;
+    encode_image(
+        &mut encoded_data,
+        &image_buffer,
+        compression,
+        filter,
+        output,
+    )?;
 
+    let properties = FilePropertyBag::new();
+    properties.set_type(output.format.mime_type());
     unsafe {
         let array_buffer = Uint8Array::view(&encoded_data);
-        File::new_with_u8_array_sequence(&Array::of1(&array_buffer), &target_file_name)
+        File::new_with_u8_array_sequence_and_options(
+            &Array::of1(&array_buffer),
+            &target_file_name,
+            &properties,
+        )
     }
 }
 
 /// Receives png buffer data and encodes it as an `ImageData` object with
-/// specified `CompressionType` and `FilterType`.
+/// specified `Compression` and `FilterType`.
 pub fn encode_image_data(
     image_buffer: ImageBuffer<Rgba<u8>, Vec<u8>>,
-    compression: CompressionType,
+    compression: Compression,
     filter: FilterType,
 ) -> Result<ImageData, JsValue> {
     let (width, height) = image_buffer.dimensions();
@@ -93,13 +125,7 @@ pub fn encode_image_data(
 /// Attempts to parse a `&String` to a `BlendAlgorithm`.
 /// Returns the enum variant if it suceeds. Otherwise it returns a `PConvertError`.
 pub fn build_algorithm(algorithm: &str) -> Result<BlendAlgorithm, PConvertError> {
-    match BlendAlgorithm::from_str(algorithm) {
-        Ok(algorithm) => Ok(algorithm),
-        Err(algorithm) => Err(PConvertError::ArgumentError(format!(
-            "Invalid algorithm '{}'",
-            algorithm
-        ))),
-    }
+    BlendAlgorithm::from_str(algorithm)
 }
 
 /// Attempts to build a vector of blending operations and extra parameters.
@@ -119,7 +145,8 @@ pub fn build_params(
 
             result.push((algorithm, None));
         } else if algorithm.is_object() {
-            let params: JSONParams = serde_wasm_bindgen::from_value(algorithm.clone()).unwrap();
+            let params: JSONParams = serde_wasm_bindgen::from_value(algorithm.clone())
+                .map_err(|err| PConvertError::invalid_parameter("algorithms", err.to_string()))?;
             let algorithm = build_algorithm(&params.algorithm)?;
 
             let mut blending_params = BlendAlgorithmParams::new();
@@ -135,34 +162,196 @@ pub fn build_params(
     Ok(result)
 }
 
-/// Retrieves the `image::codecs::png::CompressionType` value from the
-/// `HashMap<String, JSONValue>` map if it exists.
-/// Otherwise it returns the default value: `CompressionType::Fast`.
-pub fn get_compression_type(options: &Option<HashMap<String, JSONValue>>) -> CompressionType {
-    options.as_ref().map_or(CompressionType::Fast, |options| {
-        options
-            .get("compression")
-            .map_or(CompressionType::Fast, |compression| match compression {
-                JSONValue::String(compression) => image_compression_from(compression.to_string()),
-                _ => CompressionType::Fast,
-            })
-    })
+/// Splits a layer, given either as its source (e.g. a path or a `File`) or as an object
+/// with the source in the `key` property and the (optional) `transforms` and `effects`
+/// of the layer (arrays of strings such as `"rotate:90"` or `"grayscale"`), into its
+/// source and options.
+pub fn build_layer(layer: JsValue, key: &str) -> Result<(JsValue, LayerOptions), JsValue> {
+    let key = JsValue::from_str(key);
+    if !layer.is_object() || !Reflect::has(&layer, &key)? {
+        return Ok((layer, LayerOptions::default()));
+    }
+
+    let source = Reflect::get(&layer, &key)?;
+    let strings = |name: &str| -> Result<Vec<String>, JsValue> {
+        let value = Reflect::get(&layer, &JsValue::from_str(name))?;
+        if value.is_undefined() || value.is_null() {
+            return Ok(Vec::new());
+        }
+        serde_wasm_bindgen::from_value(value).map_err(|_| {
+            PConvertError::invalid_parameter(name, "expected an array of strings").into()
+        })
+    };
+    let layer_options = LayerOptions {
+        transforms: strings("transforms")?
+            .iter()
+            .map(|transform| Transform::from_str(transform))
+            .collect::<Result<Vec<_>, PConvertError>>()?,
+        effects: strings("effects")?
+            .iter()
+            .map(|effect| Effect::from_str(effect))
+            .collect::<Result<Vec<_>, PConvertError>>()?,
+    };
+    Ok((source, layer_options))
+}
+
+/// Retrieves the `Compression` from the `compression` (a name or a level
+/// from 0 to 9) and `strategy` values of the `HashMap<String, JSONValue>`
+/// map if they exist.
+/// Otherwise it returns the default value: `Compression::FAST`.
+pub fn get_compression_type(
+    options: &Option<HashMap<String, JSONValue>>,
+) -> Result<Compression, PConvertError> {
+    let get = |name: &str| options.as_ref().and_then(|options| options.get(name));
+    let strategy = match get("strategy") {
+        Some(JSONValue::String(strategy)) => Some(&strategy[..]),
+        Some(_) => {
+            return Err(PConvertError::invalid_parameter(
+                "strategy",
+                "expected a string",
+            ))
+        }
+        None => None,
+    };
+    Compression::from_options(
+        get("compression")
+            .map(CompressionLevel::from_json)
+            .transpose()?,
+        strategy,
+    )
 }
 
 /// Retrieves the `image::codecs::png::FilterType` value from the
 /// `HashMap<String, JSONValue>` map if it exists.
 /// Otherwise it returns the default value: `FilterType::NoFilter`.
-pub fn get_filter_type(options: &Option<HashMap<String, JSONValue>>) -> FilterType {
-    options.as_ref().map_or(FilterType::NoFilter, |options| {
-        options
-            .get("filter")
-            .map_or(FilterType::NoFilter, |filter| match filter {
-                JSONValue::String(filter) => image_filter_from(filter.to_string()),
-                _ => FilterType::NoFilter,
-            })
+pub fn get_filter_type(
+    options: &Option<HashMap<String, JSONValue>>,
+) -> Result<FilterType, PConvertError> {
+    match options.as_ref().and_then(|options| options.get("filter")) {
+        Some(JSONValue::String(filter)) => parse_filter(filter),
+        Some(_) => Err(PConvertError::invalid_parameter(
+            "filter",
+            "expected a string",
+        )),
+        None => Ok(FilterType::NoFilter),
+    }
+}
+
+/// Retrieves the `DimensionPolicy` from the `dimension_policy` and `gravity` values
+/// of the `HashMap<String, JSONValue>` map if they exist.
+/// Otherwise it returns the default value: `DimensionPolicy::Error`.
+pub fn get_dimension_policy(
+    options: &Option<HashMap<String, JSONValue>>,
+) -> Result<DimensionPolicy, PConvertError> {
+    let get_str = |name: &str| -> Result<Option<&str>, PConvertError> {
+        match options.as_ref().and_then(|options| options.get(name)) {
+            Some(JSONValue::String(value)) => Ok(Some(value)),
+            Some(_) => Err(PConvertError::invalid_parameter(name, "expected a string")),
+            None => Ok(None),
+        }
+    };
+    DimensionPolicy::from_options(get_str("dimension_policy")?, get_str("gravity")?)
+}
+
+/// Retrieves the `ResizeOptions` from the `size`, `scale` and `resize_filter` values
+/// of the `HashMap<String, JSONValue>` map if they exist.
+/// Otherwise it returns `None` (no resizing).
+pub fn get_resize_options(
+    options: &Option<HashMap<String, JSONValue>>,
+) -> Result<Option<ResizeOptions>, PConvertError> {
+    let get = |name: &str| options.as_ref().and_then(|options| options.get(name));
+    let get_str = |name: &str| -> Result<Option<&str>, PConvertError> {
+        match get(name) {
+            Some(JSONValue::String(value)) => Ok(Some(value)),
+            Some(_) => Err(PConvertError::invalid_parameter(name, "expected a string")),
+            None => Ok(None),
+        }
+    };
+    let scale = match get("scale") {
+        Some(JSONValue::Number(scale)) => scale.as_f64(),
+        Some(_) => {
+            return Err(PConvertError::invalid_parameter(
+                "scale",
+                "expected a number",
+            ))
+        }
+        None => None,
+    };
+    ResizeOptions::from_options(get_str("size")?, scale, get_str("resize_filter")?)
+}
+
+/// Retrieves the `OutputOptions` from the `format`, `quality`, `background`,
+/// `colors`, `dither` and `optimize` values of the `HashMap<String, JSONValue>`
+/// map if they exist, inferring the format from the extension of `path` when
+/// not given.
+pub fn get_output_options(
+    options: &Option<HashMap<String, JSONValue>>,
+    path: &str,
+) -> Result<OutputOptions, PConvertError> {
+    let get = |name: &str| options.as_ref().and_then(|options| options.get(name));
+    let get_str = |name: &str| -> Result<Option<&str>, PConvertError> {
+        match get(name) {
+            Some(JSONValue::String(value)) => Ok(Some(value)),
+            Some(_) => Err(PConvertError::invalid_parameter(name, "expected a string")),
+            None => Ok(None),
+        }
+    };
+    let get_int = |name: &str| -> Result<Option<i64>, PConvertError> {
+        match get(name) {
+            Some(JSONValue::Number(value)) => value
+                .as_i64()
+                .map(Some)
+                .ok_or_else(|| PConvertError::invalid_parameter(name, "expected an integer")),
+            Some(_) => Err(PConvertError::invalid_parameter(
+                name,
+                "expected an integer",
+            )),
+            None => Ok(None),
+        }
+    };
+    let get_bool = |name: &str| -> Result<Option<bool>, PConvertError> {
+        match get(name) {
+            Some(JSONValue::Bool(value)) => Ok(Some(*value)),
+            Some(_) => Err(PConvertError::invalid_parameter(name, "expected a boolean")),
+            None => Ok(None),
+        }
+    };
+    OutputOptions::from_options(OutputValues {
+        format: get_str("format")?,
+        quality: get_int("quality")?,
+        background: get_str("background")?,
+        colors: get_int("colors")?,
+        dither: get_bool("dither")?,
+        optimize: get_bool("optimize")?,
+        path: Some(path),
     })
 }
 
+/// Retrieves the resource `Limits` from the `max_width`, `max_height`, `max_pixels`,
+/// `max_bytes` and `max_layers` values of the `HashMap<String, JSONValue>` map if
+/// they exist, using the default value for every limit not given.
+pub fn get_limits(options: &Option<HashMap<String, JSONValue>>) -> Result<Limits, PConvertError> {
+    let mut limits = Limits::default();
+    for name in LIMIT_NAMES {
+        match options.as_ref().and_then(|options| options.get(name)) {
+            Some(JSONValue::Number(value)) => {
+                let value = value.as_u64().ok_or_else(|| {
+                    PConvertError::invalid_parameter(name, "expected a non-negative integer")
+                })?;
+                limits.set(name, value)?;
+            }
+            Some(_) => {
+                return Err(PConvertError::invalid_parameter(
+                    name,
+                    "expected a non-negative integer",
+                ))
+            }
+            None => (),
+        }
+    }
+    Ok(limits)
+}
+
 /// Logs the header/column names of the benchmarks table to the browser
 /// console (with `console.log`).
 pub fn log_benchmark_header() {
@@ -179,7 +368,7 @@ pub fn log_benchmark_header() {
 /// of the benchmarks table to the browser console (with `console.log`).
 pub fn log_benchmark(
     algorithm: String,
-    compression: CompressionType,
+    compression: Compression,
     filter: FilterType,
     blend_time: f64,
     read_time: f64,
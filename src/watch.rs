@@ -0,0 +1,154 @@
+//! Incremental re-rendering of a composition whenever its layers change.
+
+use crate::batch::{parse_json_entry, BatchItem};
+use crate::blending::dimensions::conform_layer;
+use crate::blending::{
+    blend_images, demultiply_image, get_blending_algorithm, is_algorithm_multiplied,
+};
+use crate::cache::{CacheStats, LayerCache, DEFAULT_CACHE_CAPACITY};
+use crate::errors::PConvertError;
+use crate::format::write_image_to_file;
+use crate::limits::Limits;
+use crate::resize::resize_output;
+use image::{ImageBuffer, Rgba};
+use std::fs;
+use std::time::SystemTime;
+
+/// Keeps the state of a watched composition: the modification time of
+/// each layer, the decoded layers and the partial compositions (the
+/// result of blending the first `n` layers), so that a change in a layer
+/// only requires decoding that layer and blending the layers above it.
+pub struct Watcher {
+    item: BatchItem,
+    cache: LayerCache,
+    limits: Limits,
+    modified: Vec<Option<SystemTime>>,
+    partials: Vec<ImageBuffer<Rgba<u8>, Vec<u8>>>,
+}
+
+impl Watcher {
+    /// Creates a watcher for the given composition, rendered within the
+    /// given resource limits, nothing is rendered until the first call
+    /// to `update`.
+    pub fn new(item: BatchItem, limits: Limits) -> Result<Watcher, PConvertError> {
+        limits.check_layers(item.layers.len())?;
+        if item.layers.is_empty() {
+            return Err(PConvertError::ArgumentError(
+                "'layers' must contain at least one path".to_string(),
+            ));
+        }
+
+        if item.algorithms.len() != item.layers.len() - 1 {
+            return Err(PConvertError::ArgumentError(format!(
+                "'algorithms' must be of size {} (one per blending operation)",
+                item.layers.len() - 1
+            )));
+        }
+
+        // the cache holds (up to its default capacity) every one of the
+        // watched layers, none of them larger than the bytes limit
+        let capacity = (item.layers.len() as u64)
+            .saturating_mul(limits.max_bytes)
+            .min(DEFAULT_CACHE_CAPACITY as u64) as usize;
+
+        Ok(Watcher {
+            modified: vec![None; item.layers.len()],
+            partials: Vec::with_capacity(item.layers.len()),
+            cache: LayerCache::new(capacity),
+            limits,
+            item,
+        })
+    }
+
+    /// Re-renders the composition (and writes it to the output path)
+    /// if any of the layers changed since the last render.
+    ///
+    /// Returns the index of the lowest changed layer, or `None` if
+    /// nothing changed and so nothing was rendered.
+    pub fn update(&mut self) -> Result<Option<usize>, PConvertError> {
+        let modified = self
+            .item
+            .layers
+            .iter()
+            .map(|path| Ok(fs::metadata(path)?.modified().ok()))
+            .collect::<Result<Vec<_>, PConvertError>>()?;
+
+        // finds the lowest layer that changed, every partial composition
+        // below it remains valid and is reused as the starting point
+        let changed = match (0..modified.len())
+            .find(|&index| modified[index].is_none() || modified[index] != self.modified[index])
+        {
+            Some(changed) => changed,
+            None => return Ok(None),
+        };
+        self.partials.truncate(changed);
+
+        for index in self.partials.len()..self.item.layers.len() {
+            // layers are cached as decoded (straight alpha) so that they
+            // are conformed to the composition before being demultiplied
+            let path = &self.item.layers[index];
+            let layer = (*self
+                .cache
+                .read_image_with_limits(path, false, &self.limits)
+                .map_err(|err| err.with_layer(index))?)
+            .clone();
+            let mut layer = self.item.apply_layer_options(index, layer, &self.limits)?;
+
+            let composition = if index == 0 {
+                if !self.item.algorithms.is_empty()
+                    && is_algorithm_multiplied(&self.item.algorithms[0].0)
+                {
+                    demultiply_image(&mut layer);
+                }
+                layer
+            } else {
+                let (algorithm, algorithm_params) = &self.item.algorithms[index - 1];
+                let algorithm_fn = get_blending_algorithm(algorithm);
+                let mut composition = self.partials[index - 1].clone();
+                let mut layer = conform_layer(
+                    layer,
+                    composition.dimensions(),
+                    index,
+                    &self.item.dimension_policy,
+                )?;
+                if is_algorithm_multiplied(algorithm) {
+                    demultiply_image(&mut layer);
+                }
+                blend_images(&mut composition, &layer, &algorithm_fn, algorithm_params);
+                composition
+            };
+            self.partials.push(composition);
+        }
+
+        // the partial compositions are kept for the next updates, so the
+        // last one is resized (or written) from a copy of it
+        let composition = resize_output(
+            self.partials.last().unwrap().clone(),
+            &self.item.resize,
+            &self.limits,
+        )?;
+        write_image_to_file(
+            self.item.out.clone(),
+            &composition,
+            self.item.compression,
+            self.item.filter,
+            &self.item.output,
+        )?;
+        self.modified = modified;
+
+        Ok(Some(changed))
+    }
+
+    /// Returns the statistics of the decoded layers cache, where each
+    /// miss corresponds to a (re-)decoded layer.
+    pub fn get_cache_stats(&self) -> CacheStats {
+        self.cache.get_stats()
+    }
+}
+
+/// Reads a scene file, a JSON object describing a single composition
+/// with the same fields as a batch manifest entry (e.g. `out`, `layers`
+/// and `algorithm`).
+pub fn read_scene(path: &str) -> Result<BatchItem, PConvertError> {
+    parse_json_entry(&fs::read_to_string(path)?)
+}